@@ -0,0 +1,26 @@
+#![no_main]
+
+use backend::protocol::{ self, ClientMessage };
+use base64::{ Engine as _, engine::general_purpose };
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // tungstenite only hands the server valid UTF-8 text frames
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    match protocol::parse_request(text) {
+        Ok(request) => {
+            // The insert/set_thumbnail handlers decode these next; invalid base64 must be an error, not a panic
+            let payload = match &request {
+                ClientMessage::Insert { model_data, .. } => Some(model_data),
+                ClientMessage::SetThumbnail { thumbnail_data, .. } => Some(thumbnail_data),
+                _ => None,
+            };
+            if let Some(payload) = payload {
+                let _ = general_purpose::STANDARD.decode(payload);
+            }
+        }
+        Err(e) => assert!(e.message.starts_with("Invalid request") || e.message.starts_with("Unknown action")),
+    }
+});
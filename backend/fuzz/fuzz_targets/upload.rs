@@ -0,0 +1,17 @@
+#![no_main]
+
+use backend::{ formats, lod, obj, textures };
+use libfuzzer_sys::fuzz_target;
+
+// Runs uploaded bytes through everything insert and export_obj do with them. Sizes come from
+// the upload itself, so libFuzzer's -malloc_limit_mb catches any allocation they drive unchecked.
+fuzz_target!(|data: &[u8]| {
+    // Only glTF and GLB go any further than being stored
+    if formats::sniff_format(data).and_then(formats::ModelFormat::stored_name) != Some("gltf") || formats::validate_gltf(data).is_err() {
+        return;
+    }
+    let _ = textures::texture_warnings(data);
+    let _ = textures::downscale_textures(data, 64);
+    let _ = lod::generate_lods(data);
+    let _ = obj::export_obj(data, "model");
+});
@@ -0,0 +1,240 @@
+//! Command-line management for the catalog in `DATABASE_PATH`, by default `models.db` (run
+//! from the server's directory).
+//!
+//! cargo run --bin admin -- list | show <id> | delete <id> | export <id> <file> | export-original <id> <file>
+//!     | set-source <id> <file> | reload <id> | stats | optimize
+//!
+//! `set-source` records the file a model is developed from and `reload` replaces the stored
+//! bytes with that file's current contents, so a model can be edited on disk and served from
+//! the database. Models inserted by the seed binary already know their source.
+
+use backend::{
+    db::{
+        catalog_stats, db_path, delete_model, init_db, load_all_models, load_comments, load_layout_by_id, load_layout_summaries,
+        load_metadata, load_model_by_id, load_model_original, load_source_path, load_template_summaries, load_thumbnail,
+        open_db, optimize_db, replace_model_data, schema_version, update_source_path
+    },
+    format_size,
+    formats,
+    lod,
+    textures
+};
+
+const USAGE: &str = "usage: admin <list | show <id> | delete <id> | export <id> <file> | export-original <id> <file> | \
+                     set-source <id> <file> | reload <id> | stats | optimize>";
+
+fn main() {
+    backend::init_logging();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let Err(e) = init_db() {
+        eprintln!("Failed to open {}: {}", db_path().display(), e);
+        std::process::exit(1);
+    }
+    let result = match args.as_slice() {
+        ["list"] => list(),
+        ["show", id] => parse_id(id).and_then(show),
+        ["delete", id] => parse_id(id).and_then(delete),
+        ["export", id, file] => parse_id(id).and_then(|id| export(id, file)),
+        ["export-original", id, file] => parse_id(id).and_then(|id| export_original(id, file)),
+        ["set-source", id, file] => parse_id(id).and_then(|id| set_source(id, file)),
+        ["reload", id] => parse_id(id).and_then(reload),
+        ["stats"] => stats(),
+        ["optimize"] => optimize(),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_id(id: &str) -> Result<i32, String> {
+    id.parse().map_err(|_| format!("Invalid model id: {}", id))
+}
+
+fn list() -> Result<(), String> {
+    let models = load_all_models().map_err(|e| format!("Failed to load models: {}", e))?;
+    println!("{:>5}  {:<6} {:>10}  {:<9} name", "id", "format", "size", "flags");
+    for model in models {
+        let mut flags = vec![];
+        if model.access_code_hash.is_some() {
+            flags.push("locked");
+        }
+        if model.texture_warnings {
+            flags.push("textures");
+        }
+        if model.transform_locked {
+            flags.push("pinned");
+        }
+        println!(
+            "{:>5}  {:<6} {:>10}  {:<9} {}",
+            model.id,
+            model.format,
+            format_size(model.model_data.len()),
+            flags.join(","),
+            model.name.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+fn show(id: i32) -> Result<(), String> {
+    let model = load_model_by_id(id).map_err(|e| format!("Model {} not found: {}", id, e))?;
+    println!("id:               {}", model.id);
+    println!("name:             {}", model.name.as_deref().unwrap_or("-"));
+    println!("format:           {}", model.format);
+    println!("created by:       {}", model.created_by);
+    println!("version:          {}", model.version);
+    println!("uploaded:         {}", format_timestamp(model.created_at));
+    println!("last changed:     {}", format_timestamp(model.updated_at));
+    println!("size:             {}", format_size(model.model_data.len()));
+    println!("protected:        {}", model.access_code_hash.is_some());
+    println!("texture warnings: {}", model.texture_warnings);
+    println!("transform locked: {}", model.transform_locked);
+    println!("detail levels:    {}", if model.lods.is_empty() { "-".to_string() } else { model.lods.join(", ") });
+    let source = load_source_path(id).map_err(|e| format!("Failed to load source path: {}", e))?;
+    println!("source file:      {}", source.as_deref().unwrap_or("-"));
+    let original = load_model_original(id).map_err(|e| format!("Failed to load original upload: {}", e))?;
+    println!("original upload:  {}", original.map_or("-".to_string(), |data| format!("{} before texture downscaling", format_size(data.len()))));
+
+    let metadata = load_metadata(id).map_err(|e| format!("Failed to load metadata: {}", e))?;
+    println!("metadata:{}", if metadata.is_empty() { "         -" } else { "" });
+    for (key, value) in metadata {
+        println!("  {}: {}", key, value);
+    }
+
+    let thumbnail = load_thumbnail(&model).map_err(|e| format!("Failed to load thumbnail: {}", e))?;
+    let thumbnail_state = match (thumbnail.image_data.is_empty(), thumbnail.stale) {
+        (true, _) => "none",
+        (false, true) => "stale",
+        (false, false) => "current",
+    };
+    println!("thumbnail:        {}", thumbnail_state);
+
+    let layouts = load_layout_summaries().map_err(|e| format!("Failed to load layouts: {}", e))?;
+    let templates = load_template_summaries().map_err(|e| format!("Failed to load templates: {}", e))?;
+    let mut used_in = vec![];
+    for (summary, template) in layouts.into_iter().map(|s| (s, false)).chain(templates.into_iter().map(|s| (s, true))) {
+        let layout = load_layout_by_id(summary.id).map_err(|e| format!("Failed to load layout {}: {}", summary.id, e))?;
+        if layout.entries.iter().any(|entry| entry.model_id == id) {
+            used_in.push(if template { format!("{} (template)", layout.name) } else { layout.name });
+        }
+    }
+    println!("layouts:          {}", if used_in.is_empty() { "-".to_string() } else { used_in.join(", ") });
+
+    let comments = load_comments(id).map_err(|e| format!("Failed to load comments: {}", e))?;
+    println!("comments:         {}", comments.len());
+    for comment in comments {
+        println!("  [{}] {}: {}", comment.created_at, comment.author, comment.text);
+    }
+    Ok(())
+}
+
+// UTC date and time of a stored timestamp, "-" for models stored before uploads were dated
+fn format_timestamp(secs: i64) -> String {
+    if secs <= 0 {
+        return "-".to_string();
+    }
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01, in 400-year eras starting on March 1st
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, secs_of_day / 3600, secs_of_day % 3600 / 60)
+}
+
+fn delete(id: i32) -> Result<(), String> {
+    let layouts = delete_model(id).map_err(|e| format!("Failed to delete model {}: {}", id, e))?;
+    println!("Deleted model {}", id);
+    if !layouts.is_empty() {
+        println!("Removed it from layouts {:?}", layouts);
+    }
+    Ok(())
+}
+
+fn export(id: i32, file: &str) -> Result<(), String> {
+    let model = load_model_by_id(id).map_err(|e| format!("Model {} not found: {}", id, e))?;
+    std::fs::write(file, &model.model_data).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+    println!("Exported model {} ({}) to {}", id, format_size(model.model_data.len()), file);
+    Ok(())
+}
+
+fn export_original(id: i32, file: &str) -> Result<(), String> {
+    load_model_by_id(id).map_err(|e| format!("Model {} not found: {}", id, e))?;
+    let original = load_model_original(id)
+        .map_err(|e| format!("Failed to load original upload of model {}: {}", id, e))?
+        .ok_or(format!("Model {} was stored as uploaded; use export", id))?;
+    std::fs::write(file, &original).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+    println!("Exported original upload of model {} ({}) to {}", id, format_size(original.len()), file);
+    Ok(())
+}
+
+fn set_source(id: i32, file: &str) -> Result<(), String> {
+    let path = source_file(file)?;
+    update_source_path(id, Some(&path)).map_err(|e| format!("Model {} not found: {}", id, e))?;
+    println!("Model {} now reloads from {}", id, path);
+    Ok(())
+}
+
+fn reload(id: i32) -> Result<(), String> {
+    let source = load_source_path(id)
+        .map_err(|e| format!("Model {} not found: {}", id, e))?
+        .ok_or(format!("Model {} has no source file; record one with set-source", id))?;
+    let path = source_file(&source)?;
+    let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    // Same checks and detail levels as an upload through the server
+    let format = formats::sniff_format(&data)
+        .and_then(|format| format.stored_name())
+        .ok_or(format!("{} is not a glTF, GLB or PLY model", path))?;
+    let texture_warnings = !textures::texture_warnings(&data).is_empty();
+    let lods = lod::generate_lods(&data);
+    replace_model_data(id, &data, format, texture_warnings, &lods).map_err(|e| format!("Failed to update model {}: {}", id, e))?;
+    println!("Reloaded model {} from {} ({})", id, path, format_size(data.len()));
+    Ok(())
+}
+
+// Absolute path of an existing regular file, so a recorded source doesn't depend on where
+// admin runs from
+fn source_file(file: &str) -> Result<String, String> {
+    let path = std::fs::canonicalize(file).map_err(|e| format!("Cannot use {} as a source file: {}", file, e))?;
+    if !path.is_file() {
+        return Err(format!("{} is not a regular file", path.display()));
+    }
+    path.to_str().map(str::to_string).ok_or(format!("{} is not a UTF-8 path", path.display()))
+}
+
+fn stats() -> Result<(), String> {
+    let stats = catalog_stats().map_err(|e| format!("Failed to read catalog: {}", e))?;
+    println!("models:           {}", stats.models);
+    for (format, count) in &stats.formats {
+        println!("  {:<15} {}", format, count);
+    }
+    println!("model data:       {}", format_size(stats.model_bytes as usize));
+    println!("protected:        {}", stats.protected);
+    println!("texture warnings: {}", stats.texture_warnings);
+    println!("thumbnails:       {}", stats.thumbnails);
+    println!("layouts:          {}", stats.layouts);
+    println!("comments:         {}", stats.comments);
+    let conn = open_db().map_err(|e| format!("Failed to open catalog: {}", e))?;
+    println!("schema version:   {}", schema_version(&conn).map_err(|e| format!("Failed to read schema version: {}", e))?);
+    if let Ok(metadata) = std::fs::metadata(db_path()) {
+        println!("database file:    {}", format_size(metadata.len() as usize));
+    }
+    Ok(())
+}
+
+fn optimize() -> Result<(), String> {
+    let report = optimize_db().map_err(|e| format!("Failed to optimize catalog: {}", e))?;
+    println!("orphaned rows:    {}", report.orphans_removed);
+    println!("before:           {}", format_size(report.size_before as usize));
+    println!("after:            {}", format_size(report.size_after as usize));
+    println!("reclaimed:        {}", format_size(report.size_before.saturating_sub(report.size_after) as usize));
+    Ok(())
+}
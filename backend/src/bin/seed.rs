@@ -0,0 +1,92 @@
+//! Seeds a catalog with the sample models bundled in `frontend/assets/models`, or with the
+//! model files named on the command line.
+//!
+//! The samples are embedded in the binary and inserted in a fixed order, so every fresh
+//! catalog gets the same ids. Models already present by name are skipped, making
+//! repeated runs harmless. Each model records the file it came from, so `admin reload`
+//! can pick up later edits to it.
+//!
+//! cargo run --bin seed -- [--db PATH] [FILE...]
+
+use std::borrow::Cow;
+use backend::{
+    db::{ db_path, init_db, insert_model, insert_model_lods, load_all_models, set_db_path, unix_time, update_source_path },
+    format_size,
+    formats,
+    lod,
+    textures
+};
+
+// Name, path of the asset at build time, and its bytes
+macro_rules! sample {
+    ($name:literal) => {{
+        const PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../frontend/assets/models/", $name, ".gltf");
+        ($name, PATH, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/../frontend/assets/models/", $name, ".gltf")).as_slice())
+    }};
+}
+
+const SAMPLES: [(&str, &str, &[u8]); 4] = [sample!("Building"), sample!("Eye"), sample!("Helix_bridge"), sample!("bridge")];
+
+fn main() {
+    backend::init_logging();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut files = args.as_slice();
+    if let [flag, path, rest @ ..] = files && flag == "--db" {
+        set_db_path(path);
+        files = rest;
+    }
+    if files.iter().any(|file| file.starts_with('-')) {
+        eprintln!("usage: seed [--db PATH] [FILE...]");
+        std::process::exit(2);
+    }
+    if let Err(e) = init_db() {
+        eprintln!("Failed to open {}: {}", db_path().display(), e);
+        std::process::exit(1);
+    }
+    if let Err(e) = seed(files) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn seed(files: &[String]) -> Result<(), String> {
+    let models: Vec<(String, String, Cow<[u8]>)> = if files.is_empty() {
+        SAMPLES.iter().map(|(name, path, data)| (name.to_string(), path.to_string(), Cow::Borrowed(*data))).collect()
+    } else {
+        files.iter().map(|file| read_model_file(file)).collect::<Result<_, _>>()?
+    };
+    let existing = load_all_models().map_err(|e| format!("Failed to open {}: {}", db_path().display(), e))?;
+    for (name, path, data) in &models {
+        let (name, data) = (name.as_str(), data.as_ref());
+        if existing.iter().any(|model| model.name.as_deref() == Some(name)) {
+            println!("Skipping {}, already in the catalog", name);
+            continue;
+        }
+        // Same checks and detail levels as an upload through the server
+        let texture_warnings = !textures::texture_warnings(data).is_empty();
+        let lods = lod::generate_lods(data);
+        let format = formats::sniff_format(data).and_then(|format| format.stored_name()).unwrap_or("gltf");
+        let id = insert_model(data, Some(name), format, texture_warnings, "seed", unix_time(), None)
+            .and_then(|id| insert_model_lods(id, &lods).map(|_| id))
+            .and_then(|id| update_source_path(id, Some(path.as_str())).map(|_| id))
+            .map_err(|e| format!("Failed to insert {}: {}", name, e))?;
+        println!("Inserted {} ({}) as model {}", name, format_size(data.len()), id);
+    }
+    println!("Seeded {}", db_path().display());
+    Ok(())
+}
+
+// Name (the file stem), absolute path and bytes of a model file; anything the server
+// wouldn't accept is refused before the catalog is touched
+fn read_model_file(file: &str) -> Result<(String, String, Cow<'static, [u8]>), String> {
+    let path = std::fs::canonicalize(file).map_err(|e| format!("Cannot read {}: {}", file, e))?;
+    let data = std::fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    match formats::sniff_format(&data).and_then(|format| format.stored_name()) {
+        Some("gltf") => formats::validate_gltf(&data).map_err(|e| format!("{}: {}", path.display(), e))?,
+        Some(_) => {}
+        None => return Err(format!("{} is not a glTF, GLB or PLY model", path.display())),
+    }
+    let name = path.file_stem().and_then(|stem| stem.to_str()).ok_or(format!("{} has no usable name", path.display()))?;
+    let path_str = path.to_str().ok_or(format!("{} is not a UTF-8 path", path.display()))?;
+    Ok((name.to_string(), path_str.to_string(), Cow::Owned(data)))
+}
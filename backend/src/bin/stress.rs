@@ -0,0 +1,290 @@
+//! Load-test harness for the backend.
+//!
+//! Opens N WebSocket clients that each perform random insert/delete/fetch operations at a
+//! fixed rate, then reports latency percentiles per action, failed or timed-out requests,
+//! and how many insert broadcasts clients never received (lagged).
+//!
+//! cargo run --release --bin stress -- --clients 20 --rate 5 --duration 30
+
+use futures_util::{ SinkExt, StreamExt };
+use serde_json::{ json, Value };
+use std::{
+    collections::{ BTreeMap, HashSet },
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
+};
+use tokio_tungstenite::{ connect_async_with_config, tungstenite::Message };
+use base64::{ Engine as _, engine::general_purpose };
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+// Smallest glTF the server and clients accept
+const TINY_GLTF: &str = r#"{"asset":{"version":"2.0"},"scenes":[{"nodes":[0]}],"nodes":[{}],"scene":0}"#;
+
+struct Options {
+    url: String,
+    clients: usize,
+    rate: f64, // operations per second per client
+    duration: Duration,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Options {
+            url: "ws://127.0.0.1:8000/ws".to_string(),
+            clients: 10,
+            rate: 2.0,
+            duration: Duration::from_secs(20),
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--url" => options.url = value()?,
+                "--clients" => options.clients = value()?.parse().map_err(|e| format!("--clients: {}", e))?,
+                "--rate" => options.rate = value()?.parse().map_err(|e| format!("--rate: {}", e))?,
+                "--duration" => {
+                    let secs: u64 = value()?.parse().map_err(|e| format!("--duration: {}", e))?;
+                    options.duration = Duration::from_secs(secs);
+                }
+                "--help" | "-h" => {
+                    return Err("usage: stress [--url URL] [--clients N] [--rate OPS_PER_SEC] [--duration SECS]".to_string());
+                }
+                other => return Err(format!("Unknown flag: {}", other)),
+            }
+        }
+        if options.clients == 0 || options.rate <= 0.0 {
+            return Err("--clients and --rate must be positive".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Default)]
+struct ClientReport {
+    latencies: BTreeMap<&'static str, Vec<Duration>>,
+    failed: usize, // error responses
+    timed_out: usize, // no matching response within RESPONSE_TIMEOUT
+    inserted: Vec<String>, // names of the models this client created
+    seen: HashSet<String>, // stress model names seen in broadcasts
+}
+
+// Small xorshift generator so the harness needs no extra dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let run_id = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    println!(
+        "Running {} clients at {} ops/s each for {:?} against {}",
+        options.clients, options.rate, options.duration, options.url
+    );
+
+    let mut handles = Vec::new();
+    for client in 0..options.clients {
+        let url = options.url.clone();
+        let prefix = format!("stress-{}-{}-", run_id, client);
+        let interval = Duration::from_secs_f64(1.0 / options.rate);
+        let duration = options.duration;
+        handles.push(tokio::spawn(async move {
+            run_client(&url, &prefix, client as u64 + run_id as u64, interval, duration).await
+        }));
+    }
+
+    let mut reports = Vec::new();
+    for handle in handles {
+        match handle.await {
+            Ok(Ok(report)) => reports.push(report),
+            Ok(Err(e)) => eprintln!("Client failed: {}", e),
+            Err(e) => eprintln!("Client panicked: {}", e),
+        }
+    }
+    print_summary(&reports);
+}
+
+async fn run_client(
+    url: &str,
+    prefix: &str,
+    seed: u64,
+    interval: Duration,
+    duration: Duration,
+) -> Result<ClientReport, String> {
+    let mut config = tokio_tungstenite::tungstenite::protocol::WebSocketConfig::default();
+    config.max_message_size = Some(100 * 1024 * 1024);
+    config.max_frame_size = Some(100 * 1024 * 1024);
+    let (mut ws, _) = connect_async_with_config(url, Some(config), false)
+        .await
+        .map_err(|e| format!("Failed to connect: {}", e))?;
+
+    let mut report = ClientReport::default();
+    let mut rng = Rng(seed | 1);
+    let mut known_ids: Vec<i32> = Vec::new();
+    let mut own_ids: Vec<i32> = Vec::new();
+    let mut ticker = tokio::time::interval(interval);
+    let started = Instant::now();
+
+    while started.elapsed() < duration {
+        ticker.tick().await;
+        let roll = rng.next() % 100;
+        let (action, request) = if roll < 35 || known_ids.is_empty() {
+            let name = format!("{}{}", prefix, report.inserted.len());
+            report.inserted.push(name.clone());
+            ("insert", json!({
+                "action": "insert",
+                "name": name,
+                "model_data": general_purpose::STANDARD.encode(TINY_GLTF),
+            }))
+        } else if roll < 55 && !own_ids.is_empty() {
+            let id = own_ids.swap_remove(rng.next() as usize % own_ids.len());
+            ("delete", json!({ "action": "delete", "id": id }))
+        } else if roll < 90 {
+            let id = known_ids[rng.next() as usize % known_ids.len()];
+            ("get_by_id", json!({ "action": "get_by_id", "id": id }))
+        } else {
+            ("get_all", json!({ "action": "get_all" }))
+        };
+
+        let sent_at = Instant::now();
+        ws.send(Message::Text(request.to_string().into()))
+            .await
+            .map_err(|e| format!("Send failed: {}", e))?;
+
+        // The protocol has no request ids, so match the first message that looks like our answer
+        let deadline = tokio::time::sleep(RESPONSE_TIMEOUT);
+        tokio::pin!(deadline);
+        loop {
+            let message = tokio::select! {
+                message = ws.next() => message,
+                _ = &mut deadline => {
+                    report.timed_out += 1;
+                    break;
+                }
+            };
+            let text = match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(format!("Connection error: {}", e)),
+                None => return Err("Connection closed by server".to_string()),
+            };
+            let Ok(value) = serde_json::from_str::<Value>(&text) else {
+                continue;
+            };
+            record_broadcast(&value, prefix, &mut report, &mut known_ids);
+            if value.get("error").is_some() {
+                report.failed += 1;
+                break;
+            }
+            if is_response(action, &request, &value) {
+                if action == "insert" && let Some(id) = value["id"].as_i64() {
+                    own_ids.push(id as i32);
+                }
+                report.latencies.entry(action).or_default().push(sent_at.elapsed());
+                break;
+            }
+        }
+    }
+
+    // Remove whatever this client left behind, waiting for each delete so none are dropped on close
+    for id in own_ids {
+        let request = json!({ "action": "delete", "id": id });
+        if ws.send(Message::Text(request.to_string().into())).await.is_err() {
+            break;
+        }
+        let _ = tokio::time::timeout(RESPONSE_TIMEOUT, async {
+            while let Some(Ok(message)) = ws.next().await {
+                if let Message::Text(text) = message
+                    && let Ok(value) = serde_json::from_str::<Value>(&text)
+                    && (value.get("error").is_some() || is_response("delete", &request, &value))
+                {
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+    let _ = ws.close(None).await;
+    Ok(report)
+}
+
+fn is_response(action: &str, request: &Value, value: &Value) -> bool {
+    match action {
+        "insert" => value["name"] == request["name"],
+        "get_by_id" => value["id"] == request["id"],
+        "delete" => value["model_deleted"]["id"] == request["id"],
+        _ => value.is_array(),
+    }
+}
+
+// Tracks ids to fetch and which stress models this client has heard about
+fn record_broadcast(value: &Value, prefix: &str, report: &mut ClientReport, known_ids: &mut Vec<i32>) {
+    let models: Vec<&Value> = match value {
+        Value::Array(models) => {
+            known_ids.clear();
+            models.iter().collect()
+        }
+        Value::Object(_) if value.get("model_updated").is_some() => vec![&value["model_updated"]],
+        Value::Object(_) if value.get("model_deleted").is_some() => {
+            known_ids.retain(|id| value["model_deleted"]["id"] != *id);
+            return;
+        }
+        Value::Object(_) if value.get("id").is_some() => vec![value],
+        _ => return,
+    };
+    for model in models {
+        if let Some(id) = model["id"].as_i64() && !known_ids.contains(&(id as i32)) {
+            known_ids.push(id as i32);
+        }
+        if let Some(name) = model["name"].as_str() && name.starts_with("stress-") && !name.starts_with(prefix) {
+            report.seen.insert(name.to_string());
+        }
+    }
+}
+
+fn print_summary(reports: &[ClientReport]) {
+    let mut latencies: BTreeMap<&str, Vec<Duration>> = BTreeMap::new();
+    for report in reports {
+        for (action, samples) in &report.latencies {
+            latencies.entry(action).or_default().extend(samples);
+        }
+    }
+    println!("\n{:<10} {:>7} {:>9} {:>9} {:>9} {:>9}", "action", "count", "p50", "p90", "p99", "max");
+    for (action, samples) in &mut latencies {
+        samples.sort();
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+        println!(
+            "{:<10} {:>7} {:>9.1?} {:>9.1?} {:>9.1?} {:>9.1?}",
+            action,
+            samples.len(),
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            samples[samples.len() - 1]
+        );
+    }
+
+    let failed: usize = reports.iter().map(|r| r.failed).sum();
+    let timed_out: usize = reports.iter().map(|r| r.timed_out).sum();
+    println!("\nError responses: {}", failed);
+    println!("Timed out (no response in {:?}): {}", RESPONSE_TIMEOUT, timed_out);
+
+    // Every other client should have seen each stress insert at least once,
+    // either as the insert broadcast or in a later model list
+    let inserted: usize = reports.iter().map(|r| r.inserted.len()).sum();
+    let expected: usize = reports.iter().map(|r| inserted - r.inserted.len()).sum();
+    let seen: usize = reports.iter().map(|r| r.seen.len()).sum();
+    println!("Insert broadcasts missed by other clients: {} of {}", expected.saturating_sub(seen), expected);
+}
@@ -0,0 +1,1168 @@
+//! SQLite storage shared by the server and the admin CLI.
+
+use crate::lod::LOD_LEVELS;
+use crate::protocol::{ CommentResponse, LayoutEntry, LayoutResponse, LayoutSummary, ModelTransform, ModelVersion, ThumbnailResponse };
+use base64::{ Engine as _, engine::general_purpose };
+use rusqlite::{ params, Connection, OptionalExtension, Result, Row, Transaction, TransactionBehavior };
+use sha2::{ Digest, Sha256 };
+use std::collections::BTreeMap;
+use std::ops::{ Deref, DerefMut };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Mutex, OnceLock };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+const DEFAULT_DB_PATH: &str = "models.db";
+static DB_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+// Connections handed back after use, reused by the next open_db instead of reopening the file
+static IDLE_CONNECTIONS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+
+/// Most connections kept open between queries. Busier moments open extra ones, which are
+/// closed again when they are handed back to a full pool.
+pub const MAX_IDLE_CONNECTIONS: usize = 8;
+
+#[derive(Debug)]
+pub struct ModelData {
+    pub id: i32,
+    pub name: Option<String>,
+    pub model_data: Vec<u8>, // raw binary data
+    pub access_code_hash: Option<String>,
+    pub format: String,
+    pub texture_warnings: bool,
+    pub lods: Vec<String>, // reduced-detail levels stored in model_lods, besides "full"
+    pub transform_locked: bool, // copy_transform and transform may not move it
+    pub created_by: String, // uploader's display name, "Anonymous" when none was given
+    pub version: i64, // starts at 1 and is bumped by every edit, so edits can check they aren't stale
+    pub created_at: i64, // seconds since the Unix epoch, 0 for models stored before uploads were dated
+    pub updated_at: i64, // last edit or replaced file, as created_at
+    pub transform: Option<ModelTransform>, // placement outside layouts, None until the model is placed
+}
+
+// Ordered schema changes; a database at version N has had the first N steps applied.
+// Append new steps at the end and never edit or reorder ones that have shipped.
+type Migration = (&'static str, fn(&Connection) -> Result<()>);
+
+const MIGRATIONS: &[Migration] = &[
+    ("create models", create_models),
+    ("add access codes", |conn| add_column(conn, "models", "access_code_hash", "TEXT")),
+    ("add model format", |conn| add_column(conn, "models", "format", "TEXT NOT NULL DEFAULT 'gltf'")),
+    ("add texture warning flag", |conn| add_column(conn, "models", "texture_warnings", "INTEGER NOT NULL DEFAULT 0")),
+    ("create thumbnails", create_thumbnails),
+    ("create layouts", create_layouts),
+    ("create model_lods", create_model_lods),
+    ("create comments", create_comments),
+    ("add model metadata", |conn| add_column(conn, "models", "metadata", "TEXT NOT NULL DEFAULT '{}'")),
+    ("add transform lock", |conn| add_column(conn, "models", "transform_locked", "INTEGER NOT NULL DEFAULT 0")),
+    ("add model uploader", |conn| add_column(conn, "models", "created_by", "TEXT NOT NULL DEFAULT 'Anonymous'")),
+    ("create model_originals", create_model_originals),
+    ("add layout template flag", |conn| add_column(conn, "layouts", "is_template", "INTEGER NOT NULL DEFAULT 0")),
+    ("add model source path", |conn| add_column(conn, "models", "source_path", "TEXT")),
+    ("add model version", |conn| add_column(conn, "models", "version", "INTEGER NOT NULL DEFAULT 1")),
+    ("add model timestamps", |conn| {
+        add_column(conn, "models", "created_at", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(conn, "models", "updated_at", "INTEGER NOT NULL DEFAULT 0")
+    }),
+    ("create model_versions", create_model_versions),
+    ("add model placement", |conn| {
+        for column in ["pos_x", "pos_y", "pos_z"] {
+            add_column(conn, "models", column, "REAL")?;
+        }
+        add_column(conn, "models", "rot", "TEXT")?;
+        add_column(conn, "models", "scale", "TEXT")
+    }),
+    ("add placement to model_versions", |conn| {
+        for column in ["pos_x", "pos_y", "pos_z"] {
+            add_column(conn, "model_versions", column, "REAL")?;
+        }
+        add_column(conn, "model_versions", "rot", "TEXT")?;
+        add_column(conn, "model_versions", "scale", "TEXT")
+    }),
+];
+
+/// Points every later call at `path` instead of the one `db_path` would pick. Only a call
+/// made before the database is first used has an effect.
+pub fn set_db_path(path: impl Into<PathBuf>) {
+    let _ = DB_PATH.set(path.into());
+}
+
+/// The database file: the path given to `set_db_path`, else `DATABASE_PATH`, else
+/// `models.db` in the working directory.
+pub fn db_path() -> &'static Path {
+    DB_PATH.get_or_init(|| match std::env::var_os("DATABASE_PATH") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(DEFAULT_DB_PATH),
+    })
+}
+
+/// Opens the database and applies any pending schema migrations. Binaries call this once at
+/// startup, so a database that can't be brought up to date stops them before any work is done.
+pub fn init_db() -> Result<PooledConnection> {
+    let conn = open_db()?;
+    migrate(&conn)?;
+    Ok(conn)
+}
+
+/// A connection to the database as is, idle from an earlier query when there is one. Every
+/// query below goes through this and relies on `init_db` having run first.
+pub fn open_db() -> Result<PooledConnection> {
+    let idle = IDLE_CONNECTIONS.lock().unwrap().pop();
+    let conn = match idle {
+        Some(conn) => conn,
+        None => Connection::open(db_path())?,
+    };
+    Ok(PooledConnection(Some(conn)))
+}
+
+/// Returns its connection to the pool when dropped. An unfinished transaction has already
+/// been rolled back by then, so the next user starts clean.
+pub struct PooledConnection(Option<Connection>);
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let mut idle = IDLE_CONNECTIONS.lock().unwrap();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.extend(self.0.take());
+        }
+    }
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            version INTEGER NOT NULL
+        )",
+        params![],
+    )?;
+    if schema_version(conn)? >= MIGRATIONS.len() {
+        return Ok(());
+    }
+    // Take the write lock before re-reading so concurrent connections don't both migrate
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let current = schema_version(&tx)?;
+    for (version, (name, step)) in MIGRATIONS.iter().enumerate().skip(current) {
+        step(&tx)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_version (id, version) VALUES (1, ?1)",
+            params![version as i64 + 1],
+        )?;
+        tracing::info!(version = version + 1, name, "applied schema migration");
+    }
+    tx.commit()
+}
+
+pub fn schema_version(conn: &Connection) -> Result<usize> {
+    let version: Option<i64> = conn
+        .query_row("SELECT version FROM schema_version WHERE id = 1", params![], |row| row.get(0))
+        .optional()?;
+    Ok(version.unwrap_or(0) as usize)
+}
+
+// Databases from before versioning may already have some columns, so check instead of failing
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))?
+        .exists(params![column])?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), params![])?;
+    }
+    Ok(())
+}
+
+fn create_models(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS models (
+            id INTEGER PRIMARY KEY,
+            Name TEXT,
+            model_data BLOB NOT NULL
+        )",
+        params![],
+    )?;
+    // The earliest databases were created without a name column
+    add_column(conn, "models", "Name", "TEXT")
+}
+
+// Cached thumbnails, keyed to the content hash of the model bytes they were rendered from
+fn create_thumbnails(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS thumbnails (
+            model_id INTEGER PRIMARY KEY,
+            content_hash TEXT NOT NULL,
+            image BLOB NOT NULL,
+            invalidated INTEGER NOT NULL DEFAULT 0
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// Saved multi-model arrangements, entries stored as a JSON array of LayoutEntry
+fn create_layouts(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS layouts (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            entries TEXT NOT NULL
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// Reduced-detail variants generated at insert, see lod.rs
+fn create_model_lods(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS model_lods (
+            model_id INTEGER NOT NULL,
+            level TEXT NOT NULL,
+            data BLOB NOT NULL,
+            PRIMARY KEY (model_id, level)
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// Uploads as they were before oversized textures were scaled down, see textures.rs
+fn create_model_originals(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS model_originals (
+            model_id INTEGER PRIMARY KEY,
+            data BLOB NOT NULL
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// Snapshots of each model as edits left it, for history and revert. model_data is kept only
+// on the last snapshot before the bytes were replaced; NULL means the same bytes as the next
+// snapshot that has them, or the model's current bytes when none does.
+fn create_model_versions(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS model_versions (
+            model_id INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            change TEXT NOT NULL,
+            name TEXT,
+            format TEXT NOT NULL,
+            texture_warnings INTEGER NOT NULL,
+            metadata TEXT NOT NULL,
+            transform_locked INTEGER NOT NULL,
+            changed_at INTEGER NOT NULL,
+            model_data BLOB,
+            PRIMARY KEY (model_id, version)
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// Discussion threads attached to models
+fn create_comments(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS comments (
+            id INTEGER PRIMARY KEY,
+            model_id INTEGER NOT NULL,
+            author TEXT NOT NULL,
+            text TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+// pos_x is NULL for a model that was never placed; rot and scale hold JSON arrays
+fn row_transform(row: &Row, first: usize) -> Result<Option<ModelTransform>> {
+    let Some(x) = row.get(first)? else {
+        return Ok(None);
+    };
+    Ok(Some(ModelTransform {
+        translation: [x, row.get(first + 1)?, row.get(first + 2)?],
+        rotation: json_column(row, first + 3)?,
+        scale: json_column(row, first + 4)?,
+    }))
+}
+
+// The pos_x, pos_y, pos_z, rot and scale columns for a placement, all NULL for none
+type TransformColumns = (Option<f32>, Option<f32>, Option<f32>, Option<String>, Option<String>);
+
+fn transform_columns(transform: Option<&ModelTransform>) -> TransformColumns {
+    let Some(transform) = transform else {
+        return (None, None, None, None, None);
+    };
+    let [x, y, z] = transform.translation;
+    let to_json = |values: &[f32]| serde_json::to_string(values).unwrap();
+    (Some(x), Some(y), Some(z), Some(to_json(&transform.rotation)), Some(to_json(&transform.scale)))
+}
+
+fn json_column<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> Result<T> {
+    serde_json::from_str(&row.get::<_, String>(index)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+pub fn load_model_by_id(model_id: i32) -> Result<ModelData> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, Name, model_data, access_code_hash, format, texture_warnings, (SELECT GROUP_CONCAT(level) FROM model_lods WHERE model_id = models.id), transform_locked, created_by, version, created_at, updated_at, pos_x, pos_y, pos_z, rot, scale FROM models WHERE id = ?1")?;
+    let model_data = stmt.query_row(params![model_id], |row| {
+        Ok(ModelData {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            model_data: row.get(2)?,
+            access_code_hash: row.get(3)?,
+            format: row.get(4)?,
+            texture_warnings: row.get(5)?,
+            lods: split_levels(row.get(6)?),
+            transform_locked: row.get(7)?,
+            created_by: row.get(8)?,
+            version: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+            transform: row_transform(row, 12)?,
+        })
+    })?;
+    Ok(model_data)
+}
+
+/// Fails when the catalog can't be read, for readiness checks.
+pub fn check_db() -> Result<()> {
+    let conn = open_db()?;
+    conn.prepare("SELECT 1 FROM models LIMIT 1")?.exists(params![])?;
+    Ok(())
+}
+
+pub fn model_exists(model_id: i32) -> Result<bool> {
+    let conn = open_db()?;
+    conn.prepare("SELECT 1 FROM models WHERE id = ?1")?.exists(params![model_id])
+}
+
+pub fn load_all_models() -> Result<Vec<ModelData>> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, Name, model_data, access_code_hash, format, texture_warnings, (SELECT GROUP_CONCAT(level) FROM model_lods WHERE model_id = models.id), transform_locked, created_by, version, created_at, updated_at, pos_x, pos_y, pos_z, rot, scale FROM models")?;
+    let model_iter = stmt.query_map(params![], |row| {
+        Ok(ModelData {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            model_data: row.get(2)?,
+            access_code_hash: row.get(3)?,
+            format: row.get(4)?,
+            texture_warnings: row.get(5)?,
+            lods: split_levels(row.get(6)?),
+            transform_locked: row.get(7)?,
+            created_by: row.get(8)?,
+            version: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+            transform: row_transform(row, 12)?,
+        })
+    })?;
+    let mut models = Vec::new();
+    for model in model_iter {
+        models.push(model?);
+    }
+    Ok(models)
+}
+
+/// Like `load_all_models`, but leaves `model_data` empty without reading it.
+pub fn load_model_list() -> Result<Vec<ModelData>> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, Name, access_code_hash, format, texture_warnings, (SELECT GROUP_CONCAT(level) FROM model_lods WHERE model_id = models.id), transform_locked, created_by, version, created_at, updated_at, pos_x, pos_y, pos_z, rot, scale FROM models")?;
+    let model_iter = stmt.query_map(params![], |row| {
+        Ok(ModelData {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            model_data: Vec::new(),
+            access_code_hash: row.get(2)?,
+            format: row.get(3)?,
+            texture_warnings: row.get(4)?,
+            lods: split_levels(row.get(5)?),
+            transform_locked: row.get(6)?,
+            created_by: row.get(7)?,
+            version: row.get(8)?,
+            created_at: row.get(9)?,
+            updated_at: row.get(10)?,
+            transform: row_transform(row, 11)?,
+        })
+    })?;
+    model_iter.collect()
+}
+
+pub fn load_models_by_ids(ids: &[i32]) -> Result<Vec<ModelData>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let conn = open_db()?;
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, Name, model_data, access_code_hash, format, texture_warnings, (SELECT GROUP_CONCAT(level) FROM model_lods WHERE model_id = models.id), transform_locked, created_by, version, created_at, updated_at, pos_x, pos_y, pos_z, rot, scale FROM models WHERE id IN ({}) ORDER BY id",
+        placeholders
+    ))?;
+    let model_iter = stmt.query_map(rusqlite::params_from_iter(ids), |row| {
+        Ok(ModelData {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            model_data: row.get(2)?,
+            access_code_hash: row.get(3)?,
+            format: row.get(4)?,
+            texture_warnings: row.get(5)?,
+            lods: split_levels(row.get(6)?),
+            transform_locked: row.get(7)?,
+            created_by: row.get(8)?,
+            version: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+            transform: row_transform(row, 12)?,
+        })
+    })?;
+    let mut models = Vec::new();
+    for model in model_iter {
+        models.push(model?);
+    }
+    Ok(models)
+}
+
+pub fn insert_model(
+    model_data: &[u8],
+    name: Option<&str>,
+    format: &str,
+    texture_warnings: bool,
+    created_by: &str,
+    created_at: i64,
+    transform: Option<&ModelTransform>,
+) -> Result<i32> {
+    let conn = open_db()?;
+    let (x, y, z, rot, scale) = transform_columns(transform);
+    conn.execute(
+        "INSERT INTO models (Name, model_data, format, texture_warnings, created_by, created_at, updated_at, pos_x, pos_y, pos_z, rot, scale)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![name, model_data, format, texture_warnings, created_by, created_at, x, y, z, rot, scale],
+    )?;
+    let model_id = conn.last_insert_rowid() as i32;
+    record_version(&conn, model_id, "uploaded")?;
+    Ok(model_id)
+}
+
+pub fn insert_model_lods(model_id: i32, lods: &[(&str, Vec<u8>)]) -> Result<()> {
+    let conn = open_db()?;
+    for (level, data) in lods {
+        conn.execute(
+            "INSERT OR REPLACE INTO model_lods (model_id, level, data) VALUES (?1, ?2, ?3)",
+            params![model_id, level, data],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn load_model_lod(model_id: i32, level: &str) -> Result<Vec<u8>> {
+    let conn = open_db()?;
+    conn.query_row(
+        "SELECT data FROM model_lods WHERE model_id = ?1 AND level = ?2",
+        params![model_id, level],
+        |row| row.get(0),
+    )
+}
+
+pub fn insert_model_original(model_id: i32, data: &[u8]) -> Result<()> {
+    let conn = open_db()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO model_originals (model_id, data) VALUES (?1, ?2)",
+        params![model_id, data],
+    )?;
+    Ok(())
+}
+
+/// The upload as received, when the stored model had its textures scaled down.
+pub fn load_model_original(model_id: i32) -> Result<Option<Vec<u8>>> {
+    let conn = open_db()?;
+    conn.query_row(
+        "SELECT data FROM model_originals WHERE model_id = ?1",
+        params![model_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// GROUP_CONCAT gives the stored levels in no particular order
+fn split_levels(levels: Option<String>) -> Vec<String> {
+    let stored: Vec<&str> = levels.as_deref().map(|l| l.split(',').collect()).unwrap_or_default();
+    LOD_LEVELS
+        .iter()
+        .filter(|level| stored.contains(level))
+        .map(|level| level.to_string())
+        .collect()
+}
+
+// The edits below only apply while the model is still at the version the client saw, and
+// bump it when they do. They return false, writing nothing, when someone else got there first.
+
+// Listed in the history, but snapshots leave the code out so a revert never hands back an old one
+pub fn update_access_code(model_id: i32, version: i64, access_code_hash: Option<&str>) -> Result<bool> {
+    let change = if access_code_hash.is_some() { "access code set" } else { "access code cleared" };
+    versioned_edit(model_id, change, |conn| {
+        conn.execute(
+            "UPDATE models SET access_code_hash = ?1, version = version + 1, updated_at = ?4 WHERE id = ?2 AND version = ?3",
+            params![access_code_hash, model_id, version, unix_time()],
+        )
+    })
+}
+
+/// `None` clears the name, leaving the model listed by id.
+pub fn update_model_name(model_id: i32, version: i64, name: Option<&str>) -> Result<bool> {
+    let change = match name {
+        Some(name) => format!("renamed to {}", name),
+        None => "name cleared".to_string(),
+    };
+    versioned_edit(model_id, &change, |conn| {
+        conn.execute(
+            "UPDATE models SET Name = ?1, version = version + 1, updated_at = ?4 WHERE id = ?2 AND version = ?3",
+            params![name, model_id, version, unix_time()],
+        )
+    })
+}
+
+pub fn update_transform_lock(model_id: i32, version: i64, locked: bool) -> Result<bool> {
+    let change = if locked { "placement locked" } else { "placement unlocked" };
+    versioned_edit(model_id, change, |conn| {
+        conn.execute(
+            "UPDATE models SET transform_locked = ?1, version = version + 1, updated_at = ?4 WHERE id = ?2 AND version = ?3",
+            params![locked, model_id, version, unix_time()],
+        )
+    })
+}
+
+/// `None` clears the placement. Models with a locked transform are left alone, which the
+/// caller sees as a stale version since the lock is versioned too.
+pub fn update_model_transform(model_id: i32, version: i64, transform: Option<&ModelTransform>) -> Result<bool> {
+    let change = if transform.is_some() { "moved" } else { "placement cleared" };
+    let (x, y, z, rot, scale) = transform_columns(transform);
+    versioned_edit(model_id, change, |conn| {
+        conn.execute(
+            "UPDATE models SET pos_x = ?1, pos_y = ?2, pos_z = ?3, rot = ?4, scale = ?5, version = version + 1, updated_at = ?8
+                WHERE id = ?6 AND version = ?7 AND transform_locked = 0",
+            params![x, y, z, rot, scale, model_id, version, unix_time()],
+        )
+    })
+}
+
+pub fn model_version(model_id: i32) -> Result<i64> {
+    let conn = open_db()?;
+    conn.query_row("SELECT version FROM models WHERE id = ?1", params![model_id], |row| row.get(0))
+}
+
+// Nothing updated means a newer version if the model is still there
+fn versioned_update(conn: &Connection, model_id: i32, updated: usize) -> Result<bool> {
+    if updated > 0 {
+        return Ok(true);
+    }
+    if conn.prepare("SELECT 1 FROM models WHERE id = ?1")?.exists(params![model_id])? {
+        Ok(false)
+    } else {
+        Err(rusqlite::Error::QueryReturnedNoRows)
+    }
+}
+
+// Runs a versioned update and snapshots the result under `change`. Models stored before
+// history was kept get their state beforehand recorded first, so the edit can be undone.
+fn versioned_edit(model_id: i32, change: &str, edit: impl FnOnce(&Connection) -> Result<usize>) -> Result<bool> {
+    let mut conn = open_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    record_version(&tx, model_id, "earliest recorded")?;
+    let updated = edit(&tx)?;
+    if !versioned_update(&tx, model_id, updated)? {
+        return Ok(false);
+    }
+    record_version(&tx, model_id, change)?;
+    tx.commit()?;
+    Ok(true)
+}
+
+// Snapshots the model at its current version, unless that version already has one
+fn record_version(conn: &Connection, model_id: i32, change: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO model_versions (model_id, version, change, name, format, texture_warnings, metadata, transform_locked, changed_at, pos_x, pos_y, pos_z, rot, scale)
+            SELECT id, version, ?2, Name, format, texture_warnings, metadata, transform_locked, updated_at, pos_x, pos_y, pos_z, rot, scale FROM models WHERE id = ?1",
+        params![model_id, change],
+    )?;
+    Ok(())
+}
+
+// Called before the model's bytes are replaced: the latest snapshot, which shared them with
+// the model, now has to keep its own copy
+fn keep_model_data(conn: &Connection, model_id: i32) -> Result<()> {
+    conn.execute(
+        "UPDATE model_versions SET model_data = (SELECT model_data FROM models WHERE id = ?1)
+            WHERE model_id = ?1 AND model_data IS NULL
+            AND version = (SELECT MAX(version) FROM model_versions WHERE model_id = ?1)",
+        params![model_id],
+    )?;
+    Ok(())
+}
+
+/// Every recorded version of the model, oldest first.
+pub fn load_model_history(model_id: i32) -> Result<Vec<ModelVersion>> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare(
+        "SELECT version, change, name, changed_at FROM model_versions WHERE model_id = ?1 ORDER BY version",
+    )?;
+    let version_iter = stmt.query_map(params![model_id], |row| {
+        Ok(ModelVersion {
+            version: row.get(0)?,
+            change: row.get(1)?,
+            name: row.get(2)?,
+            changed_at: row.get(3)?,
+        })
+    })?;
+    let mut history = Vec::new();
+    for version in version_iter {
+        history.push(version?);
+    }
+    Ok(history)
+}
+
+/// A recorded version as revert restores it.
+#[derive(Debug)]
+pub struct VersionSnapshot {
+    pub name: Option<String>,
+    pub format: String,
+    pub texture_warnings: bool,
+    pub metadata: String, // JSON object, as stored in models
+    pub transform_locked: bool,
+    pub model_data: Vec<u8>,
+    pub transform: Option<ModelTransform>,
+}
+
+/// The model as it was at `version`, `None` when that version wasn't recorded.
+pub fn load_version_snapshot(model_id: i32, version: i64) -> Result<Option<VersionSnapshot>> {
+    let conn = open_db()?;
+    conn.query_row(
+        "SELECT Name, format, texture_warnings, metadata, transform_locked, COALESCE(
+                (SELECT model_data FROM model_versions WHERE model_id = ?1 AND version >= ?2 AND model_data IS NOT NULL ORDER BY version LIMIT 1),
+                (SELECT model_data FROM models WHERE id = ?1)), pos_x, pos_y, pos_z, rot, scale
+            FROM model_versions WHERE model_id = ?1 AND version = ?2",
+        params![model_id, version],
+        |row| {
+            Ok(VersionSnapshot {
+                name: row.get(0)?,
+                format: row.get(1)?,
+                texture_warnings: row.get(2)?,
+                metadata: row.get(3)?,
+                transform_locked: row.get(4)?,
+                model_data: row.get(5)?,
+                transform: row_transform(row, 6)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Puts the model back as it was at `restored`, recorded as a new version. `lods` are the
+/// detail levels for the snapshot's bytes when they differ from the current ones, which then
+/// replace them as in `replace_model_data`; `None` leaves the bytes alone.
+pub fn restore_model_version(
+    model_id: i32,
+    version: i64,
+    restored: i64,
+    snapshot: &VersionSnapshot,
+    lods: Option<&[(&str, Vec<u8>)]>,
+) -> Result<bool> {
+    versioned_edit(model_id, &format!("reverted to version {}", restored), |conn| {
+        // A stale edit is rolled back as a whole, so this needn't wait for the version check
+        if lods.is_some() {
+            keep_model_data(conn, model_id)?;
+        }
+        let (x, y, z, rot, scale) = transform_columns(snapshot.transform.as_ref());
+        let updated = conn.execute(
+            "UPDATE models SET Name = ?1, metadata = ?2, transform_locked = ?3, version = version + 1, updated_at = ?6,
+                pos_x = ?7, pos_y = ?8, pos_z = ?9, rot = ?10, scale = ?11
+                WHERE id = ?4 AND version = ?5",
+            params![snapshot.name, snapshot.metadata, snapshot.transform_locked, model_id, version, unix_time(), x, y, z, rot, scale],
+        )?;
+        if let (1, Some(lods)) = (updated, lods) {
+            conn.execute(
+                "UPDATE models SET model_data = ?1, format = ?2, texture_warnings = ?3 WHERE id = ?4",
+                params![snapshot.model_data, snapshot.format, snapshot.texture_warnings, model_id],
+            )?;
+            replace_lods(conn, model_id, lods)?;
+        }
+        Ok(updated)
+    })
+}
+
+/// File the model was seeded or is developed from, which the admin `reload` re-reads.
+pub fn load_source_path(model_id: i32) -> Result<Option<String>> {
+    let conn = open_db()?;
+    conn.query_row("SELECT source_path FROM models WHERE id = ?1", params![model_id], |row| row.get(0))
+}
+
+pub fn update_source_path(model_id: i32, source_path: Option<&str>) -> Result<()> {
+    let conn = open_db()?;
+    let updated = conn.execute("UPDATE models SET source_path = ?1 WHERE id = ?2", params![source_path, model_id])?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+/// Swaps in new bytes for an existing model, with their detail levels, keeping the old ones
+/// in the history. The original upload no longer matches and is dropped, and the thumbnail is
+/// kept only as stale.
+pub fn replace_model_data(
+    model_id: i32,
+    model_data: &[u8],
+    format: &str,
+    texture_warnings: bool,
+    lods: &[(&str, Vec<u8>)],
+) -> Result<()> {
+    let mut conn = open_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    record_version(&tx, model_id, "earliest recorded")?;
+    keep_model_data(&tx, model_id)?;
+    let updated = tx.execute(
+        "UPDATE models SET model_data = ?1, format = ?2, texture_warnings = ?3, version = version + 1, updated_at = ?5 WHERE id = ?4",
+        params![model_data, format, texture_warnings, model_id, unix_time()],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    replace_lods(&tx, model_id, lods)?;
+    record_version(&tx, model_id, "file replaced")?;
+    tx.commit()
+}
+
+// New bytes: their detail levels replace the old, and the original upload and thumbnail no
+// longer match them
+fn replace_lods(conn: &Connection, model_id: i32, lods: &[(&str, Vec<u8>)]) -> Result<()> {
+    conn.execute("DELETE FROM model_lods WHERE model_id = ?1", params![model_id])?;
+    for (level, data) in lods {
+        conn.execute(
+            "INSERT INTO model_lods (model_id, level, data) VALUES (?1, ?2, ?3)",
+            params![model_id, level, data],
+        )?;
+    }
+    conn.execute("DELETE FROM model_originals WHERE model_id = ?1", params![model_id])?;
+    conn.execute("UPDATE thumbnails SET invalidated = 1 WHERE model_id = ?1", params![model_id])?;
+    Ok(())
+}
+
+pub fn load_metadata(model_id: i32) -> Result<BTreeMap<String, String>> {
+    let conn = open_db()?;
+    let metadata: String = conn.query_row("SELECT metadata FROM models WHERE id = ?1", params![model_id], |row| row.get(0))?;
+    serde_json::from_str(&metadata)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+pub fn update_metadata(model_id: i32, version: i64, metadata: &BTreeMap<String, String>) -> Result<bool> {
+    let metadata_json = serde_json::to_string(metadata)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    versioned_edit(model_id, "metadata edited", |conn| {
+        conn.execute(
+            "UPDATE models SET metadata = ?1, version = version + 1, updated_at = ?4 WHERE id = ?2 AND version = ?3",
+            params![metadata_json, model_id, version, unix_time()],
+        )
+    })
+}
+
+/// Seconds since the Unix epoch, as timestamps are stored.
+pub fn unix_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+pub fn content_hash(model_data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(model_data))
+}
+
+pub fn store_thumbnail(model: &ModelData, image: &[u8]) -> Result<()> {
+    let conn = open_db()?;
+    let stored = conn.execute(
+        "INSERT OR REPLACE INTO thumbnails (model_id, content_hash, image)
+            SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM models WHERE id = ?1)",
+        params![model.id, content_hash(&model.model_data), image],
+    )?;
+    if stored == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+pub fn load_thumbnail(model: &ModelData) -> Result<ThumbnailResponse> {
+    let conn = open_db()?;
+    let current_hash = content_hash(&model.model_data);
+    let cached = conn.query_row(
+        "SELECT content_hash, image, invalidated FROM thumbnails WHERE model_id = ?1",
+        params![model.id],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, bool>(2)?)),
+    );
+    match cached {
+        Ok((hash, image, invalidated)) => Ok(ThumbnailResponse {
+            id: model.id,
+            stale: invalidated || hash != current_hash,
+            version: hash,
+            image_data: general_purpose::STANDARD.encode(&image),
+        }),
+        // Nothing cached yet: an empty placeholder that is stale by definition
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(ThumbnailResponse {
+            id: model.id,
+            version: current_hash,
+            stale: true,
+            image_data: String::new(),
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+// Keeps the old image around (served as stale) until a client uploads a new one. A model
+// without a thumbnail yet has nothing to invalidate, but a deleted one is an error.
+pub fn invalidate_thumbnail(model_id: i32) -> Result<()> {
+    let conn = open_db()?;
+    if !conn.prepare("SELECT 1 FROM models WHERE id = ?1")?.exists(params![model_id])? {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    conn.execute("UPDATE thumbnails SET invalidated = 1 WHERE model_id = ?1", params![model_id])?;
+    Ok(())
+}
+
+/// Deletes the model with everything stored for it and takes it out of saved layouts and
+/// templates, all or nothing. Returns the layouts it was taken out of.
+pub fn delete_model(model_id: i32) -> Result<Vec<i32>> {
+    let mut conn = open_db()?;
+    let tx = conn.transaction()?;
+    let rows_affected = tx.execute("DELETE FROM models WHERE id = ?1", params![model_id])?;
+    if rows_affected == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    tx.execute("DELETE FROM thumbnails WHERE model_id = ?1", params![model_id])?;
+    tx.execute("DELETE FROM comments WHERE model_id = ?1", params![model_id])?;
+    tx.execute("DELETE FROM model_lods WHERE model_id = ?1", params![model_id])?;
+    tx.execute("DELETE FROM model_originals WHERE model_id = ?1", params![model_id])?;
+    tx.execute("DELETE FROM model_versions WHERE model_id = ?1", params![model_id])?;
+    let layouts = remove_from_layouts(&tx, model_id)?;
+    tx.commit()?;
+    Ok(layouts)
+}
+
+// Drops the model's entries from every layout that places it, returning those layouts
+fn remove_from_layouts(conn: &Connection, model_id: i32) -> Result<Vec<i32>> {
+    let mut changed = Vec::new();
+    let mut stmt = conn.prepare("SELECT id, entries FROM layouts")?;
+    let layouts = stmt.query_map(params![], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)))?;
+    for layout in layouts {
+        let (layout_id, entries_json) = layout?;
+        let mut entries: Vec<LayoutEntry> = serde_json::from_str(&entries_json)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e)))?;
+        let before = entries.len();
+        entries.retain(|entry| entry.model_id != model_id);
+        if entries.len() != before {
+            let entries_json = serde_json::to_string(&entries)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            conn.execute("UPDATE layouts SET entries = ?1 WHERE id = ?2", params![entries_json, layout_id])?;
+            changed.push(layout_id);
+        }
+    }
+    Ok(changed)
+}
+
+pub fn insert_comment(model_id: i32, author: &str, text: &str) -> Result<CommentResponse> {
+    let conn = open_db()?;
+    let created_at = unix_time();
+    // Checked in the insert itself, so a model deleted meanwhile never gets an orphaned comment
+    let inserted = conn.execute(
+        "INSERT INTO comments (model_id, author, text, created_at)
+            SELECT ?1, ?2, ?3, ?4 WHERE EXISTS (SELECT 1 FROM models WHERE id = ?1)",
+        params![model_id, author, text, created_at],
+    )?;
+    if inserted == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(CommentResponse {
+        id: conn.last_insert_rowid() as i32,
+        model_id,
+        author: author.to_string(),
+        text: text.to_string(),
+        created_at,
+    })
+}
+
+pub fn load_comments(model_id: i32) -> Result<Vec<CommentResponse>> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, model_id, author, text, created_at FROM comments WHERE model_id = ?1 ORDER BY id")?;
+    let comment_iter = stmt.query_map(params![model_id], |row| {
+        Ok(CommentResponse {
+            id: row.get(0)?,
+            model_id: row.get(1)?,
+            author: row.get(2)?,
+            text: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    })?;
+    let mut comments = Vec::new();
+    for comment in comment_iter {
+        comments.push(comment?);
+    }
+    Ok(comments)
+}
+
+// Templates are kept apart from working layouts and only ever copied, see load_template_summaries
+pub fn insert_layout(name: &str, entries: &[LayoutEntry], template: bool) -> Result<i32> {
+    let conn = open_db()?;
+    let entries_json = serde_json::to_string(entries)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    conn.execute(
+        "INSERT INTO layouts (name, entries, is_template) VALUES (?1, ?2, ?3)",
+        params![name, entries_json, template],
+    )?;
+    Ok(conn.last_insert_rowid() as i32)
+}
+
+// Appends the model at the origin unless the layout already places it
+pub fn add_model_to_layout(layout_id: i32, model_id: i32) -> Result<()> {
+    let mut layout = load_layout_by_id(layout_id)?;
+    if layout.entries.iter().any(|entry| entry.model_id == model_id) {
+        return Ok(());
+    }
+    layout.entries.push(LayoutEntry {
+        model_id,
+        translation: [0.0, 0.0, 0.0],
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        scale: [1.0, 1.0, 1.0],
+    });
+    update_layout_entries(layout_id, &layout.entries)
+}
+
+pub fn update_layout_entries(layout_id: i32, entries: &[LayoutEntry]) -> Result<()> {
+    let entries_json = serde_json::to_string(entries)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let conn = open_db()?;
+    conn.execute("UPDATE layouts SET entries = ?1 WHERE id = ?2", params![entries_json, layout_id])?;
+    Ok(())
+}
+
+// Several layouts in one transaction, for LayoutWrites::flush
+pub fn update_layouts_entries(updates: &[(i32, Vec<LayoutEntry>)]) -> Result<()> {
+    let mut conn = open_db()?;
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare("UPDATE layouts SET entries = ?1 WHERE id = ?2")?;
+        for (layout_id, entries) in updates {
+            let entries_json = serde_json::to_string(entries)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            stmt.execute(params![entries_json, layout_id])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Working layouts, without templates.
+pub fn load_layout_summaries() -> Result<Vec<LayoutSummary>> {
+    load_summaries(false)
+}
+
+/// Layouts shared as starting points; instantiating one copies it into a new working layout.
+pub fn load_template_summaries() -> Result<Vec<LayoutSummary>> {
+    load_summaries(true)
+}
+
+fn load_summaries(templates: bool) -> Result<Vec<LayoutSummary>> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, name FROM layouts WHERE is_template = ?1 ORDER BY id")?;
+    let layout_iter = stmt.query_map(params![templates], |row| {
+        Ok(LayoutSummary {
+            id: row.get(0)?,
+            name: row.get(1)?,
+        })
+    })?;
+    let mut layouts = Vec::new();
+    for layout in layout_iter {
+        layouts.push(layout?);
+    }
+    Ok(layouts)
+}
+
+pub fn load_layout_by_id(layout_id: i32) -> Result<LayoutResponse> {
+    let conn = open_db()?;
+    let mut stmt = conn.prepare("SELECT id, name, entries FROM layouts WHERE id = ?1")?;
+    stmt.query_row(params![layout_id], |row| {
+        let entries_json: String = row.get(2)?;
+        let entries = serde_json::from_str(&entries_json)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e)))?;
+        Ok(LayoutResponse {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            entries,
+        })
+    })
+}
+
+// Bytes counted against the storage quota: model data plus its reduced-detail copies, retained
+// originals and the earlier files kept in the history
+pub fn storage_used() -> Result<u64> {
+    let conn = open_db()?;
+    let used: i64 = conn.query_row(
+        "SELECT (SELECT COALESCE(SUM(LENGTH(model_data)), 0) FROM models)
+            + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM model_lods)
+            + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM model_originals)
+            + (SELECT COALESCE(SUM(LENGTH(model_data)), 0) FROM model_versions)",
+        params![],
+        |row| row.get(0),
+    )?;
+    Ok(used as u64)
+}
+
+#[derive(Debug)]
+pub struct CatalogStats {
+    pub models: i64,
+    pub model_bytes: i64,
+    pub formats: Vec<(String, i64)>, // model count per format
+    pub protected: i64,
+    pub texture_warnings: i64,
+    pub thumbnails: i64,
+    pub layouts: i64,
+    pub comments: i64,
+}
+
+pub fn catalog_stats() -> Result<CatalogStats> {
+    let conn = open_db()?;
+    let count = |sql: &str| conn.query_row(sql, params![], |row| row.get::<_, i64>(0));
+    let mut stmt = conn.prepare("SELECT format, COUNT(*) FROM models GROUP BY format ORDER BY format")?;
+    let formats = stmt
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(CatalogStats {
+        models: count("SELECT COUNT(*) FROM models")?,
+        model_bytes: count("SELECT COALESCE(SUM(LENGTH(model_data)), 0) FROM models")?,
+        formats,
+        protected: count("SELECT COUNT(*) FROM models WHERE access_code_hash IS NOT NULL")?,
+        texture_warnings: count("SELECT COUNT(*) FROM models WHERE texture_warnings != 0")?,
+        thumbnails: count("SELECT COUNT(*) FROM thumbnails")?,
+        layouts: count("SELECT COUNT(*) FROM layouts")?,
+        comments: count("SELECT COUNT(*) FROM comments")?,
+    })
+}
+
+#[derive(Debug)]
+pub struct OptimizeReport {
+    pub orphans_removed: usize, // thumbnails, comments, detail levels, originals and history of models that no longer exist
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Purges rows left behind by deleted models, rebuilds the file with `VACUUM` to return
+/// free pages to the filesystem and refreshes the query planner's statistics.
+///
+/// `VACUUM` needs the database to itself, so this waits for in-flight writes to finish and
+/// server requests block until it is done; run it when the catalog is quiet.
+pub fn optimize_db() -> Result<OptimizeReport> {
+    // Its own connection, so the long timeout doesn't stay with a pooled one
+    let conn = Connection::open(db_path())?;
+    conn.busy_timeout(std::time::Duration::from_secs(60))?;
+    let size = |conn: &Connection| {
+        conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            params![],
+            |row| row.get::<_, i64>(0),
+        )
+    };
+    let size_before = size(&conn)? as u64;
+    let mut orphans_removed = 0;
+    for table in ["thumbnails", "comments", "model_lods", "model_originals", "model_versions"] {
+        orphans_removed += conn.execute(
+            &format!("DELETE FROM {} WHERE model_id NOT IN (SELECT id FROM models)", table),
+            params![],
+        )?;
+    }
+    conn.execute_batch("VACUUM; ANALYZE;")?;
+    Ok(OptimizeReport {
+        orphans_removed,
+        size_before,
+        size_after: size(&conn)? as u64,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test_support;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::{ insert_test_model, minimal_glb, scratch_db };
+    use crate::formats::{ sniff_format, ModelFormat };
+
+    #[test]
+    fn glb_round_trips_byte_for_byte() {
+        scratch_db();
+        let glb = minimal_glb();
+        let format = sniff_format(&glb);
+        assert_eq!(format, Some(ModelFormat::Glb));
+        let stored_name = format.and_then(ModelFormat::stored_name).unwrap();
+        assert_eq!(stored_name, "gltf");
+
+        let id = insert_model(&glb, Some("cube"), stored_name, false, "tester", unix_time(), None).unwrap();
+        let model = load_model_by_id(id).unwrap();
+        assert_eq!(model.model_data, glb);
+        assert_eq!(model.format, "gltf");
+        assert_eq!(sniff_format(&model.model_data), Some(ModelFormat::Glb));
+    }
+
+    #[test]
+    fn writes_to_a_deleted_model_fail_as_not_found() {
+        let id = insert_test_model();
+        let model = load_model_by_id(id).unwrap();
+        delete_model(id).unwrap();
+        let not_found = |result: Result<()>| matches!(result, Err(rusqlite::Error::QueryReturnedNoRows));
+        assert!(not_found(delete_model(id).map(|_| ())));
+        assert!(not_found(invalidate_thumbnail(id)));
+        assert!(not_found(store_thumbnail(&model, b"png")));
+        assert!(not_found(insert_comment(id, "tester", "hello").map(|_| ())));
+        assert!(not_found(update_model_name(id, 1, Some("gone")).map(|_| ())));
+        assert!(not_found(update_transform_lock(id, 1, true).map(|_| ())));
+        assert!(not_found(update_model_transform(id, 1, None).map(|_| ())));
+    }
+
+    #[test]
+    fn deleting_a_model_takes_it_out_of_layouts() {
+        let (kept, deleted) = (insert_test_model(), insert_test_model());
+        let placed = insert_layout("both", &[], false).unwrap();
+        add_model_to_layout(placed, kept).unwrap();
+        add_model_to_layout(placed, deleted).unwrap();
+        let untouched = insert_layout("other", &[], false).unwrap();
+        add_model_to_layout(untouched, kept).unwrap();
+
+        assert_eq!(delete_model(deleted).unwrap(), vec![placed]);
+        let entries = load_layout_by_id(placed).unwrap().entries;
+        assert_eq!(entries.iter().map(|entry| entry.model_id).collect::<Vec<_>>(), vec![kept]);
+        assert_eq!(load_layout_by_id(untouched).unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn placements_are_versioned_and_reverted() {
+        scratch_db();
+        let placed = ModelTransform { translation: [1.0, 0.0, -2.0], rotation: [0.0, 0.0, 0.0, 1.0], scale: [1.0; 3] };
+        let id = insert_model(b"{}", None, "gltf", false, "tester", unix_time(), Some(&placed)).unwrap();
+        let moved = ModelTransform { translation: [5.0, 0.0, 0.0], ..placed };
+        assert!(update_model_transform(id, 1, Some(&moved)).unwrap());
+        assert!(!update_model_transform(id, 1, None).unwrap());
+
+        let snapshot = load_version_snapshot(id, 1).unwrap().unwrap();
+        assert_eq!(snapshot.transform, Some(placed));
+        assert!(restore_model_version(id, 2, 1, &snapshot, None).unwrap());
+        assert_eq!(load_model_by_id(id).unwrap().transform, Some(placed));
+
+        assert!(update_transform_lock(id, 3, true).unwrap());
+        assert!(!update_model_transform(id, 4, Some(&moved)).unwrap());
+        assert_eq!(load_model_by_id(id).unwrap().transform, Some(placed));
+    }
+}
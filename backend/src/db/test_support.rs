@@ -0,0 +1,41 @@
+//! Fixtures shared by the database tests and the server tests in main.rs, which includes this
+//! file as a module of its own since the binary can't see the library's test-only items.
+
+use backend::db::{ init_db, insert_model, set_db_path, unix_time };
+use std::sync::Once;
+
+/// Points the process at a scratch database. Every test in the process shares it, since the
+/// path is only set once.
+pub fn scratch_db() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let exe = std::env::current_exe().unwrap();
+        let binary = exe.file_stem().unwrap().to_string_lossy();
+        let path = std::env::temp_dir().join(format!("{}-{}.db", binary, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        set_db_path(path);
+        init_db().unwrap();
+    });
+}
+
+/// A model with placeholder contents in the scratch database.
+pub fn insert_test_model() -> i32 {
+    scratch_db();
+    insert_model(b"{}", None, "gltf", false, "tester", unix_time(), None).unwrap()
+}
+
+/// The smallest valid GLB: a header and a JSON chunk padded with spaces to four bytes.
+pub fn minimal_glb() -> Vec<u8> {
+    let mut json = br#"{"asset":{"version":"2.0"}}"#.to_vec();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let mut glb = Vec::new();
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(12 + 8 + json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb
+}
@@ -0,0 +1,147 @@
+//! Identifies uploaded model files from their bytes rather than their names.
+
+use crate::textures::split_glb;
+use serde_json::Value;
+
+/// What an upload turned out to contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFormat {
+    Gltf,
+    Glb,
+    Ply,
+    Obj,
+    Stl,
+}
+
+impl ModelFormat {
+    /// Value stored in the `format` column, which clients use to pick a loader. GLB shares
+    /// "gltf" since every glTF loader reads both. `None` for formats no client can display yet.
+    pub fn stored_name(self) -> Option<&'static str> {
+        match self {
+            ModelFormat::Gltf | ModelFormat::Glb => Some("gltf"),
+            ModelFormat::Ply => Some("ply"),
+            ModelFormat::Obj | ModelFormat::Stl => None,
+        }
+    }
+
+    /// File extension for downloads of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ModelFormat::Gltf => "gltf",
+            ModelFormat::Glb => "glb",
+            ModelFormat::Ply => "ply",
+            ModelFormat::Obj => "obj",
+            ModelFormat::Stl => "stl",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ModelFormat::Gltf => "glTF",
+            ModelFormat::Glb => "GLB",
+            ModelFormat::Ply => "PLY",
+            ModelFormat::Obj => "OBJ",
+            ModelFormat::Stl => "STL",
+        }
+    }
+}
+
+// Lines of an OBJ file that are checked before deciding
+const OBJ_SNIFF_LINES: usize = 64;
+const OBJ_KEYWORDS: [&str; 12] = ["v", "vt", "vn", "vp", "f", "l", "p", "o", "g", "s", "mtllib", "usemtl"];
+
+/// Detects the format of a model file, or `None` when it isn't one this server recognizes.
+pub fn sniff_format(bytes: &[u8]) -> Option<ModelFormat> {
+    if bytes.starts_with(b"glTF") {
+        return Some(ModelFormat::Glb);
+    }
+    if bytes.starts_with(b"ply\n") || bytes.starts_with(b"ply\r\n") {
+        return Some(ModelFormat::Ply);
+    }
+    // Checked before ASCII STL, as binary headers often start with "solid" too
+    if is_binary_stl(bytes) {
+        return Some(ModelFormat::Stl);
+    }
+    let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(text.len());
+    let text = &text[start..];
+    if text.starts_with(b"{") {
+        // The asset block is the one thing every glTF document must have
+        let document = serde_json::from_slice::<Value>(text).ok()?;
+        return document.get("asset").map(|_| ModelFormat::Gltf);
+    }
+    if text.starts_with(b"solid") && text.windows(8).any(|window| window == b"endsolid") {
+        return Some(ModelFormat::Stl);
+    }
+    is_obj(text).then_some(ModelFormat::Obj)
+}
+
+/// Checks that a glTF or GLB file has what a viewer needs to show something: a glTF 2.0
+/// asset block and nodes, with scenes, children and meshes referring only to what exists.
+/// The message says what is wrong, for the uploader.
+pub fn validate_gltf(bytes: &[u8]) -> Result<(), String> {
+    let json = if bytes.starts_with(b"glTF") {
+        let header = |range: std::ops::Range<usize>| bytes.get(range).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        if header(4..8) != Some(2) {
+            return Err("Only version 2 GLB files are supported".to_string());
+        }
+        if header(8..12) != Some(bytes.len() as u32) {
+            return Err("The GLB file is truncated or its header gives the wrong length".to_string());
+        }
+        split_glb(bytes).ok_or("The GLB file has no readable JSON chunk")?.0
+    } else {
+        bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes)
+    };
+    let document: Value = serde_json::from_slice(json).map_err(|e| format!("The glTF JSON is invalid: {}", e))?;
+    let version = document["asset"]["version"].as_str().ok_or("The glTF asset block has no version")?;
+    if version.split('.').next() != Some("2") {
+        return Err(format!("glTF {} isn't supported; export the model as glTF 2.0", version));
+    }
+    let count = |key: &str| document[key].as_array().map_or(0, Vec::len);
+    let nodes = document["nodes"].as_array().filter(|nodes| !nodes.is_empty()).ok_or("The glTF has no nodes, so there is nothing to show")?;
+    let indices = |value: &Value| -> Vec<u64> { value.as_array().into_iter().flatten().filter_map(Value::as_u64).collect() };
+    for (index, scene) in document["scenes"].as_array().into_iter().flatten().enumerate() {
+        if let Some(node) = indices(&scene["nodes"]).into_iter().find(|node| *node as usize >= nodes.len()) {
+            return Err(format!("Scene {} refers to node {}, which doesn't exist", index, node));
+        }
+    }
+    if let Some(scene) = document["scene"].as_u64() && scene as usize >= count("scenes") {
+        return Err(format!("The default scene {} doesn't exist", scene));
+    }
+    for (index, node) in nodes.iter().enumerate() {
+        if let Some(child) = indices(&node["children"]).into_iter().find(|child| *child as usize >= nodes.len()) {
+            return Err(format!("Node {} refers to child node {}, which doesn't exist", index, child));
+        }
+        if let Some(mesh) = node["mesh"].as_u64() && mesh as usize >= count("meshes") {
+            return Err(format!("Node {} refers to mesh {}, which doesn't exist", index, mesh));
+        }
+    }
+    Ok(())
+}
+
+// An 80-byte header and a triangle count that exactly accounts for the rest of the file
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    let Some(count) = bytes.get(80..84) else {
+        return false;
+    };
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    count > 0 && count.checked_mul(50).and_then(|size| size.checked_add(84)) == Some(bytes.len())
+}
+
+fn is_obj(text: &[u8]) -> bool {
+    let head = &text[..text.len().min(16 * 1024)];
+    // A multi-byte character may be cut at the end of the head
+    let head = match std::str::from_utf8(head) {
+        Ok(head) => head,
+        Err(e) => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or(""),
+    };
+    let mut has_vertex = false;
+    for line in head.lines().map(str::trim).filter(|line| !line.is_empty()).take(OBJ_SNIFF_LINES) {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        if !OBJ_KEYWORDS.contains(&keyword) && !keyword.starts_with('#') {
+            return false;
+        }
+        has_vertex |= keyword == "v";
+    }
+    has_vertex
+}
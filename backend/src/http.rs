@@ -0,0 +1,143 @@
+//! Just enough HTTP/1.1 for the routes served next to the WebSocket endpoint: one request
+//! per connection, answered and then closed.
+
+use std::{ io, time::Duration };
+use tokio::{ io::{ AsyncReadExt, AsyncWriteExt }, net::TcpStream };
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The request line and headers of an incoming HTTP request, read without consuming them.
+#[derive(Debug)]
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>, // names lowercased
+    len: usize, // bytes up to and including the blank line
+}
+
+impl RequestHead {
+    /// A GET for `path` with no headers, as if it had been read off a connection.
+    pub fn get(path: impl Into<String>) -> Self {
+        RequestHead { method: "GET".to_string(), path: path.into(), headers: vec![], len: 0 }
+    }
+
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.header("upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The path without its query string.
+    pub fn route(&self) -> &str {
+        self.path.split_once('?').map_or(&self.path, |(route, _)| route)
+    }
+
+    /// Value of `name` in the query string, undecoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.path.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Waits until the whole request head has arrived and parses it. Nothing is consumed,
+/// so a WebSocket handshake can still read the request afterwards.
+pub async fn peek_request_head(stream: &TcpStream) -> io::Result<RequestHead> {
+    let mut buf = vec![0; MAX_HEAD_BYTES];
+    let deadline = tokio::time::Instant::now() + HEAD_TIMEOUT;
+    loop {
+        let n = tokio::time::timeout_at(deadline, stream.peek(&mut buf))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head timed out"))??;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(end) = buf[..n].windows(4).position(|window| window == b"\r\n\r\n") {
+            return parse_head(&buf[..end], end + 4);
+        }
+        if n == buf.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+        // peek returns at once while data is buffered, so wait for more to arrive
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn parse_head(head: &[u8], len: usize) -> io::Result<RequestHead> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed request head");
+    let head = std::str::from_utf8(head).map_err(|_| invalid())?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().ok_or_else(invalid)?.split(' ');
+    let method = request_line.next().ok_or_else(invalid)?.to_string();
+    let path = request_line.next().ok_or_else(invalid)?.to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Ok(RequestHead { method, path, headers, len })
+}
+
+/// Consumes the head and reads the body, or returns `None` when it is over `max_body_bytes`.
+pub async fn read_body(stream: &mut TcpStream, head: &RequestHead, max_body_bytes: usize) -> io::Result<Option<Vec<u8>>> {
+    stream.read_exact(&mut vec![0; head.len]).await?;
+    let length: usize = head.header("content-length").map_or(Ok(0), str::parse).map_err(|_| io::ErrorKind::InvalidData)?;
+    if length > max_body_bytes {
+        return Ok(None);
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// An answer to one request. Every response allows cross-origin use, as the web client
+/// may be served from elsewhere.
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: &'static str, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Response { status, content_type, headers: vec![], body: body.into() }
+    }
+
+    pub fn empty(status: &'static str) -> Self {
+        Response::new(status, "text/plain", vec![])
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// Sends the response and closes the connection.
+pub async fn write_response(mut stream: TcpStream, response: Response) {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST\r\nAccess-Control-Allow-Headers: Content-Type, If-None-Match\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    let result = async {
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&response.body).await
+    };
+    if let Err(e) = result.await {
+        tracing::warn!(error = %e, "failed to send HTTP response");
+    }
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,47 @@
+//! Coalesces placement edits to saved layouts. Dragging a model can send a set_transform
+//! every frame; each is broadcast at once, but only the latest entries per layout are kept
+//! here and written out together every `FLUSH_INTERVAL`. Edits not yet flushed are lost
+//! if the server dies, at most one interval's worth.
+
+use crate::db::update_layouts_entries;
+use crate::protocol::LayoutEntry;
+use std::{ collections::HashMap, sync::Mutex, time::Duration };
+
+/// How often staged entries are written to the database.
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Default)]
+pub struct LayoutWrites {
+    pending: Mutex<HashMap<i32, Vec<LayoutEntry>>>, // latest entries per layout id
+}
+
+impl LayoutWrites {
+    /// Replaces whatever is staged for the layout.
+    pub fn stage(&self, layout_id: i32, entries: Vec<LayoutEntry>) {
+        self.pending.lock().unwrap().insert(layout_id, entries);
+    }
+
+    /// Entries staged for the layout, newer than the database's.
+    pub fn pending(&self, layout_id: i32) -> Option<Vec<LayoutEntry>> {
+        self.pending.lock().unwrap().get(&layout_id).cloned()
+    }
+
+    /// Writes everything staged in one transaction and returns the number of layouts written.
+    /// Call it before touching a layout any other way, so nothing reads or overwrites stale entries.
+    pub fn flush(&self) -> rusqlite::Result<usize> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        // Held across the write so a concurrent flush can't commit older entries after ours
+        let updates: Vec<(i32, Vec<LayoutEntry>)> = pending.drain().collect();
+        match update_layouts_entries(&updates) {
+            Ok(()) => Ok(updates.len()),
+            Err(e) => {
+                // Keep them for the next attempt
+                pending.extend(updates);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// Lets code shared with the binary, such as db/test_support.rs, name this crate the same way
+extern crate self as backend;
+
+pub mod db;
+pub mod formats;
+pub mod http;
+pub mod layout_writes;
+pub mod lod;
+pub mod obj;
+pub mod permissions;
+pub mod polling;
+pub mod presence;
+pub mod protocol;
+pub mod textures;
+
+/// Sends `tracing` events to stderr, filtered by `RUST_LOG` (e.g. `backend=debug`) and at
+/// info level when it is unset.
+pub fn init_logging() {
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .init();
+}
+
+/// Human-readable byte count for messages and reports.
+pub fn format_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{} bytes", b),
+    }
+}
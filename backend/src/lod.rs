@@ -0,0 +1,516 @@
+//! Reduced-detail variants of glTF models, generated at insert by vertex clustering.
+//!
+//! Each triangle primitive is snapped to a grid over its bounding box and vertices sharing a
+//! cell are merged. Buffers are repacked so a variant only carries the geometry it uses.
+//! Models this can't safely rewrite (skins, animations, morph targets, compressed or
+//! external buffers) only get the full level.
+
+use crate::textures::{ decode_data_uri, split_glb, BufferView };
+use base64::{ Engine as _, engine::general_purpose };
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+
+/// Levels from most to least detailed. "full" is the uploaded model itself.
+pub const LOD_LEVELS: [&str; 3] = ["full", "medium", "low"];
+
+// Grid cells along the longest bounding-box axis of each primitive
+const LEVEL_CELLS: [(&str, f32); 2] = [("medium", 64.0), ("low", 16.0)];
+// A level is only stored when it is at most this fraction of the next finer level's size
+const MIN_SAVING: f64 = 0.9;
+
+const FLOAT: u64 = 5126;
+const UNSIGNED_INT: u64 = 5125;
+const ARRAY_BUFFER: u64 = 34962;
+const ELEMENT_ARRAY_BUFFER: u64 = 34963;
+const UNSUPPORTED_EXTENSIONS: [&str; 3] = [
+    "KHR_draco_mesh_compression",
+    "EXT_meshopt_compression",
+    "EXT_mesh_gpu_instancing",
+];
+
+/// Returns the reduced levels worth storing for a glTF/GLB upload, coarsest last.
+pub fn generate_lods(bytes: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    let Ok(model) = Model::parse(bytes).and_then(|model| model.check_rewritable().map(|_| model)) else {
+        return vec![];
+    };
+    let mut lods = Vec::new();
+    // Compare against the upload re-encoded without decimation too, so savings from
+    // dropped whitespace or repacked buffers alone don't count as a reduced level
+    let mut finer_size = model.decimated(f32::INFINITY).map_or(bytes.len(), |data| data.len().min(bytes.len()));
+    for (level, cells) in LEVEL_CELLS {
+        let Ok(data) = model.decimated(cells) else {
+            break;
+        };
+        if (data.len() as f64) <= finer_size as f64 * MIN_SAVING {
+            finer_size = data.len();
+            lods.push((level, data));
+        }
+    }
+    lods
+}
+
+/// Picks the level to serve: the requested one if stored, otherwise the next more detailed one.
+pub fn pick_level(requested: Option<&str>, available: &[String]) -> &'static str {
+    let requested_rank = requested
+        .and_then(|level| LOD_LEVELS.iter().position(|known| *known == level))
+        .unwrap_or(0);
+    LOD_LEVELS[..=requested_rank]
+        .iter()
+        .rev()
+        .find(|level| **level == "full" || available.iter().any(|stored| stored == *level))
+        .copied()
+        .unwrap_or("full")
+}
+
+// A glTF document with its buffers decoded, also used by the OBJ exporter and texture downscaling
+pub(crate) struct Model {
+    pub(crate) document: Value,
+    buffers: Vec<Vec<u8>>,
+    binary: bool, // written back as GLB rather than JSON with a data URI
+}
+
+pub(crate) struct Attribute {
+    pub(crate) name: String,
+    pub(crate) component_type: u64,
+    pub(crate) kind: String, // SCALAR, VEC2, ...
+    pub(crate) normalized: bool,
+    element_size: usize,
+    pub(crate) data: Vec<u8>, // tightly packed elements
+}
+
+pub(crate) struct Primitive {
+    pub(crate) mesh: usize,
+    pub(crate) index: usize,
+    pub(crate) attributes: Vec<Attribute>,
+    pub(crate) indices: Vec<u32>,
+}
+
+impl Model {
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let (json, bin) = match split_glb(bytes) {
+            Some(chunks) => chunks,
+            None => (bytes, None),
+        };
+        let document: Value = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        let mut buffers = Vec::new();
+        for buffer in document["buffers"].as_array().into_iter().flatten() {
+            buffers.push(match buffer["uri"].as_str() {
+                Some(uri) if uri.starts_with("data:") => decode_data_uri(uri)?,
+                Some(_) => return Err("external buffer".to_string()),
+                None => bin.ok_or("missing GLB binary chunk")?.to_vec(),
+            });
+        }
+        Ok(Model { document, buffers, binary: bin.is_some() })
+    }
+
+    // Decimation would break skinning, animation and data it can't decode
+    fn check_rewritable(&self) -> Result<(), String> {
+        let non_empty = |key: &str| self.document[key].as_array().is_some_and(|items| !items.is_empty());
+        if non_empty("skins") || non_empty("animations") || non_empty("extensionsRequired") {
+            return Err("unsupported features".to_string());
+        }
+        let uses_unsupported = self.document["extensionsUsed"]
+            .as_array()
+            .is_some_and(|used| used.iter().any(|ext| UNSUPPORTED_EXTENSIONS.contains(&ext.as_str().unwrap_or(""))));
+        if uses_unsupported {
+            return Err("unsupported extension".to_string());
+        }
+        Ok(())
+    }
+
+    fn decimated(&self, cells: f32) -> Result<Vec<u8>, String> {
+        let mut document = self.document.clone();
+        let mut packer = Packer::default();
+
+        // Images stored in buffer views are carried over unchanged
+        let image_count = document["images"].as_array().map_or(0, Vec::len);
+        for image in 0..image_count {
+            if let Some(view) = document["images"][image]["bufferView"].as_u64() {
+                let bytes = self.buffer_view(view as usize)?;
+                document["images"][image]["bufferView"] = json!(packer.view(&bytes, None));
+            }
+        }
+
+        for primitive in self.primitives()? {
+            let primitive = cluster(primitive, cells)?;
+            let target = &mut document["meshes"][primitive.mesh]["primitives"][primitive.index];
+            let mut attributes = serde_json::Map::new();
+            for attribute in &primitive.attributes {
+                let view = packer.view(&attribute.data, Some(ARRAY_BUFFER));
+                let mut accessor = json!({
+                    "bufferView": view,
+                    "componentType": attribute.component_type,
+                    "type": attribute.kind,
+                    "count": attribute.data.len() / attribute.element_size,
+                });
+                if attribute.normalized {
+                    accessor["normalized"] = json!(true);
+                }
+                if attribute.name == "POSITION" {
+                    let (min, max) = bounds(&attribute.data);
+                    accessor["min"] = json!(min);
+                    accessor["max"] = json!(max);
+                }
+                attributes.insert(attribute.name.clone(), json!(packer.accessor(accessor)));
+            }
+            let index_bytes: Vec<u8> = primitive.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+            let view = packer.view(&index_bytes, Some(ELEMENT_ARRAY_BUFFER));
+            let indices = packer.accessor(json!({
+                "bufferView": view,
+                "componentType": UNSIGNED_INT,
+                "type": "SCALAR",
+                "count": primitive.indices.len(),
+            }));
+            target["attributes"] = Value::Object(attributes);
+            target["indices"] = json!(indices);
+        }
+
+        document["accessors"] = Value::Array(packer.accessors);
+        document["bufferViews"] = Value::Array(packer.views);
+        self.serialize(document, packer.data)
+    }
+
+    /// Writes `document` back out with every buffer view copied into one buffer, substituting
+    /// `replacements` (by view index) for the stored bytes. Used to swap out embedded images.
+    pub(crate) fn repacked(&self, mut document: Value, replacements: &HashMap<usize, Vec<u8>>) -> Result<Vec<u8>, String> {
+        let mut packer = Packer::default();
+        let view_count = document["bufferViews"].as_array().map_or(0, Vec::len);
+        if view_count == 0 && !self.binary {
+            // Nothing to pack; an empty buffer would make the file invalid
+            return serde_json::to_vec(&document).map_err(|e| e.to_string());
+        }
+        let mut views = Vec::with_capacity(view_count);
+        for index in 0..view_count {
+            let bytes = match replacements.get(&index) {
+                Some(bytes) => bytes.clone(),
+                None => self.buffer_view(index)?,
+            };
+            let packed = packer.view(&bytes, None);
+            // Stride, target and extensions of the original view still apply
+            let mut view = document["bufferViews"][index].clone();
+            view["buffer"] = json!(0);
+            view["byteOffset"] = packer.views[packed]["byteOffset"].clone();
+            view["byteLength"] = json!(bytes.len());
+            views.push(view);
+        }
+        document["bufferViews"] = Value::Array(views);
+        self.serialize(document, packer.data)
+    }
+
+    fn serialize(&self, mut document: Value, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let byte_length = data.len();
+        if self.binary {
+            document["buffers"] = json!([{ "byteLength": byte_length }]);
+            Ok(write_glb(&document, data))
+        } else {
+            let uri = format!("data:application/octet-stream;base64,{}", general_purpose::STANDARD.encode(&data));
+            document["buffers"] = json!([{ "byteLength": byte_length, "uri": uri }]);
+            serde_json::to_vec(&document).map_err(|e| e.to_string())
+        }
+    }
+
+    pub(crate) fn primitives(&self) -> Result<Vec<Primitive>, String> {
+        let mut primitives = Vec::new();
+        for (mesh_index, mesh) in self.document["meshes"].as_array().into_iter().flatten().enumerate() {
+            for (index, primitive) in mesh["primitives"].as_array().into_iter().flatten().enumerate() {
+                if primitive["mode"].as_u64().unwrap_or(4) != 4 || primitive.get("targets").is_some() {
+                    return Err("only plain triangle primitives are supported".to_string());
+                }
+                let mut attributes = Vec::new();
+                for (name, accessor) in primitive["attributes"].as_object().ok_or("primitive without attributes")? {
+                    let accessor = accessor.as_u64().ok_or("invalid accessor index")? as usize;
+                    attributes.push(self.attribute(name, accessor)?);
+                }
+                let position = attributes
+                    .iter()
+                    .find(|attribute| attribute.name == "POSITION")
+                    .ok_or("primitive without positions")?;
+                if position.component_type != FLOAT || position.kind != "VEC3" {
+                    return Err("positions must be float VEC3".to_string());
+                }
+                let vertex_count = position.data.len() / position.element_size;
+                let indices = match primitive["indices"].as_u64() {
+                    Some(accessor) => self.indices(accessor as usize)?,
+                    None => (0..vertex_count as u32).collect(),
+                };
+                if indices.iter().any(|i| *i as usize >= vertex_count) {
+                    return Err("index out of range".to_string());
+                }
+                primitives.push(Primitive { mesh: mesh_index, index, attributes, indices });
+            }
+        }
+        Ok(primitives)
+    }
+
+    fn attribute(&self, name: &str, accessor_index: usize) -> Result<Attribute, String> {
+        let accessor = &self.document["accessors"][accessor_index];
+        if accessor.get("sparse").is_some() {
+            return Err("sparse accessors are not supported".to_string());
+        }
+        let component_type = accessor["componentType"].as_u64().ok_or("accessor without component type")?;
+        let kind = accessor["type"].as_str().ok_or("accessor without type")?.to_string();
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err("unknown component type".to_string()),
+        };
+        let components = match kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => return Err("matrix attributes are not supported".to_string()),
+        };
+        let element_size = component_size * components;
+        let count = accessor["count"].as_u64().ok_or("accessor without count")? as usize;
+        let data = self.read_elements(accessor, element_size, count)?;
+        Ok(Attribute {
+            name: name.to_string(),
+            component_type,
+            kind,
+            normalized: accessor["normalized"].as_bool().unwrap_or(false),
+            element_size,
+            data,
+        })
+    }
+
+    fn indices(&self, accessor_index: usize) -> Result<Vec<u32>, String> {
+        let accessor = &self.document["accessors"][accessor_index];
+        let count = accessor["count"].as_u64().ok_or("accessor without count")? as usize;
+        let size = match accessor["componentType"].as_u64() {
+            Some(5121) => 1,
+            Some(5123) => 2,
+            Some(5125) => 4,
+            _ => return Err("invalid index component type".to_string()),
+        };
+        let data = self.read_elements(accessor, size, count)?;
+        Ok(data
+            .chunks_exact(size)
+            .map(|c| match size {
+                1 => c[0] as u32,
+                2 => u16::from_le_bytes([c[0], c[1]]) as u32,
+                _ => u32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+            })
+            .collect())
+    }
+
+    // Copies `count` elements out of the accessor's buffer view, dropping any stride padding
+    fn read_elements(&self, accessor: &Value, element_size: usize, count: usize) -> Result<Vec<u8>, String> {
+        let view_index = accessor["bufferView"].as_u64().ok_or("accessor without buffer view")? as usize;
+        let view = BufferView::parse(&self.document, view_index)?;
+        let buffer = view.slice(self.buffers.get(view.buffer).ok_or("missing buffer")?)?;
+        let start = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let stride = view.stride.unwrap_or(element_size);
+        if stride < element_size {
+            return Err("byte stride shorter than an element".to_string());
+        }
+        // The count comes straight from the upload, so it's checked against the view before
+        // anything is reserved for it
+        let end = match count.checked_sub(1) {
+            None => start,
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|offset| offset.checked_add(start))
+                .and_then(|offset| offset.checked_add(element_size))
+                .ok_or("accessor past end of buffer view")?,
+        };
+        if end > buffer.len() {
+            return Err("accessor past end of buffer view".to_string());
+        }
+        let mut data = Vec::with_capacity(element_size * count);
+        for i in 0..count {
+            let offset = start + i * stride;
+            data.extend_from_slice(&buffer[offset..offset + element_size]);
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn buffer_view(&self, view_index: usize) -> Result<Vec<u8>, String> {
+        let view = BufferView::parse(&self.document, view_index)?;
+        view.slice(self.buffers.get(view.buffer).ok_or("missing buffer")?).map(<[u8]>::to_vec)
+    }
+}
+
+// Merges vertices that fall into the same grid cell and drops triangles that collapse
+fn cluster(primitive: Primitive, cells: f32) -> Result<Primitive, String> {
+    let positions = positions(
+        &primitive
+            .attributes
+            .iter()
+            .find(|attribute| attribute.name == "POSITION")
+            .ok_or("primitive without positions")?
+            .data,
+    );
+    let (min, max) = bounds_of(&positions);
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0f32, f32::max);
+    let cell = extent / cells;
+    if !cell.is_finite() || cell <= 0.0 {
+        return Ok(primitive);
+    }
+
+    let mut cell_vertex: HashMap<[i64; 3], u32> = HashMap::new();
+    let mut representatives: Vec<usize> = Vec::new();
+    let remap: Vec<u32> = positions
+        .iter()
+        .enumerate()
+        .map(|(vertex, position)| {
+            let key = [0, 1, 2].map(|axis| ((position[axis] - min[axis]) / cell).floor() as i64);
+            *cell_vertex.entry(key).or_insert_with(|| {
+                representatives.push(vertex);
+                (representatives.len() - 1) as u32
+            })
+        })
+        .collect();
+    let indices: Vec<u32> = primitive
+        .indices
+        .chunks_exact(3)
+        .map(|triangle| [remap[triangle[0] as usize], remap[triangle[1] as usize], remap[triangle[2] as usize]])
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect();
+    if indices.is_empty() {
+        // Too small to survive at this level; keep it intact rather than letting it vanish
+        return Ok(primitive);
+    }
+
+    let attributes = primitive
+        .attributes
+        .into_iter()
+        .map(|attribute| {
+            let size = attribute.element_size;
+            let data = representatives
+                .iter()
+                .flat_map(|vertex| attribute.data[vertex * size..(vertex + 1) * size].iter().copied())
+                .collect();
+            Attribute { data, ..attribute }
+        })
+        .collect();
+    Ok(Primitive { attributes, indices, ..primitive })
+}
+
+fn positions(position_data: &[u8]) -> Vec<[f32; 3]> {
+    position_data
+        .chunks_exact(12)
+        .map(|c| {
+            let f = |i: usize| f32::from_le_bytes([c[i], c[i + 1], c[i + 2], c[i + 3]]);
+            [f(0), f(4), f(8)]
+        })
+        .collect()
+}
+
+fn bounds(position_data: &[u8]) -> ([f32; 3], [f32; 3]) {
+    bounds_of(&positions(position_data))
+}
+
+fn bounds_of(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for position in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    (min, max)
+}
+
+// Accumulates the single repacked buffer and the views/accessors pointing into it
+#[derive(Default)]
+struct Packer {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Packer {
+    fn view(&mut self, bytes: &[u8], target: Option<u64>) -> usize {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let mut view = json!({ "buffer": 0, "byteOffset": self.data.len(), "byteLength": bytes.len() });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    fn accessor(&mut self, accessor: Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+fn write_glb(document: &Value, mut bin: Vec<u8>) -> Vec<u8> {
+    let mut json = serde_json::to_vec(document).unwrap_or_default();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    let mut out = Vec::with_capacity(total);
+    out.extend_from_slice(b"glTF");
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&(total as u32).to_le_bytes());
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"JSON");
+    out.extend_from_slice(&json);
+    out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"BIN\0");
+    out.extend_from_slice(&bin);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A triangle-less glTF whose accessor claims far more vertices than its 12-byte buffer holds
+    fn oversized_accessor(count: u64, stride: Option<u64>) -> Vec<u8> {
+        let mut view = json!({ "buffer": 0, "byteLength": 12 });
+        if let Some(stride) = stride {
+            view["byteStride"] = json!(stride);
+        }
+        serde_json::to_vec(&json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "type": "VEC3", "count": count }],
+            "bufferViews": [view],
+            "buffers": [{ "byteLength": 12, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAA" }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accessor_counts_are_checked_before_reserving() {
+        for (count, stride) in [(100_000_000_000_000_000, None), (u64::MAX, None), (1_000_000, Some(0))] {
+            let model = oversized_accessor(count, stride);
+            assert!(crate::formats::validate_gltf(&model).is_ok());
+            assert!(generate_lods(&model).is_empty());
+            assert!(crate::obj::export_obj(&model, "model").is_err());
+        }
+    }
+
+    #[test]
+    fn buffer_view_offsets_cannot_overflow() {
+        let mut model = Model::parse(&oversized_accessor(1, None)).unwrap();
+        model.document["bufferViews"][0]["byteOffset"] = json!(u64::MAX);
+        assert!(model.buffer_view(0).is_err());
+        assert!(model.primitives().is_err());
+    }
+
+    #[test]
+    fn accessors_stay_inside_their_buffer_view() {
+        // The buffer holds the vertex, but the view only covers its first half
+        let mut model = Model::parse(&oversized_accessor(1, None)).unwrap();
+        model.document["bufferViews"][0]["byteLength"] = json!(6);
+        assert_eq!(model.buffer_view(0).unwrap().len(), 6);
+        assert!(model.primitives().is_err());
+    }
+}
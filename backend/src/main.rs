@@ -1,329 +1,2296 @@
-use futures_util::{ SinkExt, StreamExt };
-use rusqlite::{ params, Connection, Result };
-use serde::{ Deserialize, Serialize };
+use backend::{
+    db::{
+        add_model_to_layout, check_db, content_hash, db_path, delete_model, init_db, insert_comment, insert_layout, insert_model,
+        insert_model_lods, insert_model_original, invalidate_thumbnail, load_all_models, load_comments, load_layout_by_id,
+        load_layout_summaries, load_metadata, load_model_by_id, load_model_history, load_model_list, load_model_lod, load_models_by_ids,
+        load_template_summaries, load_thumbnail, load_version_snapshot, model_exists, model_version, open_db, restore_model_version, storage_used,
+        store_thumbnail, unix_time, update_access_code, update_layout_entries, update_metadata, update_model_name, update_model_transform,
+        update_transform_lock, ModelData
+    },
+    format_size,
+    formats,
+    http::{ self, RequestHead, Response },
+    layout_writes::{ self, LayoutWrites },
+    lod,
+    obj,
+    permissions::{ Permissions, EDITOR, VIEWER },
+    polling::{ self, PollSessions },
+    presence::{ self, Presence },
+    protocol::{
+        actions, parse_request, ClientMessage, Collaborator, DeletedModel, ExportedTexture, HelloResponse, LayoutEntry, LayoutResponse,
+        ModelResponse, ModelTransform, ObjExport, ServerMessage, SessionInfo, StorageStatus, UploadStats, ValidationReport, ViewState,
+        GZIP_ENCODING, GZIP_MESSAGE_PREFIX, PROTOCOL_VERSION,
+    },
+    textures
+};
+use flate2::{ write::GzEncoder, Compression };
+use futures_util::{ future, SinkExt, StreamExt };
+use rusqlite::params;
 use std::{
-    collections::HashSet,
-    time::Duration
+    collections::{ BTreeMap, HashMap },
+    io::Write,
+    net::{ IpAddr, Ipv4Addr, SocketAddr },
+    path::{ Path, PathBuf },
+    sync::{ atomic::{ AtomicBool, AtomicU64, Ordering }, Arc },
+    time::{ Duration, SystemTime, UNIX_EPOCH }
 };
 use tokio::{
+    io::{ AsyncRead, AsyncWrite },
     net::{ TcpListener, TcpStream },
-    sync::broadcast::{ self, Sender }
+    sync::broadcast::{ self, error::RecvError, Sender }
 };
-use tokio_tungstenite::{ accept_async_with_config, tungstenite::Message };
-use base64::{ Engine as _, engine::general_purpose };
+use tokio_tungstenite::{ accept_async_with_config, tungstenite::Message, WebSocketStream };
+use tracing::{ debug, error, info, warn };
+use base64::{ DecodeError, DecodeSliceError, Engine as _, engine::general_purpose };
+use sha2::{ Digest, Sha256 };
 
-#[derive(Serialize, Deserialize)]
-struct ModelRequest {
-    action: String,
-    id: Option<i32>,
-    name: Option<String>,
-    model_data: Option<String>, // base64-encoded model data for insert
-}
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 8000;
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 40 * 1024 * 1024; // 40 MB
+const DEFAULT_BACKUP_INTERVAL_SECS: u64 = 15 * 60;
+const DEFAULT_BACKUP_KEEP: usize = 5;
+const MAX_COMMENT_CHARS: usize = 2000;
+const MAX_METADATA_ENTRIES: usize = 100;
+const MAX_METADATA_KEY_CHARS: usize = 100;
+const MAX_METADATA_VALUE_CHARS: usize = 2000;
+const DEFAULT_CAMERA_POSITION: [f32; 3] = [-6.0, 5.0, 1.5];
+const DEFAULT_STORAGE_WARN_PERCENT: u64 = 80;
+// Actions that read or change a layout without going through LayoutWrites, which is flushed first
+const FLUSHES_LAYOUT_WRITES: [&str; 5] = [
+    actions::DELETE,
+    actions::COPY_TO_SCENE,
+    actions::COPY_TRANSFORM,
+    actions::LOAD_LAYOUT,
+    actions::INSTANTIATE_TEMPLATE,
+];
+const DEFAULT_MODEL_CACHE_MAX_AGE: u64 = 0; // always revalidate, which is cheap with the ETag
+const COMPRESS_MIN_BYTES: usize = 1024; // smaller messages gain too little to be worth compressing
+const PROBE_ROUTES: [&str; 2] = ["/health", "/ready"];
+const MIN_WEBSOCKET_MESSAGE_BYTES: usize = 100 * 1024 * 1024; // room for any request under a small upload limit
+const DECODE_CHUNK_BYTES: usize = 64 * 1024; // base64 decoded per step, a multiple of 4
+const BROADCAST_CAPACITY: usize = 1024; // broadcasts a slow client may fall behind by before it is resent the model list
 
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
-struct ModelResponse {
-    id: i32,
-    name: Option<String>,
-    model_data: String, // base64-encoded model data
+// Source of the ids whoami reports, one per connection
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug)]
+struct ServerConfig {
+    listen_addr: SocketAddr, // where WebSocket and HTTP clients connect, from BIND_ADDR and PORT
+    rest_addr: Option<SocketAddr>, // plain HTTP listing and downloads only, off unless REST_PORT is set
+    max_upload_bytes: usize, // largest decoded payload accepted by insert/set_thumbnail
+    backup: Option<BackupConfig>, // periodic snapshots are off unless BACKUP_DIR is set
+    camera_position: [f32; 3], // default view sent to clients in the hello message
+    camera_focus: [f32; 3],
+    read_only: bool, // demo mode: every mutating action is rejected
+    storage_quota: Option<u64>, // hard limit on stored bytes, unlimited unless STORAGE_QUOTA_BYTES is set
+    storage_warn_percent: u64, // share of the quota past which uploads carry a warning
+    log_messages: bool, // print every request and response body, for protocol debugging
+    broadcast_model_data: bool, // include model bytes in broadcasts, for clients that can't fetch them lazily
+    max_texture_size: Option<u32>, // uploaded textures are scaled down to fit, off unless MAX_TEXTURE_SIZE is set
+    model_cache_max_age: u64, // seconds caches may reuse a GET /models/<id> response without revalidating
+    compress_messages: bool, // offer gzip-compressed messages to WebSocket clients
+    model_poll_interval: Option<Duration>, // re-read the catalog for outside changes, off unless MODEL_POLL_INTERVAL_MS is set
+    permissions: Permissions, // actions each role may use, from PERMISSIONS_FILE over the defaults
+    role: String, // role of every connection, ROLE or by default "editor" ("viewer" when read-only)
 }
 
 #[derive(Debug)]
-struct ModelData {
-    id: i32,
-    name: Option<String>,
-    model_data: Vec<u8>, // raw binary data
+struct BackupConfig {
+    dir: PathBuf,
+    interval: Duration,
+    keep: usize, // number of most recent snapshots kept in `dir`
+}
+
+impl ServerConfig {
+    fn from_env() -> Self {
+        // Serving somewhere other than asked would be hard to notice, so refuse to start instead
+        let listen_addr = SocketAddr::new(
+            env_parsed("BIND_ADDR").unwrap_or(DEFAULT_BIND_ADDR),
+            env_parsed("PORT").unwrap_or(DEFAULT_PORT),
+        );
+        let rest_addr = env_parsed("REST_PORT").map(|port| SocketAddr::new(listen_addr.ip(), port));
+        let max_upload_bytes = env_or("MAX_UPLOAD_BYTES", DEFAULT_MAX_UPLOAD_BYTES);
+        let backup = std::env::var("BACKUP_DIR").ok().map(|dir| BackupConfig {
+            dir: PathBuf::from(dir),
+            interval: Duration::from_secs(env_or("BACKUP_INTERVAL_SECS", DEFAULT_BACKUP_INTERVAL_SECS).max(1)),
+            keep: env_or("BACKUP_KEEP", DEFAULT_BACKUP_KEEP).max(1),
+        });
+        let camera_focus = env_vec3("CAMERA_FOCUS").unwrap_or([0.0; 3]);
+        let mut camera_position = env_vec3("CAMERA_POSITION").unwrap_or(DEFAULT_CAMERA_POSITION);
+        // CAMERA_RADIUS keeps the viewing direction but moves the camera to that distance from the focus
+        if let Some(radius) = std::env::var("CAMERA_RADIUS").ok().and_then(|value| value.parse::<f32>().ok()) {
+            let offset: Vec<f32> = camera_position.iter().zip(camera_focus).map(|(p, f)| p - f).collect();
+            let length = offset.iter().map(|o| o * o).sum::<f32>().sqrt();
+            if radius > 0.0 && length > 0.0 {
+                for i in 0..3 {
+                    camera_position[i] = camera_focus[i] + offset[i] / length * radius;
+                }
+            }
+        }
+        // A mistyped READ_ONLY would leave a demo deployment writable, so refuse to start instead
+        let read_only = env_parsed("READ_ONLY").unwrap_or(false);
+        // Likewise an unreadable quota would silently leave storage unlimited
+        let storage_quota = env_parsed("STORAGE_QUOTA_BYTES");
+        let storage_warn_percent = env_or("STORAGE_WARN_PERCENT", DEFAULT_STORAGE_WARN_PERCENT).min(100);
+        let log_messages = env_or("LOG_MESSAGES", false);
+        let broadcast_model_data = env_or("BROADCAST_MODEL_DATA", false);
+        let max_texture_size = std::env::var("MAX_TEXTURE_SIZE").ok().and_then(|value| value.parse().ok()).filter(|size| *size > 0);
+        let model_cache_max_age = env_or("MODEL_CACHE_MAX_AGE", DEFAULT_MODEL_CACHE_MAX_AGE);
+        let compress_messages = env_or("COMPRESS_MESSAGES", true);
+        let model_poll_interval = std::env::var("MODEL_POLL_INTERVAL_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|millis| *millis > 0)
+            .map(Duration::from_millis);
+        // A bad permissions setup would open or lock up the catalog unexpectedly, so refuse to start
+        let permissions = match std::env::var("PERMISSIONS_FILE") {
+            Ok(path) => Permissions::load(Path::new(&path)).unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            }),
+            Err(_) => Permissions::default(),
+        };
+        let role = std::env::var("ROLE").unwrap_or_else(|_| if read_only { VIEWER } else { EDITOR }.to_string());
+        if !permissions.has_role(&role) {
+            error!(role, "ROLE is not defined; add it to PERMISSIONS_FILE");
+            std::process::exit(1);
+        }
+        ServerConfig {
+            listen_addr,
+            rest_addr,
+            max_upload_bytes,
+            backup,
+            camera_position,
+            camera_focus,
+            read_only,
+            storage_quota,
+            storage_warn_percent,
+            log_messages,
+            broadcast_model_data,
+            max_texture_size,
+            model_cache_max_age,
+            compress_messages,
+            model_poll_interval,
+            permissions,
+            role,
+        }
+    }
+}
+
+// Parses "x,y,z"
+fn env_vec3(name: &str) -> Option<[f32; 3]> {
+    let value = std::env::var(name).ok()?;
+    let parts: Vec<f32> = value
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    parts.try_into().ok()
+}
+
+// Like env_or, but exits when the variable is set to something unparsable
+fn env_parsed<T: std::str::FromStr>(name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = std::env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            error!(name, value, error = %e, "invalid setting");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
 }
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:8000").await.expect("Failed to bind");
-    println!("Backend WebSocket server running on ws://127.0.0.1:8000/ws");
+    backend::init_logging();
+    let config = Arc::new(ServerConfig::from_env());
+    info!(?config, "loaded server config");
+    // Migrating up front keeps a broken database from failing every request one by one
+    if let Err(e) = init_db() {
+        error!(path = %db_path().display(), error = %e, "failed to open or migrate the database");
+        std::process::exit(1);
+    }
+    // A relative DATABASE_PATH depends on where the server was started, so name the exact file
+    let path = std::fs::canonicalize(db_path()).unwrap_or_else(|_| db_path().to_path_buf());
+    info!(path = %path.display(), "using database");
 
-    let (tx, _) = broadcast::channel(16);
+    // Plain TCP only: there is no TLS listener yet, so HTTPS pages reach the server through a
+    // proxy that terminates TLS (see the README)
+    let listener = TcpListener::bind(config.listen_addr).await.expect("Failed to bind");
+    info!("Backend WebSocket server running on ws://{}/ws", config.listen_addr);
+    info!("HTTP long-polling fallback available at http://{}/poll", config.listen_addr);
 
-    let tx_clone = tx.clone();
-    tokio::spawn(async move {
-        let mut last_models: HashSet<ModelResponse> = HashSet::new();
-        loop {
-            match load_all_models() {
-                Ok(models) => {
-                    let current_models: HashSet<ModelResponse> = models
-                        .into_iter()
-                        .map(|m| ModelResponse {
-                            id: m.id,
-                            name: m.name,
-                            model_data: general_purpose::STANDARD.encode(&m.model_data),
-                        })
-                        .collect();
-                    if current_models != last_models {
-                        let updated_list: Vec<ModelResponse> = current_models.iter().cloned().collect();
-                        let update = serde_json::to_string(&updated_list).unwrap();
-                        if let Err(e) = tx_clone.send(update) {
-                            eprintln!("Broadcast error: {}", e);
-                        }
-                        last_models = current_models;
-                    }
+    // For scripts and CDNs that can't speak the WebSocket protocol
+    if let Some(rest_addr) = config.rest_addr {
+        let rest_listener = TcpListener::bind(rest_addr).await.expect("Failed to bind the REST port");
+        info!("Model listing and downloads available at http://{}/models", rest_addr);
+        let config = config.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _addr)) = rest_listener.accept().await {
+                tokio::spawn(serve_rest(stream, config.clone()));
+            }
+        });
+    }
+
+    let (tx, _) = broadcast::channel(BROADCAST_CAPACITY);
+
+    // Handlers broadcast their own changes; this only catches edits made outside the server
+    if let Some(interval) = config.model_poll_interval {
+        let tx = tx.clone();
+        let config = config.clone();
+        info!(?interval, "polling the database for outside changes");
+        tokio::spawn(poll_models(interval, config, tx));
+    }
+
+    if let Some(backup) = &config.backup {
+        let config = config.clone();
+        info!(dir = %backup.dir.display(), interval = ?backup.interval, "backing up the database");
+        tokio::spawn(async move {
+            let backup = config.backup.as_ref().unwrap();
+            let mut interval = tokio::time::interval(backup.interval);
+            interval.tick().await; // the first tick completes immediately
+            loop {
+                interval.tick().await;
+                let backup_config = config.clone();
+                match on_db_thread(move || backup_database(backup_config.backup.as_ref().unwrap())).await {
+                    Ok(path) => info!(path = %path.display(), "saved backup"),
+                    Err(e) => error!(error = %e, "backup failed"),
                 }
-                Err(e) => eprintln!("Failed to poll models: {}", e),
             }
-            tokio::time::sleep(Duration::from_millis(500)).await;
+        });
+    }
+
+    let layout_writes = Arc::new(LayoutWrites::default());
+    let flushed_writes = layout_writes.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(layout_writes::FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush_layout_writes(&flushed_writes).await;
         }
     });
 
+    let sessions = Arc::new(PollSessions::default());
+    let presence = Arc::new(Presence::default());
     while let Ok((stream, _addr)) = listener.accept().await {
         let tx = tx.clone();
-        tokio::spawn(handle_connection(stream, tx));
+        tokio::spawn(handle_connection(stream, tx, config.clone(), sessions.clone(), layout_writes.clone(), presence.clone()));
+    }
+}
+
+async fn flush_layout_writes(layout_writes: &Arc<LayoutWrites>) {
+    let layout_writes = layout_writes.clone();
+    if let Err(e) = on_db_thread(move || layout_writes.flush()).await {
+        error!(error = %e, "failed to save layout changes");
+    }
+}
+
+// Runs blocking database work on tokio's blocking pool, so a slow query or a large BLOB
+// holds up only its own request instead of every connection sharing the worker thread.
+// The work stays in the caller's span, and a panic in it resumes here as it would inline.
+async fn on_db_thread<T, F>(work: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let span = tracing::Span::current();
+    match tokio::task::spawn_blocking(move || span.in_scope(work)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("database work was cancelled: {}", e),
     }
 }
 
-async fn handle_connection(stream: TcpStream, tx: Sender<String>) {
+async fn handle_connection(
+    stream: TcpStream,
+    tx: Sender<String>,
+    server_config: Arc<ServerConfig>,
+    sessions: Arc<PollSessions>,
+    layout_writes: Arc<LayoutWrites>,
+    presence: Arc<Presence>,
+) {
+    let head = match http::peek_request_head(&stream).await {
+        Ok(head) => head,
+        Err(e) => {
+            warn!(error = %e, "failed to read request");
+            return;
+        }
+    };
+    if head.route() == "/models" || head.route().starts_with("/models/") {
+        serve_model_http(stream, head, server_config).await;
+        return;
+    }
+    if PROBE_ROUTES.contains(&head.route()) {
+        serve_probe(stream, head).await;
+        return;
+    }
+    if !head.is_websocket_upgrade() {
+        // Clients that can't open a WebSocket fall back to long polling over plain HTTP,
+        // which relays text lines only, so compression is never offered there
+        let max_body_bytes = max_request_bytes(&server_config);
+        polling::handle_http(stream, head, sessions, max_body_bytes, |ws_stream, peer| {
+            tokio::spawn(serve_client(ws_stream, peer, tx, server_config, layout_writes, presence, false));
+        })
+        .await;
+        return;
+    }
+
     let mut config = tokio_tungstenite::tungstenite::protocol::WebSocketConfig::default();
-    config.max_message_size = Some(100 * 1024 * 1024); // 100 MB
-    config.max_frame_size = Some(100 * 1024 * 1024);   // 100 MB
+    // Oversized uploads must still arrive whole to get a readable error; a message over
+    // these limits closes the connection instead
+    let max_message_bytes = max_request_bytes(&server_config).max(MIN_WEBSOCKET_MESSAGE_BYTES);
+    config.max_message_size = Some(max_message_bytes);
+    config.max_frame_size = Some(max_message_bytes);
     config.accept_unmasked_frames = false;
+    let stream_peer = stream.peer_addr().ok();
     let ws_stream = match accept_async_with_config(stream, Some(config)).await {
         Ok(ws) => ws,
         Err(e) => {
-            eprintln!("Failed to accept WebSocket connection: {:?}", e);
+            warn!(error = %e, "failed to accept WebSocket connection");
             return;
         }
     };
+    let peer = stream_peer.map_or_else(|| "unknown peer".to_string(), |addr| addr.to_string());
+    let compression = server_config.compress_messages;
+    serve_client(ws_stream, peer, tx, server_config, layout_writes, presence, compression).await;
+}
+
+// The REST port answers only the model routes and the probes
+async fn serve_rest(stream: TcpStream, config: Arc<ServerConfig>) {
+    match http::peek_request_head(&stream).await {
+        Ok(head) if PROBE_ROUTES.contains(&head.route()) => serve_probe(stream, head).await,
+        Ok(head) => serve_model_http(stream, head, config).await,
+        Err(e) => warn!(error = %e, "failed to read request"),
+    }
+}
+
+// GET /health answers as long as the process is up; GET /ready also needs the database to
+// answer, so load balancers stop sending clients to a server that can't serve them
+async fn serve_probe(mut stream: TcpStream, head: RequestHead) {
+    let response = match (http::read_body(&mut stream, &head, 0).await, head.method.as_str()) {
+        (Err(e), _) => {
+            warn!(error = %e, "HTTP request failed");
+            Response::empty("400 Bad Request")
+        }
+        (Ok(_), "GET") if head.route() == "/health" => Response::new("200 OK", "text/plain", "ok"),
+        (Ok(_), "GET") => match on_db_thread(check_db).await {
+            Ok(()) => Response::new("200 OK", "text/plain", "ready"),
+            Err(e) => {
+                warn!(error = %e, "readiness check failed");
+                Response::new("503 Service Unavailable", "text/plain", "database unavailable")
+            }
+        },
+        (Ok(_), "OPTIONS") => Response::empty("204 No Content"),
+        (Ok(_), _) => Response::empty("405 Method Not Allowed"),
+    };
+    http::write_response(stream, response).await;
+}
+
+// GET /models: every model's details as in list_metadata, without bytes.
+// GET /models/<id>[?lod=level]: the model bytes, cacheable by browsers and CDNs through
+// their content hash. Protected models are only served over the WebSocket, with their code.
+async fn serve_model_http(mut stream: TcpStream, head: RequestHead, config: Arc<ServerConfig>) {
+    let response = match http::read_body(&mut stream, &head, 0).await {
+        Err(e) => {
+            warn!(error = %e, "HTTP request failed");
+            Response::empty("400 Bad Request")
+        }
+        Ok(_) => on_db_thread(move || model_http_response(&head, &config)).await,
+    };
+    http::write_response(stream, response).await;
+}
+
+fn model_http_response(head: &RequestHead, config: &ServerConfig) -> Response {
+    match head.method.as_str() {
+        "OPTIONS" => return Response::empty("204 No Content"),
+        "GET" => {}
+        _ => return Response::empty("405 Method Not Allowed"),
+    }
+    if head.route() == "/models" {
+        return match load_model_list() {
+            Ok(models) => {
+                let models: Vec<ModelResponse> = models.into_iter().map(list_response).collect();
+                Response::new("200 OK", "application/json", serde_json::to_string(&models).unwrap())
+            }
+            Err(e) => {
+                error!(error = %e, "failed to list models");
+                Response::empty("500 Internal Server Error")
+            }
+        };
+    }
+    let Some(id) = head.route().strip_prefix("/models/").and_then(|id| id.parse().ok()) else {
+        return Response::empty("404 Not Found");
+    };
+    let mut model = match load_model_by_id(id) {
+        Ok(model) => model,
+        Err(_) => return Response::empty("404 Not Found"),
+    };
+    if model.access_code_hash.is_some() {
+        return Response::empty("403 Forbidden");
+    }
+    let level = serve_lod(&mut model, head.query("lod"));
+    let etag = format!("\"{}\"", content_hash(&model.model_data));
+    let cache_control = match config.model_cache_max_age {
+        0 => "no-cache".to_string(),
+        max_age => format!("public, max-age={}", max_age),
+    };
+    // A list of tags, possibly weak, or * for any version
+    let cached = head.header("if-none-match").is_some_and(|tags| {
+        tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    });
+    let format = formats::sniff_format(&model.model_data);
+    let content_type = match format {
+        Some(formats::ModelFormat::Glb) => "model/gltf-binary",
+        Some(formats::ModelFormat::Gltf) => "model/gltf+json",
+        _ => "application/octet-stream",
+    };
+    let extension = format.map_or("bin", formats::ModelFormat::extension);
+    let disposition = content_disposition(model.name.as_deref().unwrap_or(""), id, extension);
+    let response = if cached {
+        Response::empty("304 Not Modified")
+    } else {
+        Response::new("200 OK", content_type, model.model_data)
+    };
+    response
+        .with_header("ETag", etag)
+        .with_header("Cache-Control", cache_control)
+        .with_header("X-Model-Lod", level)
+        .with_header("Content-Disposition", disposition)
+}
 
-    let (mut write, mut read) = ws_stream.split();
+// Saves downloads under the model's name, or "model-<id>" for unnamed ones. Plain `filename`
+// gets an ASCII stand-in for anything a quoted header value can't hold; browsers prefer the
+// exact UTF-8 name in `filename*`.
+fn content_disposition(name: &str, id: i32, extension: &str) -> String {
+    let name = name.trim();
+    let name = if name.is_empty() { format!("model-{}", id) } else { name.to_string() };
+    let ascii: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || " -_.()".contains(c) { c } else { '_' })
+        .collect();
+    let encoded: String = format!("{}.{}", name, extension)
+        .bytes()
+        .map(|b| if b.is_ascii_alphanumeric() || b"-_.".contains(&b) { (b as char).to_string() } else { format!("%{:02X}", b) })
+        .collect();
+    format!("attachment; filename=\"{}.{}\"; filename*=UTF-8''{}", ascii, extension, encoded)
+}
+
+// Everything logged for the connection carries its session id and peer
+#[tracing::instrument(name = "connection", skip_all, fields(session = tracing::field::Empty, peer = %peer))]
+async fn serve_client<S>(
+    ws_stream: WebSocketStream<S>,
+    peer: String,
+    tx: Sender<String>,
+    server_config: Arc<ServerConfig>,
+    layout_writes: Arc<LayoutWrites>,
+    presence: Arc<Presence>,
+    compression: bool,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let log_messages = server_config.log_messages;
+    let session_number = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    tracing::Span::current().record("session", session_number);
+    let session_id = session_number.to_string();
+    let compressing = AtomicBool::new(false); // set once the client opts in with set_compression
+    let (write, mut read) = ws_stream.split();
+    let mut write = write.with(|message: Message| {
+        if log_messages && let Message::Text(text) = &message {
+            info!(direction = "out", bytes = text.len(), text = text.as_str(), "message");
+        }
+        future::ok::<_, tokio_tungstenite::tungstenite::Error>(compress_message(message, compressing.load(Ordering::Relaxed)))
+    });
     let mut rx = tx.subscribe();
 
+    let hello = HelloResponse {
+        protocol_version: PROTOCOL_VERSION,
+        camera_position: server_config.camera_position,
+        camera_focus: server_config.camera_focus,
+        read_only: server_config.read_only,
+        compression: if compression { vec![GZIP_ENCODING.to_string()] } else { vec![] },
+        max_upload_bytes: Some(server_config.max_upload_bytes as u64),
+    };
+    let hello_str = serde_json::to_string(&ServerMessage::Hello { hello }).unwrap();
+    if let Err(e) = write.send(Message::Text(hello_str.into())).await {
+        warn!(error = %e, "send failed");
+        return;
+    }
+    let config = server_config.clone();
+    if let Some(storage) = on_db_thread(move || storage_status(&config)).await {
+        let storage_str = serde_json::to_string(&ServerMessage::Storage { storage }).unwrap();
+        if let Err(e) = write.send(Message::Text(storage_str.into())).await {
+            warn!(error = %e, "send failed");
+            return;
+        }
+    }
+    // Joined only once the hello is out, so the roster is the first broadcast the client sees
+    broadcast_presence(presence.join(session_number), &tx);
+    info!("client connected");
+
     loop {
         tokio::select! {
-            Some(Ok(message)) = read.next() => {
+            message = read.next() => {
+                // Ends without a close frame when the client just goes away
+                let Some(Ok(message)) = message else {
+                    break;
+                };
                 if let Message::Text(text) = message {
-                    match serde_json::from_str::<ModelRequest>(&text) {
+                    if log_messages {
+                        info!(direction = "in", bytes = text.len(), text = text.as_str(), "message");
+                    }
+                    let bytes = text.len();
+                    let parsed = parse_request(&text);
+                    // The request owns its payload now, so a large upload isn't held twice while it's decoded
+                    drop(text);
+                    match parsed {
                         Ok(request) => {
-                            match request.action.as_str() {
-                                "get_by_id" => {
-                                    if let Some(id) = request.id {
-                                        match load_model_by_id(id) {
-                                            Ok(model) => {
-                                                let response = ModelResponse {
-                                                    id: model.id,
-                                                    name: model.name,
-                                                    model_data: general_purpose::STANDARD.encode(&model.model_data),
-                                                };
-                                                let response_str = serde_json::to_string(&response).unwrap();
-                                                if let Err(e) = write
-                                                    .send(Message::Text(response_str.into()))
-                                                    .await
-                                                {
-                                                    eprintln!("Send error: {:?}", e);
-                                                    break;
-                                                }
-                                            }
-                                            Err(e) => {
-                                                send_error(&mut write, &format!("Model not found: {}", e)).await;
+                            let action = request.action();
+                            let named = request.named_models();
+                            debug!(action, models = ?named, bytes, "request");
+                            if server_config.read_only && actions::MUTATING.contains(&action) {
+                                send_error(&mut write, Some(action), "The catalog is read-only on this server").await;
+                                continue;
+                            }
+                            if !server_config.permissions.allows(&server_config.role, action) {
+                                let error = format!("The {} role may not use {}", server_config.role, action);
+                                send_error(&mut write, Some(action), &error).await;
+                                continue;
+                            }
+                            let models = named.clone();
+                            match on_db_thread(move || missing_model(&models)).await {
+                                Ok(None) => {}
+                                Ok(Some(id)) => {
+                                    let response_str = serde_json::to_string(&ServerMessage::model_not_found(id)).unwrap();
+                                    if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                                        warn!(error = %e, "send failed");
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                Err(e) => {
+                                    send_error(&mut write, Some(action), &format!("Failed to look up model: {}", e)).await;
+                                    continue;
+                                }
+                            }
+                            if FLUSHES_LAYOUT_WRITES.contains(&action) {
+                                flush_layout_writes(&layout_writes).await;
+                            }
+                            match request {
+                                ClientMessage::GetById { id, access_code, lod } => {
+                                    let response = on_db_thread(move || -> Result<ModelResponse, String> {
+                                        let mut model = load_model_by_id(id).map_err(|e| format!("Model not found: {}", e))?;
+                                        check_access(&model, access_code.as_deref())?;
+                                        let lod = serve_lod(&mut model, lod.as_deref());
+                                        Ok(ModelResponse {
+                                            id: model.id,
+                                            name: model.name,
+                                            model_data: general_purpose::STANDARD.encode(&model.model_data),
+                                            protected: model.access_code_hash.is_some(),
+                                            format: model.format,
+                                            texture_warnings: model.texture_warnings,
+                                            lods: available_lods(&model.lods),
+                                            lod: lod.to_string(),
+                                            warning: None,
+                                            texture_bytes_saved: None,
+                                            metadata: load_metadata(model.id).unwrap_or_else(|e| {
+                                                warn!(model_id = model.id, error = %e, "failed to load metadata");
+                                                BTreeMap::new()
+                                            }),
+                                            transform_locked: model.transform_locked,
+                                            created_by: model.created_by,
+                                            version: model.version,
+                                            created_at: model.created_at,
+                                            updated_at: model.updated_at,
+                                            data_replaced: false,
+                                            transform: model.transform,
+                                        })
+                                    })
+                                    .await;
+                                    match response {
+                                        Ok(response) => {
+                                            let response_str = serde_json::to_string(&response).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
                                             }
                                         }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
                                     }
                                 }
-                                "get_all" => {
-                                    match load_all_models() {
-                                        Ok(models) => {
-                                            let response: Vec<ModelResponse> = models
-                                                .into_iter()
-                                                .map(|m| ModelResponse {
-                                                    id: m.id,
-                                                    name: m.name,
-                                                    model_data: general_purpose::STANDARD.encode(&m.model_data),
-                                                })
-                                                .collect();
+                                ClientMessage::GetAll => {
+                                    let models = on_db_thread(|| {
+                                        load_all_models().map(|models| models.into_iter().map(list_response).collect::<Vec<_>>())
+                                    })
+                                    .await;
+                                    match models {
+                                        Ok(response) => {
                                             let response_str = serde_json::to_string(&response).unwrap();
                                             if let Err(e) = write
                                                 .send(Message::Text(response_str.into()))
                                                 .await
                                             {
-                                                eprintln!("Send error: {:?}", e);
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Failed to load models: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::ListMetadata => {
+                                    // Everything clients list and sort by, without reading a single model's bytes
+                                    let models = on_db_thread(|| {
+                                        load_model_list().map(|models| models.into_iter().map(list_response).collect::<Vec<_>>())
+                                    })
+                                    .await;
+                                    match models {
+                                        Ok(response) => {
+                                            let response_str = serde_json::to_string(&response).unwrap();
+                                            if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Failed to load models: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Insert { model_data: base64_data, name, format, author, transform } => {
+                                    if let Err(e) = check_payload_size(&base64_data, server_config.max_upload_bytes) {
+                                        send_error(&mut write, Some(action), &e).await;
+                                        continue;
+                                    }
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let inserted = on_db_thread(move || insert_upload(base64_data, name, format, author, transform, &config, &tx)).await;
+                                    match inserted {
+                                        Ok(response) => {
+                                            if let Err(e) = write
+                                                .send(Message::Text(serde_json::to_string(&response).unwrap().into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
                                                 break;
                                             }
                                         }
                                         Err(e) => {
-                                            send_error(&mut write, &format!("Failed to load models: {}", e)).await;
-                                        }
-                                    }
-                                }
-                                "insert" => {
-                                    if let Some(base64_data) = request.model_data {
-                                        match general_purpose::STANDARD.decode(&base64_data) {
-                                            Ok(model_data) => {
-                                                match insert_model(&model_data, request.name.as_deref()) {
-                                                    Ok(new_id) => {
-                                                        let new_model = ModelResponse {
-                                                            id: new_id,
-                                                            name: request.name,
-                                                            model_data: base64_data,
-                                                        };
-                                                        let update = serde_json::to_string(&new_model).unwrap();
+                                            send_error(&mut write, Some(action), &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Validate { model_data, name, format } => {
+                                    let config = server_config.clone();
+                                    let validation = on_db_thread(move || validate_upload(model_data, name.as_deref(), format.as_deref(), &config)).await;
+                                    let response_str = serde_json::to_string(&ServerMessage::Validation { validation }).unwrap();
+                                    if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                                        warn!(error = %e, "send failed");
+                                        break;
+                                    }
+                                }
+                                ClientMessage::Delete { id } => {
+                                    let (config, tx, session_id) = (server_config.clone(), tx.clone(), session_id.clone());
+                                    let deleted = on_db_thread(move || {
+                                        delete_model(id).map(|layouts| {
+                                            info!(model_id = id, "model deleted");
+                                            let deleted = DeletedModel { id, session_id };
+                                            let update = serde_json::to_string(&ServerMessage::ModelDeleted { model_deleted: deleted }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                            // Clients showing a layout that placed the model drop it from there too
+                                            for layout_id in layouts {
+                                                match load_layout_by_id(layout_id) {
+                                                    Ok(layout) => {
+                                                        let update = serde_json::to_string(&ServerMessage::LayoutUpdated { layout_updated: layout }).unwrap();
                                                         if let Err(e) = tx.send(update) {
-                                                            eprintln!("Broadcast error: {:?}", e);
-                                                        }
-                                                        if let Err(e) = write
-                                                            .send(Message::Text(serde_json::to_string(&new_model).unwrap().into()))
-                                                            .await
-                                                        {
-                                                            eprintln!("Send error: {:?}", e);
-                                                            break;
+                                                            warn!(error = %e, "broadcast failed");
                                                         }
                                                     }
-                                                    Err(e) => {
-                                                        send_error(&mut write, &format!("Failed to insert model: {}", e)).await;
-                                                    }
+                                                    Err(e) => error!(layout_id, error = %e, "failed to load layout after delete"),
                                                 }
                                             }
-                                            Err(e) => {
-                                                send_error(&mut write, &format!("Invalid base64 data: {}", e)).await;
+                                            broadcast_storage(&config, &tx);
+                                        })
+                                    })
+                                    .await;
+                                    if let Err(e) = deleted {
+                                        send_request_error(&mut write, action, &named, &format!("Failed to delete model: {}", e)).await;
+                                    }
+                                }
+                                ClientMessage::Rename { id, version, name } => {
+                                    // A blank name clears it rather than listing the model as ""
+                                    let name = name.as_deref().map(str::trim).filter(|name| !name.is_empty()).map(str::to_string);
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let result = on_db_thread(move || {
+                                        update_model_name(id, version, name.as_deref())
+                                            .and_then(|written| broadcast_if_written(written, id, &config, &tx))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(true) => {}
+                                        Ok(false) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &format!("Failed to rename model {}: {}", id, e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetAccessCode { id, version, access_code, new_access_code } => {
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let result = on_db_thread(move || -> Result<bool, String> {
+                                        let model = load_model_by_id(id).map_err(|e| format!("Model not found: {}", e))?;
+                                        // Changing or clearing an existing code requires the current one
+                                        check_access(&model, access_code.as_deref())?;
+                                        let new_hash = new_access_code
+                                            .as_deref()
+                                            .filter(|code| !code.is_empty())
+                                            .map(|code| hash_access_code(id, code));
+                                        update_access_code(id, version, new_hash.as_deref())
+                                            .and_then(|written| broadcast_if_written(written, id, &config, &tx))
+                                            .map_err(|e| format!("Failed to set access code: {}", e))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(true) => {}
+                                        Ok(false) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetThumbnail { id, thumbnail_data: base64_image, access_code } => {
+                                    if let Err(e) = check_payload_size(&base64_image, server_config.max_upload_bytes) {
+                                        send_error(&mut write, Some(action), &e).await;
+                                        continue;
+                                    }
+                                    let image = decode_payload(base64_image).and_then(|image| match image::guess_format(&image) {
+                                        Ok(image::ImageFormat::Png) => Ok(image),
+                                        _ => Err("Thumbnails must be PNG images".to_string()),
+                                    });
+                                    match image {
+                                        Ok(image) => {
+                                            let stored = on_db_thread(move || {
+                                                load_model_by_id(id)
+                                                    .map_err(|e| format!("Model not found: {}", e))
+                                                    .and_then(|model| check_access(&model, access_code.as_deref()).map(|_| model))
+                                                    .and_then(|model| store_thumbnail(&model, &image).map_err(|e| format!("Failed to store thumbnail: {}", e)))
+                                            })
+                                            .await;
+                                            if let Err(e) = stored {
+                                                send_request_error(&mut write, action, &named, &e).await;
                                             }
                                         }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &e).await;
+                                        }
                                     }
                                 }
-                                "delete" => {
-                                    if let Some(id) = request.id {
-                                        match delete_model(id) {
-                                            Ok(()) => {
-                                                // Broadcast updated model list
-                                                match load_all_models() {
-                                                    Ok(models) => {
-                                                        let response: Vec<ModelResponse> = models
-                                                            .into_iter()
-                                                            .map(|m| ModelResponse {
-                                                                id: m.id,
-                                                                name: m.name,
-                                                                model_data: general_purpose::STANDARD.encode(&m.model_data),
-                                                            })
-                                                            .collect();
-                                                        let update = serde_json::to_string(&response).unwrap();
-                                                        if let Err(e) = tx.send(update) {
-                                                            eprintln!("Broadcast error: {:?}", e);
-                                                        }
-                                                        if let Err(e) = write
-                                                            .send(Message::Text(serde_json::to_string(&response).unwrap().into()))
-                                                            .await
-                                                        {
-                                                            eprintln!("Send error: {:?}", e);
-                                                            break;
-                                                        }
-                                                    }
-                                                    Err(e) => {
-                                                        send_error(&mut write, &format!("Failed to load models after delete: {}", e)).await;
-                                                    }
+                                ClientMessage::GetThumbnail { id, access_code } => {
+                                    let thumbnail = on_db_thread(move || {
+                                        load_model_by_id(id)
+                                            .map_err(|e| format!("Model not found: {}", e))
+                                            .and_then(|model| check_access(&model, access_code.as_deref()).map(|_| model))
+                                            .and_then(|model| load_thumbnail(&model).map_err(|e| format!("Failed to load thumbnail: {}", e)))
+                                    })
+                                    .await;
+                                    match thumbnail {
+                                        Ok(thumbnail) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Thumbnail { thumbnail }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::RegenerateThumbnail { id, access_code } => {
+                                    let invalidated = on_db_thread(move || {
+                                        load_model_by_id(id)
+                                            .map_err(|e| format!("Model not found: {}", e))
+                                            .and_then(|model| check_access(&model, access_code.as_deref()))
+                                            .and_then(|_| invalidate_thumbnail(id).map_err(|e| format!("Failed to invalidate thumbnail: {}", e)))
+                                    })
+                                    .await;
+                                    match invalidated {
+                                        Ok(()) => {
+                                            // Ask clients to render and upload a fresh thumbnail
+                                            let update = serde_json::to_string(&ServerMessage::ThumbnailInvalidated { thumbnail_invalidated: id }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SaveLayout { name, entries, template } => {
+                                    if name.trim().is_empty() {
+                                        send_error(&mut write, Some(action), "A layout needs a name").await;
+                                        continue;
+                                    }
+                                    let template = template.unwrap_or(false);
+                                    let result = on_db_thread(move || {
+                                        insert_layout(name.trim(), &entries, template)
+                                            .map_err(|e| format!("Failed to save layout: {}", e))
+                                            .and_then(|_| {
+                                                let summaries = if template { load_template_summaries() } else { load_layout_summaries() };
+                                                summaries.map_err(|e| format!("Failed to load layouts: {}", e))
+                                            })
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(summaries) => {
+                                            // Broadcast the updated layout or template list
+                                            let message = if template {
+                                                ServerMessage::Templates { templates: summaries }
+                                            } else {
+                                                ServerMessage::Layouts { layouts: summaries }
+                                            };
+                                            let update = serde_json::to_string(&message).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::CopyToScene { id, target_id } => {
+                                    // The layout references the existing model row, so nothing is re-uploaded
+                                    let result = on_db_thread(move || {
+                                        load_model_by_id(id)
+                                            .map_err(|e| format!("Model not found: {}", e))
+                                            .and_then(|_| add_model_to_layout(target_id, id)
+                                                .map_err(|e| format!("Failed to copy model to layout: {}", e)))
+                                            .and_then(|_| load_layout_by_id(target_id)
+                                                .map_err(|e| format!("Failed to load layout: {}", e)))
+                                            .and_then(|layout| load_layout_summaries()
+                                                .map(|layouts| (layout, layouts))
+                                                .map_err(|e| format!("Failed to load layouts: {}", e)))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok((layout, layouts)) => {
+                                            // Clients showing the target layout see the model appear; the rest ignore it
+                                            let update = serde_json::to_string(&ServerMessage::LayoutUpdated { layout_updated: layout }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                            let response_str = serde_json::to_string(&ServerMessage::Layouts { layouts }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::CopyTransform { id, source_id, target_id: layout_id } => {
+                                    let result = on_db_thread(move || {
+                                        load_model_by_id(id)
+                                            .map_err(|e| format!("Model not found: {}", e))
+                                            .and_then(|model| if model.transform_locked {
+                                                Err(format!("Model {} has a locked transform", id))
+                                            } else {
+                                                Ok(())
+                                            })
+                                            .and_then(|_| load_layout_by_id(layout_id).map_err(|e| format!("Layout not found: {}", e)))
+                                            .and_then(|layout| copy_entry_transform(layout, source_id, id))
+                                            .and_then(|layout| update_layout_entries(layout.id, &layout.entries)
+                                                .map(|_| layout)
+                                                .map_err(|e| format!("Failed to update layout: {}", e)))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(layout) => {
+                                            // Clients showing this layout move the model; the rest ignore it
+                                            let update = serde_json::to_string(&ServerMessage::LayoutUpdated { layout_updated: layout }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetTransform { target_id: layout_id, entries } => {
+                                    if entries.is_empty() {
+                                        send_error(&mut write, Some(action), "set_transform needs at least one entry").await;
+                                        continue;
+                                    }
+                                    let pending_writes = layout_writes.clone();
+                                    let result = on_db_thread(move || {
+                                        entries
+                                            .iter()
+                                            .try_for_each(|entry| load_model_by_id(entry.model_id)
+                                                .map_err(|e| format!("Model not found: {}", e))
+                                                .and_then(|model| if model.transform_locked {
+                                                    Err(format!("Model {} has a locked transform", entry.model_id))
+                                                } else {
+                                                    Ok(())
+                                                }))
+                                            .and_then(|_| load_layout_by_id(layout_id).map_err(|e| format!("Layout not found: {}", e)))
+                                            .map(|mut layout| {
+                                                // Build on edits still waiting to be written
+                                                if let Some(entries) = pending_writes.pending(layout.id) {
+                                                    layout.entries = entries;
+                                                }
+                                                layout
+                                            })
+                                            .and_then(|layout| set_entry_transforms(layout, entries))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(layout) => {
+                                            // Everyone sees the move now; the database catches up at the next flush
+                                            layout_writes.stage(layout.id, layout.entries.clone());
+                                            let update = serde_json::to_string(&ServerMessage::LayoutUpdated { layout_updated: layout }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Whoami => {
+                                    let whoami = SessionInfo {
+                                        session_id: session_id.clone(),
+                                        role: server_config.role.clone(),
+                                        allowed_actions: server_config
+                                            .permissions
+                                            .allowed_actions(&server_config.role)
+                                            .into_iter()
+                                            .filter(|action| !server_config.read_only || !actions::MUTATING.contains(action))
+                                            .map(str::to_string)
+                                            .collect(),
+                                    };
+                                    let response_str = serde_json::to_string(&ServerMessage::Whoami { whoami }).unwrap();
+                                    if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                                        warn!(error = %e, "send failed");
+                                        break;
+                                    }
+                                }
+                                ClientMessage::SetName { name } => {
+                                    if name.trim().chars().count() > presence::MAX_NAME_CHARS {
+                                        let error = format!("Display names are limited to {} characters", presence::MAX_NAME_CHARS);
+                                        send_error(&mut write, Some(action), &error).await;
+                                    } else if let Some(roster) = presence.rename(session_number, &name) {
+                                        broadcast_presence(roster, &tx);
+                                    }
+                                }
+                                ClientMessage::SetView { view } => {
+                                    if !valid_view(&view) {
+                                        send_error(&mut write, Some(action), "set_view needs a view with finite numbers and a positive radius").await;
+                                        continue;
+                                    }
+                                    if let Some(collaborator_view) = presence.set_view(session_number, view) {
+                                        let update = serde_json::to_string(&ServerMessage::CollaboratorView { collaborator_view }).unwrap();
+                                        if let Err(e) = tx.send(update) {
+                                            warn!(error = %e, "broadcast failed");
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetCompression { encoding } => {
+                                    match encoding.as_deref().filter(|encoding| !encoding.is_empty()) {
+                                        None => compressing.store(false, Ordering::Relaxed),
+                                        Some(GZIP_ENCODING) if compression => compressing.store(true, Ordering::Relaxed),
+                                        Some(encoding) => {
+                                            send_error(&mut write, Some(action), &format!("Unsupported message encoding: {}", encoding)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::AddComment { id, author, text } => {
+                                    let text = text.trim();
+                                    let author = author.as_deref().map(str::trim).filter(|a| !a.is_empty()).unwrap_or("Anonymous");
+                                    if text.is_empty() {
+                                        send_error(&mut write, Some(action), "A comment needs some text").await;
+                                    } else if text.chars().count() > MAX_COMMENT_CHARS {
+                                        send_error(&mut write, Some(action), &format!("Comments are limited to {} characters", MAX_COMMENT_CHARS)).await;
+                                    } else {
+                                        let (author, text) = (author.to_string(), text.to_string());
+                                        let result = on_db_thread(move || {
+                                            load_model_by_id(id)
+                                                .map_err(|e| format!("Model not found: {}", e))
+                                                .and_then(|_| insert_comment(id, &author, &text)
+                                                    .map_err(|e| format!("Failed to add comment: {}", e)))
+                                        })
+                                        .await;
+                                        match result {
+                                            Ok(comment) => {
+                                                // Every client gets it; each shows only the thread it has open
+                                                let update = serde_json::to_string(&ServerMessage::NewComment { comment }).unwrap();
+                                                if let Err(e) = tx.send(update) {
+                                                    warn!(error = %e, "broadcast failed");
                                                 }
                                             }
                                             Err(e) => {
-                                                send_error(&mut write, &format!("Failed to delete model: {}", e)).await;
+                                                send_request_error(&mut write, action, &named, &e).await;
                                             }
                                         }
                                     }
                                 }
-                                _ => eprintln!("Unknown action: {}", request.action),
-                            }
-                        }
-                        Err(e) => eprintln!("Failed to parse request: {}", e),
-                    }
-                } else if let Message::Ping(data) = message {
-                    if let Err(e) = write.send(Message::Pong(data)).await {
-                        eprintln!("Send pong error: {:?}", e);
-                        break;
-                    }
-                } else if let Message::Close(_) = message {
-                    break;
-                }
-            }
-            Ok(update) = rx.recv() => {
-                if let Err(e) = write.send(Message::Text(update.into())).await {
-                    eprintln!("Forward error: {:?}", e);
-                    break;
-                }
-            }
-            else => {
-                break;
-            }
-        }
-    }
-}
-
-async fn send_error<S>(write: &mut S, message: &str)
-where
-    S: SinkExt<Message> + Unpin,
-    <S as futures_util::Sink<Message>>::Error: std::fmt::Debug,
-{
-    let error_response = serde_json::to_string(&serde_json::json!({ "error": message })).unwrap();
-    if let Err(e) = write.send(Message::Text(error_response.into())).await {
-        eprintln!("Error sending error: {:?}", e);
-    }
+                                ClientMessage::ListComments { id } => {
+                                    match on_db_thread(move || load_comments(id)).await {
+                                        Ok(comments) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Comments { model_id: id, comments }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &format!("Failed to load comments: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::GetMetadata { id } => {
+                                    match on_db_thread(move || load_metadata(id)).await {
+                                        Ok(metadata) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Metadata { model_id: id, metadata }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &format!("Failed to load metadata: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetMetadata { id, version, metadata } => {
+                                    let (config, updates) = (server_config.clone(), tx.clone());
+                                    let result = on_db_thread(move || {
+                                        clean_metadata(metadata).and_then(|metadata| {
+                                            update_metadata(id, version, &metadata)
+                                                .and_then(|written| broadcast_if_written(written, id, &config, &updates))
+                                                .map(|written| written.then_some(metadata))
+                                                .map_err(|e| format!("Failed to update metadata for model {}: {}", id, e))
+                                        })
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(Some(metadata)) => {
+                                            // Every client gets it; each shows only the model it has open
+                                            let update = serde_json::to_string(&ServerMessage::Metadata { model_id: id, metadata }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                        }
+                                        Ok(None) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::SetTransformLock { id, version, locked } => {
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let result = on_db_thread(move || {
+                                        update_transform_lock(id, version, locked)
+                                            .and_then(|written| broadcast_if_written(written, id, &config, &tx))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(true) => {}
+                                        Ok(false) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &format!("Failed to update transform lock for model {}: {}", id, e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Transform { id, version, transform } => {
+                                    if transform.is_some_and(|transform| !valid_transform(&transform)) {
+                                        send_error(&mut write, Some(action), &format!("Placement of model {} has a non-finite value", id)).await;
+                                        continue;
+                                    }
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let result = on_db_thread(move || -> Result<bool, String> {
+                                        let model = load_model_by_id(id).map_err(|e| format!("Model not found: {}", e))?;
+                                        // Says why up front; the update itself also skips locked models
+                                        if model.transform_locked && model.version == version {
+                                            return Err(format!("Model {} has a locked transform", id));
+                                        }
+                                        update_model_transform(id, version, transform.as_ref())
+                                            .and_then(|written| broadcast_if_written(written, id, &config, &tx))
+                                            .map_err(|e| format!("Failed to place model {}: {}", id, e))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(true) => {}
+                                        Ok(false) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::History { id } => {
+                                    match on_db_thread(move || load_model_history(id)).await {
+                                        Ok(history) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::History { model_id: id, history }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &format!("Failed to load history: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Revert { id, version, restore_version: restored, access_code } => {
+                                    let (config, tx) = (server_config.clone(), tx.clone());
+                                    let reverted = on_db_thread(move || revert_model(id, version, restored, access_code.as_deref(), &config, &tx)).await;
+                                    match reverted {
+                                        Ok(true) => {}
+                                        Ok(false) => send_stale_version(&mut write, id, action).await,
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::ExportObj { id, name, access_code } => {
+                                    // The names become file names on the client, so keep them to safe characters
+                                    let base_name: String = name
+                                        .as_deref()
+                                        .unwrap_or("")
+                                        .trim()
+                                        .chars()
+                                        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                                        .collect();
+                                    let exported = on_db_thread(move || -> Result<ObjExport, String> {
+                                        let model = load_model_by_id(id).map_err(|e| format!("Model not found: {}", e))?;
+                                        check_access(&model, access_code.as_deref())?;
+                                        if model.format != "gltf" {
+                                            return Err(format!("Model {} is {}; only glTF models can be exported as OBJ", id, model.format));
+                                        }
+                                        let base_name = if base_name.is_empty() { "model" } else { base_name.as_str() };
+                                        let export = obj::export_obj(&model.model_data, base_name)
+                                            .map_err(|e| format!("Failed to export model {} as OBJ: {}", id, e))?;
+                                        Ok(ObjExport {
+                                            model_id: id,
+                                            obj: export.obj,
+                                            mtl: export.mtl,
+                                            textures: export
+                                                .textures
+                                                .into_iter()
+                                                .map(|(file_name, data)| ExportedTexture { file_name, data: general_purpose::STANDARD.encode(data) })
+                                                .collect(),
+                                        })
+                                    })
+                                    .await;
+                                    match exported {
+                                        Ok(obj_export) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::ObjExport { obj_export }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_request_error(&mut write, action, &named, &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::GetMany { ids, lod } => {
+                                    let loaded = on_db_thread(move || {
+                                        load_models_by_ids(&ids).map(|models| {
+                                            let missing: Vec<i32> = ids
+                                                .iter()
+                                                .filter(|id| !models.iter().any(|model| model.id == **id))
+                                                .copied()
+                                                .collect();
+                                            // Protected bytes are withheld as in get_all; unlock them with get_by_id
+                                            let models: Vec<ModelResponse> = models
+                                                .into_iter()
+                                                .map(|mut model| {
+                                                    let lod = if model.access_code_hash.is_none() {
+                                                        serve_lod(&mut model, lod.as_deref())
+                                                    } else {
+                                                        "full"
+                                                    };
+                                                    ModelResponse { lod: lod.to_string(), ..list_response(model) }
+                                                })
+                                                .collect();
+                                            (models, missing)
+                                        })
+                                    })
+                                    .await;
+                                    match loaded {
+                                        Ok((models, missing)) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::ManyModels { models, missing }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Failed to load models: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::ListLayouts => {
+                                    match on_db_thread(load_layout_summaries).await {
+                                        Ok(layouts) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Layouts { layouts }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Failed to load layouts: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::ListTemplates => {
+                                    match on_db_thread(load_template_summaries).await {
+                                        Ok(templates) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Templates { templates }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Failed to load templates: {}", e)).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::InstantiateTemplate { id, name } => {
+                                    // The copy is an ordinary layout; later edits never touch the template
+                                    let result = on_db_thread(move || {
+                                        load_template_summaries()
+                                            .map_err(|e| format!("Failed to load templates: {}", e))
+                                            .and_then(|templates| templates
+                                                .into_iter()
+                                                .find(|template| template.id == id)
+                                                .ok_or_else(|| format!("Template {} not found", id)))
+                                            .and_then(|_| load_layout_by_id(id).map_err(|e| format!("Template not found: {}", e)))
+                                            .and_then(|template| {
+                                                let name = name
+                                                    .as_deref()
+                                                    .map(str::trim)
+                                                    .filter(|name| !name.is_empty())
+                                                    .map_or_else(|| template.name.clone(), str::to_string);
+                                                insert_layout(&name, &template.entries, false)
+                                                    .map(|layout_id| LayoutResponse { id: layout_id, name, entries: template.entries })
+                                                    .map_err(|e| format!("Failed to create layout: {}", e))
+                                            })
+                                            .and_then(|layout| load_layout_summaries()
+                                                .map(|layouts| (layout, layouts))
+                                                .map_err(|e| format!("Failed to load layouts: {}", e)))
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok((layout, layouts)) => {
+                                            let update = serde_json::to_string(&ServerMessage::Layouts { layouts }).unwrap();
+                                            if let Err(e) = tx.send(update) {
+                                                warn!(error = %e, "broadcast failed");
+                                            }
+                                            // The requester starts working in the new layout right away
+                                            let response_str = serde_json::to_string(&ServerMessage::Layout { layout }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &e).await;
+                                        }
+                                    }
+                                }
+                                ClientMessage::LoadLayout { id } => {
+                                    match on_db_thread(move || load_layout_by_id(id)).await {
+                                        Ok(layout) => {
+                                            let response_str = serde_json::to_string(&ServerMessage::Layout { layout }).unwrap();
+                                            if let Err(e) = write
+                                                .send(Message::Text(response_str.into()))
+                                                .await
+                                            {
+                                                warn!(error = %e, "send failed");
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            send_error(&mut write, Some(action), &format!("Layout not found: {}", e)).await;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            send_error(&mut write, e.action.as_deref(), &e.message).await;
+                        }
+                    }
+                } else if let Message::Binary(_) = message {
+                    send_error(&mut write, None, "Binary messages are not supported").await;
+                } else if let Message::Ping(data) = message {
+                    if let Err(e) = write.send(Message::Pong(data)).await {
+                        warn!(error = %e, "send failed");
+                        break;
+                    }
+                } else if let Message::Close(_) = message {
+                    break;
+                }
+            }
+            update = rx.recv() => match update {
+                Ok(update) => {
+                    if let Err(e) = write.send(Message::Text(update.into())).await {
+                        warn!(error = %e, "forwarding broadcast failed");
+                        break;
+                    }
+                }
+                // The missed broadcasts are gone, so the whole list brings the client back in step
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "client fell behind on broadcasts, resending the model list");
+                    let models = on_db_thread(|| {
+                        load_model_list().map(|models| models.into_iter().map(list_response).collect::<Vec<_>>())
+                    })
+                    .await;
+                    match models {
+                        Ok(models) => {
+                            let response_str = serde_json::to_string(&ServerMessage::Models(models)).unwrap();
+                            if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                                warn!(error = %e, "send failed");
+                                break;
+                            }
+                        }
+                        Err(e) => error!(error = %e, "failed to load models to resync a client"),
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            else => {
+                break;
+            }
+        }
+    }
+    broadcast_presence(presence.leave(session_number), &tx);
+    info!("client disconnected");
+}
+
+// First of the named models that doesn't exist
+fn missing_model(ids: &[i32]) -> rusqlite::Result<Option<i32>> {
+    for &id in ids {
+        if !model_exists(id)? {
+            return Ok(Some(id));
+        }
+    }
+    Ok(None)
+}
+
+// A request whose model was deleted after the check above gets the answer the check would
+// have given, rather than whatever error the vanished row caused
+async fn send_request_error<S>(write: &mut S, action: &str, models: &[i32], message: &str)
+where
+    S: SinkExt<Message> + Unpin,
+    <S as futures_util::Sink<Message>>::Error: std::fmt::Debug,
+{
+    let models = models.to_vec();
+    if let Ok(Some(id)) = on_db_thread(move || missing_model(&models)).await {
+        let response_str = serde_json::to_string(&ServerMessage::model_not_found(id)).unwrap();
+        if let Err(e) = write.send(Message::Text(response_str.into())).await {
+            warn!(error = ?e, "send failed");
+        }
+        return;
+    }
+    send_error(write, Some(action), message).await;
+}
+
+// Large text messages go out as gzip behind GZIP_MESSAGE_PREFIX once the client has opted in
+fn compress_message(message: Message, compress: bool) -> Message {
+    match message {
+        Message::Text(text) if compress && text.len() >= COMPRESS_MIN_BYTES => {
+            let mut encoder = GzEncoder::new(vec![GZIP_MESSAGE_PREFIX], Compression::default());
+            match encoder.write_all(text.as_bytes()).and_then(|_| encoder.finish()) {
+                Ok(bytes) => Message::Binary(bytes.into()),
+                Err(e) => {
+                    warn!(error = %e, "failed to compress message, sending it as text");
+                    Message::Text(text)
+                }
+            }
+        }
+        message => message,
+    }
+}
+
+// `action` is the request that failed, when the message could be parsed that far
+async fn send_error<S>(write: &mut S, action: Option<&str>, message: &str)
+where
+    S: SinkExt<Message> + Unpin,
+    <S as futures_util::Sink<Message>>::Error: std::fmt::Debug,
+{
+    info!(action, error = message, "request failed");
+    let error = ServerMessage::Error { error: message.to_string(), action: action.map(str::to_string) };
+    let error_response = serde_json::to_string(&error).unwrap();
+    if let Err(e) = write.send(Message::Text(error_response.into())).await {
+        warn!(error = ?e, "send failed");
+    }
+}
+
+// Answers an edit that lost the race with the version it should have been made against
+async fn send_stale_version<S>(write: &mut S, id: i32, action: &str)
+where
+    S: SinkExt<Message> + Unpin,
+    <S as futures_util::Sink<Message>>::Error: std::fmt::Debug,
+{
+    match on_db_thread(move || model_version(id)).await {
+        Ok(version) => {
+            let response_str = serde_json::to_string(&ServerMessage::stale_version(id, version, action)).unwrap();
+            if let Err(e) = write.send(Message::Text(response_str.into())).await {
+                warn!(error = ?e, "send failed");
+            }
+        }
+        Err(e) => send_error(write, Some(action), &format!("Failed to look up model {}: {}", id, e)).await,
+    }
 }
 
-fn init_db() -> Result<Connection> {
-    let conn = Connection::open("models.db")?;
-    // Migration: Add Name column if it doesn't exist
-    conn.execute(
-        "ALTER TABLE models ADD COLUMN Name TEXT",
-        params![],
-    )
-    .unwrap_or_else(|e| {
-        if !e.to_string().contains("duplicate column name") {
-            panic!("Failed to add Name column: {}", e);
+// The stored format comes from the bytes; the uploader's label is only checked against it
+fn detect_format(model_data: &[u8], labeled: Option<&str>, name: Option<&str>) -> std::result::Result<&'static str, String> {
+    let detected = formats::sniff_format(model_data)
+        .ok_or("Unrecognized model file; upload glTF, GLB or PLY".to_string())?;
+    let format = detected
+        .stored_name()
+        .ok_or_else(|| format!("{} models aren't supported yet; convert to glTF or PLY", detected.label()))?;
+    if let Some(labeled) = labeled && labeled != format {
+        info!(name, labeled, detected = detected.label(), format, "upload was mislabeled");
+    }
+    Ok(format)
+}
+
+// An upload as insert stores it: format detected from the bytes, textures checked and scaled
+// down if configured, detail levels generated
+struct PreparedUpload {
+    model_data: Vec<u8>, // what is stored and served
+    original: Option<Vec<u8>>, // the upload as received, when its textures were scaled down
+    format: &'static str,
+    warnings: Vec<String>, // texture problems the model is stored with anyway
+    lods: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl PreparedUpload {
+    // Bytes it would take from the storage quota
+    fn stored_size(&self) -> u64 {
+        (self.model_data.len()
+            + self.original.as_ref().map_or(0, Vec::len)
+            + self.lods.iter().map(|(_, data)| data.len()).sum::<usize>()) as u64
+    }
+
+    fn texture_bytes_saved(&self) -> Option<u64> {
+        self.original.as_ref().map(|original| (original.len() - self.model_data.len()) as u64)
+    }
+}
+
+fn prepare_upload(
+    model_data: Vec<u8>,
+    name: Option<&str>,
+    labeled: Option<&str>,
+    config: &ServerConfig,
+) -> Result<PreparedUpload, String> {
+    let format = detect_format(&model_data, labeled, name)?;
+    // Broken glTF would be stored fine and then show as an empty scene
+    if format == "gltf" {
+        formats::validate_gltf(&model_data)?;
+    }
+    let warnings = if format == "gltf" {
+        textures::texture_warnings(&model_data)
+    } else {
+        vec![]
+    };
+    for warning in &warnings {
+        warn!(name, warning = warning.as_str(), "texture problem in upload");
+    }
+    // The slimmed model is stored and served; the upload is kept alongside it
+    let downscaled = config
+        .max_texture_size
+        .filter(|_| format == "gltf")
+        .and_then(|max| textures::downscale_textures(&model_data, max));
+    let (model_data, original) = match downscaled {
+        Some((slimmed, count)) => {
+            info!(
+                name,
+                count,
+                max_size = config.max_texture_size.unwrap_or_default(),
+                bytes_before = model_data.len(),
+                bytes_after = slimmed.len(),
+                "downscaled textures"
+            );
+            (slimmed, Some(model_data))
         }
-        0
+        None => (model_data, None),
+    };
+    let lods = if format == "gltf" {
+        lod::generate_lods(&model_data)
+    } else {
+        vec![]
+    };
+    Ok(PreparedUpload { model_data, original, format, warnings, lods })
+}
+
+// Stores an upload and announces it, answering the uploader with its own view of the new model
+fn insert_upload(
+    base64_data: String,
+    name: Option<String>,
+    labeled: Option<String>,
+    author: Option<String>,
+    transform: Option<ModelTransform>,
+    config: &ServerConfig,
+    tx: &Sender<String>,
+) -> Result<ModelResponse, String> {
+    if transform.is_some_and(|transform| !valid_transform(&transform)) {
+        return Err("The placement has a non-finite value".to_string());
+    }
+    let model_data = decode_payload(base64_data)?;
+    let upload = prepare_upload(model_data, name.as_deref(), labeled.as_deref(), config)?;
+    check_quota(config, upload.stored_size())?;
+    let texture_warnings = !upload.warnings.is_empty();
+    let texture_bytes_saved = upload.texture_bytes_saved();
+    let PreparedUpload { model_data, original, format, lods, .. } = upload;
+    let format = format.to_string();
+    let created_by = author.as_deref().map(str::trim).filter(|a| !a.is_empty()).unwrap_or("Anonymous");
+    let created_at = unix_time();
+    let new_id = insert_model(&model_data, name.as_deref(), &format, texture_warnings, created_by, created_at, transform.as_ref())
+        .and_then(|new_id| insert_model_lods(new_id, &lods).map(|_| new_id))
+        .and_then(|new_id| match &original {
+            Some(original) => insert_model_original(new_id, original).map(|_| new_id),
+            None => Ok(new_id),
+        })
+        .map_err(|e| format!("Failed to insert model: {}", e))?;
+    info!(model_id = new_id, bytes = model_data.len(), format = format.as_str(), "model uploaded");
+    let levels: Vec<String> = lods.iter().map(|(level, _)| level.to_string()).collect();
+    let new_model = ModelResponse {
+        id: new_id,
+        name,
+        // Encoded afresh, as the upload's own base64 was decoded in place
+        model_data: general_purpose::STANDARD.encode(&model_data),
+        protected: false,
+        format,
+        texture_warnings,
+        lods: available_lods(&levels),
+        lod: "full".to_string(),
+        warning: None,
+        texture_bytes_saved: None,
+        metadata: BTreeMap::new(),
+        transform_locked: false,
+        created_by: created_by.to_string(),
+        version: 1,
+        created_at,
+        updated_at: created_at,
+        data_replaced: false,
+        transform,
+    };
+    let model_updated = broadcast_response(new_model.clone(), config);
+    let update = serde_json::to_string(&ServerMessage::ModelUpdated { model_updated }).unwrap();
+    if let Err(e) = tx.send(update) {
+        warn!(error = %e, "broadcast failed");
+    }
+    let storage = broadcast_storage(config, tx);
+    // Only the uploader hears about the soft quota on its own insert
+    let warning = storage.filter(|storage| storage.nearing_capacity).map(|storage| {
+        format!(
+            "Server storage is {}% full ({} of {})",
+            storage.used_bytes * 100 / storage.quota_bytes.max(1),
+            format_size(storage.used_bytes as usize),
+            format_size(storage.quota_bytes as usize)
+        )
     });
-    // Create table with new schema
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS models (
-            id INTEGER PRIMARY KEY,
-            Name TEXT,
-            model_data BLOB NOT NULL
-        )",
-        params![],
-    )?;
-    Ok(conn)
-}
-
-fn load_model_by_id(model_id: i32) -> Result<ModelData> {
-    let conn = init_db()?;
-    let mut stmt = conn.prepare("SELECT id, Name, model_data FROM models WHERE id = ?1")?;
-    let model_data = stmt.query_row(params![model_id], |row| {
-        Ok(ModelData {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            model_data: row.get(2)?,
+    Ok(ModelResponse { warning, texture_bytes_saved, ..new_model })
+}
+
+// Runs an upload through everything insert checks and reports the outcome instead of storing it
+fn validate_upload(model_data: Option<String>, name: Option<&str>, format: Option<&str>, config: &ServerConfig) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let model_data = match model_data {
+        None => Err("No model data to validate".to_string()),
+        Some(base64_data) => check_payload_size(&base64_data, config.max_upload_bytes).and_then(|_| decode_payload(base64_data)),
+    };
+    match model_data.and_then(|model_data| {
+        report.stats.upload_bytes = model_data.len() as u64;
+        prepare_upload(model_data, name, format, config)
+    }) {
+        Ok(upload) => {
+            let levels: Vec<String> = upload.lods.iter().map(|(level, _)| level.to_string()).collect();
+            report.stats = UploadStats {
+                format: Some(upload.format.to_string()),
+                upload_bytes: report.stats.upload_bytes,
+                stored_bytes: upload.stored_size(),
+                texture_bytes_saved: upload.texture_bytes_saved(),
+                lods: available_lods(&levels),
+            };
+            if let Err(e) = check_quota(config, upload.stored_size()) {
+                report.errors.push(e);
+            }
+            report.warnings = upload.warnings;
+        }
+        Err(e) => report.errors.push(e),
+    }
+    report.valid = report.errors.is_empty();
+    report
+}
+
+// Largest request that can carry an upload at the size limit: the base64 payload and the rest of the JSON
+fn max_request_bytes(config: &ServerConfig) -> usize {
+    config.max_upload_bytes.div_ceil(3) * 4 + 64 * 1024
+}
+
+// Rejects oversized base64 payloads from their length alone, before decoding allocates anything
+fn check_payload_size(base64_data: &str, max_bytes: usize) -> std::result::Result<(), String> {
+    let decoded_len = base64_data.len() / 4 * 3;
+    if decoded_len > max_bytes {
+        return Err(format!(
+            "Payload of {} exceeds the {} limit",
+            format_size(decoded_len),
+            format_size(max_bytes)
+        ));
+    }
+    Ok(())
+}
+
+// Decodes a base64 payload into its own buffer a chunk at a time. The decoded bytes are shorter
+// than what they're decoded from, so they never overtake the unread input, and an upload never
+// needs room for both copies at once.
+fn decode_payload(base64_data: String) -> std::result::Result<Vec<u8>, String> {
+    let mut buffer = base64_data.into_bytes();
+    let mut chunk = vec![0; DECODE_CHUNK_BYTES / 4 * 3];
+    let mut written = 0;
+    for start in (0..buffer.len()).step_by(DECODE_CHUNK_BYTES) {
+        let end = (start + DECODE_CHUNK_BYTES).min(buffer.len());
+        let input = &buffer[start..end];
+        // Padding is only valid at the very end, not at the end of any chunk before it
+        if let Some(offset) = input.iter().position(|&byte| byte == b'=').filter(|_| end < buffer.len()) {
+            return Err(format!("Invalid base64 data: {}", DecodeError::InvalidByte(start + offset, b'=')));
+        }
+        let decoded = general_purpose::STANDARD.decode_slice(input, &mut chunk).map_err(|e| {
+            let e = match e {
+                DecodeSliceError::DecodeError(DecodeError::InvalidByte(offset, byte)) => DecodeError::InvalidByte(start + offset, byte),
+                DecodeSliceError::DecodeError(DecodeError::InvalidLength(length)) => DecodeError::InvalidLength(start + length),
+                DecodeSliceError::DecodeError(DecodeError::InvalidLastSymbol(offset, byte)) => DecodeError::InvalidLastSymbol(start + offset, byte),
+                DecodeSliceError::DecodeError(e) => e,
+                // The chunk buffer holds everything a full chunk decodes to
+                DecodeSliceError::OutputSliceTooSmall => unreachable!("decode chunk too small"),
+            };
+            format!("Invalid base64 data: {}", e)
+        })?;
+        buffer[written..written + decoded].copy_from_slice(&chunk[..decoded]);
+        written += decoded;
+    }
+    buffer.truncate(written);
+    buffer.shrink_to_fit();
+    Ok(buffer)
+}
+
+// Writes a consistent snapshot of the database and removes all but the newest `keep` snapshots
+fn backup_database(backup: &BackupConfig) -> std::result::Result<PathBuf, String> {
+    std::fs::create_dir_all(&backup.dir).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = backup.dir.join(format!("models-{}.db", timestamp));
+    if path.exists() {
+        std::fs::remove_file(&path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+    }
+    let conn = open_db().map_err(|e| format!("Failed to open database: {}", e))?;
+    conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+        .map_err(|e| format!("Failed to write snapshot: {}", e))?;
+    rotate_backups(&backup.dir, backup.keep)?;
+    Ok(path)
+}
+
+fn rotate_backups(dir: &Path, keep: usize) -> std::result::Result<(), String> {
+    let mut snapshots: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to list backups: {}", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("models-") && name.ends_with(".db"))
         })
-    })?;
-    Ok(model_data)
-}
-
-fn load_all_models() -> Result<Vec<ModelData>> {
-    let conn = init_db()?;
-    let mut stmt = conn.prepare("SELECT id, Name, model_data FROM models")?;
-    let model_iter = stmt.query_map(params![], |row| {
-        Ok(ModelData {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            model_data: row.get(2)?,
+        .collect();
+    // Timestamps have the same width, so name order is age order
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for old in &snapshots[..excess] {
+        if let Err(e) = std::fs::remove_file(old) {
+            warn!(path = %old.display(), error = %e, "failed to remove old backup");
+        }
+    }
+    Ok(())
+}
+
+// Access codes are salted with the model id so equal codes on different models hash differently
+fn hash_access_code(model_id: i32, code: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{}:{}", model_id, code).as_bytes()))
+}
+
+fn check_access(model: &ModelData, access_code: Option<&str>) -> std::result::Result<(), String> {
+    match (&model.access_code_hash, access_code) {
+        (None, _) => Ok(()),
+        (Some(hash), Some(code)) if hash_access_code(model.id, code) == *hash => Ok(()),
+        (Some(_), Some(_)) => Err(format!("Invalid access code for model {}", model.id)),
+        (Some(_), None) => Err(format!("Access code required for model {}", model.id)),
+    }
+}
+
+// Re-reads the catalog every `interval` and broadcasts what changed since the last read, one
+// model at a time like the handlers do
+async fn poll_models(interval: Duration, config: Arc<ServerConfig>, tx: Sender<String>) {
+    let mut last_models: Option<HashMap<i32, ModelResponse>> = None;
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        // Bytes only matter when broadcasts carry them
+        let read_config = config.clone();
+        let models = on_db_thread(move || {
+            let models = if read_config.broadcast_model_data { load_all_models() } else { load_model_list() };
+            models.map(|models| {
+                models
+                    .into_iter()
+                    .map(|model| (model.id, broadcast_response(list_response(model), &read_config)))
+                    .collect::<HashMap<i32, ModelResponse>>()
+            })
         })
-    })?;
-    let mut models = Vec::new();
-    for model in model_iter {
-        models.push(model?);
+        .await;
+        let current_models = match models {
+            Ok(models) => models,
+            Err(e) => {
+                error!(error = %e, "failed to poll models");
+                continue;
+            }
+        };
+        // The first read only sets the baseline; clients get the catalog from get_all
+        if let Some(last_models) = &last_models {
+            let mut updates = vec![];
+            for id in last_models.keys().filter(|id| !current_models.contains_key(id)) {
+                // No connection made this change, so no client takes it for its own
+                let model_deleted = DeletedModel { id: *id, session_id: String::new() };
+                updates.push(ServerMessage::ModelDeleted { model_deleted });
+            }
+            for (id, model) in &current_models {
+                if last_models.get(id) != Some(model) {
+                    updates.push(ServerMessage::ModelUpdated { model_updated: model.clone() });
+                }
+            }
+            for update in updates {
+                if let Err(e) = tx.send(serde_json::to_string(&update).unwrap()) {
+                    warn!(error = %e, "broadcast failed");
+                }
+            }
+        }
+        last_models = Some(current_models);
     }
-    Ok(models)
 }
 
-fn insert_model(model_data: &[u8], name: Option<&str>) -> Result<i32> {
-    let conn = init_db()?;
-    conn.execute("INSERT INTO models (Name, model_data) VALUES (?1, ?2)", params![name, model_data])?;
-    Ok(conn.last_insert_rowid() as i32)
+// Announces a versioned edit, which also tells every client the model's new version
+fn broadcast_if_written(written: bool, id: i32, config: &ServerConfig, tx: &Sender<String>) -> rusqlite::Result<bool> {
+    if written {
+        broadcast_model_update(id, config, tx)?;
+    }
+    Ok(written)
 }
 
-fn delete_model(model_id: i32) -> Result<()> {
-    let conn = init_db()?;
-    let rows_affected = conn.execute("DELETE FROM models WHERE id = ?1", params![model_id])?;
-    if rows_affected == 0 {
-        return Err(rusqlite::Error::QueryReturnedNoRows);
+// Sends the model's new list entry to every client, the caller included
+fn broadcast_model_update(id: i32, config: &ServerConfig, tx: &Sender<String>) -> rusqlite::Result<()> {
+    let model_updated = broadcast_response(list_response(load_model_by_id(id)?), config);
+    if let Err(e) = tx.send(serde_json::to_string(&ServerMessage::ModelUpdated { model_updated }).unwrap()) {
+        warn!(error = %e, "broadcast failed");
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Puts back a recorded version and announces it, returning false when the edit was stale.
+// Restored bytes get fresh detail levels and a new thumbnail, as after an admin reload.
+fn revert_model(
+    id: i32,
+    version: i64,
+    restored: i64,
+    access_code: Option<&str>,
+    config: &ServerConfig,
+    tx: &Sender<String>,
+) -> Result<bool, String> {
+    let model = load_model_by_id(id).map_err(|e| format!("Model not found: {}", e))?;
+    // Protected bytes may only be swapped by someone who could fetch them
+    check_access(&model, access_code)?;
+    let snapshot = load_version_snapshot(id, restored)
+        .map_err(|e| format!("Failed to load version {} of model {}: {}", restored, id, e))?
+        .ok_or(format!("Model {} has no recorded version {}", id, restored))?;
+    let data_replaced = snapshot.model_data != model.model_data;
+    let lods = data_replaced.then(|| lod::generate_lods(&snapshot.model_data));
+    let failed = |e: rusqlite::Error| format!("Failed to revert model {}: {}", id, e);
+    if !restore_model_version(id, version, restored, &snapshot, lods.as_deref()).map_err(failed)? {
+        return Ok(false);
+    }
+    info!(model_id = id, restored, data_replaced, bytes = snapshot.model_data.len(), "model reverted");
+    let model_updated = ModelResponse {
+        data_replaced,
+        ..broadcast_response(list_response(load_model_by_id(id).map_err(failed)?), config)
+    };
+    let metadata = load_metadata(id).map_err(failed)?;
+    let mut updates = vec![ServerMessage::ModelUpdated { model_updated }, ServerMessage::Metadata { model_id: id, metadata }];
+    if data_replaced {
+        updates.push(ServerMessage::ThumbnailInvalidated { thumbnail_invalidated: id });
+    }
+    for update in updates {
+        if let Err(e) = tx.send(serde_json::to_string(&update).unwrap()) {
+            warn!(error = %e, "broadcast failed");
+        }
+    }
+    if data_replaced {
+        broadcast_storage(config, tx);
+    }
+    Ok(true)
+}
+
+fn valid_transform(transform: &ModelTransform) -> bool {
+    transform.translation.iter().chain(&transform.rotation).chain(&transform.scale).all(|value| value.is_finite())
+}
+
+fn valid_view(view: &ViewState) -> bool {
+    view.focus.iter().chain([&view.yaw, &view.pitch, &view.radius]).all(|value| value.is_finite()) && view.radius > 0.0
+}
+
+fn broadcast_presence(presence: Vec<Collaborator>, tx: &Sender<String>) {
+    // Fails only when nobody is connected, leaving no one to tell
+    let _ = tx.send(serde_json::to_string(&ServerMessage::Presence { presence }).unwrap());
+}
+
+// Builds the list entry for a model, withholding the bytes of protected models
+fn list_response(model: ModelData) -> ModelResponse {
+    let protected = model.access_code_hash.is_some();
+    ModelResponse {
+        id: model.id,
+        name: model.name,
+        model_data: if protected {
+            String::new()
+        } else {
+            general_purpose::STANDARD.encode(&model.model_data)
+        },
+        protected,
+        format: model.format,
+        texture_warnings: model.texture_warnings,
+        lods: available_lods(&model.lods),
+        lod: "full".to_string(),
+        warning: None,
+        texture_bytes_saved: None,
+        metadata: BTreeMap::new(),
+        transform_locked: model.transform_locked,
+        created_by: model.created_by,
+        version: model.version,
+        created_at: model.created_at,
+        updated_at: model.updated_at,
+        data_replaced: false,
+        transform: model.transform,
+    }
+}
+
+// Broadcasts go to every client, so unless BROADCAST_MODEL_DATA is set they leave out the
+// bytes; clients fetch the models they actually show with get_many
+fn broadcast_response(mut response: ModelResponse, config: &ServerConfig) -> ModelResponse {
+    if !config.broadcast_model_data {
+        response.model_data.clear();
+    }
+    response
+}
+
+// Trims keys and values and enforces the size limits; empty keys are an error
+fn clean_metadata(metadata: BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err(format!("Models can have at most {} metadata fields", MAX_METADATA_ENTRIES));
+    }
+    let mut cleaned = BTreeMap::new();
+    for (key, value) in metadata {
+        let key = key.trim();
+        if key.is_empty() {
+            return Err("Metadata keys can't be empty".to_string());
+        }
+        if key.chars().count() > MAX_METADATA_KEY_CHARS || value.chars().count() > MAX_METADATA_VALUE_CHARS {
+            return Err(format!(
+                "Metadata keys are limited to {} characters and values to {}",
+                MAX_METADATA_KEY_CHARS, MAX_METADATA_VALUE_CHARS
+            ));
+        }
+        if cleaned.insert(key.to_string(), value.trim().to_string()).is_some() {
+            return Err(format!("Duplicate metadata key: {}", key));
+        }
+    }
+    Ok(cleaned)
+}
+
+// Rejects an insert of `size` bytes that would take the catalog past the hard quota
+fn check_quota(config: &ServerConfig, size: u64) -> Result<(), String> {
+    let Some(quota) = config.storage_quota else {
+        return Ok(());
+    };
+    let used = storage_used().map_err(|e| format!("Failed to check storage: {}", e))?;
+    if used + size > quota {
+        return Err(format!(
+            "Storage quota exceeded: {} used of {}, upload needs {}",
+            format_size(used as usize),
+            format_size(quota as usize),
+            format_size(size as usize)
+        ));
+    }
+    Ok(())
+}
+
+fn storage_status(config: &ServerConfig) -> Option<StorageStatus> {
+    let quota_bytes = config.storage_quota?;
+    let used_bytes = storage_used().map_err(|e| error!(error = %e, "failed to read storage use")).ok()?;
+    Some(StorageStatus {
+        used_bytes,
+        quota_bytes,
+        nearing_capacity: used_bytes * 100 >= quota_bytes * config.storage_warn_percent,
+    })
+}
+
+// Tells every client how full the catalog is after an insert or delete, when a quota is set
+fn broadcast_storage(config: &ServerConfig, tx: &Sender<String>) -> Option<StorageStatus> {
+    let storage = storage_status(config)?;
+    let update = serde_json::to_string(&ServerMessage::Storage { storage: storage.clone() }).unwrap();
+    if let Err(e) = tx.send(update) {
+        warn!(error = %e, "broadcast failed");
+    }
+    Some(storage)
+}
+
+// Gives `model_id` the placement of `source_id`; both must already be in the layout
+fn copy_entry_transform(mut layout: LayoutResponse, source_id: i32, model_id: i32) -> Result<LayoutResponse, String> {
+    let source = layout
+        .entries
+        .iter()
+        .find(|entry| entry.model_id == source_id)
+        .cloned()
+        .ok_or_else(|| format!("Model {} is not in layout {}", source_id, layout.id))?;
+    let entry = layout
+        .entries
+        .iter_mut()
+        .find(|entry| entry.model_id == model_id)
+        .ok_or_else(|| format!("Model {} is not in layout {}", model_id, layout.id))?;
+    *entry = LayoutEntry { model_id, ..source };
+    Ok(layout)
+}
+
+// Replaces the placements of models already in the layout
+fn set_entry_transforms(mut layout: LayoutResponse, entries: Vec<LayoutEntry>) -> Result<LayoutResponse, String> {
+    for entry in entries {
+        if entry.translation.iter().chain(&entry.rotation).chain(&entry.scale).any(|value| !value.is_finite()) {
+            return Err(format!("Placement of model {} has a non-finite value", entry.model_id));
+        }
+        let slot = layout
+            .entries
+            .iter_mut()
+            .find(|existing| existing.model_id == entry.model_id)
+            .ok_or_else(|| format!("Model {} is not in layout {}", entry.model_id, layout.id))?;
+        *slot = entry;
+    }
+    Ok(layout)
+}
+
+fn available_lods(stored: &[String]) -> Vec<String> {
+    std::iter::once("full".to_string()).chain(stored.iter().cloned()).collect()
+}
+
+// Swaps in the stored variant closest to the requested level and returns the level served
+fn serve_lod(model: &mut ModelData, requested: Option<&str>) -> &'static str {
+    let level = lod::pick_level(requested, &model.lods);
+    if level == "full" {
+        return level;
+    }
+    match load_model_lod(model.id, level) {
+        Ok(data) => {
+            model.model_data = data;
+            level
+        }
+        Err(e) => {
+            warn!(model_id = model.id, level, error = %e, "failed to load detail level, serving full");
+            "full"
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "db/test_support.rs"]
+mod test_support;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::{ insert_test_model, minimal_glb, scratch_db };
+    use serde_json::json;
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response.headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str())
+    }
+
+    // The shared state of one server, for clients talking to serve_client over in-memory streams
+    struct TestServer {
+        tx: Sender<String>,
+        config: Arc<ServerConfig>,
+        layout_writes: Arc<LayoutWrites>,
+        presence: Arc<Presence>,
+    }
+
+    impl TestServer {
+        fn new() -> Self {
+            scratch_db();
+            TestServer {
+                tx: broadcast::channel(BROADCAST_CAPACITY).0,
+                config: Arc::new(ServerConfig::from_env()),
+                layout_writes: Arc::new(LayoutWrites::default()),
+                presence: Arc::new(Presence::default()),
+            }
+        }
+
+        async fn connect(&self) -> WebSocketStream<tokio::io::DuplexStream> {
+            use tokio_tungstenite::tungstenite::protocol::Role;
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (tx, config) = (self.tx.clone(), self.config.clone());
+            let (layout_writes, presence) = (self.layout_writes.clone(), self.presence.clone());
+            tokio::spawn(async move {
+                let ws_stream = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+                serve_client(ws_stream, "test".to_string(), tx, config, layout_writes, presence, false).await;
+            });
+            WebSocketStream::from_raw_socket(client, Role::Client, None).await
+        }
+    }
+
+    // The next reply meant for this client, skipping the hello and presence broadcasts
+    async fn reply(ws: &mut WebSocketStream<tokio::io::DuplexStream>) -> ServerMessage {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+            match serde_json::from_str(message.to_text().unwrap()).unwrap() {
+                ServerMessage::Hello { .. } | ServerMessage::Presence { .. } => {}
+                reply => return reply,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_for_a_missing_model_say_so() {
+        let mut ws = TestServer::new().connect().await;
+        let missing = 999_999;
+        let requests = [
+            json!({ "action": "get_by_id", "id": missing }),
+            json!({ "action": "delete", "id": missing }),
+            json!({ "action": "rename", "id": missing, "version": 1, "name": "gone" }),
+            json!({ "action": "set_access_code", "id": missing, "version": 1, "new_access_code": "secret" }),
+            json!({ "action": "set_thumbnail", "id": missing, "thumbnail_data": "" }),
+            json!({ "action": "regenerate_thumbnail", "id": missing }),
+            json!({ "action": "copy_to_scene", "id": missing, "target_id": 1 }),
+            json!({ "action": "add_comment", "id": missing, "text": "hello" }),
+            json!({ "action": "set_metadata", "id": missing, "version": 1, "metadata": {} }),
+            json!({ "action": "set_transform_lock", "id": missing, "version": 1, "locked": true }),
+            json!({ "action": "revert", "id": missing, "version": 2, "restore_version": 1 }),
+            json!({ "action": "history", "id": missing }),
+        ];
+        for request in requests {
+            ws.send(Message::Text(request.to_string().into())).await.unwrap();
+            let reply = reply(&mut ws).await;
+            assert!(matches!(reply, ServerMessage::ModelNotFound { id, .. } if id == missing), "{}: {:?}", request, reply);
+        }
+    }
+
+    #[tokio::test]
+    async fn thumbnails_of_protected_models_need_the_access_code() {
+        let mut ws = TestServer::new().connect().await;
+        let id = insert_test_model();
+        assert!(update_access_code(id, 1, Some(&hash_access_code(id, "secret"))).unwrap());
+        let png = general_purpose::STANDARD.encode(b"\x89PNG\r\n\x1a\n");
+        let requests = [
+            (json!({ "action": "set_thumbnail", "id": id, "thumbnail_data": png }), "Access code required"),
+            (json!({ "action": "set_thumbnail", "id": id, "thumbnail_data": png, "access_code": "wrong" }), "Invalid access code"),
+            (json!({ "action": "set_thumbnail", "id": id, "thumbnail_data": "aGVsbG8=", "access_code": "secret" }), "must be PNG"),
+            (json!({ "action": "regenerate_thumbnail", "id": id }), "Access code required"),
+        ];
+        for (request, expected) in requests {
+            ws.send(Message::Text(request.to_string().into())).await.unwrap();
+            let reply = reply(&mut ws).await;
+            assert!(matches!(&reply, ServerMessage::Error { error, .. } if error.contains(expected)), "{}: {:?}", request, reply);
+        }
+
+        let request = json!({ "action": "regenerate_thumbnail", "id": id, "access_code": "secret" });
+        ws.send(Message::Text(request.to_string().into())).await.unwrap();
+        assert!(matches!(reply(&mut ws).await, ServerMessage::ThumbnailInvalidated { thumbnail_invalidated } if thumbnail_invalidated == id));
+        let request = json!({ "action": "set_thumbnail", "id": id, "thumbnail_data": png, "access_code": "secret" });
+        ws.send(Message::Text(request.to_string().into())).await.unwrap();
+        let request = json!({ "action": "get_thumbnail", "id": id, "access_code": "secret" });
+        ws.send(Message::Text(request.to_string().into())).await.unwrap();
+        assert!(matches!(reply(&mut ws).await, ServerMessage::Thumbnail { thumbnail } if !thumbnail.stale && thumbnail.image_data == png));
+    }
+
+    #[tokio::test]
+    async fn copying_to_a_scene_updates_everyone_showing_it() {
+        let server = TestServer::new();
+        let (mut copier, mut watcher) = (server.connect().await, server.connect().await);
+        let id = insert_test_model();
+        let layout_id = insert_layout("scene", &[], false).unwrap();
+
+        let request = json!({ "action": "copy_to_scene", "id": id, "target_id": layout_id });
+        copier.send(Message::Text(request.to_string().into())).await.unwrap();
+        // The copier's own reply goes out before it forwards the broadcast
+        assert!(matches!(reply(&mut copier).await, ServerMessage::Layouts { .. }));
+        for ws in [&mut copier, &mut watcher] {
+            let reply = reply(ws).await;
+            assert!(
+                matches!(&reply, ServerMessage::LayoutUpdated { layout_updated } if layout_updated.id == layout_id && layout_updated.entries[0].model_id == id),
+                "{:?}",
+                reply
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn model_placements_are_stored_and_shared() {
+        let server = TestServer::new();
+        let (mut mover, mut watcher) = (server.connect().await, server.connect().await);
+        let id = insert_test_model();
+        let transform = json!({ "translation": [1.0, 2.0, 3.0], "rotation": [0.0, 0.0, 0.0, 1.0], "scale": [2.0, 2.0, 2.0] });
+
+        let request = json!({ "action": "transform", "id": id, "version": 1, "transform": transform });
+        mover.send(Message::Text(request.to_string().into())).await.unwrap();
+        for ws in [&mut mover, &mut watcher] {
+            let reply = reply(ws).await;
+            assert!(
+                matches!(&reply, ServerMessage::ModelUpdated { model_updated } if model_updated.id == id && model_updated.transform.is_some_and(|t| t.translation == [1.0, 2.0, 3.0])),
+                "{:?}",
+                reply
+            );
+        }
+        let stored = load_model_by_id(id).unwrap();
+        let placement = stored.transform.unwrap();
+        assert_eq!((placement.rotation, placement.scale, stored.version), ([0.0, 0.0, 0.0, 1.0], [2.0, 2.0, 2.0], 2));
+        assert_eq!(load_model_history(id).unwrap().last().unwrap().change, "moved");
+
+        update_transform_lock(id, 2, true).unwrap();
+        let request = json!({ "action": "transform", "id": id, "version": 3, "transform": null });
+        mover.send(Message::Text(request.to_string().into())).await.unwrap();
+        let reply = reply(&mut mover).await;
+        assert!(matches!(&reply, ServerMessage::Error { error, .. } if error.contains("locked")), "{:?}", reply);
+        assert!(load_model_by_id(id).unwrap().transform.is_some());
+    }
+
+    #[tokio::test]
+    async fn stale_placements_are_refused() {
+        let server = TestServer::new();
+        let mut ws = server.connect().await;
+        let id = insert_test_model();
+        update_model_name(id, 1, Some("renamed")).unwrap();
+
+        let transform = json!({ "translation": [1.0, 0.0, 0.0], "rotation": [0.0, 0.0, 0.0, 1.0], "scale": [1.0, 1.0, 1.0] });
+        let request = json!({ "action": "transform", "id": id, "version": 1, "transform": transform });
+        ws.send(Message::Text(request.to_string().into())).await.unwrap();
+        let reply = reply(&mut ws).await;
+        assert!(
+            matches!(&reply, ServerMessage::StaleVersion { id: stale, version: 2, action, .. } if *stale == id && action == "transform"),
+            "{:?}",
+            reply
+        );
+        let model = load_model_by_id(id).unwrap();
+        assert_eq!((model.version, model.transform), (2, None));
+    }
+
+    #[tokio::test]
+    async fn clients_that_fall_behind_get_the_whole_list() {
+        let server = TestServer::new();
+        let mut ws = server.connect().await;
+        // Once the hello arrives the client is subscribed to broadcasts
+        ws.next().await.unwrap().unwrap();
+        let id = insert_test_model();
+        // Nothing is forwarded while this runs, so the client's queue overflows
+        let presence = serde_json::to_string(&ServerMessage::Presence { presence: vec![] }).unwrap();
+        for _ in 0..BROADCAST_CAPACITY + 1 {
+            server.tx.send(presence.clone()).unwrap();
+        }
+        let reply = reply(&mut ws).await;
+        assert!(matches!(&reply, ServerMessage::Models(models) if models.iter().any(|model| model.id == id)), "{:?}", reply);
+    }
+
+    #[test]
+    fn downloads_are_named_after_the_model() {
+        assert_eq!(content_disposition("cube", 1, "gltf"), "attachment; filename=\"cube.gltf\"; filename*=UTF-8''cube.gltf");
+        assert_eq!(content_disposition("  ", 7, "glb"), "attachment; filename=\"model-7.glb\"; filename*=UTF-8''model-7.glb");
+    }
+
+    #[test]
+    fn download_names_escape_quotes_and_non_ascii() {
+        assert_eq!(
+            content_disposition("say \"hi\"", 1, "glb"),
+            "attachment; filename=\"say _hi_.glb\"; filename*=UTF-8''say%20%22hi%22.glb"
+        );
+        assert_eq!(
+            content_disposition("Küche", 1, "gltf"),
+            "attachment; filename=\"K_che.gltf\"; filename*=UTF-8''K%C3%BCche.gltf"
+        );
+    }
+
+    #[test]
+    fn model_downloads_carry_their_type_and_name() {
+        scratch_db();
+        let config = ServerConfig::from_env();
+        let gltf = br#"{"asset":{"version":"2.0"}}"#;
+        let glb = minimal_glb();
+
+        for (bytes, name, content_type, filename) in [
+            (&gltf[..], "scene", "model/gltf+json", "scene.gltf"),
+            (&glb[..], "crate \"v2\"", "model/gltf-binary", "crate _v2_.glb"),
+        ] {
+            let id = insert_model(bytes, Some(name), "gltf", false, "tester", unix_time(), None).unwrap();
+            let response = model_http_response(&RequestHead::get(format!("/models/{}", id)), &config);
+            assert_eq!(response.status, "200 OK");
+            assert_eq!(response.content_type, content_type);
+            assert_eq!(response.body, bytes);
+            let disposition = header(&response, "Content-Disposition").unwrap();
+            assert!(disposition.starts_with(&format!("attachment; filename=\"{}\";", filename)), "{}", disposition);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Wavefront OBJ/MTL export of stored glTF models, for tools that don't read glTF.
+//!
+//! Every mesh instance in the default scene is written with its node transform applied, one
+//! OBJ group per primitive. Materials keep their base color, opacity and base color texture;
+//! the textures are returned as separate files to be saved next to the MTL file.
+
+use crate::lod::{ Attribute, Model, Primitive };
+use crate::textures::decode_data_uri;
+use serde_json::Value;
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::fmt::Write as _;
+
+const FLOAT: u64 = 5126;
+const UNSIGNED_BYTE: u64 = 5121;
+const UNSIGNED_SHORT: u64 = 5123;
+const IDENTITY: Mat4 = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+
+type Mat4 = [f32; 16]; // column-major, as in glTF
+
+pub struct ObjExport {
+    pub obj: String,
+    pub mtl: String,
+    pub textures: Vec<(String, Vec<u8>)>, // file name referenced by the MTL, image bytes
+}
+
+/// Converts a glTF/GLB model. Files are named after `base_name`: `<base_name>.mtl` and
+/// `<base_name>_<image>.<png|jpg>` for textures.
+pub fn export_obj(bytes: &[u8], base_name: &str) -> Result<ObjExport, String> {
+    let model = Model::parse(bytes).map_err(|e| format!("Failed to read glTF: {}", e))?;
+    let document = &model.document;
+    let mut meshes: HashMap<usize, Vec<Primitive>> = HashMap::new();
+    for primitive in model.primitives()? {
+        meshes.entry(primitive.mesh).or_default().push(primitive);
+    }
+
+    let mut obj = format!("mtllib {}.mtl\n", base_name);
+    let mut counts = [0usize; 3]; // vertices, texture coordinates and normals written so far
+    let mut used_materials = BTreeMap::new();
+    for (mesh_index, transform) in mesh_instances(document) {
+        let mesh_name = document["meshes"][mesh_index]["name"].as_str().map_or_else(|| format!("mesh_{}", mesh_index), clean_name);
+        for primitive in meshes.get(&mesh_index).into_iter().flatten() {
+            let material = document["meshes"][mesh_index]["primitives"][primitive.index]["material"].as_u64().map(|m| m as usize);
+            let material_name = material_name(document, material);
+            used_materials.insert(material_name.clone(), material);
+            let _ = writeln!(obj, "g {}_{}\nusemtl {}", mesh_name, primitive.index, material_name);
+            write_primitive(&mut obj, primitive, &transform, &mut counts);
+        }
+    }
+
+    let mut mtl = String::new();
+    let mut textures = Vec::new();
+    let mut exported_images = HashSet::new();
+    for (name, material) in used_materials {
+        let pbr = material.map_or(&Value::Null, |m| &document["materials"][m]["pbrMetallicRoughness"]);
+        // Primitives without a material render light grey, as in the clients
+        let default = |i| if material.is_none() && i < 3 { 0.8 } else { 1.0 };
+        let color: Vec<f64> = (0..4).map(|i| pbr["baseColorFactor"][i].as_f64().unwrap_or(default(i))).collect();
+        let _ = writeln!(mtl, "newmtl {}\nKd {} {} {}\nd {}", name, color[0], color[1], color[2], color[3]);
+        let image = pbr["baseColorTexture"]["index"]
+            .as_u64()
+            .and_then(|texture| document["textures"][texture as usize]["source"].as_u64());
+        if let Some(image) = image {
+            let image = image as usize;
+            match image_file(&model, image, base_name) {
+                Ok((file_name, data)) => {
+                    let _ = writeln!(mtl, "map_Kd {}", file_name);
+                    if exported_images.insert(image) {
+                        textures.push((file_name, data));
+                    }
+                }
+                Err(e) => tracing::warn!(material = name.as_str(), error = %e, "skipping texture"),
+            }
+        }
+        mtl.push('\n');
+    }
+    Ok(ObjExport { obj, mtl, textures })
+}
+
+// Meshes referenced from the default scene with their world transforms. Files without
+// scenes still get each mesh once, untransformed.
+fn mesh_instances(document: &Value) -> Vec<(usize, Mat4)> {
+    let scene = document["scene"].as_u64().unwrap_or(0) as usize;
+    let Some(roots) = document["scenes"][scene]["nodes"].as_array() else {
+        let mesh_count = document["meshes"].as_array().map_or(0, Vec::len);
+        return (0..mesh_count).map(|mesh| (mesh, IDENTITY)).collect();
+    };
+    let mut instances = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<(usize, Mat4)> = roots.iter().filter_map(|node| node.as_u64()).map(|node| (node as usize, IDENTITY)).collect();
+    while let Some((node_index, parent)) = stack.pop() {
+        // Guards against malformed files whose node graph isn't a tree
+        if !visited.insert(node_index) {
+            continue;
+        }
+        let node = &document["nodes"][node_index];
+        let transform = multiply(&parent, &local_transform(node));
+        if let Some(mesh) = node["mesh"].as_u64() {
+            instances.push((mesh as usize, transform));
+        }
+        for child in node["children"].as_array().into_iter().flatten().filter_map(Value::as_u64) {
+            stack.push((child as usize, transform));
+        }
+    }
+    instances
+}
+
+fn local_transform(node: &Value) -> Mat4 {
+    if let Some(matrix) = node["matrix"].as_array().filter(|m| m.len() == 16) {
+        let mut out = IDENTITY;
+        for (i, value) in matrix.iter().enumerate() {
+            out[i] = value.as_f64().unwrap_or(out[i] as f64) as f32;
+        }
+        return out;
+    }
+    let component = |key: &str, i: usize, default: f32| node[key][i].as_f64().map_or(default, |v| v as f32);
+    let [tx, ty, tz] = [0, 1, 2].map(|i| component("translation", i, 0.0));
+    let [x, y, z, w] = [0, 1, 2, 3].map(|i| component("rotation", i, if i == 3 { 1.0 } else { 0.0 }));
+    let [sx, sy, sz] = [0, 1, 2].map(|i| component("scale", i, 1.0));
+    // T * R * S, written out column by column
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * sx, 2.0 * (x * y + z * w) * sx, 2.0 * (x * z - y * w) * sx, 0.0,
+        2.0 * (x * y - z * w) * sy, (1.0 - 2.0 * (x * x + z * z)) * sy, 2.0 * (y * z + x * w) * sy, 0.0,
+        2.0 * (x * z + y * w) * sz, 2.0 * (y * z - x * w) * sz, (1.0 - 2.0 * (x * x + y * y)) * sz, 0.0,
+        tx, ty, tz, 1.0,
+    ]
+}
+
+fn multiply(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut out = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            out[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    out
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn write_primitive(obj: &mut String, primitive: &Primitive, transform: &Mat4, counts: &mut [usize; 3]) {
+    let attribute = |name: &str| primitive.attributes.iter().find(|attribute| attribute.name == name);
+    let positions = attribute("POSITION").and_then(|a| floats(a, 3)).unwrap_or_default();
+    let uvs = attribute("TEXCOORD_0").and_then(|a| floats(a, 2));
+    let normals = attribute("NORMAL").and_then(|a| floats(a, 3));
+
+    let m = transform;
+    for p in &positions {
+        let _ = writeln!(
+            obj,
+            "v {} {} {}",
+            m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12],
+            m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13],
+            m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14]
+        );
+    }
+    for uv in uvs.iter().flatten() {
+        // glTF puts the texture origin at the top left, OBJ at the bottom left
+        let _ = writeln!(obj, "vt {} {}", uv[0], 1.0 - uv[1]);
+    }
+    let columns = [[m[0], m[1], m[2]], [m[4], m[5], m[6]], [m[8], m[9], m[10]]];
+    let normal_columns = [cross(columns[1], columns[2]), cross(columns[2], columns[0]), cross(columns[0], columns[1])];
+    let determinant: f32 = (0..3).map(|i| columns[0][i] * normal_columns[0][i]).sum();
+    if let Some(normals) = &normals {
+        // The cofactor matrix keeps normals perpendicular under non-uniform scale; it carries the
+        // determinant's sign, which would turn mirrored normals inside out
+        let sign = determinant.signum();
+        for n in normals {
+            let mut out = [0.0; 3];
+            for (axis, value) in out.iter_mut().enumerate() {
+                *value = sign * (0..3).map(|i| normal_columns[i][axis] * n[i]).sum::<f32>();
+            }
+            let length = out.iter().map(|v| v * v).sum::<f32>().sqrt().max(f32::EPSILON);
+            let _ = writeln!(obj, "vn {} {} {}", out[0] / length, out[1] / length, out[2] / length);
+        }
+    }
+
+    // A mirroring transform flips the winding, so reverse it to keep faces pointing outwards
+    for triangle in primitive.indices.chunks_exact(3) {
+        let corners = if determinant < 0.0 { [triangle[0], triangle[2], triangle[1]] } else { [triangle[0], triangle[1], triangle[2]] };
+        obj.push('f');
+        for index in corners {
+            let index = index as usize;
+            let v = counts[0] + index + 1;
+            let _ = match (uvs.is_some(), normals.is_some()) {
+                (true, true) => write!(obj, " {}/{}/{}", v, counts[1] + index + 1, counts[2] + index + 1),
+                (true, false) => write!(obj, " {}/{}", v, counts[1] + index + 1),
+                (false, true) => write!(obj, " {}//{}", v, counts[2] + index + 1),
+                (false, false) => write!(obj, " {}", v),
+            };
+        }
+        obj.push('\n');
+    }
+    counts[0] += positions.len();
+    counts[1] += uvs.map_or(0, |uvs| uvs.len());
+    counts[2] += normals.map_or(0, |normals| normals.len());
+}
+
+// Decodes float or normalized unsigned attributes; anything else is left out of the export
+fn floats(attribute: &Attribute, components: usize) -> Option<Vec<Vec<f32>>> {
+    let values: Vec<f32> = match (attribute.component_type, attribute.normalized) {
+        (FLOAT, _) => attribute.data.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+        (UNSIGNED_BYTE, true) => attribute.data.iter().map(|b| *b as f32 / 255.0).collect(),
+        (UNSIGNED_SHORT, true) => attribute.data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]) as f32 / 65535.0).collect(),
+        _ => return None,
+    };
+    let expected = match attribute.kind.as_str() {
+        "VEC2" => 2,
+        "VEC3" => 3,
+        _ => return None,
+    };
+    (expected == components).then(|| values.chunks_exact(components).map(<[f32]>::to_vec).collect())
+}
+
+fn material_name(document: &Value, material: Option<usize>) -> String {
+    match material {
+        Some(m) => document["materials"][m]["name"].as_str().map_or_else(|| format!("material_{}", m), |name| format!("{}_{}", clean_name(name), m)),
+        None => "default".to_string(),
+    }
+}
+
+// OBJ and MTL names end at whitespace
+fn clean_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect()
+}
+
+fn image_file(model: &Model, image: usize, base_name: &str) -> Result<(String, Vec<u8>), String> {
+    let entry = &model.document["images"][image];
+    let data = if let Some(uri) = entry["uri"].as_str() {
+        if !uri.starts_with("data:") {
+            return Err(format!("external image {:?} isn't stored with the model", uri));
+        }
+        decode_data_uri(uri)?
+    } else {
+        let view = entry["bufferView"].as_u64().ok_or("image has no data")?;
+        model.buffer_view(view as usize)?
+    };
+    let extension = match image::guess_format(&data) {
+        Ok(image::ImageFormat::Png) => "png",
+        Ok(image::ImageFormat::Jpeg) => "jpg",
+        _ => return Err("only PNG and JPEG textures are exported".to_string()),
+    };
+    Ok((format!("{}_{}.{}", base_name, image, extension), data))
+}
@@ -0,0 +1,61 @@
+//! Which actions each role may request. Out of the box an "editor" may use every action and
+//! a "viewer" everything that leaves stored data alone. A JSON file mapping role names to
+//! action lists can redefine those roles or add new ones, with "*" standing for every action.
+
+use crate::protocol::actions;
+use std::{ collections::{ BTreeMap, BTreeSet }, path::Path };
+
+pub const EDITOR: &str = "editor";
+pub const VIEWER: &str = "viewer";
+
+#[derive(Debug)]
+pub struct Permissions {
+    roles: BTreeMap<String, BTreeSet<&'static str>>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        let viewer = actions::ALL.into_iter().filter(|action| !actions::MUTATING.contains(action)).collect();
+        Permissions {
+            roles: BTreeMap::from([(EDITOR.to_string(), actions::ALL.into_iter().collect()), (VIEWER.to_string(), viewer)]),
+        }
+    }
+}
+
+impl Permissions {
+    /// The defaults with the roles from a file such as `{"reviewer": ["get_all", "add_comment"]}`
+    /// laid over them. Unknown action names are rejected, so a typo can't quietly withhold one.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let roles: BTreeMap<String, Vec<String>> =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid permissions in {}: {}", path.display(), e))?;
+        let mut permissions = Permissions::default();
+        for (role, names) in roles {
+            let mut allowed = BTreeSet::new();
+            for name in &names {
+                match actions::ALL.iter().find(|action| *action == name) {
+                    Some(action) => {
+                        allowed.insert(*action);
+                    }
+                    None if name == "*" => allowed.extend(actions::ALL),
+                    None => return Err(format!("Unknown action {:?} for role {:?} in {}", name, role, path.display())),
+                }
+            }
+            permissions.roles.insert(role, allowed);
+        }
+        Ok(permissions)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains_key(role)
+    }
+
+    pub fn allows(&self, role: &str, action: &str) -> bool {
+        self.roles.get(role).is_some_and(|allowed| allowed.contains(action))
+    }
+
+    /// Everything the role may request, in the order of `actions::ALL`.
+    pub fn allowed_actions(&self, role: &str) -> Vec<&'static str> {
+        actions::ALL.into_iter().filter(|action| self.allows(role, action)).collect()
+    }
+}
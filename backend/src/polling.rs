@@ -0,0 +1,233 @@
+//! HTTP long-polling fallback for clients whose network blocks WebSocket upgrades.
+//!
+//! A client opens a session with `POST /poll`, sends requests with `POST /poll/<session>`
+//! and collects replies and broadcasts with `GET /poll/<session>`, which answers as soon as
+//! anything is queued (one JSON message per line) or with an empty body after
+//! `LONG_POLL_WAIT`. Each session drives the ordinary WebSocket handler over an in-memory
+//! pipe, so both transports speak exactly the same protocol.
+
+use crate::http::{ read_body, write_response, RequestHead, Response };
+use futures_util::{ SinkExt, StreamExt };
+use std::{
+    collections::{ HashMap, VecDeque },
+    io,
+    net::SocketAddr,
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant }
+};
+use tokio::{
+    io::DuplexStream,
+    net::TcpStream,
+    sync::{ mpsc, Notify }
+};
+use tokio_tungstenite::{
+    tungstenite::{ protocol::{ Role, WebSocketConfig }, Message },
+    WebSocketStream
+};
+
+/// Longest a `GET /poll/<session>` waits for a message before answering empty.
+pub const LONG_POLL_WAIT: Duration = Duration::from_secs(25);
+// Sessions nobody has polled for this long are closed
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const PIPE_BUFFER_BYTES: usize = 256 * 1024;
+
+struct Session {
+    requests: mpsc::UnboundedSender<String>, // to the WebSocket handler
+    messages: Mutex<VecDeque<String>>, // from the handler, waiting to be polled
+    arrived: Notify,
+    last_polled: Mutex<Instant>,
+}
+
+/// Open polling sessions, shared by every connection.
+#[derive(Default)]
+pub struct PollSessions {
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+}
+
+/// Answers one request to a `/poll` route. `open` is handed the server end of every new
+/// session's pipe and should serve it exactly like an accepted WebSocket connection.
+pub async fn handle_http<F>(
+    mut stream: TcpStream,
+    head: RequestHead,
+    sessions: Arc<PollSessions>,
+    max_body_bytes: usize,
+    open: F,
+) where
+    F: FnOnce(WebSocketStream<DuplexStream>, String),
+{
+    let (status, body) = match respond(&mut stream, head, &sessions, max_body_bytes, open).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!(error = %e, "HTTP request failed");
+            ("400 Bad Request", String::new())
+        }
+    };
+    write_response(stream, Response::new(status, "application/x-ndjson", body)).await;
+}
+
+async fn respond<F>(
+    stream: &mut TcpStream,
+    head: RequestHead,
+    sessions: &Arc<PollSessions>,
+    max_body_bytes: usize,
+    open: F,
+) -> io::Result<(&'static str, String)>
+where
+    F: FnOnce(WebSocketStream<DuplexStream>, String),
+{
+    let Some(body) = read_body(stream, &head, max_body_bytes).await? else {
+        return Ok(("413 Payload Too Large", String::new()));
+    };
+    let body = String::from_utf8(body).map_err(|_| io::ErrorKind::InvalidData)?;
+
+    let session_id = head.route().strip_prefix("/poll/");
+    Ok(match (head.method.as_str(), head.route(), session_id) {
+        ("OPTIONS", _, _) => ("204 No Content", String::new()),
+        ("POST", "/poll", _) => {
+            let id = open_session(sessions, stream.peer_addr().ok(), open).await;
+            ("200 OK", format!("{{\"session\":\"{}\"}}\n", id))
+        }
+        (method, _, Some(id)) => {
+            let session = sessions.sessions.lock().unwrap().get(id).cloned();
+            match (method, session) {
+                (_, None) => ("404 Not Found", String::new()),
+                ("POST", Some(session)) => {
+                    let _ = session.requests.send(body);
+                    ("202 Accepted", String::new())
+                }
+                ("GET", Some(session)) => ("200 OK", wait_for_messages(&session).await),
+                _ => ("405 Method Not Allowed", String::new()),
+            }
+        }
+        _ => ("404 Not Found", String::new()),
+    })
+}
+
+async fn open_session<F>(sessions: &Arc<PollSessions>, peer: Option<SocketAddr>, open: F) -> String
+where
+    F: FnOnce(WebSocketStream<DuplexStream>, String),
+{
+    // The ID is all a poller needs to read the session, so it comes from the OS's CSPRNG
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("the OS random number generator failed");
+    let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let mut config = WebSocketConfig::default();
+    config.max_message_size = None; // the pipe never leaves the process; HTTP bodies are capped instead
+    config.max_frame_size = None;
+    let (server_io, client_io) = tokio::io::duplex(PIPE_BUFFER_BYTES);
+    let server = WebSocketStream::from_raw_socket(server_io, Role::Server, Some(config)).await;
+    let client = WebSocketStream::from_raw_socket(client_io, Role::Client, Some(config)).await;
+    let (requests, requests_rx) = mpsc::unbounded_channel();
+    let session = Arc::new(Session {
+        requests,
+        messages: Mutex::new(VecDeque::new()),
+        arrived: Notify::new(),
+        last_polled: Mutex::new(Instant::now()),
+    });
+    sessions.sessions.lock().unwrap().insert(id.clone(), session.clone());
+    let peer = peer.map_or_else(|| "unknown peer".to_string(), |addr| addr.to_string());
+    open(server, format!("{} (polling {})", peer, &id[..8]));
+    tokio::spawn(pump(id.clone(), session, client, requests_rx, sessions.clone()));
+    id
+}
+
+// Moves requests into the pipe and messages out of it until the session goes idle or the handler hangs up
+async fn pump(
+    id: String,
+    session: Arc<Session>,
+    mut client: WebSocketStream<DuplexStream>,
+    mut requests: mpsc::UnboundedReceiver<String>,
+    sessions: Arc<PollSessions>,
+) {
+    let mut idle_check = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        tokio::select! {
+            message = client.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    session.messages.lock().unwrap().push_back(text.to_string());
+                    session.arrived.notify_waiters();
+                }
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+            Some(request) = requests.recv() => {
+                if client.send(Message::Text(request.into())).await.is_err() {
+                    break;
+                }
+            }
+            _ = idle_check.tick() => {
+                if session.last_polled.lock().unwrap().elapsed() > SESSION_IDLE_TIMEOUT {
+                    break;
+                }
+            }
+        }
+    }
+    sessions.sessions.lock().unwrap().remove(&id);
+    let _ = client.close(None).await;
+}
+
+async fn wait_for_messages(session: &Session) -> String {
+    let deadline = tokio::time::Instant::now() + LONG_POLL_WAIT;
+    loop {
+        // Registered before the queue is checked, so a message pushed in between still wakes us
+        let arrived = session.arrived.notified();
+        *session.last_polled.lock().unwrap() = Instant::now();
+        let messages: Vec<String> = session.messages.lock().unwrap().drain(..).collect();
+        if !messages.is_empty() {
+            return messages.into_iter().map(|message| message + "\n").collect();
+        }
+        tokio::select! {
+            _ = arrived => {}
+            _ = tokio::time::sleep_until(deadline) => return String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::peek_request_head;
+    use tokio::{ io::{ AsyncReadExt, AsyncWriteExt }, net::TcpListener };
+
+    // Sends one raw request through handle_http, with sessions whose handler greets the client
+    // straight away so polls needn't wait
+    async fn request(listener: &TcpListener, sessions: &Arc<PollSessions>, raw: &str) -> String {
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let head = peek_request_head(&stream).await.unwrap();
+        handle_http(stream, head, sessions.clone(), 1024, |mut server, _| {
+            tokio::spawn(async move {
+                server.send(Message::Text("hello".into())).await.unwrap();
+                server.next().await;
+            });
+        })
+        .await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn session_id(response: &str) -> String {
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        serde_json::from_str::<serde_json::Value>(body).unwrap()["session"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn sessions_are_random_and_polls_may_carry_a_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sessions = Arc::new(PollSessions::default());
+        let open = "POST /poll HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let first = session_id(&request(&listener, &sessions, open).await);
+        let second = session_id(&request(&listener, &sessions, open).await);
+        assert_ne!(first, second);
+        assert!(first.len() == 32 && first.chars().all(|c| c.is_ascii_hexdigit()), "{}", first);
+
+        let poll = format!("GET /poll/{}?t=1 HTTP/1.1\r\n\r\n", first);
+        let response = request(&listener, &sessions, &poll).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("hello\n"), "{}", response);
+        let unknown = request(&listener, &sessions, "GET /poll/0123?t=1 HTTP/1.1\r\n\r\n").await;
+        assert!(unknown.starts_with("HTTP/1.1 404"), "{}", unknown);
+    }
+}
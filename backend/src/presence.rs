@@ -0,0 +1,74 @@
+//! Who is connected. A session joins under a placeholder name once its hello is sent, can
+//! pick its own with set_name and leaves when its connection closes. Every change is
+//! broadcast as the whole roster, which stays small enough to resend. Views shared with
+//! set_view are broadcast on their own, so followers needn't receive the roster each time.
+
+use crate::protocol::{ Collaborator, ViewState, VIEW_SHARE_INTERVAL };
+use std::{ collections::BTreeMap, sync::Mutex, time::Instant };
+
+/// Longest display name set_name accepts.
+pub const MAX_NAME_CHARS: usize = 64;
+
+struct Member {
+    collaborator: Collaborator,
+    view_broadcast: Option<Instant>, // when its view last went out to everyone
+}
+
+#[derive(Default)]
+pub struct Presence {
+    members: Mutex<BTreeMap<u64, Member>>, // by session id, so listed in connection order
+}
+
+impl Presence {
+    /// Adds the session as "Guest <id>" and returns the new roster.
+    pub fn join(&self, session_id: u64) -> Vec<Collaborator> {
+        let mut members = self.members.lock().unwrap();
+        let collaborator = Collaborator { session_id: session_id.to_string(), name: guest_name(session_id), view: None };
+        members.insert(session_id, Member { collaborator, view_broadcast: None });
+        roster(&members)
+    }
+
+    /// Renames the session, back to its placeholder for a blank name. Returns the new roster,
+    /// or `None` when nothing changed.
+    pub fn rename(&self, session_id: u64, name: &str) -> Option<Vec<Collaborator>> {
+        let name = match name.trim() {
+            "" => guest_name(session_id),
+            name => name.to_string(),
+        };
+        let mut members = self.members.lock().unwrap();
+        let current = &mut members.get_mut(&session_id)?.collaborator.name;
+        if *current == name {
+            return None;
+        }
+        *current = name;
+        Some(roster(&members))
+    }
+
+    /// Records the session's view. Returns it for broadcasting unless the last broadcast was
+    /// too recent, in which case later joiners still get it with the roster.
+    pub fn set_view(&self, session_id: u64, view: ViewState) -> Option<Collaborator> {
+        let mut members = self.members.lock().unwrap();
+        let member = members.get_mut(&session_id)?;
+        member.collaborator.view = Some(view);
+        if member.view_broadcast.is_some_and(|at| at.elapsed() < VIEW_SHARE_INTERVAL / 2) {
+            return None;
+        }
+        member.view_broadcast = Some(Instant::now());
+        Some(member.collaborator.clone())
+    }
+
+    /// Removes the session and returns the roster left behind.
+    pub fn leave(&self, session_id: u64) -> Vec<Collaborator> {
+        let mut members = self.members.lock().unwrap();
+        members.remove(&session_id);
+        roster(&members)
+    }
+}
+
+fn guest_name(session_id: u64) -> String {
+    format!("Guest {}", session_id)
+}
+
+fn roster(members: &BTreeMap<u64, Member>) -> Vec<Collaborator> {
+    members.values().map(|member| member.collaborator.clone()).collect()
+}
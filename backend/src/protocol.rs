@@ -0,0 +1,39 @@
+//! Server-side handling of the shared WebSocket protocol.
+
+pub use ::protocol::{
+    actions, ClientMessage, Collaborator, CommentResponse, DeletedModel, ExportedTexture, HelloResponse, LayoutEntry,
+    LayoutResponse, LayoutSummary, ModelResponse, ModelTransform, ModelVersion, ObjExport, ServerMessage, SessionInfo,
+    StorageStatus, ThumbnailResponse, UploadStats, ValidationReport, ViewState, GZIP_ENCODING, GZIP_MESSAGE_PREFIX,
+    PROTOCOL_VERSION, VIEW_SHARE_INTERVAL,
+};
+use serde::Deserialize;
+
+/// Why a text frame isn't a request, with the action it named when that much could be read.
+#[derive(Debug)]
+pub struct RequestError {
+    pub action: Option<String>,
+    pub message: String,
+}
+
+// Just the tag, to tell an unknown action from a known one with bad fields
+#[derive(Deserialize)]
+struct Tagged {
+    action: String,
+}
+
+/// Parses a text frame from a client. Never panics: malformed or hostile
+/// input comes back as an error message suitable for `send_error`.
+pub fn parse_request(text: &str) -> Result<ClientMessage, RequestError> {
+    if text.trim().is_empty() {
+        return Err(RequestError { action: None, message: "Invalid request: empty message".to_string() });
+    }
+    // serde_json caps nesting depth, so deeply nested input is rejected rather than overflowing
+    serde_json::from_str::<ClientMessage>(text).map_err(|e| {
+        let action = serde_json::from_str::<Tagged>(text).ok().map(|tagged| tagged.action);
+        let message = match &action {
+            Some(action) if !actions::ALL.contains(&action.as_str()) => format!("Unknown action: {}", action),
+            _ => format!("Invalid request: {}", e),
+        };
+        RequestError { action, message }
+    })
+}
@@ -0,0 +1,207 @@
+//! Checks that the textures embedded in an uploaded glTF/GLB actually decode, and
+//! optionally shrinks oversized ones.
+//!
+//! A texture that fails to decode still lets the model load, but clients render it
+//! black or untextured, so problems are reported as warnings rather than rejected.
+
+use crate::lod::Model;
+use base64::{ Engine as _, engine::general_purpose };
+use image::{ codecs::jpeg::JpegEncoder, imageops::FilterType, ImageFormat };
+use serde_json::{ json, Value };
+use std::{ collections::HashMap, io::Cursor };
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+
+/// Returns one message per image that can't be decoded. Images in formats the
+/// server has no decoder for (anything but PNG and JPEG) are skipped.
+pub fn texture_warnings(bytes: &[u8]) -> Vec<String> {
+    let (json, bin) = match split_glb(bytes) {
+        Some(chunks) => chunks,
+        None => (bytes, None),
+    };
+    let Ok(document) = serde_json::from_slice::<Value>(json) else {
+        return vec![];
+    };
+    let images = document["images"].as_array().cloned().unwrap_or_default();
+    let mut buffers: HashMap<usize, Result<Vec<u8>, String>> = HashMap::new();
+    let mut warnings = Vec::new();
+
+    for (index, image) in images.iter().enumerate() {
+        let label = match image["name"].as_str() {
+            Some(name) => format!("Image {} ({})", index, name),
+            None => format!("Image {}", index),
+        };
+        let mime_type = image["mimeType"].as_str();
+        let data = if let Some(uri) = image["uri"].as_str() {
+            if !uri.starts_with("data:") {
+                warnings.push(format!("{} references external file {:?}, which is not uploaded with the model", label, uri));
+                continue;
+            }
+            decode_data_uri(uri)
+        } else if let Some(view) = image["bufferView"].as_u64() {
+            buffer_view_bytes(&document, view as usize, bin, &mut buffers)
+        } else {
+            Err("has no image data".to_string())
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                warnings.push(format!("{} {}", label, e));
+                continue;
+            }
+        };
+        let format = match mime_type.or_else(|| image["uri"].as_str().and_then(data_uri_mime_type)) {
+            Some("image/png") => ImageFormat::Png,
+            Some("image/jpeg") => ImageFormat::Jpeg,
+            Some(_) => continue,
+            None => match image::guess_format(&data) {
+                Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+                _ => continue,
+            },
+        };
+        if let Err(e) = image::load_from_memory_with_format(&data, format) {
+            warnings.push(format!("{} failed to decode: {}", label, e));
+        }
+    }
+    warnings
+}
+
+/// Rewrites the model with every PNG or JPEG texture larger than `max_dimension` on either
+/// side scaled down to fit, keeping its aspect ratio and format. Returns the new bytes and
+/// how many textures shrank, or `None` when nothing needed (or could be) resized.
+pub fn downscale_textures(bytes: &[u8], max_dimension: u32) -> Option<(Vec<u8>, usize)> {
+    let model = Model::parse(bytes).ok()?;
+    let mut document = model.document.clone();
+    let mut replaced_views = HashMap::new();
+    let mut downscaled = 0;
+    let image_count = document["images"].as_array().map_or(0, Vec::len);
+    for index in 0..image_count {
+        let image = &document["images"][index];
+        let data = match (image["uri"].as_str(), image["bufferView"].as_u64()) {
+            (Some(uri), _) if uri.starts_with("data:") => decode_data_uri(uri),
+            (None, Some(view)) => model.buffer_view(view as usize),
+            _ => continue,
+        };
+        let Some(resized) = data.ok().and_then(|data| downscale_image(&data, max_dimension)) else {
+            continue;
+        };
+        downscaled += 1;
+        if let Some(view) = image["bufferView"].as_u64() {
+            replaced_views.insert(view as usize, resized);
+        } else {
+            let mime_type = data_uri_mime_type(image["uri"].as_str().unwrap_or("")).unwrap_or("image/png").to_string();
+            let uri = format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(resized));
+            document["images"][index]["uri"] = json!(uri);
+        }
+    }
+    if downscaled == 0 {
+        return None;
+    }
+    let slimmed = model.repacked(document, &replaced_views).ok()?;
+    (slimmed.len() < bytes.len()).then_some((slimmed, downscaled))
+}
+
+// Re-encodes an oversized PNG or JPEG in its own format; None when it already fits,
+// can't be decoded or wouldn't get smaller
+fn downscale_image(data: &[u8], max_dimension: u32) -> Option<Vec<u8>> {
+    let format = image::guess_format(data).ok().filter(|format| matches!(format, ImageFormat::Png | ImageFormat::Jpeg))?;
+    let image = image::load_from_memory_with_format(data, format).ok()?;
+    if image.width().max(image.height()) <= max_dimension {
+        return None;
+    }
+    let resized = image.resize(max_dimension, max_dimension, FilterType::Triangle);
+    let mut out = Vec::new();
+    if format == ImageFormat::Jpeg {
+        resized.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut out, 90)).ok()?;
+    } else {
+        resized.write_to(&mut Cursor::new(&mut out), ImageFormat::Png).ok()?;
+    }
+    (out.len() < data.len()).then_some(out)
+}
+
+// Splits a binary glTF container into its JSON chunk and optional BIN chunk
+pub(crate) fn split_glb(bytes: &[u8]) -> Option<(&[u8], Option<&[u8]>)> {
+    if bytes.len() < 12 || &bytes[..4] != GLB_MAGIC {
+        return None;
+    }
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_le_bytes(bytes[offset..offset + 4].try_into().ok()?) as usize;
+        let kind = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().ok()?);
+        let chunk = bytes.get(offset + 8..(offset + 8).checked_add(length)?)?;
+        match kind {
+            GLB_JSON_CHUNK => json = Some(chunk),
+            GLB_BIN_CHUNK => bin = Some(chunk),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    Some((json?, bin))
+}
+
+/// Where a glTF buffer view's bytes are, as read from the document. Checked against the buffer
+/// itself by `slice`, so every reader of buffer views shares the same bounds checks.
+pub(crate) struct BufferView {
+    pub(crate) buffer: usize,
+    pub(crate) stride: Option<usize>,
+    offset: usize,
+    length: usize,
+}
+
+impl BufferView {
+    pub(crate) fn parse(document: &Value, view_index: usize) -> Result<Self, String> {
+        let view = &document["bufferViews"][view_index];
+        Ok(BufferView {
+            buffer: view["buffer"].as_u64().ok_or("buffer view without buffer")? as usize,
+            stride: view["byteStride"].as_u64().map(|stride| stride as usize),
+            offset: view["byteOffset"].as_u64().unwrap_or(0) as usize,
+            length: view["byteLength"].as_u64().ok_or("buffer view without length")? as usize,
+        })
+    }
+
+    // The view's bytes within its buffer; offsets come straight from the upload, so they may overflow
+    pub(crate) fn slice<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], String> {
+        self.offset
+            .checked_add(self.length)
+            .and_then(|end| buffer.get(self.offset..end))
+            .ok_or_else(|| "buffer view past end of buffer".to_string())
+    }
+}
+
+fn data_uri_mime_type(uri: &str) -> Option<&str> {
+    uri.strip_prefix("data:")?.split([';', ',']).next().filter(|mime| !mime.is_empty())
+}
+
+pub(crate) fn decode_data_uri(uri: &str) -> Result<Vec<u8>, String> {
+    let (header, payload) = uri.split_once(',').ok_or("has a malformed data URI")?;
+    if !header.ends_with(";base64") {
+        return Err("has a data URI that is not base64-encoded".to_string());
+    }
+    general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|e| format!("has invalid base64 data: {}", e))
+}
+
+fn buffer_view_bytes(
+    document: &Value,
+    view_index: usize,
+    bin: Option<&[u8]>,
+    buffers: &mut HashMap<usize, Result<Vec<u8>, String>>,
+) -> Result<Vec<u8>, String> {
+    let view = BufferView::parse(document, view_index).map_err(|e| format!("points at a {}", e))?;
+    let buffer = buffers.entry(view.buffer).or_insert_with(|| {
+        let buffer = &document["buffers"][view.buffer];
+        match buffer["uri"].as_str() {
+            Some(uri) if uri.starts_with("data:") => decode_data_uri(uri),
+            Some(uri) => Err(format!("is stored in external file {:?}, which is not uploaded with the model", uri)),
+            // A buffer without a URI is the GLB binary chunk
+            None => bin.map(<[u8]>::to_vec).ok_or_else(|| "points at a missing GLB binary chunk".to_string()),
+        }
+    });
+    let buffer = buffer.as_ref().map_err(Clone::clone)?;
+    view.slice(buffer).map(<[u8]>::to_vec).map_err(|e| format!("points at a {}", e))
+}
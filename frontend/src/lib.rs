@@ -1,470 +1,3916 @@
+mod ply;
+mod transport;
+
 use bevy::{
+    input::mouse::MouseWheel,
     pbr::{ CascadeShadowConfigBuilder, DirectionalLightShadowMap },
     prelude::*,
+    render::{
+        camera::{ RenderTarget, ScalingMode },
+        mesh::PrimitiveTopology,
+        primitives::Aabb,
+        render_asset::RenderAssetUsages,
+        render_resource::{ Extent3d, TextureDimension, TextureFormat, TextureUsages },
+        view::{ screenshot::{ Screenshot, ScreenshotCaptured }, RenderLayers },
+    },
+    window::WindowResolution,
 };
 use bevy_panorbit_camera::{ PanOrbitCameraPlugin, PanOrbitCamera };
-use bevy_egui::{ egui, EguiContexts, EguiPlugin };
-use serde::{ Deserialize, Serialize };
+use bevy_egui::{ egui, EguiContextSettings, EguiContexts, EguiPlugin };
 use std::{
-    time::Duration,
+    collections::{ HashMap, HashSet, VecDeque },
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
     fs::File,
-    io::Write,
-    path::Path,
+    io::{ Cursor, Write },
+    ops::Range,
+    path::{ Path, PathBuf },
 };
 use tokio::sync::mpsc;
-use tokio_tungstenite::{ connect_async_with_config, tungstenite::Message };
-use futures_util::{ SinkExt, StreamExt };
 use uuid::Uuid;
 use base64::{ Engine as _, engine::general_purpose };
-use rfd::FileDialog;
+use rfd::{ FileDialog, MessageDialog, MessageLevel };
+use serde::{ Deserialize, Serialize };
+use ply::PlyLoader;
+use transport::{ backend_url, PollingTransport, Transport, WebSocketTransport };
+use protocol::{
+    actions, ClientMessage, Collaborator, CommentResponse, HelloResponse, LayoutEntry, LayoutSummary, ModelResponse, ModelTransform,
+    ModelVersion, ObjExport, ServerMessage, ValidationReport, ViewState, GZIP_ENCODING, PROTOCOL_VERSION, VIEW_SHARE_INTERVAL,
+};
 
-#[derive(Serialize, Deserialize)]
-struct ModelRequest {
-    action: String,
-    id: Option<i32>,
-    name: Option<String>,
-    model_data: Option<String>, // base64-encoded
-}
+/// Detail levels the server can reduce glTF models to, finest first.
+const DETAIL_LEVELS: [(&str, &str); 3] = [("full", "Full"), ("medium", "Medium"), ("low", "Low")];
+/// Models spawned at once unless the user raises the limit; large catalogs are paged.
+const DEFAULT_MAX_RENDERED_MODELS: usize = 50;
+/// Default grid step for placement edits, in scene units and degrees.
+const DEFAULT_SNAP_TRANSLATION: f32 = 0.25;
+const DEFAULT_SNAP_ANGLE: f32 = 15.0;
+/// Distance between neighbouring cells when All Models is arranged in a grid, in scene units.
+const DEFAULT_GRID_SPACING: f32 = 2.0;
+/// Side length of the ground plane and its one-unit grid, in scene units.
+const DEFAULT_GROUND_SIZE: f32 = 20.0;
+/// Render layer of the ground, which the main camera sees and thumbnail cameras don't.
+pub const GROUND_LAYER: usize = 1;
+/// Side length of the overview map, in points.
+const MINIMAP_SIZE: f32 = 220.0;
+/// Side length of thumbnails rendered for the Model List, in pixels, and of the list's icons, in points.
+const THUMBNAIL_SIZE: u32 = 128;
+const THUMBNAIL_ICON_SIZE: f32 = 40.0;
+/// Frames the offscreen thumbnail camera renders before it is captured, so late materials make it in.
+const THUMBNAIL_SETTLE_FRAMES: u32 = 3;
+/// Window size in logical pixels unless `WINDOW_SIZE` (e.g. `1600x900`) says otherwise.
+const DEFAULT_WINDOW_SIZE: (f32, f32) = (1280.0, 720.0);
+/// Choices for the size of windows and text, on top of the display's own scaling.
+const UI_SCALES: [f32; 6] = [0.75, 1.0, 1.25, 1.5, 2.0, 2.5];
+/// How long the lighting has to stay unchanged before it is saved.
+const LIGHTING_SAVE_DELAY: Duration = Duration::from_secs(1);
+/// How long a toast stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+/// Server errors kept on screen before the oldest are dropped.
+const MAX_NOTIFICATIONS: usize = 5;
+/// Messages kept by the message log before the oldest are dropped.
+const MESSAGE_LOG_CAPACITY: usize = 500;
+/// Longest message body shown in full in the message log.
+const MESSAGE_LOG_PREVIEW_CHARS: usize = 400;
+
+/// Server defaults sent once per connection.
+#[derive(Event, Deref)]
+struct Hello(HelloResponse);
+
+type FileResult = (String, Result<(Vec<u8>, Option<String>), String>);
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct ModelResponse {
+#[derive(Clone)]
+struct ModelEntry {
     id: i32,
+    temp_path: String,
     name: Option<String>,
-    model_data: String, // base64-encoded
+    protected: bool,
+    format: String, // "gltf" or "ply"
+    transform_locked: bool, // pinned in place; Copy Transform can't move it
+    created_by: String,
+    version: i64, // sent with edits so the server can refuse ones made against an outdated copy
+    created_at: i64, // upload time in seconds since the Unix epoch, 0 when the server doesn't know
+    updated_at: i64,
+    transform: Option<Transform>, // the model's own placement, used outside layouts
 }
 
+impl ModelEntry {
+    // Name shown in lists, and the one searched
+    fn list_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("Model {}", self.id))
+    }
+}
+
+/// Marks model entities spawned from a PLY mesh rather than a glTF scene.
+#[derive(Component)]
+struct PlyModel;
+
+type PendingPlyMaterial = (With<PlyModel>, Without<MeshMaterial3d<StandardMaterial>>);
+
+/// Marks the ground plane spawned in setup. It isn't a model, so the scene updates never despawn it.
+#[derive(Component)]
+struct Ground;
+
+/// Grid lines drawn over the ground. Unlike the placement gizmo they are hidden behind models.
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct GroundGizmos;
+
 #[derive(Resource)]
 struct ModelState {
-    models: Vec<(i32, String, Option<String>)>, // (id, temp_file_path, name)
+    models: Vec<ModelEntry>,
     model_entities: Vec<(i32, Entity)>,
+    transforms: HashMap<i32, Transform>, // placement applied to each spawned model
+    locked: HashSet<i32>, // protected models whose bytes haven't been unlocked yet
+    detail: &'static str, // requested detail level for glTF models
+    respawn: HashSet<i32>, // models whose file changed under a spawned entity
+    unfetched: HashSet<i32>, // listed by a broadcast without their bytes; fetched once shown
+    fetching: HashSet<i32>, // unfetched models with a get_many request in flight
+    order: ModelOrder, // Model List order, kept across list refreshes
+}
+
+impl ModelState {
+    // Where a model goes: its entry in the shown layout or, outside layouts, its own placement
+    // ahead of wherever the last layout put it
+    fn placement(&self, id: i32, in_layout: bool) -> Transform {
+        let own = self.models.iter().find(|model| model.id == id).and_then(|model| model.transform).filter(|_| !in_layout);
+        own.or(self.transforms.get(&id).copied()).unwrap_or_default()
+    }
+
+    // Shows an edit right away; the server's broadcast then confirms it
+    fn set_placement(&mut self, id: i32, in_layout: bool, transform: Transform) {
+        if in_layout {
+            self.transforms.insert(id, transform);
+        } else if let Some(model) = self.models.iter_mut().find(|model| model.id == id) {
+            model.transform = Some(transform);
+        }
+    }
 }
 
 #[derive(Resource)]
-struct ModelUpdateReceiver(mpsc::Receiver<Vec<ModelResponse>>);
+struct ModelUpdateReceiver(mpsc::Receiver<ServerMessage>);
 
 #[derive(Resource)]
 struct UploadState {
     status: String,
     ws_tx: mpsc::Sender<String>,
-    file_tx: mpsc::Sender<(String, Result<(Vec<u8>, Option<String>), String>)>,
-    file_rx: mpsc::Receiver<(String, Result<(Vec<u8>, Option<String>), String>)>,
+    file_tx: mpsc::Sender<FileResult>,
+    file_rx: mpsc::Receiver<FileResult>,
     model_name: String,
-    selected_model: Option<i32>, // None for "All Models", Some(id) for single model
-}
-
-#[derive(Resource, Default)]
-struct LastSelectedModel {
-    id: Option<i32>,
+    author: String, // display name attributed on uploads and comments
+    check_before_upload: bool, // validate picked files and upload only once the report is accepted
+    pending_upload: Option<(Vec<u8>, Option<String>)>, // checked file and its name, until uploaded or dropped
+    validation: Option<ValidationReport>, // server's report on the pending upload
+    selected_models: HashSet<i32>, // models composing the scene, empty for "All Models"
+    list_cursor: Option<i32>, // Model List row highlighted by keyboard navigation
+    search: String, // Model List and Model Selection only list models whose name contains it, ignoring case
+    renaming: Option<(i32, String)>, // Model List row whose name is being edited, and the draft
+    confirm_delete: Option<i32>, // model whose Delete was clicked, until the confirmation is answered
+    scroll_to_cursor: bool,
+    read_only: bool, // the server's demo mode, set from the hello message
+    max_upload_bytes: Option<u64>, // the server's limit, from the hello message; older servers don't say
+    session_id: Option<String>, // this connection as the server knows it, from whoami
+    allowed_actions: Option<HashSet<String>>, // from whoami; until it arrives only read_only is known
+    collaborators: Vec<Collaborator>, // everyone connected to the server, this session included
+    following: Option<String>, // session whose camera and selection this client mirrors
+    downloads: HashSet<i32>, // models whose get_by_id reply is to be saved to disk
+    storage_warning: Option<String>, // banner text while the catalog is nearing its storage quota
+    toast: Option<(String, Instant)>, // short-lived notice and when it was raised
 }
 
-pub fn run() {
-    App::new()
-        .insert_resource(DirectionalLightShadowMap { size: 4096 })
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "PGS Renderman".to_string(),
-                ..Default::default()
-            }),
-            ..Default::default()
-        }))
-        .add_plugins(PanOrbitCameraPlugin)
-        .add_plugins(EguiPlugin)
-        .add_systems(Startup, setup)
-        .add_systems(Update, (
-            ui_system,
-            handle_model_updates,
-            handle_file_results,
-            update_scene_on_selection,
-            block_camera_on_egui
-        ))
-        .add_systems(Startup, debug_resources)
-        .run();
-}
+impl UploadState {
+    // Whether to offer controls that send this action
+    fn allows(&self, action: &str) -> bool {
+        match &self.allowed_actions {
+            Some(allowed) => allowed.contains(action),
+            None => !self.read_only || !actions::MUTATING.contains(&action),
+        }
+    }
 
-fn block_camera_on_egui(
-    mut camera_query: Query<&mut PanOrbitCamera>,
-    mut egui_context: EguiContexts,
-) {
-    let is_egui_active = egui_context.ctx_mut().wants_pointer_input();
-    for mut camera in camera_query.iter_mut() {
-        camera.enabled = !is_egui_active;
+    // The model shown on its own, if exactly one is selected
+    fn selected_model(&self) -> Option<i32> {
+        match self.selected_models.len() {
+            1 => self.selected_models.iter().next().copied(),
+            _ => None,
+        }
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn((
-        Transform::from_translation(Vec3::new(-6.0, 5.0, 1.5)),
-        PanOrbitCamera::default(),
-    ));
+/// Errors reported by the server, shown until dismissed.
+#[derive(Resource, Default)]
+struct Notifications {
+    errors: Vec<(String, usize)>, // message and how many times in a row it arrived
+}
 
-    commands.spawn((
-        DirectionalLight {
-            shadows_enabled: true,
-            ..default()
-        },
-        CascadeShadowConfigBuilder {
-            num_cascades: 1,
-            maximum_distance: 1.6,
-            ..default()
+impl Notifications {
+    fn push(&mut self, message: String) {
+        match self.errors.last_mut() {
+            Some((last, count)) if *last == message => *count += 1,
+            _ => {
+                self.errors.push((message, 1));
+                if self.errors.len() > MAX_NOTIFICATIONS {
+                    self.errors.remove(0);
+                }
+            }
         }
-        .build(),
-    ));
+    }
+}
 
-    commands.insert_resource(ModelState {
-        models: vec![],
-        model_entities: vec![],
-    });
+#[derive(Resource, Default)]
+struct LastSelectedModel {
+    ids: HashSet<i32>,
+}
 
-    let (update_tx, update_rx) = mpsc::channel(100);
-    let (ws_tx, mut ws_rx) = mpsc::channel(100);
-    let (file_tx, file_rx) = mpsc::channel(1);
-    commands.insert_resource(ModelUpdateReceiver(update_rx));
-    commands.insert_resource(UploadState {
-        status: "Ready".to_string(),
-        ws_tx,
-        file_tx,
-        file_rx,
-        model_name: String::new(),
-        selected_model: None, // Explicitly None for All Models
-    });
-    commands.insert_resource(LastSelectedModel::default());
+#[derive(Clone, Copy, PartialEq)]
+enum AccessCodeMode {
+    Unlock,
+    Set { protected: bool },
+    Download,
+    Revert { restore_version: i64 },
+}
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create Tokio runtime");
+#[derive(Resource, Default)]
+struct AccessCodePrompt {
+    target: Option<(i32, AccessCodeMode)>,
+    code: String,
+    new_code: String,
+}
 
-        rt.block_on(async {
-            let connection_id = Uuid::new_v4().to_string();
-            loop {
-                let mut config = tokio_tungstenite::tungstenite::protocol::WebSocketConfig::default();
-                config.max_message_size = Some(100 * 1024 * 1024);
-                config.max_frame_size = Some(100 * 1024 * 1024);
-                config.accept_unmasked_frames = false;
-                match connect_async_with_config("ws://127.0.0.1:8000/ws", Some(config), false).await {
-                    Ok((mut ws_stream, _)) => {
-                        let request = ModelRequest {
-                            action: "get_all".to_string(),
-                            id: None,
-                            name: None,
-                            model_data: None,
-                        };
-                        let request_str = serde_json::to_string(&request).unwrap();
-                        if let Err(e) = ws_stream
-                            .send(Message::Text(request_str.clone().into()))
-                            .await
-                        {
-                            error!("Connection {}: Failed to send initial get_all request: {}", connection_id, e);
-                            tokio::time::sleep(Duration::from_secs(5)).await;
-                            continue;
-                        }
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+enum DespawnStrategy {
+    #[default]
+    Diff, // only despawn/spawn the models whose visibility changed
+    RespawnAll, // rebuild every model entity on any change
+}
 
-                        let mut ping_interval = tokio::time::interval(Duration::from_secs(10));
-
-                        loop {
-                            tokio::select! {
-                                Some(message_result) = ws_stream.next() => {
-                                    match message_result {
-                                        Ok(Message::Text(text)) => {
-                                            match serde_json::from_str::<Vec<ModelResponse>>(&text) {
-                                                Ok(models) => {
-                                                    if let Err(e) = update_tx.send(models).await {
-                                                        error!("Connection {}: Failed to send models to channel: {}", connection_id, e);
-                                                        break;
-                                                    }
-                                                }
-                                                Err(e) => {
-                                                    error!("Connection {}: Failed to parse WebSocket message: {}", connection_id, e);
-                                                }
-                                            }
-                                        }
-                                        Ok(Message::Ping(_)) => {
-                                            if let Err(e) = ws_stream.send(Message::Pong(vec![].into())).await {
-                                                error!("Connection {}: Failed to send pong: {}", connection_id, e);
-                                                break;
-                                            }
-                                        }
-                                        Ok(_) => {}
-                                        Err(e) => {
-                                            error!("Connection {}: WebSocket error: {}", connection_id, e);
-                                            break;
-                                        }
-                                    }
-                                }
-                                _ = ping_interval.tick() => {
-                                    if let Err(e) = ws_stream.send(Message::Ping(vec![].into())).await {
-                                        error!("Connection {}: Failed to send ping: {}", connection_id, e);
-                                        break;
-                                    }
-                                }
-                                Some(upload_request) = ws_rx.recv() => {
-                                    if let Err(e) = ws_stream.send(Message::Text(upload_request.into())).await {
-                                        error!("Connection {}: Failed to send upload request: {}", connection_id, e);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        tokio::time::sleep(Duration::from_secs(5)).await;
-                    }
-                    Err(e) => {
-                        error!("Connection {}: WebSocket connection failed: {}", connection_id, e);
-                        tokio::time::sleep(Duration::from_secs(5)).await;
-                    }
-                }
-            }
-        });
-    });
+#[derive(Clone, Copy, PartialEq, Default, Debug, Serialize, Deserialize)]
+enum LightingPreset {
+    #[default]
+    Default, // Bevy's stock sun and ambient light
+    Studio, // angled key light with a bright fill, neutral backdrop
+    Outdoor, // high warm sun, sky-tinted ambient and background
+    Flat, // no directional light or shadows, even ambient only
 }
 
-fn ui_system(
-    mut contexts: EguiContexts,
-    state: Res<ModelState>,
-    mut upload_state: ResMut<UploadState>,
-) {
-    // Model List Window (default position, left side)
-    egui::Window::new("Model List").show(contexts.ctx_mut(), |ui| {
-        ui.label("Loaded Models:");
-        for (id, _path, name) in &state.models {
-            let display_name = name
-                .as_ref()
-                .map_or_else(|| format!("Model {}", id), |n| n.clone());
-            ui.horizontal(|ui| {
-                ui.label(format!("{}. {}", id, display_name));
-                if ui.button("Delete").clicked() {
-                    let request = ModelRequest {
-                        action: "delete".to_string(),
-                        id: Some(*id),
-                        name: None,
-                        model_data: None,
-                    };
-                    let request_str = serde_json::to_string(&request).unwrap();
-                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
-                        error!("Failed to send delete request for ID {}: {}", id, e);
-                    }
-                }
-            });
+impl LightingPreset {
+    const ALL: [LightingPreset; 4] = [
+        LightingPreset::Default,
+        LightingPreset::Studio,
+        LightingPreset::Outdoor,
+        LightingPreset::Flat,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            LightingPreset::Default => "Default",
+            LightingPreset::Studio => "Studio 3-point",
+            LightingPreset::Outdoor => "Outdoor sun",
+            LightingPreset::Flat => "Flat/none",
         }
-    });
+    }
 
-    // Upload Model Window (positioned on the right)
-    egui::Window::new("Upload Model")
-        .default_pos([1000.0, 50.0]) // Right side for 1280x720 window
-        .show(contexts.ctx_mut(), |ui| {
-            ui.label("Model Name:");
-            ui.text_edit_singleline(&mut upload_state.model_name);
-            ui.label("Select a .gltf file to upload:");
-            if ui.button("Choose File").clicked() {
-                if upload_state.status != "Uploading..." {
-                    upload_state.status = "Uploading...".to_string();
-                    let file_tx = upload_state.file_tx.clone();
-                    std::thread::spawn(move || {
-                        let (path_str, result) = if let Some(path) = FileDialog::new()
-                            .add_filter("GLTF Files", &["gltf"])
-                            .pick_file()
-                        {
-                            let path_str = path.to_string_lossy().to_string();
-                            let file_name = Path::new(&path_str)
-                                .file_stem()
-                                .and_then(|stem| stem.to_str())
-                                .map(|s| s.to_string());
-                            match std::fs::read(&path) {
-                                Ok(data) => (path_str, Ok((data, file_name))),
-                                Err(e) => (path_str, Err(format!("Failed to read file: {}", e))),
-                            }
-                        } else {
-                            ("".to_string(), Err("No file selected".to_string()))
-                        };
-                        if let Err(e) = file_tx.blocking_send((path_str, result)) {
-                            error!("Failed to send file result: {}", e);
-                        }
-                    });
-                }
+    // Where the Lighting window's controls start from when the preset is picked
+    fn sun(self) -> Sun {
+        match self {
+            LightingPreset::Default => Sun::from_position(Vec3::Z, Color::WHITE, light_consts::lux::AMBIENT_DAYLIGHT, true),
+            LightingPreset::Studio => Sun::from_position(Vec3::new(-4.0, 6.0, 4.0), Color::WHITE, light_consts::lux::OVERCAST_DAY, true),
+            LightingPreset::Outdoor => {
+                Sun::from_position(Vec3::new(3.0, 10.0, 2.0), Color::srgb(1.0, 0.96, 0.88), light_consts::lux::FULL_DAYLIGHT, true)
             }
-            ui.label(&upload_state.status);
-        });
+            LightingPreset::Flat => Sun::from_position(Vec3::Z, Color::WHITE, 0.0, false),
+        }
+    }
 
-    // Model Selection Window (centered)
-    egui::Window::new("Model Selection")
-        .default_pos([640.0, 360.0]) // Center for 1280x720 window
-        .show(contexts.ctx_mut(), |ui| {
-            let selected_text = match upload_state.selected_model {
-                None => "All Models".to_string(),
-                Some(id) => state
-                    .models
-                    .iter()
-                    .find(|(model_id, _, _)| *model_id == id)
-                    .map(|(_, _, name)| {
-                        name.as_ref()
-                            .map_or_else(|| format!("Model {}", id), |n| format!("{}: {}", id, n))
-                    })
-                    .unwrap_or_else(|| "Model Not Found".to_string()),
-            };
+    // Ambient color and brightness, and the background
+    fn ambient(self) -> (Color, f32, Color) {
+        match self {
+            LightingPreset::Default => (Color::WHITE, 80.0, ClearColor::default().0),
+            LightingPreset::Studio => (Color::WHITE, 500.0, Color::srgb(0.18, 0.18, 0.2)),
+            LightingPreset::Outdoor => (Color::srgb(0.6, 0.75, 1.0), 1500.0, Color::srgb(0.53, 0.73, 0.92)),
+            LightingPreset::Flat => (Color::WHITE, 3000.0, ClearColor::default().0),
+        }
+    }
+}
 
-            egui::ComboBox::from_label("Select Model")
-                .selected_text(selected_text)
-                .show_ui(ui, |ui| {
-                    // Option for All Models
-                    ui.selectable_value(&mut upload_state.selected_model, None, "All Models");
-                    // Options for individual models
-                    for (id, _, name) in &state.models {
-                        let display_name = name
-                            .as_ref()
-                            .map_or_else(|| format!("Model {}", id), |n| format!("{}: {}", id, n));
-                        ui.selectable_value(&mut upload_state.selected_model, Some(*id), display_name);
-                    }
-                });
-        });
+/// The directional light, as tuned in the Lighting window.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+struct Sun {
+    azimuth: f32, // degrees around the vertical axis, 0 shining from +Z towards -Z
+    elevation: f32, // degrees above the horizon
+    color: [u8; 3], // sRGB
+    illuminance: f32, // lux
+    shadows: bool,
 }
 
-fn handle_file_results(
-    mut upload_state: ResMut<UploadState>,
-) {
-    while let Ok((path, result)) = upload_state.file_rx.try_recv() {
-        match result {
-            Ok((data, file_name)) => {
-                // Set model_name to file_name if not user-edited
-                if upload_state.model_name.is_empty() {
-                    if let Some(name) = &file_name {
-                        upload_state.model_name = name.clone();
-                    }
-                }
-                let base64_data = general_purpose::STANDARD.encode(&data);
-                let request = ModelRequest {
-                    action: "insert".to_string(),
-                    id: None,
-                    name: if upload_state.model_name.is_empty() {
-                        file_name
-                    } else {
-                        Some(upload_state.model_name.clone())
-                    },
-                    model_data: Some(base64_data),
-                };
-                let request_str = serde_json::to_string(&request).unwrap();
-                if let Err(e) = upload_state.ws_tx.try_send(request_str) {
-                    upload_state.status = format!("Failed to queue upload: {}", e);
-                    error!("Failed to queue upload: {}", e);
-                } else {
-                    upload_state.status = "Upload queued".to_string();
-                    upload_state.model_name.clear(); // Clear name for next upload
-                }
-            }
-            Err(e) => {
-                upload_state.status = e.clone();
-                if e != "No file selected" {
-                    error!("File error for {}: {}", path, e);
-                }
-            }
+impl Sun {
+    // A light shining from the given point towards the origin
+    fn from_position(position: Vec3, color: Color, illuminance: f32, shadows: bool) -> Sun {
+        let position = position.normalize();
+        Sun {
+            azimuth: position.x.atan2(position.z).to_degrees(),
+            elevation: position.y.asin().to_degrees(),
+            color: color.to_srgba().to_u8_array_no_alpha(),
+            illuminance,
+            shadows,
         }
     }
-}
 
-fn update_scene_on_selection(
-    mut commands: Commands,
-    mut state: ResMut<ModelState>,
-    upload_state: Res<UploadState>,
-    mut last_selected: ResMut<LastSelectedModel>,
-    asset_server: Res<AssetServer>,
-) {
-    // Always check if scene needs update
-    let should_update = last_selected.id != upload_state.selected_model ||
-        state.model_entities.iter().map(|(id, _)| *id).collect::<Vec<_>>() !=
-        match upload_state.selected_model {
-            Some(id) => state.models.iter().filter(|(mid, _, _)| *mid == id).map(|(id, _, _)| *id).collect::<Vec<_>>(),
-            None => state.models.iter().map(|(id, _, _)| *id).collect::<Vec<_>>(),
-        };
+    fn rotation(&self) -> Quat {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        let position = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos());
+        Transform::from_translation(position).looking_at(Vec3::ZERO, Vec3::Y).rotation
+    }
+}
 
-    if should_update {
-        info!("Updating scene, selected: {:?}", upload_state.selected_model);
+/// Everything the Lighting window controls, saved so the next session looks the same.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Lighting {
+    preset: LightingPreset, // ambient light and background, and where the sun was last reset to
+    sun: Sun,
+    shadow_map: ShadowQuality,
+}
 
-        // Despawn all existing entities
-        for (_, entity) in state.model_entities.drain(..) {
-            info!("Despawning entity for model");
-            commands.entity(entity).despawn();
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting {
+            preset: LightingPreset::default(),
+            sun: LightingPreset::default().sun(),
+            shadow_map: ShadowQuality::default(),
         }
-        state.model_entities.clear();
+    }
+}
 
-        // Load models based on selection
-        let filtered_models = match upload_state.selected_model {
-            Some(selected_id) => state
-                .models
-                .iter()
-                .filter(|(id, _, _)| *id == selected_id)
-                .cloned()
-                .collect::<Vec<_>>(),
-            None => state.models.clone(),
-        };
+// Shadow map resolution; the cost of shadows grows with it, which weak GPUs notice first
+#[derive(Clone, Copy, PartialEq, Default, Debug, Serialize, Deserialize)]
+enum ShadowQuality {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
 
-        // Spawn filtered models
-        for (id, temp_path_str, _name) in filtered_models {
-            info!("Loading model ID={} at path {}", id, temp_path_str);
-            let entity = commands
-                .spawn(SceneRoot(asset_server.load(
-                    GltfAssetLabel::Scene(0).from_asset(temp_path_str.clone()),
-                )))
-                .id();
-            state.model_entities.push((id, entity));
+impl ShadowQuality {
+    const ALL: [ShadowQuality; 3] = [ShadowQuality::Low, ShadowQuality::Medium, ShadowQuality::High];
+
+    fn label(self) -> &'static str {
+        match self {
+            ShadowQuality::Low => "Low (1024)",
+            ShadowQuality::Medium => "Medium (2048)",
+            ShadowQuality::High => "High (4096)",
         }
+    }
 
-        // Update last selected
-        last_selected.id = upload_state.selected_model;
+    fn map_size(self) -> usize {
+        match self {
+            ShadowQuality::Low => 1024,
+            ShadowQuality::Medium => 2048,
+            ShadowQuality::High => 4096,
+        }
     }
 }
 
-fn handle_model_updates(
-    mut state: ResMut<ModelState>,
-    mut receiver: ResMut<ModelUpdateReceiver>,
-    mut upload_state: ResMut<UploadState>,
-    mut last_selected: ResMut<LastSelectedModel>,
-) {
-    while let Ok(models) = receiver.0.try_recv() {
-        info!("Received {} models, selected: {:?}", models.len(), upload_state.selected_model);
-
-        // Update upload status if new models detected
-        if !models.is_empty() && upload_state.status == "Upload queued" {
-            upload_state.status = "Upload successful".to_string();
-        }
+// Order of the Model List, which the All Models grid follows
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+enum ModelOrder {
+    #[default]
+    Uploaded, // ids are handed out in upload order
+    Newest, // most recently uploaded first
+    Name,
+    Uploader, // also groups the grid, one block of rows per uploader
+}
 
-        // Update state.models with all models to keep dropdown accurate
-        let mut new_models = vec![];
-        for model in models {
-            let temp_path = state
-                .models
-                .iter()
-                .find(|(id, _, _)| *id == model.id)
-                .map(|(_, path, _)| path.clone())
-                .unwrap_or_else(|| {
-                    let temp_dir = std::env::temp_dir();
-                    let temp_file_name = format!("model_{}.gltf", model.id);
-                    let temp_path = temp_dir.join(&temp_file_name);
-                    let temp_path_str = temp_path.to_str().expect("Invalid temp path").to_string();
+impl ModelOrder {
+    const ALL: [ModelOrder; 4] = [ModelOrder::Uploaded, ModelOrder::Newest, ModelOrder::Name, ModelOrder::Uploader];
 
-                    // Write to temp file
-                    match general_purpose::STANDARD.decode(&model.model_data) {
-                        Ok(model_data) => {
-                            let mut file = File::create(&temp_path).expect("Failed to create temp file");
-                            file.write_all(&model_data).expect("Failed to write temp file");
-                        }
-                        Err(e) => {
-                            error!("Failed to decode base64 for model ID={}: {}", model.id, e);
-                        }
-                    }
-                    temp_path_str
-                });
-            new_models.push((model.id, temp_path, model.name));
+    fn label(self) -> &'static str {
+        match self {
+            ModelOrder::Uploaded => "Upload order",
+            ModelOrder::Newest => "Newest first",
+            ModelOrder::Name => "Name",
+            ModelOrder::Uploader => "Uploader",
         }
-        state.models = new_models;
+    }
+}
 
-        // Trigger scene update
-        last_selected.id = None;
+// What dragging the placement gizmo's handles does to the model
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+enum GizmoMode {
+    #[default]
+    Move, // along the scene's axes
+    Rotate, // about the scene's axes
+    Scale, // along the model's own axes
+}
+
+impl GizmoMode {
+    const ALL: [GizmoMode; 3] = [GizmoMode::Move, GizmoMode::Rotate, GizmoMode::Scale];
+
+    fn label(self) -> &'static str {
+        match self {
+            GizmoMode::Move => "Move",
+            GizmoMode::Rotate => "Rotate",
+            GizmoMode::Scale => "Scale",
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ViewSettings {
+    despawn_strategy: DespawnStrategy,
+    lighting: Lighting, // loaded from lighting_path() at startup
+    reset_selection_on_reconnect: bool, // otherwise the selection survives a reconnect if the model still exists
+    auto_frame: bool, // frame models the first time they're shown, until the user moves the camera
+    max_rendered_models: usize, // cap on spawned models; the rest of the view is paged
+    render_page: usize, // page of the current view that is spawned
+    orthographic: bool, // no perspective distortion, for judging proportions
+    ui_scale: f32, // egui scale factor on top of the display's, starts at UI_SCALE if set
+    snap: bool, // round placement edits to the steps below before they're applied and sent
+    snap_translation: f32, // scene units
+    snap_angle: f32, // degrees
+    arrange_grid: bool, // spread All Models over a grid in list order instead of their saved placements
+    ground: bool, // show the ground plane and its grid
+    ground_size: f32, // scene units
+    grid_spacing: f32, // scene units
+    gizmo_mode: GizmoMode,
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        ViewSettings {
+            despawn_strategy: DespawnStrategy::default(),
+            lighting: Lighting::default(),
+            reset_selection_on_reconnect: false,
+            auto_frame: true,
+            max_rendered_models: DEFAULT_MAX_RENDERED_MODELS,
+            render_page: 0,
+            orthographic: false,
+            ui_scale: 1.0,
+            snap: true,
+            snap_translation: DEFAULT_SNAP_TRANSLATION,
+            snap_angle: DEFAULT_SNAP_ANGLE,
+            arrange_grid: false,
+            ground: true,
+            ground_size: DEFAULT_GROUND_SIZE,
+            grid_spacing: DEFAULT_GRID_SPACING,
+            gizmo_mode: GizmoMode::default(),
+        }
+    }
+}
+
+/// Tracks which models the camera has already been framed on.
+#[derive(Resource, Default)]
+struct AutoFrame {
+    framed: HashSet<i32>,
+    camera_moved: bool, // the user orbited, panned or zoomed; never reframe after that
+    requested: bool, // Frame Selected was clicked; frame everything shown once it has loaded
+}
+
+/// Model List thumbnails. The server caches one per model, keyed to the bytes it was rendered
+/// from; when it has none for the current bytes this client renders the model offscreen the
+/// next time it is shown on its own and uploads the result.
+#[derive(Resource, Default)]
+struct Thumbnails {
+    textures: HashMap<i32, egui::TextureHandle>,
+    requested: HashSet<i32>, // asked the server for; forgotten again when the thumbnail is invalidated
+    received: Vec<(i32, Vec<u8>)>, // PNGs waiting to become textures
+    stale: HashSet<i32>, // missing or outdated on the server, so rendered here
+    capture: Option<ThumbnailCapture>,
+}
+
+/// Offscreen camera rendering one model's thumbnail.
+struct ThumbnailCapture {
+    model_id: i32,
+    camera: Entity,
+    image: Handle<Image>,
+    frames: u32, // rendered so far; captured at THUMBNAIL_SETTLE_FRAMES
+}
+
+/// Details of the selected model shown in the Inspector window.
+#[derive(Resource, Default)]
+struct InspectorState {
+    model_id: Option<i32>, // model whose metadata and thread are shown
+    metadata: Vec<(String, String)>, // editable key/value rows
+    comments: Vec<CommentResponse>,
+    history: Vec<ModelVersion>, // oldest first
+    history_version: i64, // model version the history was last requested at
+    draft: String,
+    scene_nodes: Vec<SceneNode>, // the spawned hierarchy of the model, depth first
+    visibility_toggles: Vec<Entity>, // nodes whose visibility checkbox was clicked this frame
+}
+
+type SceneNodeData = (
+    Option<&'static Name>,
+    Has<Mesh3d>,
+    Has<Camera>,
+    Has<PointLight>,
+    Has<SpotLight>,
+    Has<DirectionalLight>,
+);
+
+/// One entity under a spawned model, as listed in the Inspector.
+struct SceneNode {
+    entity: Entity,
+    depth: usize, // 0 for the model's root entity
+    label: String, // name and the kinds of component it carries
+    visible: bool,
+}
+
+/// One protocol message seen by the connection thread.
+struct LoggedMessage {
+    at: Duration, // since the Unix epoch
+    sent: bool,
+    text: String,
+}
+
+/// Every request sent and message received, shared with the connection thread.
+/// Only present when the client is started with `MESSAGE_LOG=true`.
+#[derive(Resource, Clone)]
+struct MessageLog(Arc<Mutex<VecDeque<LoggedMessage>>>);
+
+impl MessageLog {
+    fn record(&self, sent: bool, text: &str) {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut messages = self.0.lock().unwrap();
+        if messages.len() == MESSAGE_LOG_CAPACITY {
+            messages.pop_front();
+        }
+        messages.push_back(LoggedMessage { at, sent, text: text.to_string() });
+    }
+}
+
+#[derive(Resource, Default)]
+struct LayoutState {
+    layouts: Vec<LayoutSummary>,
+    templates: Vec<LayoutSummary>, // shared starting arrangements, copied into a new layout when used
+    layout_name: String,
+    save_as_template: bool,
+    active: Option<(i32, String, Vec<i32>)>, // (layout id, name, model ids) while a layout is shown
+    copy_model: Option<i32>, // model picked for "Copy to Layout"
+    copy_target: Option<i32>, // destination layout id
+    transform_source: Option<i32>, // "Copy Transform" model whose placement is copied
+    transform_target: Option<i32>, // "Copy Transform" model that is moved
+    placement_model: Option<i32>, // model whose placement is edited, in the shown layout or its own
+}
+
+/// Handles drawn on the model picked under "Edit Placement".
+#[derive(Resource, Default)]
+struct PlacementGizmo {
+    pointer: Option<Ray3d>, // from the camera through the cursor, None while egui has the pointer
+    over_ui: bool,
+    target: Option<(Option<i32>, i32)>, // (layout id, None outside layouts; model id) whose handles are shown
+    handle_length: f32, // scene units, grows with camera distance so handles keep their size on screen
+    hovered: Option<usize>, // axis under the cursor, 0..3 for X, Y and Z
+    drag: Option<GizmoDrag>,
+}
+
+struct GizmoDrag {
+    axis: usize,
+    start: Transform, // placement when the handle was grabbed
+    grab: Vec3, // point on the handle that was grabbed
+}
+
+impl PlacementGizmo {
+    // The camera stays put while a handle is under the cursor, so grabbing one doesn't orbit
+    fn holds_pointer(&self) -> bool {
+        self.hovered.is_some() || self.drag.is_some()
+    }
+}
+
+// Models in the current view (the selected ones, a layout or the whole catalog), before paging
+fn displayed_model_ids(state: &ModelState, upload_state: &UploadState, layout_state: &LayoutState) -> Vec<i32> {
+    state
+        .models
+        .iter()
+        .map(|model| model.id)
+        .filter(|id| !state.locked.contains(id))
+        .filter(|id| match upload_state.selected_models.is_empty() {
+            false => upload_state.selected_models.contains(id),
+            true => layout_state
+                .active
+                .as_ref()
+                .is_none_or(|(_, _, layout_ids)| layout_ids.contains(id)),
+        })
+        .collect()
+}
+
+// Byte range of the first case-insensitive occurrence of the search in the text, empty for a blank search
+fn search_match(text: &str, search: &str) -> Option<Range<usize>> {
+    let search = search.trim().to_lowercase();
+    if search.is_empty() {
+        return Some(0..0);
+    }
+    text.char_indices().find_map(|(start, _)| {
+        // Lowercasing can change a character's length, so compare character by character
+        let mut lowered = String::new();
+        let mut end = start;
+        for c in text[start..].chars() {
+            if lowered.len() >= search.len() {
+                break;
+            }
+            lowered.extend(c.to_lowercase());
+            end += c.len_utf8();
+        }
+        (lowered == search).then_some(start..end)
+    })
+}
+
+// List label with the part of the name that matched the search highlighted
+fn highlighted(ui: &egui::Ui, prefix: &str, name: &str, matched: Range<usize>, suffix: &str) -> egui::WidgetText {
+    if matched.is_empty() {
+        return format!("{}{}{}", prefix, name, suffix).into();
+    }
+    let font_id = egui::TextStyle::Button.resolve(ui.style());
+    let plain = egui::TextFormat::simple(font_id.clone(), ui.visuals().text_color());
+    let highlight = egui::TextFormat {
+        background: ui.visuals().selection.bg_fill,
+        ..egui::TextFormat::simple(font_id, ui.visuals().strong_text_color())
+    };
+    let mut job = egui::text::LayoutJob::default();
+    job.append(prefix, 0.0, plain.clone());
+    job.append(&name[..matched.start], 0.0, plain.clone());
+    job.append(&name[matched.clone()], 0.0, highlight);
+    job.append(&name[matched.end..], 0.0, plain.clone());
+    job.append(suffix, 0.0, plain);
+    job.into()
+}
+
+fn sort_models(models: &mut [ModelEntry], order: ModelOrder) {
+    match order {
+        ModelOrder::Uploaded => models.sort_by_key(|model| model.id),
+        // Undated models are the oldest ones, so they go last
+        ModelOrder::Newest => models.sort_by_key(|model| std::cmp::Reverse((model.created_at, model.id))),
+        // Unnamed models go last, in upload order
+        ModelOrder::Name => models.sort_by_cached_key(|model| (model.name.is_none(), model.name.as_ref().map(|name| name.to_lowercase()), model.id)),
+        ModelOrder::Uploader => models.sort_by(|a, b| a.created_by.cmp(&b.created_by).then(a.id.cmp(&b.id))),
+    }
+}
+
+// Lays the models out row by row in the given order, centred on the origin. When grouping by
+// uploader each uploader starts a new row, with an empty row between groups.
+fn grid_transforms(models: &[ModelEntry], ids: &[i32], order: ModelOrder, spacing: f32) -> HashMap<i32, Transform> {
+    let columns = (ids.len() as f32).sqrt().ceil().max(1.0) as usize;
+    let mut groups: Vec<Vec<i32>> = vec![];
+    let mut last_key = None;
+    for id in ids {
+        let key = match order {
+            ModelOrder::Uploader => models.iter().find(|model| model.id == *id).map(|model| model.created_by.as_str()),
+            _ => None,
+        };
+        if groups.is_empty() || key != last_key {
+            groups.push(vec![]);
+        }
+        groups.last_mut().unwrap().push(*id);
+        last_key = key;
+    }
+    let mut cells = vec![];
+    let mut row = 0;
+    for group in &groups {
+        for (index, id) in group.iter().enumerate() {
+            cells.push((*id, index % columns, row + index / columns));
+        }
+        row += group.len().div_ceil(columns) + 1;
+    }
+    let rows = row.saturating_sub(1);
+    let offset = Vec3::new((columns - 1) as f32, 0.0, rows.saturating_sub(1) as f32) * spacing / 2.0;
+    cells
+        .into_iter()
+        .map(|(id, column, row)| {
+            (id, Transform::from_translation(Vec3::new(column as f32 * spacing, 0.0, row as f32 * spacing) - offset))
+        })
+        .collect()
+}
+
+// Returns the page to spawn and its index, clamping a page left past the end by a shrinking view
+fn rendered_page<'a>(ids: &'a [i32], view_settings: &ViewSettings) -> (&'a [i32], usize) {
+    let per_page = view_settings.max_rendered_models.max(1);
+    let page = view_settings.render_page.min(ids.len().saturating_sub(1) / per_page);
+    (&ids[page * per_page..ids.len().min((page + 1) * per_page)], page)
+}
+
+fn layout_entry(model_id: i32, transform: &Transform) -> LayoutEntry {
+    LayoutEntry {
+        model_id,
+        translation: transform.translation.to_array(),
+        rotation: transform.rotation.to_array(),
+        scale: transform.scale.to_array(),
+    }
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0.0 { (value / step).round() * step } else { value }
+}
+
+// Rounds translation and yaw/pitch/roll to the grid when snapping is on; scale is kept as is
+fn snapped_transform(translation: Vec3, angles: Vec3, scale: Vec3, view_settings: &ViewSettings) -> Transform {
+    let (translation, angles) = if view_settings.snap {
+        (
+            translation.to_array().map(|value| snap(value, view_settings.snap_translation)),
+            angles.to_array().map(|value| snap(value, view_settings.snap_angle)),
+        )
+    } else {
+        (translation.to_array(), angles.to_array())
+    };
+    let [yaw, pitch, roll] = angles.map(f32::to_radians);
+    Transform {
+        translation: Vec3::from_array(translation),
+        rotation: Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll),
+        scale,
+    }
+}
+
+fn stored_transform(transform: &ModelTransform) -> Transform {
+    Transform {
+        translation: Vec3::from_array(transform.translation),
+        rotation: Quat::from_array(transform.rotation),
+        scale: Vec3::from_array(transform.scale),
+    }
+}
+
+// Sends a finished placement edit, to the layout it was made in or as the model's own placement
+fn send_placement(upload_state: &UploadState, state: &ModelState, layout_id: Option<i32>, id: i32, transform: &Transform) {
+    let request = match layout_id {
+        Some(layout_id) => ClientMessage::SetTransform {
+            target_id: layout_id,
+            entries: vec![layout_entry(id, transform)],
+        },
+        None => ClientMessage::Transform {
+            id,
+            version: state.models.iter().find(|model| model.id == id).map_or(0, |model| model.version),
+            transform: Some(ModelTransform {
+                translation: transform.translation.to_array(),
+                rotation: transform.rotation.to_array(),
+                scale: transform.scale.to_array(),
+            }),
+        },
+    };
+    let request_str = serde_json::to_string(&request).unwrap();
+    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+        error!("Failed to send {} request for ID {}: {}", request.action(), id, e);
+    }
+}
+
+fn entry_transform(entry: &LayoutEntry) -> Transform {
+    Transform {
+        translation: Vec3::from_array(entry.translation),
+        rotation: Quat::from_array(entry.rotation),
+        scale: Vec3::from_array(entry.scale),
+    }
+}
+
+// Parses WINDOW_SIZE as "<width>x<height>"
+fn window_size() -> (f32, f32) {
+    std::env::var("WINDOW_SIZE")
+        .ok()
+        .and_then(|value| {
+            let (width, height) = value.split_once('x')?;
+            Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+        })
+        .filter(|(width, height): &(f32, f32)| *width >= 1.0 && *height >= 1.0)
+        .unwrap_or(DEFAULT_WINDOW_SIZE)
+}
+
+/// The viewer as a standalone app: a window, Bevy's default plugins and the viewer plugin.
+pub fn run() {
+    install_panic_hook();
+    let (width, height) = window_size();
+    App::new()
+        .insert_resource(DirectionalLightShadowMap { size: 4096 })
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: "PGS Renderman".to_string(),
+                // Logical size, so HiDPI displays get a proportionally larger window
+                resolution: WindowResolution::new(width, height),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+        .add_plugins(Collaborative3dPlugin::default())
+        .run();
+}
+
+/// Everything the viewer adds to an app: the connection to the backend, the model scene and
+/// its lighting and ground, and the egui windows. Apps embedding it bring their own
+/// `DefaultPlugins`; egui and the orbit camera are added unless the app already has them.
+pub struct Collaborative3dPlugin {
+    /// Spawns the orbit camera the standalone viewer uses. Apps with their own camera turn this
+    /// off; the viewer then steers and frames any camera with a `PanOrbitCamera`, which also
+    /// needs `GROUND_LAYER` in its `RenderLayers` to show the ground.
+    pub spawn_camera: bool,
+}
+
+impl Default for Collaborative3dPlugin {
+    fn default() -> Self {
+        Collaborative3dPlugin { spawn_camera: true }
+    }
+}
+
+impl Plugin for Collaborative3dPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<PanOrbitCameraPlugin>() {
+            app.add_plugins(PanOrbitCameraPlugin);
+        }
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        if self.spawn_camera {
+            app.add_systems(Startup, spawn_camera);
+        }
+        app.init_asset_loader::<PlyLoader>()
+            .init_gizmo_group::<GroundGizmos>()
+            .add_event::<Hello>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, (
+                ui_system,
+                handle_model_updates,
+                handle_file_results,
+                update_scene_on_selection,
+                apply_model_transforms,
+                assign_ply_materials,
+                apply_lighting,
+                apply_projection,
+                apply_ui_scale,
+                apply_camera_defaults,
+                resync_after_reconnect,
+                block_camera_on_egui,
+                track_camera_input,
+                frame_new_models,
+                inspect_scene_nodes,
+                minimap_window,
+                notifications_window,
+                message_log_window
+            ))
+            .add_systems(Update, (
+                share_display_name,
+                share_view,
+                follow_collaborator,
+                load_thumbnails,
+                render_thumbnails,
+                lighting_window,
+                save_lighting,
+                apply_ground
+            ))
+            .add_systems(Update, (track_gizmo_pointer, drag_placement_gizmo, draw_placement_gizmo).chain())
+            .add_systems(Startup, debug_resources);
+    }
+}
+
+// Keeps the default panic report on stderr and also tells the user in a dialog,
+// since a crash otherwise just closes (or freezes) the window without explanation.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let reason = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        let location = info
+            .location()
+            .map(|l| format!(" ({}:{})", l.file(), l.line()))
+            .unwrap_or_default();
+        MessageDialog::new()
+            .set_level(MessageLevel::Error)
+            .set_title("PGS Renderman")
+            .set_description(format!("The viewer crashed: {}{}; restart to continue.", reason, location))
+            .show();
+    }));
+}
+
+fn block_camera_on_egui(
+    mut camera_query: Query<&mut PanOrbitCamera>,
+    mut egui_context: EguiContexts,
+    mut gizmo: ResMut<PlacementGizmo>,
+    upload_state: Res<UploadState>,
+) {
+    let is_egui_active = egui_context.ctx_mut().wants_pointer_input();
+    gizmo.over_ui = is_egui_active;
+    for mut camera in camera_query.iter_mut() {
+        // A followed collaborator steers the camera until Follow is switched off
+        camera.enabled = !is_egui_active && !gizmo.holds_pointer() && upload_state.following.is_none();
+    }
+}
+
+fn track_gizmo_pointer(
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    mut gizmo: ResMut<PlacementGizmo>,
+) {
+    let cursor = windows.iter().find_map(Window::cursor_position);
+    gizmo.pointer = match (cursor, cameras.get_single()) {
+        (Some(cursor), Ok((camera, camera_transform))) if !gizmo.over_ui || gizmo.drag.is_some() => {
+            camera.viewport_to_world(camera_transform, cursor).ok()
+        }
+        _ => None,
+    };
+}
+
+// The layout whose placements can be edited, or None for the models' own placements outside
+// layouts, with the models that may be moved. Nothing while a grid arrangement overrides them.
+fn placement_scope(
+    state: &ModelState,
+    upload_state: &UploadState,
+    layout_state: &LayoutState,
+    view_settings: &ViewSettings,
+) -> Option<(Option<i32>, Vec<i32>)> {
+    let (layout_id, ids) = match &layout_state.active {
+        Some((layout_id, _, ids)) if upload_state.allows(actions::SET_TRANSFORM) => (Some(*layout_id), ids.clone()),
+        None if upload_state.allows(actions::TRANSFORM)
+            && !(upload_state.selected_models.is_empty() && view_settings.arrange_grid) =>
+        {
+            (None, state.model_entities.iter().map(|(id, _)| *id).collect())
+        }
+        _ => return None,
+    };
+    let movable = ids
+        .into_iter()
+        .filter(|id| !state.models.iter().any(|model| model.id == *id && model.transform_locked))
+        .collect();
+    Some((layout_id, movable))
+}
+
+// Picks the handle under the cursor and applies drags to the model's placement, sending
+// the result once the mouse button is released
+fn drag_placement_gizmo(
+    mut gizmo: ResMut<PlacementGizmo>,
+    mut state: ResMut<ModelState>,
+    layout_state: Res<LayoutState>,
+    upload_state: Res<UploadState>,
+    view_settings: Res<ViewSettings>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+) {
+    let target = placement_scope(&state, &upload_state, &layout_state, &view_settings).and_then(|(layout_id, movable)| {
+        let id = layout_state.placement_model.filter(|id| movable.contains(id))?;
+        let spawned = state.model_entities.iter().any(|(spawned, _)| *spawned == id);
+        spawned.then_some((layout_id, id))
+    });
+    if gizmo.target != target {
+        gizmo.target = target;
+        gizmo.drag = None;
+    }
+    let (Some((layout_id, id)), Some(ray)) = (target, gizmo.pointer) else {
+        gizmo.hovered = None;
+        return;
+    };
+    let current = state.placement(id, layout_id.is_some());
+    if gizmo.drag.is_none() {
+        gizmo.handle_length = 0.15 * ray.origin.distance(current.translation).max(1.0);
+    }
+    let length = gizmo.handle_length;
+
+    if let Some(drag) = &gizmo.drag {
+        if !mouse_buttons.pressed(MouseButton::Left) {
+            // Everyone receives the snapped placement, so all clients agree on it
+            gizmo.drag = None;
+            send_placement(&upload_state, &state, layout_id, id, &current);
+            return;
+        }
+        let start = drag.start;
+        let axis = gizmo_axis(view_settings.gizmo_mode, drag.axis, &start);
+        let moved = match view_settings.gizmo_mode {
+            GizmoMode::Rotate => ray_plane_point(ray, start.translation, axis).map(|point| {
+                let (from, to) = (drag.grab - start.translation, point - start.translation);
+                let angle = from.cross(to).dot(axis).atan2(from.dot(to));
+                Transform { rotation: Quat::from_axis_angle(axis, angle) * start.rotation, ..start }
+            }),
+            GizmoMode::Move => ray_axis_distance(ray, start.translation, axis).map(|(along, _)| {
+                let grabbed = (drag.grab - start.translation).dot(axis);
+                Transform { translation: start.translation + axis * (along - grabbed), ..start }
+            }),
+            GizmoMode::Scale => ray_axis_distance(ray, start.translation, axis).map(|(along, _)| {
+                let grabbed = (drag.grab - start.translation).dot(axis);
+                let mut scale = start.scale;
+                scale[drag.axis] = (start.scale[drag.axis] * along / grabbed).max(0.01);
+                Transform { scale, ..start }
+            }),
+        };
+        if let Some(moved) = moved {
+            let (yaw, pitch, roll) = moved.rotation.to_euler(EulerRot::YXZ);
+            let angles = Vec3::new(yaw, pitch, roll).map(f32::to_degrees);
+            let transform = snapped_transform(moved.translation, angles, moved.scale, &view_settings);
+            if current != transform {
+                state.set_placement(id, layout_id.is_some(), transform);
+            }
+        }
+        return;
+    }
+
+    // The handle closest to the cursor ray, if any is within reach
+    let reach = 0.1 * length;
+    gizmo.hovered = (0..3)
+        .filter_map(|index| {
+            let axis = gizmo_axis(view_settings.gizmo_mode, index, &current);
+            let (miss, grab) = match view_settings.gizmo_mode {
+                GizmoMode::Rotate => {
+                    let point = ray_plane_point(ray, current.translation, axis)?;
+                    ((point.distance(current.translation) - length).abs(), point)
+                }
+                GizmoMode::Move | GizmoMode::Scale => {
+                    let (along, miss) = ray_axis_distance(ray, current.translation, axis)?;
+                    // Not too near the middle, where scaling would divide by almost nothing
+                    if !(length * 0.2..=length * 1.1).contains(&along) {
+                        return None;
+                    }
+                    (miss, current.translation + axis * along)
+                }
+            };
+            (miss < reach).then_some((index, miss, grab))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _, grab)| {
+            if mouse_buttons.just_pressed(MouseButton::Left) {
+                gizmo.drag = Some(GizmoDrag { axis: index, start: current, grab });
+            }
+            index
+        });
+}
+
+fn draw_placement_gizmo(
+    mut gizmos: Gizmos,
+    gizmo: Res<PlacementGizmo>,
+    state: Res<ModelState>,
+    view_settings: Res<ViewSettings>,
+) {
+    let Some((layout_id, id)) = gizmo.target else {
+        return;
+    };
+    let current = state.placement(id, layout_id.is_some());
+    let length = gizmo.handle_length;
+    let origin = current.translation;
+    let active = gizmo.drag.as_ref().map(|drag| drag.axis).or(gizmo.hovered);
+    for (index, color) in [Color::srgb(0.9, 0.2, 0.2), Color::srgb(0.2, 0.8, 0.2), Color::srgb(0.2, 0.4, 0.9)]
+        .into_iter()
+        .enumerate()
+    {
+        let color = if active == Some(index) { Color::srgb(1.0, 0.85, 0.1) } else { color };
+        let axis = gizmo_axis(view_settings.gizmo_mode, index, &current);
+        match view_settings.gizmo_mode {
+            GizmoMode::Move => {
+                gizmos.arrow(origin, origin + axis * length, color);
+            }
+            GizmoMode::Rotate => {
+                gizmos.circle(Isometry3d::new(origin, Quat::from_rotation_arc(Vec3::Z, axis)), length, color);
+            }
+            GizmoMode::Scale => {
+                let end = origin + axis * length;
+                gizmos.line(origin, end, color);
+                gizmos.cuboid(
+                    Transform::from_translation(end).with_rotation(current.rotation).with_scale(Vec3::splat(0.1 * length)),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+// Scaling follows the model's own axes; moving and rotating use the scene's
+fn gizmo_axis(mode: GizmoMode, index: usize, transform: &Transform) -> Vec3 {
+    let axis = [Vec3::X, Vec3::Y, Vec3::Z][index];
+    match mode {
+        GizmoMode::Scale => transform.rotation * axis,
+        GizmoMode::Move | GizmoMode::Rotate => axis,
+    }
+}
+
+// Where along the axis through `origin` the ray passes closest, and how far it misses by
+fn ray_axis_distance(ray: Ray3d, origin: Vec3, axis: Vec3) -> Option<(f32, f32)> {
+    let direction = *ray.direction;
+    let cos = direction.dot(axis);
+    let denominator = 1.0 - cos * cos;
+    if denominator < 1e-4 {
+        return None; // looking straight down the axis
+    }
+    let offset = ray.origin - origin;
+    let (ray_dot, axis_dot) = (direction.dot(offset), axis.dot(offset));
+    let distance = (cos * axis_dot - ray_dot) / denominator;
+    let along = (axis_dot - cos * ray_dot) / denominator;
+    (distance > 0.0).then(|| (along, (ray.get_point(distance) - (origin + axis * along)).length()))
+}
+
+// Where the ray meets the plane through `origin` facing `normal`
+fn ray_plane_point(ray: Ray3d, origin: Vec3, normal: Vec3) -> Option<Vec3> {
+    let distance = ray.intersect_plane(origin, InfinitePlane3d::new(normal))?;
+    Some(ray.get_point(distance))
+}
+
+// Any orbit, pan or zoom over the viewport means the user has taken over the camera
+fn track_camera_input(
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    cameras: Query<&PanOrbitCamera>,
+    mut auto_frame: ResMut<AutoFrame>,
+) {
+    let scrolled = wheel_events.read().count() > 0;
+    let pressed = mouse_buttons.get_just_pressed().next().is_some();
+    if (scrolled || pressed) && cameras.iter().any(|camera| camera.enabled) {
+        auto_frame.camera_moved = true;
+    }
+}
+
+// Points the camera at models shown for the first time, once their meshes have bounds
+fn frame_new_models(
+    state: Res<ModelState>,
+    view_settings: Res<ViewSettings>,
+    mut auto_frame: ResMut<AutoFrame>,
+    mut upload_state: ResMut<UploadState>,
+    children: Query<&Children>,
+    bounds: Query<(&Aabb, &GlobalTransform)>,
+    mut cameras: Query<(&mut PanOrbitCamera, &Projection)>,
+) {
+    let requested = auto_frame.requested;
+    if requested && state.model_entities.is_empty() {
+        auto_frame.requested = false;
+        upload_state.toast = Some(("There are no models on screen to frame".to_string(), Instant::now()));
+        return;
+    }
+    if !requested && (!view_settings.auto_frame || auto_frame.camera_moved) {
+        return;
+    }
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    let mut loaded = vec![];
+    for (id, entity) in &state.model_entities {
+        if !requested && auto_frame.framed.contains(id) {
+            continue;
+        }
+        // Scenes spawn their meshes a few frames after the root entity
+        if let Some((model_min, model_max)) = world_bounds(*entity, &children, &bounds) {
+            min = min.min(model_min);
+            max = max.max(model_max);
+            loaded.push(*id);
+        }
+    }
+    // A requested framing waits until every model on screen has spawned its meshes
+    if loaded.is_empty() || (requested && loaded.len() < state.model_entities.len()) {
+        return;
+    }
+    auto_frame.framed.extend(loaded);
+    auto_frame.requested = false;
+    for (mut camera, projection) in &mut cameras {
+        frame_camera(&mut camera, projection, min, max);
+    }
+}
+
+// World-space box around a model's meshes, None until the first mesh has spawned
+fn world_bounds(entity: Entity, children: &Query<&Children>, bounds: &Query<(&Aabb, &GlobalTransform)>) -> Option<(Vec3, Vec3)> {
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    let mut found = false;
+    for (aabb, transform) in std::iter::once(entity)
+        .chain(children.iter_descendants(entity))
+        .filter_map(|entity| bounds.get(entity).ok())
+    {
+        // Box around the eight transformed corners
+        let (center, half) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+        for corner in 0..8 {
+            let sign = Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let point = transform.transform_point(center + half * sign);
+            min = min.min(point);
+            max = max.max(point);
+        }
+        found = true;
+    }
+    found.then_some((min, max))
+}
+
+// Points the camera at the box and backs off until all of it is in view
+fn frame_camera(camera: &mut PanOrbitCamera, projection: &Projection, min: Vec3, max: Vec3) {
+    let radius = (max - min).length() / 2.0;
+    if !radius.is_finite() || radius <= f32::EPSILON {
+        return;
+    }
+    let fov = match projection {
+        Projection::Perspective(perspective) => perspective.fov,
+        // apply_projection keeps the orthographic scale in step with the default field of view
+        _ => PerspectiveProjection::default().fov,
+    };
+    camera.target_focus = (min + max) / 2.0;
+    camera.target_radius = radius / (fov / 2.0).sin() * 1.1;
+    camera.force_update = true;
+}
+
+// Asks the server for the thumbnail of each listed model and turns the PNGs that arrive into textures
+fn load_thumbnails(
+    mut contexts: EguiContexts,
+    state: Res<ModelState>,
+    upload_state: Res<UploadState>,
+    mut thumbnails: ResMut<Thumbnails>,
+) {
+    // Protected models stay text-only, a preview would give away what the code guards
+    for model in state.models.iter().filter(|model| !model.protected) {
+        if !thumbnails.requested.insert(model.id) {
+            continue;
+        }
+        let request = ClientMessage::GetThumbnail {
+            id: model.id,
+            access_code: None,
+        };
+        let request_str = serde_json::to_string(&request).unwrap();
+        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+            error!("Failed to send thumbnail request for ID {}: {}", model.id, e);
+            // Try again next frame
+            thumbnails.requested.remove(&model.id);
+            break;
+        }
+    }
+    for (id, png) in std::mem::take(&mut thumbnails.received) {
+        match image::load_from_memory_with_format(&png, image::ImageFormat::Png) {
+            Ok(decoded) => {
+                let rgba = decoded.to_rgba8();
+                let size = [rgba.width() as usize, rgba.height() as usize];
+                let color_image = egui::ColorImage::from_rgba_unmultiplied(size, rgba.as_raw());
+                let texture = contexts.ctx_mut().load_texture(format!("thumbnail-{}", id), color_image, egui::TextureOptions::LINEAR);
+                thumbnails.textures.insert(id, texture);
+            }
+            Err(e) => warn!("Ignoring unreadable thumbnail for model ID={}: {}", id, e),
+        }
+    }
+}
+
+// Renders a model whose thumbnail is missing or stale once it is loaded and shown on its own,
+// then captures the image and uploads it
+fn render_thumbnails(
+    mut commands: Commands,
+    (state, upload_state): (Res<ModelState>, Res<UploadState>),
+    mut thumbnails: ResMut<Thumbnails>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    roots: Query<(Option<&SceneRoot>, Option<&Mesh3d>)>,
+    (children, bounds): (Query<&Children>, Query<(&Aabb, &GlobalTransform)>),
+) {
+    let shown = match state.model_entities.as_slice() {
+        [(id, entity)] => Some((*id, *entity)),
+        _ => None,
+    };
+    if let Some(capture) = thumbnails.capture.as_mut() {
+        if shown.map(|(id, _)| id) != Some(capture.model_id) {
+            // The model left the screen before its image was taken
+            commands.entity(capture.camera).despawn_recursive();
+            thumbnails.capture = None;
+            return;
+        }
+        capture.frames += 1;
+        if capture.frames == THUMBNAIL_SETTLE_FRAMES {
+            let (model_id, camera) = (capture.model_id, capture.camera);
+            commands
+                .spawn(Screenshot::image(capture.image.clone()))
+                .observe(move |trigger: Trigger<ScreenshotCaptured>, mut commands: Commands, mut thumbnails: ResMut<Thumbnails>, upload_state: Res<UploadState>| {
+                    if thumbnails.capture.as_ref().is_none_or(|capture| capture.camera != camera) {
+                        return;
+                    }
+                    commands.entity(camera).despawn_recursive();
+                    thumbnails.capture = None;
+                    thumbnails.stale.remove(&model_id);
+                    let mut png = Cursor::new(Vec::new());
+                    let encoded = trigger
+                        .event()
+                        .0
+                        .clone()
+                        .try_into_dynamic()
+                        .map_err(|e| e.to_string())
+                        .and_then(|image| image.to_rgb8().write_to(&mut png, image::ImageFormat::Png).map_err(|e| e.to_string()));
+                    if let Err(e) = encoded {
+                        error!("Failed to encode thumbnail for model ID={}: {}", model_id, e);
+                        return;
+                    }
+                    let png = png.into_inner();
+                    let request = ClientMessage::SetThumbnail {
+                        id: model_id,
+                        thumbnail_data: general_purpose::STANDARD.encode(&png),
+                        access_code: None, // only unprotected models get thumbnails
+                    };
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to upload thumbnail for model ID={}: {}", model_id, e);
+                    }
+                    thumbnails.received.push((model_id, png));
+                });
+        }
+        return;
+    }
+    let Some((id, entity)) = shown.filter(|(id, _)| thumbnails.stale.contains(id)) else {
+        return;
+    };
+    if !upload_state.allows(actions::SET_THUMBNAIL) {
+        return;
+    }
+    // Wait for textures too, or the thumbnail shows the model untextured
+    let loaded = match roots.get(entity) {
+        Ok((Some(scene), _)) => asset_server.is_loaded_with_dependencies(&scene.0),
+        Ok((_, Some(mesh))) => asset_server.is_loaded_with_dependencies(&mesh.0),
+        _ => false,
+    };
+    let Some((min, max)) = loaded.then(|| world_bounds(entity, &children, &bounds)).flatten() else {
+        return;
+    };
+    let radius = (max - min).length() / 2.0;
+    if !radius.is_finite() || radius <= f32::EPSILON {
+        thumbnails.stale.remove(&id);
+        return;
+    }
+    let size = Extent3d { width: THUMBNAIL_SIZE, height: THUMBNAIL_SIZE, depth_or_array_layers: 1 };
+    let mut target = Image::new_fill(size, TextureDimension::D2, &[0, 0, 0, 255], TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::default());
+    target.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    let image = images.add(target);
+    // Three-quarter view from far enough back that the whole model fits
+    let center = (min + max) / 2.0;
+    let distance = radius / (PerspectiveProjection::default().fov / 2.0).sin() * 1.1;
+    let camera = commands
+        .spawn((
+            Camera3d::default(),
+            Camera {
+                target: RenderTarget::Image(image.clone()),
+                order: -1,
+                clear_color: ClearColorConfig::Custom(Color::srgb(0.2, 0.2, 0.22)),
+                ..default()
+            },
+            Transform::from_translation(center + Vec3::new(1.0, 0.8, 1.0).normalize() * distance).looking_at(center, Vec3::Y),
+        ))
+        .id();
+    info!("Rendering thumbnail for model ID={}", id);
+    thumbnails.capture = Some(ThumbnailCapture { model_id: id, camera, image, frames: 0 });
+}
+
+// Applies the Inspector's visibility toggles and lists the selected model's entity hierarchy for it
+fn inspect_scene_nodes(
+    state: Res<ModelState>,
+    mut inspector: ResMut<InspectorState>,
+    children: Query<&Children>,
+    nodes: Query<SceneNodeData>,
+    mut visibilities: Query<&mut Visibility>,
+) {
+    for entity in std::mem::take(&mut inspector.visibility_toggles) {
+        if let Ok(mut visibility) = visibilities.get_mut(entity) {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+    let root = inspector
+        .model_id
+        .and_then(|id| state.model_entities.iter().find(|(model_id, _)| *model_id == id))
+        .map(|(_, entity)| *entity);
+    let mut scene_nodes = vec![];
+    let mut stack: Vec<(Entity, usize)> = root.into_iter().map(|entity| (entity, 0)).collect();
+    while let Some((entity, depth)) = stack.pop() {
+        let Ok((name, mesh, camera, point_light, spot_light, directional_light)) = nodes.get(entity) else {
+            continue;
+        };
+        let mut label = name.map_or_else(|| format!("Entity {}", entity.index()), |name| name.to_string());
+        let kinds: Vec<&str> = [
+            (mesh, "mesh"),
+            (camera, "camera"),
+            (point_light || spot_light || directional_light, "light"),
+        ]
+        .into_iter()
+        .filter_map(|(has, kind)| has.then_some(kind))
+        .collect();
+        if !kinds.is_empty() {
+            label = format!("{} [{}]", label, kinds.join(", "));
+        }
+        scene_nodes.push(SceneNode {
+            entity,
+            depth,
+            label,
+            visible: visibilities.get(entity).is_ok_and(|visibility| *visibility != Visibility::Hidden),
+        });
+        if let Ok(node_children) = children.get(entity) {
+            // Reversed so the first child is popped, and listed, first
+            stack.extend(node_children.iter().rev().map(|child| (*child, depth + 1)));
+        }
+    }
+    inspector.scene_nodes = scene_nodes;
+}
+
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn((
+        Transform::from_translation(Vec3::new(-6.0, 5.0, 1.5)),
+        PanOrbitCamera::default(),
+        RenderLayers::from_layers(&[0, GROUND_LAYER]),
+    ));
+}
+
+fn setup(
+    mut commands: Commands,
+    mut gizmo_config: ResMut<GizmoConfigStore>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // The placement gizmo stays visible through the model it's attached to
+    gizmo_config.config_mut::<DefaultGizmoConfigGroup>().0.depth_bias = -1.0;
+    gizmo_config.config_mut::<GroundGizmos>().0.render_layers = RenderLayers::layer(GROUND_LAYER);
+
+    // A unit plane, scaled to the ground size by apply_ground
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(0.5)))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgb(0.3, 0.3, 0.32),
+            perceptual_roughness: 1.0,
+            ..default()
+        })),
+        Transform::from_scale(Vec3::splat(DEFAULT_GROUND_SIZE)),
+        RenderLayers::layer(GROUND_LAYER),
+        Ground,
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
+        CascadeShadowConfigBuilder {
+            num_cascades: 1,
+            maximum_distance: 1.6,
+            ..default()
+        }
+        .build(),
+    ));
+
+    commands.insert_resource(ModelState {
+        models: vec![],
+        model_entities: vec![],
+        transforms: HashMap::new(),
+        locked: HashSet::new(),
+        detail: "full",
+        respawn: HashSet::new(),
+        unfetched: HashSet::new(),
+        fetching: HashSet::new(),
+        order: ModelOrder::default(),
+    });
+
+    let (update_tx, update_rx) = mpsc::channel(100);
+    let (ws_tx, mut ws_rx) = mpsc::channel(100);
+    let (file_tx, file_rx) = mpsc::channel(1);
+    commands.insert_resource(ModelUpdateReceiver(update_rx));
+    commands.insert_resource(UploadState {
+        status: "Ready".to_string(),
+        ws_tx,
+        file_tx,
+        file_rx,
+        model_name: String::new(),
+        author: String::new(),
+        check_before_upload: false,
+        pending_upload: None,
+        validation: None,
+        selected_models: HashSet::new(), // Empty for All Models
+        list_cursor: None,
+        search: String::new(),
+        renaming: None,
+        confirm_delete: None,
+        scroll_to_cursor: false,
+        read_only: false,
+        max_upload_bytes: None,
+        session_id: None,
+        allowed_actions: None,
+        collaborators: Vec::new(),
+        following: None,
+        downloads: HashSet::new(),
+        storage_warning: None,
+        toast: None,
+    });
+    commands.insert_resource(LastSelectedModel::default());
+    commands.insert_resource(Notifications::default());
+    commands.insert_resource(PlacementGizmo::default());
+    commands.insert_resource(LayoutState::default());
+    commands.insert_resource(AccessCodePrompt::default());
+    let ui_scale = std::env::var("UI_SCALE").ok().and_then(|value| value.parse::<f32>().ok()).filter(|scale| scale.is_finite());
+    commands.insert_resource(ViewSettings {
+        ui_scale: ui_scale.map_or(1.0, |scale| scale.clamp(UI_SCALES[0], UI_SCALES[UI_SCALES.len() - 1])),
+        lighting: load_lighting(),
+        ..Default::default()
+    });
+    commands.insert_resource(AutoFrame::default());
+    commands.insert_resource(Thumbnails::default());
+    commands.insert_resource(InspectorState::default());
+
+    let message_log = std::env::var("MESSAGE_LOG")
+        .is_ok_and(|value| value.parse().unwrap_or(false))
+        .then(|| MessageLog(Arc::new(Mutex::new(VecDeque::new()))));
+    if let Some(message_log) = &message_log {
+        commands.insert_resource(message_log.clone());
+    }
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to create Tokio runtime");
+
+        rt.block_on(async {
+            let connection_id = Uuid::new_v4().to_string();
+            info!("Connection {}: Using backend {}", connection_id, backend_url());
+            loop {
+                match WebSocketTransport::connect().await {
+                    Ok(transport) => {
+                        run_connection(transport, &connection_id, &update_tx, &mut ws_rx, message_log.as_ref()).await;
+                    }
+                    Err(e) => {
+                        error!("Connection {}: WebSocket connection failed: {}", connection_id, e);
+                        match PollingTransport::connect().await {
+                            Ok(transport) => {
+                                warn!("Connection {}: Falling back to HTTP long polling", connection_id);
+                                run_connection(transport, &connection_id, &update_tx, &mut ws_rx, message_log.as_ref()).await;
+                            }
+                            Err(e) => error!("Connection {}: HTTP polling fallback failed: {}", connection_id, e),
+                        }
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+    });
+}
+
+// Waits for the hello, opting in to compressed messages if it offers them, requests the
+// initial state, then relays messages both ways until the connection drops
+async fn run_connection<T: Transport>(
+    mut transport: T,
+    connection_id: &str,
+    update_tx: &mpsc::Sender<ServerMessage>,
+    ws_rx: &mut mpsc::Receiver<String>,
+    message_log: Option<&MessageLog>,
+) {
+    // The server speaks first, so the large model list below can already arrive compressed
+    let hello = match transport.recv().await {
+        Ok(text) => parse_server_message(&text, connection_id, message_log),
+        Err(e) => {
+            error!("Connection {}: Connection error: {}", connection_id, e);
+            return;
+        }
+    };
+    let compress = T::COMPRESSION &&
+        matches!(&hello, Some(ServerMessage::Hello { hello }) if hello.compression.iter().any(|encoding| encoding == GZIP_ENCODING));
+    if let Some(hello) = hello {
+        if update_tx.send(hello).await.is_err() {
+            return;
+        }
+    }
+    let mut requests = vec![];
+    if compress {
+        requests.push(ClientMessage::SetCompression { encoding: Some(GZIP_ENCODING.to_string()) });
+    }
+    // Bytes are fetched per model once it is shown
+    requests.extend([ClientMessage::ListMetadata, ClientMessage::ListLayouts, ClientMessage::ListTemplates, ClientMessage::Whoami]);
+    for request in requests {
+        let request_str = serde_json::to_string(&request).unwrap();
+        if let Some(message_log) = message_log {
+            message_log.record(true, &request_str);
+        }
+        if let Err(e) = transport.send(request_str).await {
+            error!("Connection {}: Failed to send initial {} request: {}", connection_id, request.action(), e);
+            return;
+        }
+    }
+
+    let mut ping_interval = tokio::time::interval(Duration::from_secs(10));
+
+    loop {
+        tokio::select! {
+            message_result = transport.recv() => {
+                match message_result {
+                    Ok(text) => {
+                        if let Some(message) = parse_server_message(&text, connection_id, message_log) {
+                            if let Err(e) = update_tx.send(message).await {
+                                error!("Connection {}: Failed to send message to channel: {}", connection_id, e);
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Connection {}: Connection error: {}", connection_id, e);
+                        break;
+                    }
+                }
+            }
+            _ = ping_interval.tick() => {
+                if let Err(e) = transport.keep_alive().await {
+                    error!("Connection {}: {}", connection_id, e);
+                    break;
+                }
+            }
+            Some(upload_request) = ws_rx.recv() => {
+                if let Some(message_log) = message_log {
+                    message_log.record(true, &upload_request);
+                }
+                if let Err(e) = transport.send(upload_request).await {
+                    error!("Connection {}: Failed to send upload request: {}", connection_id, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn parse_server_message(text: &str, connection_id: &str, message_log: Option<&MessageLog>) -> Option<ServerMessage> {
+    if let Some(message_log) = message_log {
+        message_log.record(false, text);
+    }
+    serde_json::from_str(text)
+        .map_err(|e| error!("Connection {}: Failed to parse {}-byte server message: {}", connection_id, text.len(), e))
+        .ok()
+}
+
+fn ui_system(
+    mut contexts: EguiContexts,
+    mut state: ResMut<ModelState>,
+    mut upload_state: ResMut<UploadState>,
+    mut layout_state: ResMut<LayoutState>,
+    mut access_prompt: ResMut<AccessCodePrompt>,
+    mut view_settings: ResMut<ViewSettings>,
+    (mut inspector, thumbnails, mut auto_frame): (ResMut<InspectorState>, Res<Thumbnails>, ResMut<AutoFrame>),
+) {
+    // Arrow keys move the list cursor and Enter selects it, unless a widget (e.g. a text field) has focus
+    let ctx = contexts.ctx_mut().clone();
+    if let Some(warning) = &upload_state.storage_warning {
+        egui::TopBottomPanel::top("storage_warning").show(&ctx, |ui| {
+            ui.colored_label(egui::Color32::from_rgb(230, 160, 0), format!("⚠ {}", warning));
+        });
+    }
+    if let Some((message, raised)) = &upload_state.toast {
+        if raised.elapsed() < TOAST_DURATION {
+            egui::Area::new(egui::Id::new("toast"))
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+                .show(&ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(message));
+                });
+            ctx.request_repaint_after(TOAST_DURATION);
+        } else {
+            upload_state.toast = None;
+        }
+    }
+    // The cursor only visits rows the search leaves in the list
+    let listed: Vec<i32> = state
+        .models
+        .iter()
+        .filter(|model| search_match(&model.list_name(), &upload_state.search).is_some())
+        .map(|model| model.id)
+        .collect();
+    if ctx.memory(|memory| memory.focused().is_none()) && !listed.is_empty() && upload_state.confirm_delete.is_none() {
+        let cursor_index = upload_state
+            .list_cursor
+            .and_then(|id| listed.iter().position(|listed_id| *listed_id == id));
+        let (up, down, enter) = ctx.input(|input| (
+            input.key_pressed(egui::Key::ArrowUp),
+            input.key_pressed(egui::Key::ArrowDown),
+            input.key_pressed(egui::Key::Enter),
+        ));
+        let last = listed.len() - 1;
+        let moved_to = match cursor_index {
+            _ if up == down => None,
+            None if down => Some(0),
+            None => Some(last),
+            Some(i) if down => Some((i + 1).min(last)),
+            Some(i) => Some(i.saturating_sub(1)),
+        };
+        if let Some(index) = moved_to {
+            upload_state.list_cursor = Some(listed[index]);
+            upload_state.scroll_to_cursor = true;
+        }
+        if enter {
+            if let Some(id) = upload_state.list_cursor.filter(|id| listed.contains(id)) {
+                upload_state.selected_models = HashSet::from([id]);
+                layout_state.active = None;
+                if state.locked.contains(&id) {
+                    access_prompt.target = Some((id, AccessCodeMode::Unlock));
+                }
+            }
+        }
+    }
+
+    // Model List Window (default position, left side)
+    egui::Window::new("Model List").show(contexts.ctx_mut(), |ui| {
+        let mut order = state.order;
+        egui::ComboBox::from_label("Sort")
+            .selected_text(order.label())
+            .show_ui(ui, |ui| {
+                for option in ModelOrder::ALL {
+                    ui.selectable_value(&mut order, option, option.label());
+                }
+            });
+        if order != state.order {
+            state.order = order;
+            sort_models(&mut state.models, order);
+        }
+        ui.add(egui::TextEdit::singleline(&mut upload_state.search).hint_text("Search by name"));
+        ui.label("Loaded Models:");
+        egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+            for model in &state.models {
+                let ModelEntry { id, name, protected, created_by, version, created_at, updated_at, .. } = model;
+                let display_name = model.list_name();
+                let Some(matched) = search_match(&display_name, &upload_state.search) else {
+                    continue;
+                };
+                ui.horizontal(|ui| {
+                    match thumbnails.textures.get(id) {
+                        Some(texture) => {
+                            ui.add(egui::Image::new(texture).fit_to_exact_size(egui::Vec2::splat(THUMBNAIL_ICON_SIZE)));
+                        }
+                        // Keep names lined up while thumbnails are missing
+                        None => {
+                            ui.add_space(THUMBNAIL_ICON_SIZE);
+                        }
+                    }
+                    let lock = if *protected { " [locked]" } else { "" };
+                    let selected = upload_state.selected_models.contains(id);
+                    let uploaded = match *created_at {
+                        0 => format!("Uploaded by {}", created_by),
+                        created_at => format!("Uploaded by {} {}", created_by, format_age(created_at)),
+                    };
+                    let changed = match *updated_at {
+                        updated_at if updated_at > *created_at => format!("\nChanged {}", format_age(updated_at)),
+                        _ => String::new(),
+                    };
+                    let label = highlighted(ui, &format!("{}. ", id), &display_name, matched, lock);
+                    let mut row = ui
+                        .selectable_label(selected, label)
+                        .on_hover_text(format!("{}{}", uploaded, changed));
+                    if *created_at > 0 {
+                        ui.weak(format_age(*created_at));
+                    }
+                    if upload_state.list_cursor == Some(*id) {
+                        row = row.highlight();
+                        if upload_state.scroll_to_cursor {
+                            row.scroll_to_me(None);
+                            upload_state.scroll_to_cursor = false;
+                        }
+                    }
+                    if row.clicked() {
+                        // Keep arrow keys driving the list rather than egui's focus navigation
+                        row.surrender_focus();
+                        upload_state.list_cursor = Some(*id);
+                        // Ctrl (Cmd on macOS) adds the model to the scene or takes it out again
+                        let toggle = ui.input(|input| input.modifiers.command);
+                        if toggle && selected {
+                            upload_state.selected_models.remove(id);
+                        } else {
+                            if !toggle {
+                                upload_state.selected_models.clear();
+                            }
+                            upload_state.selected_models.insert(*id);
+                            layout_state.active = None;
+                            if state.locked.contains(id) {
+                                access_prompt.target = Some((*id, AccessCodeMode::Unlock));
+                            }
+                        }
+                    }
+                    if *protected && state.locked.contains(id) && ui.button("Unlock").clicked() {
+                        access_prompt.target = Some((*id, AccessCodeMode::Unlock));
+                    }
+                    // Enter sends the new name; Escape or clicking elsewhere drops it
+                    let rename_id = egui::Id::new(("rename", *id));
+                    let mut renamed = None;
+                    if let Some((_, draft)) = upload_state.renaming.as_mut().filter(|(renaming, _)| renaming == id) {
+                        let field = ui.add(egui::TextEdit::singleline(draft).id(rename_id).desired_width(120.0));
+                        if field.lost_focus() {
+                            renamed = Some(ui.input(|input| input.key_pressed(egui::Key::Enter)).then(|| draft.clone()));
+                        }
+                    } else if upload_state.allows(actions::RENAME) && ui.button("Rename").clicked() {
+                        upload_state.renaming = Some((*id, name.clone().unwrap_or_default()));
+                        ui.memory_mut(|memory| memory.request_focus(rename_id));
+                    }
+                    if let Some(new_name) = renamed {
+                        upload_state.renaming = None;
+                        if let Some(new_name) = new_name.filter(|new_name| Some(new_name) != name.as_ref()) {
+                            // The server clears the name when it's blank
+                            let request = ClientMessage::Rename {
+                                id: *id,
+                                version: *version,
+                                name: Some(new_name),
+                            };
+                            let request_str = serde_json::to_string(&request).unwrap();
+                            if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                                error!("Failed to send rename request for ID {}: {}", id, e);
+                            }
+                        }
+                    }
+                    if ui.button("Download").on_hover_text("Save the uploaded file").clicked() {
+                        if *protected {
+                            access_prompt.target = Some((*id, AccessCodeMode::Download));
+                        } else {
+                            let request = ClientMessage::GetById {
+                                id: *id,
+                                access_code: None,
+                                lod: Some("full".to_string()),
+                            };
+                            let request_str = serde_json::to_string(&request).unwrap();
+                            if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                                error!("Failed to send download request for ID {}: {}", id, e);
+                            } else {
+                                upload_state.downloads.insert(*id);
+                            }
+                        }
+                    }
+                    if upload_state.allows(actions::SET_ACCESS_CODE) && ui.button("Access Code").clicked() {
+                        access_prompt.target = Some((*id, AccessCodeMode::Set { protected: *protected }));
+                    }
+                    if upload_state.allows(actions::DELETE) && ui.button("Delete").clicked() {
+                        upload_state.confirm_delete = Some(*id);
+                    }
+                });
+            }
+        });
+    });
+
+    // Deleting can't be undone and removes the model for everyone, so it takes a second click
+    if let Some(id) = upload_state.confirm_delete {
+        match state.models.iter().find(|model| model.id == id).map(ModelEntry::list_name) {
+            // Someone else deleted it first
+            None => upload_state.confirm_delete = None,
+            Some(name) => {
+                let modal = egui::Modal::new(egui::Id::new("confirm_delete")).show(&ctx, |ui| {
+                    ui.heading("Delete Model?");
+                    ui.label(format!("{} will be removed for everyone connected. This can't be undone.", name));
+                    ui.horizontal(|ui| {
+                        let delete = ui.button(egui::RichText::new("Delete").color(egui::Color32::RED)).clicked();
+                        (delete, ui.button("Cancel").clicked())
+                    })
+                    .inner
+                });
+                let (delete, cancel) = modal.inner;
+                if delete {
+                    let request = ClientMessage::Delete { id };
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to send delete request for ID {}: {}", id, e);
+                    }
+                }
+                // Escape or a click outside also cancels
+                if delete || cancel || modal.should_close() {
+                    upload_state.confirm_delete = None;
+                }
+            }
+        }
+    }
+
+    // Upload Model Window (positioned on the right)
+    if upload_state.allows(actions::INSERT) {
+        egui::Window::new("Upload Model")
+            .default_pos([1000.0, 50.0]) // Right side for 1280x720 window
+            .show(contexts.ctx_mut(), |ui| {
+                ui.label("Model Name:");
+                ui.text_edit_singleline(&mut upload_state.model_name);
+                ui.label("Your Name:");
+                ui.text_edit_singleline(&mut upload_state.author);
+                ui.label("Select a .gltf or .ply file to upload:");
+                ui.checkbox(&mut upload_state.check_before_upload, "Check before uploading");
+                if ui.button("Choose File").clicked() && upload_state.status != "Uploading..." {
+                    upload_state.status = "Uploading...".to_string();
+                    let file_tx = upload_state.file_tx.clone();
+                    std::thread::spawn(move || {
+                        let (path_str, result) = if let Some(path) = FileDialog::new()
+                            .add_filter("Model Files", &["gltf", "glb", "ply"])
+                            .add_filter("GLTF Files", &["gltf", "glb"])
+                            .add_filter("PLY Files", &["ply"])
+                            .pick_file()
+                        {
+                            let path_str = path.to_string_lossy().to_string();
+                            let file_name = Path::new(&path_str)
+                                .file_stem()
+                                .and_then(|stem| stem.to_str())
+                                .map(|s| s.to_string());
+                            match std::fs::read(&path) {
+                                Ok(data) => (path_str, Ok((data, file_name))),
+                                Err(e) => (path_str, Err(format!("Failed to read file: {}", e))),
+                            }
+                        } else {
+                            ("".to_string(), Err("No file selected".to_string()))
+                        };
+                        if let Err(e) = file_tx.blocking_send((path_str, result)) {
+                            error!("Failed to send file result: {}", e);
+                        }
+                    });
+                }
+                ui.label(&upload_state.status);
+                if let Some(report) = upload_state.validation.clone() {
+                    ui.separator();
+                    if report.valid {
+                        ui.colored_label(egui::Color32::GREEN, "Ready to upload");
+                    } else {
+                        ui.colored_label(egui::Color32::RED, "The server would refuse this file");
+                    }
+                    let stats = &report.stats;
+                    ui.label(format!("Format: {}", stats.format.as_deref().unwrap_or("unrecognized")));
+                    ui.label(format!(
+                        "Size: {:.1} MB, {:.1} MB stored with detail levels",
+                        megabytes(stats.upload_bytes),
+                        megabytes(stats.stored_bytes)
+                    ));
+                    if let Some(saved) = stats.texture_bytes_saved {
+                        ui.label(format!("Textures would be scaled down, saving {:.1} MB", megabytes(saved)));
+                    }
+                    if !stats.lods.is_empty() {
+                        ui.label(format!("Detail levels: {}", stats.lods.join(", ")));
+                    }
+                    for warning in &report.warnings {
+                        ui.colored_label(egui::Color32::YELLOW, warning);
+                    }
+                    for error in &report.errors {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(report.valid, egui::Button::new("Upload")).clicked() {
+                            if let Some((data, file_name)) = upload_state.pending_upload.take() {
+                                queue_upload(&mut upload_state, &data, file_name);
+                            }
+                            upload_state.validation = None;
+                        }
+                        if ui.button("Discard").clicked() {
+                            upload_state.pending_upload = None;
+                            upload_state.validation = None;
+                            upload_state.status = "Ready".to_string();
+                        }
+                    });
+                }
+            });
+    }
+
+    // Model Selection Window (centered)
+    egui::Window::new("Model Selection")
+        .default_pos([640.0, 360.0]) // Center for 1280x720 window
+        .show(contexts.ctx_mut(), |ui| {
+            let selected_text = match upload_state.selected_models.len() {
+                0 => match &layout_state.active {
+                    Some((_, layout_name, _)) => format!("Layout: {}", layout_name),
+                    None => "All Models".to_string(),
+                },
+                1 => upload_state
+                    .selected_model()
+                    .and_then(|id| state.models.iter().find(|model| model.id == id))
+                    .map(|model| {
+                        model.name.as_ref()
+                            .map_or_else(|| format!("Model {}", model.id), |n| format!("{}: {}", model.id, n))
+                    })
+                    .unwrap_or_else(|| "Model Not Found".to_string()),
+                count => format!("{} models", count),
+            };
+
+            // Stays open while models are ticked, so a scene can be composed in one go
+            egui::ComboBox::from_label("Select Models")
+                .selected_text(selected_text)
+                .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+                .show_ui(ui, |ui| {
+                    // Option for All Models
+                    let all = upload_state.selected_models.is_empty() && layout_state.active.is_none();
+                    if ui.selectable_label(all, "All Models").clicked() {
+                        upload_state.selected_models.clear();
+                        layout_state.active = None;
+                    }
+                    // Tick boxes for individual models
+                    for model in &state.models {
+                        let id = &model.id;
+                        let display_name = model.list_name();
+                        let Some(matched) = search_match(&display_name, &upload_state.search) else {
+                            continue;
+                        };
+                        let prefix = if model.name.is_some() { format!("{}: ", id) } else { String::new() };
+                        let mut checked = upload_state.selected_models.contains(id);
+                        if ui.checkbox(&mut checked, highlighted(ui, &prefix, &display_name, matched, "")).changed() {
+                            layout_state.active = None;
+                            if !checked {
+                                upload_state.selected_models.remove(id);
+                            } else {
+                                upload_state.selected_models.insert(*id);
+                                if state.locked.contains(id) {
+                                    access_prompt.target = Some((*id, AccessCodeMode::Unlock));
+                                }
+                            }
+                        }
+                    }
+                });
+            let frame = ui
+                .add_enabled(upload_state.following.is_none(), egui::Button::new("Frame Selected"))
+                .on_hover_text("Fit the models on screen in view")
+                .on_disabled_hover_text("The camera follows a collaborator");
+            if frame.clicked() {
+                auto_frame.requested = true;
+            }
+
+            let view_ids = displayed_model_ids(&state, &upload_state, &layout_state);
+            let per_page = view_settings.max_rendered_models.max(1);
+            if view_ids.len() > per_page {
+                let (page_ids, page) = rendered_page(&view_ids, &view_settings);
+                let first = page * per_page + 1;
+                ui.colored_label(
+                    egui::Color32::from_rgb(230, 160, 0),
+                    format!(
+                        "⚠ {} models exceed the limit of {}; showing {}–{}",
+                        view_ids.len(),
+                        per_page,
+                        first,
+                        first + page_ids.len() - 1
+                    ),
+                );
+                ui.horizontal(|ui| {
+                    let last_page = (view_ids.len() - 1) / per_page;
+                    if ui.add_enabled(page > 0, egui::Button::new("◀ Previous")).clicked() {
+                        view_settings.render_page = page - 1;
+                    }
+                    ui.label(format!("Page {} of {}", page + 1, last_page + 1));
+                    if ui.add_enabled(page < last_page, egui::Button::new("Next ▶")).clicked() {
+                        view_settings.render_page = page + 1;
+                    }
+                });
+            }
+        });
+
+    // Access Code Window (unlocking or changing a model's access code)
+    if let Some((model_id, mode)) = access_prompt.target {
+        let mut open = true;
+        let mut done = false;
+        egui::Window::new("Access Code")
+            .open(&mut open)
+            .show(contexts.ctx_mut(), |ui| {
+                let request = match mode {
+                    AccessCodeMode::Unlock => {
+                        ui.label(format!("Model {} is protected. Enter its access code:", model_id));
+                        ui.add(egui::TextEdit::singleline(&mut access_prompt.code).password(true));
+                        ui.button("Unlock").clicked().then(|| ClientMessage::GetById {
+                            id: model_id,
+                            access_code: Some(access_prompt.code.clone()),
+                            lod: None,
+                        })
+                    }
+                    AccessCodeMode::Download => {
+                        ui.label(format!("Model {} is protected. Enter its access code to download it:", model_id));
+                        ui.add(egui::TextEdit::singleline(&mut access_prompt.code).password(true));
+                        ui.button("Download").clicked().then(|| ClientMessage::GetById {
+                            id: model_id,
+                            access_code: Some(access_prompt.code.clone()),
+                            lod: Some("full".to_string()),
+                        })
+                    }
+                    AccessCodeMode::Revert { restore_version } => {
+                        ui.label(format!("Model {} is protected. Enter its access code to revert it to version {}:", model_id, restore_version));
+                        ui.add(egui::TextEdit::singleline(&mut access_prompt.code).password(true));
+                        ui.button("Revert").clicked().then(|| ClientMessage::Revert {
+                            id: model_id,
+                            version: state.models.iter().find(|model| model.id == model_id).map(|model| model.version).unwrap_or_default(),
+                            restore_version,
+                            access_code: Some(access_prompt.code.clone()),
+                        })
+                    }
+                    AccessCodeMode::Set { protected } => {
+                        if protected {
+                            ui.label("Current Code:");
+                            ui.add(egui::TextEdit::singleline(&mut access_prompt.code).password(true));
+                        }
+                        ui.label("New Code (leave empty to remove):");
+                        ui.add(egui::TextEdit::singleline(&mut access_prompt.new_code).password(true));
+                        ui.button("Apply").clicked().then(|| ClientMessage::SetAccessCode {
+                            id: model_id,
+                            version: state.models.iter().find(|model| model.id == model_id).map(|model| model.version).unwrap_or_default(),
+                            access_code: protected.then(|| access_prompt.code.clone()),
+                            new_access_code: Some(access_prompt.new_code.clone()),
+                        })
+                    }
+                };
+                if let Some(request) = request {
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to send {} request for ID {}: {}", request.action(), model_id, e);
+                    } else if mode == AccessCodeMode::Download {
+                        upload_state.downloads.insert(model_id);
+                    }
+                    done = true;
+                }
+            });
+        if done || !open {
+            access_prompt.target = None;
+            access_prompt.code.clear();
+            access_prompt.new_code.clear();
+        }
+    }
+
+    // View Settings Window
+    egui::Window::new("View Settings")
+        .default_pos([1000.0, 500.0])
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ComboBox::from_label("Scene Updates")
+                .selected_text(match view_settings.despawn_strategy {
+                    DespawnStrategy::Diff => "Only changed models",
+                    DespawnStrategy::RespawnAll => "Respawn all models",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut view_settings.despawn_strategy, DespawnStrategy::Diff, "Only changed models");
+                    ui.selectable_value(&mut view_settings.despawn_strategy, DespawnStrategy::RespawnAll, "Respawn all models");
+                });
+            let mut detail = state.detail;
+            egui::ComboBox::from_label("Detail")
+                .selected_text(DETAIL_LEVELS.iter().find(|(level, _)| *level == detail).map_or("Full", |(_, label)| *label))
+                .show_ui(ui, |ui| {
+                    for (level, label) in DETAIL_LEVELS {
+                        ui.selectable_value(&mut detail, level, label);
+                    }
+                });
+            if detail != state.detail {
+                // Refetch every loaded model at the new level; the server falls back when a level isn't stored
+                state.detail = detail;
+                let ids: Vec<i32> = state
+                    .models
+                    .iter()
+                    .filter(|model| !state.locked.contains(&model.id))
+                    .map(|model| model.id)
+                    .collect();
+                request_detail(&upload_state.ws_tx, ids, detail);
+            }
+            ui.checkbox(&mut view_settings.reset_selection_on_reconnect, "Reset selection after reconnecting");
+            ui.checkbox(&mut view_settings.auto_frame, "Frame models when first shown");
+            ui.checkbox(&mut view_settings.orthographic, "Orthographic projection");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut view_settings.ground, "Show ground");
+                ui.add_enabled(
+                    view_settings.ground,
+                    egui::DragValue::new(&mut view_settings.ground_size)
+                        .range(1.0..=1000.0)
+                        .speed(0.5)
+                        .prefix("Size: "),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut view_settings.arrange_grid, "Arrange All Models in a grid");
+                ui.add_enabled(
+                    view_settings.arrange_grid,
+                    egui::DragValue::new(&mut view_settings.grid_spacing)
+                        .range(0.1..=100.0)
+                        .speed(0.1)
+                        .prefix("Spacing: "),
+                );
+            });
+            // Picked from a list; a slider would rescale itself out from under the pointer mid-drag
+            egui::ComboBox::from_label("UI Scale")
+                .selected_text(format!("{:.0}%", view_settings.ui_scale * 100.0))
+                .show_ui(ui, |ui| {
+                    for scale in UI_SCALES {
+                        ui.selectable_value(&mut view_settings.ui_scale, scale, format!("{:.0}%", scale * 100.0));
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut view_settings.max_rendered_models)
+                    .range(1..=1000)
+                    .prefix("Max rendered models: "),
+            );
+            ui.checkbox(&mut view_settings.snap, "Snap placement edits to grid");
+            ui.add_enabled_ui(view_settings.snap, |ui| {
+                ui.add(
+                    egui::DragValue::new(&mut view_settings.snap_translation)
+                        .range(0.01..=100.0)
+                        .speed(0.01)
+                        .prefix("Move step: "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut view_settings.snap_angle)
+                        .range(1.0..=90.0)
+                        .prefix("Rotate step: ")
+                        .suffix("°"),
+                );
+            });
+        });
+
+    // Inspector Window (metadata and discussion thread for the selected model)
+    if let Some(model_id) = upload_state.selected_model() {
+        if inspector.model_id != Some(model_id) {
+            inspector.model_id = Some(model_id);
+            inspector.metadata.clear();
+            inspector.comments.clear();
+            inspector.history.clear();
+            inspector.history_version = 0;
+            for request in [ClientMessage::GetMetadata { id: model_id }, ClientMessage::ListComments { id: model_id }] {
+                let request_str = serde_json::to_string(&request).unwrap();
+                if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                    error!("Failed to send {} request for ID {}: {}", request.action(), model_id, e);
+                }
+            }
+        }
+        egui::Window::new("Inspector")
+            .default_pos([1000.0, 100.0])
+            .show(contexts.ctx_mut(), |ui| {
+                let model = state.models.iter().find(|model| model.id == model_id);
+                if let Some(model) = model {
+                    ui.label(format!("Uploaded by {}", model.created_by));
+                    // Every edit bumps the version and adds an entry, so ask again whenever it moves
+                    if inspector.history_version != model.version {
+                        inspector.history_version = model.version;
+                        let request = ClientMessage::History { id: model_id };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send history request for ID {}: {}", model_id, e);
+                        }
+                    }
+                }
+                let protected = model.is_some_and(|model| model.protected);
+                let exportable = model.is_some_and(|model| model.format == "gltf" && !model.protected);
+                let export_name = model.and_then(|model| model.name.clone());
+                let transform_locked = model.is_some_and(|model| model.transform_locked);
+                let version = model.map(|model| model.version);
+                let mut locked = transform_locked;
+                ui.add_enabled(upload_state.allows(actions::SET_TRANSFORM_LOCK), egui::Checkbox::new(&mut locked, "Lock transform"))
+                    .on_hover_text("Pinned models can't be moved with Copy Transform by anyone");
+                if locked != transform_locked {
+                    let request = ClientMessage::SetTransformLock {
+                        id: model_id,
+                        version: version.unwrap_or_default(),
+                        locked,
+                    };
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to send set_transform_lock request for ID {}: {}", model_id, e);
+                    } else if let Some(model) = state.models.iter_mut().find(|model| model.id == model_id) {
+                        // Show the new state until the model list broadcast confirms it
+                        model.transform_locked = locked;
+                    }
+                }
+                let export_button = ui
+                    .add_enabled(exportable, egui::Button::new("Download as OBJ"))
+                    .on_disabled_hover_text("Only unprotected glTF models can be exported");
+                if export_button.clicked() {
+                    let request = ClientMessage::ExportObj {
+                        id: model_id,
+                        name: export_name,
+                        access_code: None,
+                    };
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to send export_obj request for ID {}: {}", model_id, e);
+                    }
+                }
+                ui.separator();
+                ui.label("Metadata:");
+                if !upload_state.allows(actions::SET_METADATA) {
+                    for (key, value) in &inspector.metadata {
+                        ui.label(format!("{}: {}", key, value));
+                    }
+                } else {
+                    let mut removed = None;
+                    for (index, (key, value)) in inspector.metadata.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::TextEdit::singleline(key).hint_text("Key").desired_width(100.0));
+                            ui.add(egui::TextEdit::singleline(value).hint_text("Value").desired_width(160.0));
+                            if ui.button("✖").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(index) = removed {
+                        inspector.metadata.remove(index);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add Field").clicked() {
+                            inspector.metadata.push((String::new(), String::new()));
+                        }
+                        let valid = inspector.metadata.iter().all(|(key, _)| !key.trim().is_empty());
+                        if ui.add_enabled(valid, egui::Button::new("Save Metadata")).clicked() {
+                            let request = ClientMessage::SetMetadata {
+                                id: model_id,
+                                version: version.unwrap_or_default(),
+                                metadata: inspector.metadata.iter().cloned().collect(),
+                            };
+                            let request_str = serde_json::to_string(&request).unwrap();
+                            if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                                error!("Failed to send set_metadata request for ID {}: {}", model_id, e);
+                            }
+                        }
+                    });
+                }
+                ui.separator();
+                egui::CollapsingHeader::new("History").id_salt("history").show(ui, |ui| {
+                    egui::ScrollArea::vertical().id_salt("history").max_height(200.0).show(ui, |ui| {
+                        if inspector.history.is_empty() {
+                            ui.label("No edits recorded yet.");
+                        }
+                        for entry in inspector.history.iter().rev() {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("v{}", entry.version));
+                                ui.label(&entry.change)
+                                    .on_hover_text(format!("Name: {}", entry.name.as_deref().unwrap_or("(none)")));
+                                if entry.changed_at > 0 {
+                                    ui.weak(format_age(entry.changed_at));
+                                }
+                                if Some(entry.version) == version {
+                                    ui.weak("(current)");
+                                } else if ui
+                                    .add_enabled(upload_state.allows(actions::REVERT), egui::Button::new("Revert"))
+                                    .on_hover_text("Restore the model as it was at this version, recorded as a new edit")
+                                    .clicked()
+                                {
+                                    if protected {
+                                        access_prompt.target = Some((model_id, AccessCodeMode::Revert { restore_version: entry.version }));
+                                    } else {
+                                        let request = ClientMessage::Revert {
+                                            id: model_id,
+                                            version: version.unwrap_or_default(),
+                                            restore_version: entry.version,
+                                            access_code: None,
+                                        };
+                                        let request_str = serde_json::to_string(&request).unwrap();
+                                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                                            error!("Failed to send revert request for ID {}: {}", model_id, e);
+                                        }
+                                    }
+                                }
+                            });
+                        }
+                    });
+                });
+                ui.separator();
+                ui.label("Scene Nodes:");
+                let InspectorState { scene_nodes, visibility_toggles, .. } = &mut *inspector;
+                egui::ScrollArea::vertical().id_salt("scene_nodes").max_height(200.0).show(ui, |ui| {
+                    if scene_nodes.is_empty() {
+                        ui.label("Not loaded yet.");
+                    }
+                    let mut index = 0;
+                    while index < scene_nodes.len() {
+                        index = scene_node_ui(ui, scene_nodes, index, visibility_toggles);
+                    }
+                });
+                ui.separator();
+                ui.label("Comments:");
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        if inspector.comments.is_empty() {
+                            ui.label("No comments yet.");
+                        }
+                        for comment in &inspector.comments {
+                            ui.label(format!("{} ({}): {}", comment.author, format_age(comment.created_at), comment.text));
+                        }
+                    });
+                if upload_state.allows(actions::ADD_COMMENT) {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Name:");
+                        ui.text_edit_singleline(&mut upload_state.author);
+                    });
+                    ui.text_edit_multiline(&mut inspector.draft);
+                    if ui.add_enabled(!inspector.draft.trim().is_empty(), egui::Button::new("Post Comment")).clicked() {
+                        let request = ClientMessage::AddComment {
+                            id: model_id,
+                            author: Some(upload_state.author.clone()),
+                            text: inspector.draft.trim().to_string(),
+                        };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send add_comment request for ID {}: {}", model_id, e);
+                        } else {
+                            inspector.draft.clear();
+                        }
+                    }
+                }
+            });
+    } else {
+        inspector.model_id = None;
+    }
+
+    // Layouts Window (saved multi-model arrangements)
+    egui::Window::new("Layouts")
+        .default_pos([1000.0, 300.0])
+        .show(contexts.ctx_mut(), |ui| {
+            if upload_state.allows(actions::SAVE_LAYOUT) {
+                ui.label("Layout Name:");
+                ui.text_edit_singleline(&mut layout_state.layout_name);
+                let can_save = !layout_state.layout_name.trim().is_empty() && !state.model_entities.is_empty();
+                if ui.add_enabled(can_save, egui::Button::new("Save Current Arrangement")).clicked() {
+                    let entries = state
+                        .model_entities
+                        .iter()
+                        .map(|(id, _)| layout_entry(*id, &state.placement(*id, layout_state.active.is_some())))
+                        .collect();
+                    let request = ClientMessage::SaveLayout {
+                        name: layout_state.layout_name.trim().to_string(),
+                        entries,
+                        template: Some(layout_state.save_as_template),
+                    };
+                    let request_str = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                        error!("Failed to send save_layout request: {}", e);
+                    } else {
+                        layout_state.layout_name.clear();
+                    }
+                }
+                ui.checkbox(&mut layout_state.save_as_template, "Save as template")
+                    .on_hover_text("Templates are shared starting points; using one copies it into a new layout");
+                ui.separator();
+            }
+            ui.label("Saved Layouts:");
+            for layout in &layout_state.layouts {
+                ui.horizontal(|ui| {
+                    ui.label(&layout.name);
+                    if ui.button("Load").clicked() {
+                        let request = ClientMessage::LoadLayout { id: layout.id };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send load_layout request for ID {}: {}", layout.id, e);
+                        }
+                    }
+                });
+            }
+            if !layout_state.templates.is_empty() {
+                ui.separator();
+                ui.label("Templates:");
+                for template in &layout_state.templates {
+                    ui.horizontal(|ui| {
+                        ui.label(&template.name);
+                        let mut request = None;
+                        if ui.button("View").clicked() {
+                            request = Some(ClientMessage::LoadLayout { id: template.id });
+                        }
+                        // Named after the Layout Name field when it's filled in, otherwise after the template
+                        let use_button = ui
+                            .add_enabled(upload_state.allows(actions::INSTANTIATE_TEMPLATE), egui::Button::new("Start From"))
+                            .on_hover_text("Copy into a new layout of your own");
+                        if use_button.clicked() {
+                            let name = layout_state.layout_name.trim();
+                            request = Some(ClientMessage::InstantiateTemplate {
+                                id: template.id,
+                                name: (!name.is_empty()).then(|| name.to_string()),
+                            });
+                        }
+                        if let Some(request) = request {
+                            let request_str = serde_json::to_string(&request).unwrap();
+                            if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                                error!("Failed to send {} request for template ID {}: {}", request.action(), template.id, e);
+                            }
+                        }
+                    });
+                }
+            }
+            let shown_layout = layout_state.active.clone().filter(|_| upload_state.allows(actions::COPY_TRANSFORM));
+            if let Some((layout_id, _, layout_ids)) = shown_layout {
+                ui.separator();
+                ui.label("Copy Transform in Shown Layout:");
+                let model_label = |id: i32| {
+                    state
+                        .models
+                        .iter()
+                        .find(|model| model.id == id)
+                        .and_then(|model| model.name.clone())
+                        .unwrap_or_else(|| format!("Model {}", id))
+                };
+                let LayoutState { transform_source, transform_target, .. } = &mut *layout_state;
+                let is_locked = |id: i32| state.models.iter().any(|model| model.id == id && model.transform_locked);
+                for (salt, placeholder, value, movable_only) in [
+                    ("transform_source", "From", transform_source, false),
+                    ("transform_target", "To", transform_target, true),
+                ] {
+                    egui::ComboBox::from_id_salt(salt)
+                        .selected_text(value.map_or_else(|| placeholder.to_string(), model_label))
+                        .show_ui(ui, |ui| {
+                            for id in layout_ids.iter().filter(|id| !(movable_only && is_locked(**id))) {
+                                ui.selectable_value(value, Some(*id), model_label(*id));
+                            }
+                        });
+                }
+                let pair = layout_state
+                    .transform_source
+                    .zip(layout_state.transform_target)
+                    .filter(|(source, target)| source != target && layout_ids.contains(source) && layout_ids.contains(target))
+                    .filter(|(_, target)| !is_locked(*target));
+                if ui.add_enabled(pair.is_some(), egui::Button::new("Copy Transform")).clicked() {
+                    if let Some((source_id, target)) = pair {
+                        let request = ClientMessage::CopyTransform {
+                            id: target,
+                            source_id,
+                            target_id: layout_id,
+                        };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send copy_transform request for ID {}: {}", target, e);
+                        }
+                    }
+                }
+            }
+            if let Some((layout_id, movable)) = placement_scope(&state, &upload_state, &layout_state, &view_settings) {
+                ui.separator();
+                ui.label(if layout_id.is_some() { "Edit Placement in Shown Layout:" } else { "Edit Placement:" });
+                let model_label = |id: i32| {
+                    state
+                        .models
+                        .iter()
+                        .find(|model| model.id == id)
+                        .and_then(|model| model.name.clone())
+                        .unwrap_or_else(|| format!("Model {}", id))
+                };
+                let placement_model = &mut layout_state.placement_model;
+                egui::ComboBox::from_id_salt("placement_model")
+                    .selected_text(placement_model.map_or_else(|| "Select Model".to_string(), model_label))
+                    .show_ui(ui, |ui| {
+                        for id in &movable {
+                            ui.selectable_value(placement_model, Some(*id), model_label(*id));
+                        }
+                    });
+                if let Some(id) = layout_state.placement_model.filter(|id| movable.contains(id)) {
+                    let current = state.placement(id, layout_id.is_some());
+                    let mut translation = current.translation;
+                    let (yaw, pitch, roll) = current.rotation.to_euler(EulerRot::YXZ);
+                    let mut angles = Vec3::new(yaw, pitch, roll).map(f32::to_degrees);
+                    let (mut changed, mut finished) = (false, false);
+                    for (label, values, speed, suffix) in [
+                        ("Position", &mut translation, 0.05, ""),
+                        ("Rotation", &mut angles, 1.0, "°"),
+                    ] {
+                        ui.horizontal(|ui| {
+                            ui.label(label);
+                            for (axis, value) in ["X: ", "Y: ", "Z: "].into_iter().zip(values.as_mut()) {
+                                // egui keeps the unrounded value while dragging, so snapping doesn't pin it in place
+                                let response = ui.add(egui::DragValue::new(value).speed(speed).prefix(axis).suffix(suffix));
+                                changed |= response.changed();
+                                finished |= response.drag_stopped() || response.lost_focus();
+                            }
+                        });
+                    }
+                    ui.label("Rotation is yaw (Y), pitch (X) and roll (Z).");
+                    ui.horizontal(|ui| {
+                        ui.label("Handles:");
+                        for mode in GizmoMode::ALL {
+                            ui.selectable_value(&mut view_settings.gizmo_mode, mode, mode.label());
+                        }
+                    });
+                    if changed {
+                        let transform = snapped_transform(translation, angles, current.scale, &view_settings);
+                        state.set_placement(id, layout_id.is_some(), transform);
+                    }
+                    if finished {
+                        // Everyone receives the snapped placement, so all clients agree on it
+                        let transform = state.placement(id, layout_id.is_some());
+                        send_placement(&upload_state, &state, layout_id, id, &transform);
+                    }
+                }
+            }
+            if upload_state.allows(actions::COPY_TO_SCENE) {
+                ui.separator();
+                ui.label("Copy Model to Layout:");
+                let model_text = layout_state
+                    .copy_model
+                    .and_then(|id| state.models.iter().find(|model| model.id == id))
+                    .map_or_else(
+                        || "Select Model".to_string(),
+                        |model| model.name.clone().unwrap_or_else(|| format!("Model {}", model.id)),
+                    );
+                egui::ComboBox::from_id_salt("copy_model")
+                    .selected_text(model_text)
+                    .show_ui(ui, |ui| {
+                        for ModelEntry { id, name, .. } in &state.models {
+                            let display_name = name
+                                .as_ref()
+                                .map_or_else(|| format!("Model {}", id), |n| format!("{}: {}", id, n));
+                            ui.selectable_value(&mut layout_state.copy_model, Some(*id), display_name);
+                        }
+                    });
+                let target_text = layout_state
+                    .copy_target
+                    .and_then(|id| layout_state.layouts.iter().find(|layout| layout.id == id))
+                    .map_or_else(|| "Select Layout".to_string(), |layout| layout.name.clone());
+                let LayoutState { layouts, copy_target, .. } = &mut *layout_state;
+                egui::ComboBox::from_id_salt("copy_target")
+                    .selected_text(target_text)
+                    .show_ui(ui, |ui| {
+                        for layout in layouts.iter() {
+                            ui.selectable_value(copy_target, Some(layout.id), &layout.name);
+                        }
+                    });
+                if let (Some(model_id), Some(target_id)) = (layout_state.copy_model, layout_state.copy_target) {
+                    if ui.button("Copy").clicked() {
+                        let request = ClientMessage::CopyToScene {
+                            id: model_id,
+                            target_id,
+                        };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send copy_to_scene request for ID {}: {}", model_id, e);
+                        }
+                    }
+                }
+            }
+        });
+
+    // Collaborators Window
+    egui::Window::new("Collaborators")
+        .default_pos([760.0, 50.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Your Name:");
+                ui.text_edit_singleline(&mut upload_state.author);
+            });
+            ui.separator();
+            if upload_state.collaborators.is_empty() {
+                ui.label("Not connected.");
+            }
+            let mut toggled = None;
+            for collaborator in &upload_state.collaborators {
+                if upload_state.session_id.as_ref() == Some(&collaborator.session_id) {
+                    ui.strong(format!("{} (you)", collaborator.name));
+                    continue;
+                }
+                ui.horizontal(|ui| {
+                    let following = upload_state.following.as_ref() == Some(&collaborator.session_id);
+                    let button = ui.add_enabled(collaborator.view.is_some() || following, egui::SelectableLabel::new(following, "Follow"));
+                    if button.on_disabled_hover_text("Hasn't shared a view yet").clicked() {
+                        toggled = Some(collaborator.session_id.clone());
+                    }
+                    ui.label(&collaborator.name);
+                    let shown = collaborator.view.as_ref().map(|view| match view.selected_models.as_slice() {
+                        [] => "All Models".to_string(),
+                        [id] => state
+                            .models
+                            .iter()
+                            .find(|model| model.id == *id)
+                            .and_then(|model| model.name.clone())
+                            .unwrap_or_else(|| format!("Model {}", id)),
+                        ids => format!("{} models", ids.len()),
+                    });
+                    if let Some(shown) = shown {
+                        ui.weak(format!("viewing {}", shown));
+                    }
+                });
+            }
+            if let Some(session_id) = toggled {
+                upload_state.following = if upload_state.following.as_ref() == Some(&session_id) { None } else { Some(session_id) };
+            }
+        });
+}
+
+// Shares the camera and selection at most every VIEW_SHARE_INTERVAL while they change, so
+// collaborators can follow along; the last change always goes out once the interval is up
+fn share_view(
+    mut hello_events: EventReader<Hello>,
+    mut shared: Local<Option<(ViewState, Instant)>>,
+    cameras: Query<&PanOrbitCamera>,
+    upload_state: Res<UploadState>,
+) {
+    if hello_events.read().count() > 0 {
+        *shared = None;
+    }
+    let Some(camera) = cameras.iter().next() else {
+        return;
+    };
+    let mut selected_models: Vec<i32> = upload_state.selected_models.iter().copied().collect();
+    selected_models.sort_unstable();
+    // The targets are where the camera is headed, so followers skip the easing in between
+    let view = ViewState {
+        focus: camera.target_focus.to_array(),
+        yaw: camera.target_yaw,
+        pitch: camera.target_pitch,
+        radius: camera.target_radius,
+        selected_models,
+    };
+    if let Some((last, at)) = shared.as_ref() {
+        if *last == view || at.elapsed() < VIEW_SHARE_INTERVAL {
+            return;
+        }
+    }
+    let request = ClientMessage::SetView { view: view.clone() };
+    if upload_state.ws_tx.try_send(serde_json::to_string(&request).unwrap()).is_ok() {
+        *shared = Some((view, Instant::now()));
+    }
+}
+
+// Mirrors the followed collaborator's camera and selection whenever they share a new view
+fn follow_collaborator(
+    mut upload_state: ResMut<UploadState>,
+    mut layout_state: ResMut<LayoutState>,
+    mut auto_frame: ResMut<AutoFrame>,
+    mut cameras: Query<&mut PanOrbitCamera>,
+    mut applied: Local<Option<(String, ViewState)>>,
+) {
+    let Some(session_id) = upload_state.following.clone() else {
+        *applied = None;
+        return;
+    };
+    let view = upload_state
+        .collaborators
+        .iter()
+        .find(|collaborator| collaborator.session_id == session_id)
+        .and_then(|collaborator| collaborator.view.clone());
+    let Some(view) = view else {
+        return;
+    };
+    if applied.as_ref().is_some_and(|(applied_id, applied_view)| *applied_id == session_id && *applied_view == view) {
+        return;
+    }
+    *applied = Some((session_id, view.clone()));
+    // Moving the camera for the followed user counts as taking it over
+    auto_frame.camera_moved = true;
+    for mut camera in &mut cameras {
+        camera.target_focus = Vec3::from_array(view.focus);
+        camera.target_yaw = view.yaw;
+        camera.target_pitch = view.pitch;
+        camera.target_radius = view.radius;
+        camera.force_update = true;
+    }
+    let selected_models: HashSet<i32> = view.selected_models.into_iter().collect();
+    if upload_state.selected_models != selected_models {
+        upload_state.selected_models = selected_models;
+        layout_state.active = None;
+    }
+}
+
+// Shares the display name once it is no longer being typed, and again with every new connection
+fn share_display_name(
+    mut contexts: EguiContexts,
+    mut hello_events: EventReader<Hello>,
+    mut shared: Local<String>,
+    upload_state: Res<UploadState>,
+) {
+    if hello_events.read().count() > 0 {
+        // The server starts each session out as a guest
+        shared.clear();
+    }
+    let name = upload_state.author.trim();
+    if *shared == name || contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let request = ClientMessage::SetName { name: name.to_string() };
+    if let Err(e) = upload_state.ws_tx.try_send(serde_json::to_string(&request).unwrap()) {
+        error!("Failed to send set_name request: {}", e);
+        return;
+    }
+    *shared = name.to_string();
+}
+
+fn handle_file_results(
+    mut upload_state: ResMut<UploadState>,
+    mut notifications: ResMut<Notifications>,
+) {
+    while let Ok((path, result)) = upload_state.file_rx.try_recv() {
+        match result {
+            Ok((data, file_name)) => {
+                // The server would only refuse it after the whole file was sent
+                if let Some(max) = upload_state.max_upload_bytes.filter(|max| data.len() as u64 > *max) {
+                    let message = format!(
+                        "Upload failed: {} is {:.1} MB, over the server's {:.1} MB limit",
+                        path,
+                        megabytes(data.len() as u64),
+                        megabytes(max)
+                    );
+                    upload_state.status = message.clone();
+                    notifications.push(message);
+                    continue;
+                }
+                // Set model_name to file_name if not user-edited
+                if upload_state.model_name.is_empty() {
+                    if let Some(name) = &file_name {
+                        upload_state.model_name = name.clone();
+                    }
+                }
+                if !upload_state.check_before_upload {
+                    queue_upload(&mut upload_state, &data, file_name);
+                    continue;
+                }
+                // The same checks as an upload, without storing anything; the report replaces any earlier one
+                let request = ClientMessage::Validate {
+                    model_data: Some(general_purpose::STANDARD.encode(&data)),
+                    name: file_name.clone(),
+                    format: None,
+                };
+                let request_str = serde_json::to_string(&request).unwrap();
+                upload_state.validation = None;
+                if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                    upload_state.status = format!("Failed to queue check: {}", e);
+                    error!("Failed to queue check: {}", e);
+                } else {
+                    upload_state.status = "Checking...".to_string();
+                    upload_state.pending_upload = Some((data, file_name));
+                }
+            }
+            Err(e) => {
+                upload_state.status = e.clone();
+                if e != "No file selected" {
+                    error!("File error for {}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn queue_upload(upload_state: &mut UploadState, data: &[u8], file_name: Option<String>) {
+    // The server detects glTF, GLB or PLY from the bytes, whatever the file is called
+    let request = ClientMessage::Insert {
+        model_data: general_purpose::STANDARD.encode(data),
+        name: if upload_state.model_name.is_empty() {
+            file_name
+        } else {
+            Some(upload_state.model_name.clone())
+        },
+        format: None,
+        author: Some(upload_state.author.clone()),
+        transform: None,
+    };
+    let request_str = serde_json::to_string(&request).unwrap();
+    if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+        upload_state.status = format!("Failed to queue upload: {}", e);
+        error!("Failed to queue upload: {}", e);
+    } else {
+        upload_state.status = "Upload queued".to_string();
+        upload_state.model_name.clear(); // Clear name for next upload
+    }
+}
+
+fn update_scene_on_selection(
+    mut commands: Commands,
+    mut state: ResMut<ModelState>,
+    upload_state: Res<UploadState>,
+    layout_state: Res<LayoutState>,
+    mut last_selected: ResMut<LastSelectedModel>,
+    view_settings: Res<ViewSettings>,
+    asset_server: Res<AssetServer>,
+) {
+    let view_ids = displayed_model_ids(&state, &upload_state, &layout_state);
+    let (page_ids, _) = rendered_page(&view_ids, &view_settings);
+    // Fetch the bytes of models on this page that were only listed, and show them once they arrive
+    let fetch: Vec<i32> = page_ids
+        .iter()
+        .copied()
+        .filter(|id| state.unfetched.contains(id) && !state.fetching.contains(id))
+        .collect();
+    if !fetch.is_empty() {
+        state.fetching.extend(&fetch);
+        request_detail(&upload_state.ws_tx, fetch, state.detail);
+    }
+    let displayed_ids: Vec<i32> = page_ids.iter().copied().filter(|id| !state.unfetched.contains(id)).collect();
+
+    // Always check if scene needs update
+    let should_update = last_selected.ids != upload_state.selected_models ||
+        !state.respawn.is_empty() ||
+        state.model_entities.iter().map(|(id, _)| *id).collect::<Vec<_>>() != displayed_ids;
+
+    if should_update {
+        info!("Updating scene, selected: {:?}", upload_state.selected_models);
+
+        match view_settings.despawn_strategy {
+            DespawnStrategy::Diff => {
+                // Despawn only the entities that are no longer displayed
+                let mut kept = vec![];
+                let entities: Vec<_> = state.model_entities.drain(..).collect();
+                for (id, entity) in entities {
+                    if displayed_ids.contains(&id) && !state.respawn.contains(&id) {
+                        kept.push((id, entity));
+                    } else {
+                        info!("Despawning entity for model ID={}", id);
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+                state.model_entities = kept;
+            }
+            DespawnStrategy::RespawnAll => {
+                // Despawn all existing entities
+                for (_, entity) in state.model_entities.drain(..) {
+                    info!("Despawning entity for model");
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+        }
+
+        state.respawn.clear();
+
+        // Load displayed models that don't have an entity yet
+        let filtered_models = state
+            .models
+            .iter()
+            .filter(|model| displayed_ids.contains(&model.id))
+            .filter(|model| !state.model_entities.iter().any(|(spawned_id, _)| *spawned_id == model.id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Spawn filtered models
+        for model in filtered_models {
+            info!("Loading model ID={} at path {}", model.id, model.temp_path);
+            let transform = state.placement(model.id, layout_state.active.is_some());
+            let entity = if model.format == "ply" {
+                commands
+                    .spawn((Mesh3d(asset_server.load(model.temp_path.clone())), PlyModel, transform))
+                    .id()
+            } else {
+                commands
+                    .spawn((
+                        SceneRoot(asset_server.load(
+                            GltfAssetLabel::Scene(0).from_asset(model.temp_path.clone()),
+                        )),
+                        transform,
+                    ))
+                    .id()
+            };
+            state.model_entities.push((model.id, entity));
+        }
+        // Keep entity order in step with the display order so the comparison above settles
+        state
+            .model_entities
+            .sort_by_key(|(id, _)| displayed_ids.iter().position(|displayed_id| displayed_id == id));
+
+        // Update last selected
+        last_selected.ids = upload_state.selected_models.clone();
+    }
+}
+
+fn handle_model_updates(
+    mut state: ResMut<ModelState>,
+    mut receiver: ResMut<ModelUpdateReceiver>,
+    (mut upload_state, mut notifications): (ResMut<UploadState>, ResMut<Notifications>),
+    mut last_selected: ResMut<LastSelectedModel>,
+    mut layout_state: ResMut<LayoutState>,
+    (mut inspector, mut thumbnails): (ResMut<InspectorState>, ResMut<Thumbnails>),
+    mut hello_events: EventWriter<Hello>,
+) {
+    while let Ok(message) = receiver.0.try_recv() {
+        match message {
+            ServerMessage::Models(models) => {
+                info!("Received {} models, selected: {:?}", models.len(), upload_state.selected_models);
+
+                // Update state.models with all models to keep dropdown accurate
+                let mut new_models = vec![];
+                let mut fetch_detail = vec![];
+                for model in models {
+                    new_models.push(model_entry(&mut state, model, &mut fetch_detail));
+                }
+                // The server's list order is arbitrary; keep the list and dropdown stable
+                sort_models(&mut new_models, state.order);
+                // Another user may have deleted models this client has selected
+                let mut deleted: Vec<i32> = upload_state
+                    .selected_models
+                    .iter()
+                    .copied()
+                    .filter(|selected_id| !new_models.iter().any(|model| model.id == *selected_id))
+                    .collect();
+                deleted.sort_unstable();
+                if let [first, ..] = deleted[..] {
+                    info!("Selected models {:?} were deleted", deleted);
+                    upload_state.selected_models.retain(|selected_id| !deleted.contains(selected_id));
+                    let name = state
+                        .models
+                        .iter()
+                        .find(|model| model.id == first)
+                        .and_then(|model| model.name.clone())
+                        .unwrap_or_else(|| format!("Model {}", first));
+                    let message = match deleted.len() {
+                        1 => format!("{} was removed from the catalog", name),
+                        count => format!("{} and {} more were removed from the catalog", name, count - 1),
+                    };
+                    upload_state.toast = Some((message, Instant::now()));
+                }
+                state.locked.retain(|locked_id| new_models.iter().any(|known| known.id == *locked_id));
+                state.unfetched.retain(|unfetched_id| new_models.iter().any(|known| known.id == *unfetched_id));
+                state.models = new_models;
+                if !fetch_detail.is_empty() {
+                    request_detail(&upload_state.ws_tx, fetch_detail, state.detail);
+                }
+
+                // Trigger scene update
+                last_selected.ids.clear();
+            }
+            ServerMessage::Layouts { layouts } => {
+                layout_state.layouts = layouts;
+            }
+            ServerMessage::Templates { templates } => {
+                layout_state.templates = templates;
+            }
+            ServerMessage::Layout { layout } => {
+                info!("Loading layout ID={} with {} models", layout.id, layout.entries.len());
+                for entry in &layout.entries {
+                    state.transforms.insert(entry.model_id, entry_transform(entry));
+                }
+                upload_state.selected_models.clear();
+                let ids: Vec<i32> = layout.entries.iter().map(|entry| entry.model_id).collect();
+                // Fetch the layout's whole model set in one request
+                let request = ClientMessage::GetMany {
+                    ids: ids.clone(),
+                    lod: Some(state.detail.to_string()),
+                };
+                let request_str = serde_json::to_string(&request).unwrap();
+                if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                    error!("Failed to send get_many request for layout ID={}: {}", layout.id, e);
+                }
+                layout_state.active = Some((layout.id, layout.name, ids));
+            }
+            ServerMessage::LayoutUpdated { layout_updated } => {
+                // Only the layout on screen needs its models moved
+                if let Some((layout_id, _, ids)) = layout_state.active.as_mut().filter(|(id, _, _)| *id == layout_updated.id) {
+                    // Models deleted meanwhile are no longer placed
+                    ids.retain(|id| layout_updated.entries.iter().any(|entry| entry.model_id == *id));
+                    let mut added = Vec::new();
+                    for entry in &layout_updated.entries {
+                        let transform = entry_transform(entry);
+                        if state.transforms.insert(entry.model_id, transform) != Some(transform) {
+                            state.respawn.insert(entry.model_id);
+                        }
+                        if !ids.contains(&entry.model_id) {
+                            ids.push(entry.model_id);
+                            added.push(entry.model_id);
+                        }
+                    }
+                    // Models copied in from elsewhere are fetched like the rest of the layout
+                    if !added.is_empty() {
+                        let request = ClientMessage::GetMany {
+                            ids: added,
+                            lod: Some(state.detail.to_string()),
+                        };
+                        let request_str = serde_json::to_string(&request).unwrap();
+                        if let Err(e) = upload_state.ws_tx.try_send(request_str) {
+                            error!("Failed to send get_many request for layout ID={}: {}", layout_id, e);
+                        }
+                    }
+                }
+            }
+            ServerMessage::ManyModels { models, missing } => {
+                if !missing.is_empty() {
+                    warn!("Models no longer on the server: {:?}", missing);
+                }
+                for id in &missing {
+                    state.fetching.remove(id);
+                }
+                for model in models {
+                    state.fetching.remove(&model.id);
+                    if model.model_data.is_empty() {
+                        continue;
+                    }
+                    let temp_path = temp_model_path(model.id, &model.format, &model.lod);
+                    // A file left from a previous session may belong to a deleted model with the same id
+                    if state.unfetched.remove(&model.id) || !Path::new(&temp_path).exists() {
+                        write_temp_model(&temp_path, model.id, &model.model_data);
+                    }
+                    // Point the entry at this detail level and respawn it if it was already shown
+                    if let Some(entry) = state.models.iter_mut().find(|entry| entry.id == model.id) {
+                        if entry.temp_path != temp_path {
+                            entry.temp_path = temp_path;
+                            state.respawn.insert(model.id);
+                        }
+                    }
+                }
+            }
+            ServerMessage::StaleVersion { id, action, .. } => {
+                // Someone else's edit landed first; reload what they changed before trying again
+                warn!("{} for model ID={} was made against an outdated version", action, id);
+                let name = state
+                    .models
+                    .iter()
+                    .find(|model| model.id == id)
+                    .map_or_else(|| format!("Model {}", id), |model| model.name.clone().unwrap_or_else(|| format!("Model {}", id)));
+                notifications.push(format!("{} failed: {} was changed by someone else meanwhile; reloaded it", action_label(&action), name));
+                if let Err(e) = upload_state.ws_tx.try_send(serde_json::to_string(&ClientMessage::ListMetadata).unwrap()) {
+                    error!("Failed to send list_metadata request: {}", e);
+                }
+                if inspector.model_id == Some(id) {
+                    // Refetches its metadata too
+                    inspector.model_id = None;
+                }
+            }
+            ServerMessage::ModelNotFound { id, .. } => {
+                // Deleted since the list was sent; drop it now rather than at the next broadcast
+                warn!("Model ID={} no longer exists on the server", id);
+                let name = state
+                    .models
+                    .iter()
+                    .find(|model| model.id == id)
+                    .map(|model| model.name.clone().unwrap_or_else(|| format!("Model {}", id)));
+                state.models.retain(|model| model.id != id);
+                state.locked.remove(&id);
+                state.unfetched.remove(&id);
+                thumbnails.textures.remove(&id);
+                thumbnails.requested.remove(&id);
+                thumbnails.stale.remove(&id);
+                upload_state.downloads.remove(&id);
+                if upload_state.selected_models.remove(&id) {
+                    if let Some(name) = name {
+                        upload_state.toast = Some((format!("{} was removed from the catalog", name), Instant::now()));
+                    }
+                }
+            }
+            ServerMessage::Error { error, action } => {
+                error!("Server error: {}", error);
+                let message = match action.as_deref() {
+                    Some(action) => format!("{} failed: {}", action_label(action), error),
+                    None => error,
+                };
+                if matches!(action.as_deref(), Some(actions::INSERT | actions::VALIDATE)) {
+                    upload_state.status = message.clone();
+                }
+                if action.as_deref() == Some(actions::GET_BY_ID) {
+                    // Likely a wrong access code; don't save the file if it arrives later some other way
+                    upload_state.downloads.clear();
+                }
+                notifications.push(message);
+            }
+            ServerMessage::Hello { hello } => {
+                if hello.protocol_version != PROTOCOL_VERSION {
+                    warn!(
+                        "Server speaks protocol version {}, this client was built for {}",
+                        hello.protocol_version, PROTOCOL_VERSION
+                    );
+                }
+                upload_state.read_only = hello.read_only;
+                upload_state.max_upload_bytes = hello.max_upload_bytes;
+                // A new session; its permissions follow in the whoami reply
+                upload_state.allowed_actions = None;
+                hello_events.send(Hello(hello));
+            }
+            ServerMessage::Validation { validation } => {
+                // A report for a file that was discarded meanwhile has nothing left to show
+                if upload_state.pending_upload.is_some() {
+                    upload_state.status = if validation.valid { "Checked" } else { "Check failed" }.to_string();
+                    upload_state.validation = Some(validation);
+                }
+            }
+            ServerMessage::Whoami { whoami } => {
+                info!("Connected as session {} ({})", whoami.session_id, whoami.role);
+                upload_state.session_id = Some(whoami.session_id);
+                // Older servers don't list them, leaving read_only to decide
+                if !whoami.allowed_actions.is_empty() {
+                    upload_state.allowed_actions = Some(whoami.allowed_actions.into_iter().collect());
+                }
+            }
+            ServerMessage::Presence { presence } => {
+                if let Some(session_id) = upload_state.following.clone() {
+                    if !presence.iter().any(|collaborator| collaborator.session_id == session_id) {
+                        let name = upload_state
+                            .collaborators
+                            .iter()
+                            .find(|collaborator| collaborator.session_id == session_id)
+                            .map_or_else(|| format!("Session {}", session_id), |collaborator| collaborator.name.clone());
+                        upload_state.toast = Some((format!("{} left, stopped following", name), Instant::now()));
+                        upload_state.following = None;
+                    }
+                }
+                upload_state.collaborators = presence;
+            }
+            ServerMessage::CollaboratorView { collaborator_view } => {
+                let entry = upload_state
+                    .collaborators
+                    .iter_mut()
+                    .find(|collaborator| collaborator.session_id == collaborator_view.session_id);
+                if let Some(entry) = entry {
+                    *entry = collaborator_view;
+                }
+            }
+            ServerMessage::ModelDeleted { model_deleted } => {
+                let id = model_deleted.id;
+                if upload_state.selected_models.remove(&id) {
+                    // Deleting the selected model here needs no toast
+                    if upload_state.session_id.as_ref() != Some(&model_deleted.session_id) {
+                        let name = state
+                            .models
+                            .iter()
+                            .find(|model| model.id == id)
+                            .and_then(|model| model.name.clone())
+                            .unwrap_or_else(|| format!("Model {}", id));
+                        upload_state.toast = Some((format!("{} was removed from the catalog", name), Instant::now()));
+                    }
+                }
+                state.models.retain(|model| model.id != id);
+                state.locked.remove(&id);
+                state.unfetched.remove(&id);
+                thumbnails.textures.remove(&id);
+                thumbnails.requested.remove(&id);
+                thumbnails.stale.remove(&id);
+                last_selected.ids.clear();
+            }
+            ServerMessage::ModelUpdated { model_updated } => {
+                info!("Model ID={} was added or changed", model_updated.id);
+                let mut fetch_detail = vec![];
+                let entry = model_entry(&mut state, model_updated, &mut fetch_detail);
+                match state.models.iter_mut().find(|model| model.id == entry.id) {
+                    Some(known) => *known = entry,
+                    None => state.models.push(entry),
+                }
+                let order = state.order;
+                sort_models(&mut state.models, order);
+                if !fetch_detail.is_empty() {
+                    request_detail(&upload_state.ws_tx, fetch_detail, state.detail);
+                }
+                last_selected.ids.clear();
+            }
+            ServerMessage::Comments { model_id, comments } => {
+                if inspector.model_id == Some(model_id) {
+                    inspector.comments = comments;
+                }
+            }
+            ServerMessage::Metadata { model_id, metadata } => {
+                if inspector.model_id == Some(model_id) {
+                    inspector.metadata = metadata.into_iter().collect();
+                }
+            }
+            ServerMessage::History { model_id, history } => {
+                if inspector.model_id == Some(model_id) {
+                    inspector.history = history;
+                }
+            }
+            ServerMessage::NewComment { comment } => {
+                if inspector.model_id == Some(comment.model_id) {
+                    inspector.comments.push(comment);
+                }
+            }
+            ServerMessage::Thumbnail { thumbnail } => {
+                if thumbnail.stale {
+                    thumbnails.stale.insert(thumbnail.id);
+                }
+                // Empty until some client has rendered one; a stale image is still better than none
+                match general_purpose::STANDARD.decode(&thumbnail.image_data) {
+                    Ok(png) if png.is_empty() => {}
+                    Ok(png) => thumbnails.received.push((thumbnail.id, png)),
+                    Err(e) => warn!("Ignoring thumbnail for model ID={}: {}", thumbnail.id, e),
+                }
+            }
+            ServerMessage::Storage { storage } => {
+                upload_state.storage_warning = storage.nearing_capacity.then(|| {
+                    format!(
+                        "Server storage is {}% full, uploads stop at the quota",
+                        storage.used_bytes * 100 / storage.quota_bytes.max(1)
+                    )
+                });
+            }
+            ServerMessage::ThumbnailInvalidated { thumbnail_invalidated } => {
+                info!("Thumbnail for model ID={} was invalidated", thumbnail_invalidated);
+                // Fetched again, and rendered here if nobody has uploaded a new one yet
+                thumbnails.requested.remove(&thumbnail_invalidated);
+            }
+            ServerMessage::ObjExport { obj_export } => {
+                info!("Received OBJ export of model ID={}", obj_export.model_id);
+                std::thread::spawn(move || save_obj_export(obj_export));
+            }
+            ServerMessage::Model(model) => {
+                if !model.model_data.is_empty() && upload_state.downloads.remove(&model.id) {
+                    let (id, name, data) = (model.id, model.name.clone(), model.model_data.clone());
+                    let format = model.format.clone();
+                    std::thread::spawn(move || save_model_download(id, name, &format, &data));
+                }
+                if state.locked.contains(&model.id) && !model.model_data.is_empty() {
+                    // get_by_id response for a protected model we just unlocked
+                    info!("Unlocked protected model ID={}", model.id);
+                    write_temp_model(&temp_model_path(model.id, &model.format, &model.lod), model.id, &model.model_data);
+                    state.locked.remove(&model.id);
+                } else {
+                    info!("Server stored model ID={}", model.id);
+                    if upload_state.status == "Upload queued" {
+                        upload_state.status = "Upload successful".to_string();
+                    }
+                }
+                if let Some(saved) = model.texture_bytes_saved {
+                    upload_state.status = format!(
+                        "Upload successful; oversized textures were scaled down, saving {:.1} MB",
+                        megabytes(saved)
+                    );
+                }
+                if let Some(warning) = model.warning {
+                    warn!("Upload warning for model ID={}: {}", model.id, warning);
+                    upload_state.status = warning;
+                }
+            }
+        }
+    }
+}
+
+// Asks where to save a downloaded model, suggesting its name and the extension its bytes call for
+fn save_model_download(id: i32, name: Option<String>, format: &str, data: &str) {
+    let bytes = match general_purpose::STANDARD.decode(data) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to decode download of model ID={}: {}", id, e);
+            return;
+        }
+    };
+    let extension = match format {
+        "ply" => "ply",
+        _ if bytes.starts_with(b"glTF") => "glb",
+        _ => "gltf",
+    };
+    let stem: String = name
+        .map(|name| name.trim().chars().map(|c| if c.is_alphanumeric() || " -_".contains(c) { c } else { '_' }).collect())
+        .filter(|stem: &String| !stem.is_empty())
+        .unwrap_or_else(|| format!("model-{}", id));
+    let Some(path) = FileDialog::new()
+        .add_filter("Model Files", &[extension])
+        .set_file_name(format!("{}.{}", stem, extension))
+        .save_file()
+    else {
+        return;
+    };
+    match std::fs::write(&path, bytes) {
+        Ok(()) => info!("Saved model ID={} to {}", id, path.display()),
+        Err(e) => error!("Failed to write {}: {}", path.display(), e),
+    }
+}
+
+// Asks where to save the OBJ file; the MTL file and textures go next to it under the names it references
+fn save_obj_export(export: ObjExport) {
+    let mtl_name = export
+        .obj
+        .lines()
+        .find_map(|line| line.strip_prefix("mtllib "))
+        .unwrap_or("model.mtl")
+        .to_string();
+    let Some(obj_path) = FileDialog::new()
+        .add_filter("OBJ Files", &["obj"])
+        .set_file_name(mtl_name.replace(".mtl", ".obj"))
+        .save_file()
+    else {
+        return;
+    };
+    let directory = obj_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut files = vec![(obj_path.clone(), export.obj.into_bytes()), (directory.join(&mtl_name), export.mtl.into_bytes())];
+    for texture in export.textures {
+        match general_purpose::STANDARD.decode(&texture.data) {
+            Ok(data) => files.push((directory.join(&texture.file_name), data)),
+            Err(e) => error!("Failed to decode exported texture {}: {}", texture.file_name, e),
+        }
+    }
+    for (path, data) in files {
+        // File names come from the server, so never write outside the chosen directory
+        if path.parent() != Some(directory.as_path()) {
+            error!("Skipping export file outside {}: {}", directory.display(), path.display());
+            continue;
+        }
+        if let Err(e) = std::fs::write(&path, data) {
+            error!("Failed to write {}: {}", path.display(), e);
+            return;
+        }
+    }
+    info!("Exported model ID={} to {}", export.model_id, obj_path.display());
+}
+
+// Lists logged messages, newest last; long bodies (model data) are cut short
+// What the user was doing when an action failed, e.g. "Upload" for insert
+fn action_label(action: &str) -> String {
+    match action {
+        actions::INSERT => "Upload".to_string(),
+        actions::VALIDATE => "Checking the file".to_string(),
+        actions::GET_ALL | actions::LIST_METADATA | actions::GET_BY_ID | actions::GET_MANY => "Loading models".to_string(),
+        _ => {
+            let words = action.replace('_', " ");
+            let mut chars = words.chars();
+            chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+        }
+    }
+}
+
+// Server errors stack in the top right corner until dismissed
+fn notifications_window(mut contexts: EguiContexts, mut notifications: ResMut<Notifications>) {
+    if notifications.errors.is_empty() {
+        return;
+    }
+    let mut dismissed = None;
+    let mut dismiss_all = false;
+    egui::Area::new(egui::Id::new("notifications"))
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for (index, (message, count)) in notifications.errors.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("✖").on_hover_text("Dismiss").clicked() {
+                            dismissed = Some(index);
+                        }
+                        let text = if *count > 1 { format!("{} (×{})", message, count) } else { message.clone() };
+                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), text);
+                    });
+                }
+                if notifications.errors.len() > 1 && ui.button("Dismiss all").clicked() {
+                    dismiss_all = true;
+                }
+            });
+        });
+    if dismiss_all {
+        notifications.errors.clear();
+    } else if let Some(index) = dismissed {
+        notifications.errors.remove(index);
+    }
+}
 
-        // Reset selection if model not found
-        if let Some(selected_id) = upload_state.selected_model {
-            if !state.models.iter().any(|(id, _, _)| *id == selected_id) {
-                info!("Selected model ID={} not found, resetting to All Models", selected_id);
-                upload_state.selected_model = None;
+fn message_log_window(mut contexts: EguiContexts, message_log: Option<Res<MessageLog>>, upload_state: Res<UploadState>) {
+    let Some(message_log) = message_log else {
+        return;
+    };
+    egui::Window::new("Message Log").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.label(format!("Session: {}", upload_state.session_id.as_deref().unwrap_or("unknown")));
+        let mut messages = message_log.0.lock().unwrap();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} messages", messages.len()));
+            if ui.button("Clear").clicked() {
+                messages.clear();
             }
+        });
+        ui.separator();
+        egui::ScrollArea::vertical().max_height(400.0).stick_to_bottom(true).show(ui, |ui| {
+            for message in messages.iter() {
+                let secs = message.at.as_secs();
+                let body = match message.text.char_indices().nth(MESSAGE_LOG_PREVIEW_CHARS) {
+                    Some((end, _)) => format!("{}… ({} bytes)", &message.text[..end], message.text.len()),
+                    None => message.text.clone(),
+                };
+                ui.label(format!(
+                    "{:02}:{:02}:{:02}.{:03} UTC {} {}",
+                    secs / 3600 % 24,
+                    secs / 60 % 60,
+                    secs % 60,
+                    message.at.subsec_millis(),
+                    if message.sent { "→" } else { "←" },
+                    body
+                ));
+            }
+        });
+    });
+}
+
+// Top-down map of the spawned models; clicking one frames it and moves the list cursor to it
+fn minimap_window(
+    mut contexts: EguiContexts,
+    state: Res<ModelState>,
+    mut upload_state: ResMut<UploadState>,
+    mut auto_frame: ResMut<AutoFrame>,
+    children: Query<&Children>,
+    bounds: Query<(&Aabb, &GlobalTransform)>,
+    mut cameras: Query<(&mut PanOrbitCamera, &Projection)>,
+) {
+    let footprints: Vec<(i32, Vec3, Vec3)> = state
+        .model_entities
+        .iter()
+        .filter_map(|(id, entity)| world_bounds(*entity, &children, &bounds).map(|(min, max)| (*id, min, max)))
+        .collect();
+    egui::Window::new("Overview").default_open(false).show(contexts.ctx_mut(), |ui| {
+        if footprints.is_empty() {
+            ui.label("No models spawned yet.");
+            return;
+        }
+        let (response, painter) = ui.allocate_painter(egui::Vec2::splat(MINIMAP_SIZE), egui::Sense::hover());
+        let map = response.rect.shrink(8.0);
+        painter.rect_filled(response.rect, 4.0, ui.visuals().extreme_bg_color);
+        // Looking down the Y axis, with +X to the right and +Z towards the bottom
+        let (min, max) = footprints
+            .iter()
+            .fold((Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)), |(min, max), (_, lo, hi)| {
+                (min.min(lo.xz()), max.max(hi.xz()))
+            });
+        let scale = map.width() / (max - min).max_element().max(f32::EPSILON);
+        let center = (min + max) / 2.0;
+        let to_map = |point: Vec2| map.center() + egui::vec2(point.x - center.x, point.y - center.y) * scale;
+        let mut clicked = None;
+        for (id, lo, hi) in &footprints {
+            let rect = egui::Rect::from_two_pos(to_map(lo.xz()), to_map(hi.xz()));
+            // Markers stay clickable even for models too small to see at this scale
+            let hit = rect.expand(4.0);
+            let marker = ui.interact(hit, ui.id().with(("minimap", *id)), egui::Sense::click());
+            let highlighted = upload_state.list_cursor == Some(*id) || marker.hovered();
+            let color = if highlighted { ui.visuals().selection.bg_fill } else { ui.visuals().widgets.inactive.fg_stroke.color };
+            painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.5, color), egui::StrokeKind::Middle);
+            painter.circle_filled(rect.center(), 3.0, color);
+            let name = state
+                .models
+                .iter()
+                .find(|model| model.id == *id)
+                .and_then(|model| model.name.clone())
+                .unwrap_or_else(|| format!("Model {}", id));
+            if marker.on_hover_text(name).clicked() {
+                clicked = Some((*id, *lo, *hi));
+            }
+        }
+        for (camera, _) in &cameras {
+            let focus = to_map(camera.focus.xz());
+            if map.expand(8.0).contains(focus) {
+                let stroke = egui::Stroke::new(1.0, ui.visuals().warn_fg_color);
+                painter.line_segment([focus - egui::vec2(4.0, 0.0), focus + egui::vec2(4.0, 0.0)], stroke);
+                painter.line_segment([focus - egui::vec2(0.0, 4.0), focus + egui::vec2(0.0, 4.0)], stroke);
+            }
+        }
+        if let Some((id, lo, hi)) = clicked {
+            upload_state.list_cursor = Some(id);
+            upload_state.scroll_to_cursor = true;
+            auto_frame.camera_moved = true;
+            for (mut camera, projection) in &mut cameras {
+                frame_camera(&mut camera, projection, lo, hi);
+            }
+        }
+    });
+}
+
+// Draws `nodes[index]` with its subtree collapsible under it and returns the index after the subtree
+fn scene_node_ui(ui: &mut egui::Ui, nodes: &[SceneNode], index: usize, toggles: &mut Vec<Entity>) -> usize {
+    let node = &nodes[index];
+    let end = nodes[index + 1..]
+        .iter()
+        .position(|child| child.depth <= node.depth)
+        .map_or(nodes.len(), |offset| index + 1 + offset);
+    let mut visible = node.visible;
+    if end == index + 1 {
+        ui.horizontal(|ui| {
+            ui.add_space(ui.spacing().indent);
+            ui.checkbox(&mut visible, &node.label);
+        });
+    } else {
+        let id = ui.make_persistent_id(node.entity);
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, node.depth == 0)
+            .show_header(ui, |ui| ui.checkbox(&mut visible, &node.label))
+            .body(|ui| {
+                let mut child = index + 1;
+                while child < end {
+                    child = scene_node_ui(ui, nodes, child, toggles);
+                }
+            });
+    }
+    if visible != node.visible {
+        toggles.push(node.entity);
+    }
+    end
+}
+
+fn format_age(created_at: i64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    match (now - created_at).max(0) {
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{} min ago", s / 60),
+        s if s < 86400 => format!("{} h ago", s / 3600),
+        s => format!("{} d ago", s / 86400),
+    }
+}
+
+// Builds the list entry for a model from the server, writing or noting its bytes the first
+// time it is seen or once it is unlocked
+fn model_entry(state: &mut ModelState, model: ModelResponse, fetch_detail: &mut Vec<i32>) -> ModelEntry {
+    let known_path = state
+        .models
+        .iter()
+        .find(|known| known.id == model.id)
+        .map(|known| known.temp_path.clone())
+        // Bytes put back by a revert are fetched afresh, like a new model's
+        .filter(|_| !model.data_replaced);
+    let needs_bytes = known_path.is_none() || state.locked.contains(&model.id);
+    let temp_path = known_path.unwrap_or_else(|| temp_model_path(model.id, &model.format, &model.lod));
+    if needs_bytes {
+        if model.protected {
+            // Bytes are withheld until the access code is entered
+            state.locked.insert(model.id);
+        } else if model.model_data.is_empty() || model.data_replaced {
+            // Broadcasts leave the bytes out; update_scene_on_selection fetches them when shown.
+            // Replaced bytes always go that way, so the entity showing the old file is despawned
+            // and its asset dropped before the new file is loaded from the same path.
+            state.locked.remove(&model.id);
+            state.unfetched.insert(model.id);
+        } else {
+            write_temp_model(&temp_path, model.id, &model.model_data);
+            state.locked.remove(&model.id);
+            if model.lod != state.detail && model.lods.iter().any(|level| level != "full") {
+                fetch_detail.push(model.id);
+            }
+        }
+    }
+    ModelEntry {
+        id: model.id,
+        temp_path,
+        name: model.name,
+        protected: model.protected,
+        format: model.format,
+        transform_locked: model.transform_locked,
+        created_by: model.created_by,
+        version: model.version,
+        created_at: model.created_at,
+        updated_at: model.updated_at,
+        transform: model.transform.as_ref().map(stored_transform),
+    }
+}
+
+fn temp_model_path(model_id: i32, format: &str, lod: &str) -> String {
+    // The extension picks the asset loader: Bevy's glTF loader or PlyLoader
+    let file_name = match lod {
+        "full" => format!("model_{}.{}", model_id, format),
+        lod => format!("model_{}_{}.{}", model_id, lod, format),
+    };
+    let temp_path = std::env::temp_dir().join(file_name);
+    temp_path.to_str().expect("Invalid temp path").to_string()
+}
+
+fn request_detail(ws_tx: &mpsc::Sender<String>, ids: Vec<i32>, detail: &str) {
+    if ids.is_empty() {
+        return;
+    }
+    let request = ClientMessage::GetMany {
+        ids,
+        lod: Some(detail.to_string()),
+    };
+    let request_str = serde_json::to_string(&request).unwrap();
+    if let Err(e) = ws_tx.try_send(request_str) {
+        error!("Failed to request {} detail models: {}", detail, e);
+    }
+}
+
+fn write_temp_model(temp_path: &str, model_id: i32, base64_data: &str) {
+    match general_purpose::STANDARD.decode(base64_data) {
+        Ok(model_data) => {
+            let mut file = File::create(temp_path).expect("Failed to create temp file");
+            file.write_all(&model_data).expect("Failed to write temp file");
+        }
+        Err(e) => {
+            error!("Failed to decode base64 for model ID={}: {}", model_id, e);
+        }
+    }
+}
+
+// Moves the camera to the server's default framing once; later hellos (after a reconnect) leave the view alone
+fn apply_camera_defaults(
+    mut hello_events: EventReader<Hello>,
+    mut cameras: Query<&mut PanOrbitCamera>,
+    mut applied: Local<bool>,
+) {
+    let Some(hello) = hello_events.read().last() else {
+        return;
+    };
+    if *applied {
+        return;
+    }
+    *applied = true;
+    let focus = Vec3::from_array(hello.camera_focus);
+    let offset = Vec3::from_array(hello.camera_position) - focus;
+    let radius = offset.length();
+    if radius <= f32::EPSILON {
+        return;
+    }
+    for mut camera in &mut cameras {
+        camera.target_focus = focus;
+        camera.target_radius = radius;
+        camera.target_yaw = offset.x.atan2(offset.z);
+        camera.target_pitch = (offset.y / radius).asin();
+        camera.force_update = true;
+    }
+}
+
+// Every connection starts with a hello, so any hello after the first means the socket reconnected.
+// The model list that follows keeps the selection unless the model was deleted while disconnected.
+fn resync_after_reconnect(
+    mut hello_events: EventReader<Hello>,
+    mut connected_before: Local<bool>,
+    view_settings: Res<ViewSettings>,
+    mut upload_state: ResMut<UploadState>,
+    mut layout_state: ResMut<LayoutState>,
+    mut inspector: ResMut<InspectorState>,
+    mut state: ResMut<ModelState>,
+) {
+    for _ in hello_events.read() {
+        if !*connected_before {
+            *connected_before = true;
+            continue;
+        }
+        info!("Reconnected, resyncing with the server");
+        // Requests in flight were lost with the old connection
+        state.fetching.clear();
+        // Refetch the open model's details in case they changed while disconnected
+        inspector.model_id = None;
+        if view_settings.reset_selection_on_reconnect {
+            upload_state.selected_models.clear();
+            layout_state.active = None;
+        }
+    }
+}
+
+// Configures the sun, ambient light and background whenever the Lighting window changes them
+fn apply_lighting(
+    view_settings: Res<ViewSettings>,
+    mut applied: Local<Option<Lighting>>,
+    mut lights: Query<(&mut DirectionalLight, &mut Transform)>,
+    mut ambient: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+) {
+    let lighting = view_settings.lighting;
+    if *applied == Some(lighting) {
+        return;
+    }
+    *applied = Some(lighting);
+
+    let Lighting { preset, sun, shadow_map: shadow_quality } = lighting;
+    // Resizing reallocates the map, so leave it alone while shadows are off
+    if sun.shadows && shadow_quality.map_size() != shadow_map.size {
+        shadow_map.size = shadow_quality.map_size();
+    }
+    for (mut light, mut transform) in &mut lights {
+        light.illuminance = sun.illuminance;
+        light.shadows_enabled = sun.shadows;
+        light.color = Color::srgb_u8(sun.color[0], sun.color[1], sun.color[2]);
+        transform.rotation = sun.rotation();
+    }
+    let (ambient_color, brightness, background) = preset.ambient();
+    ambient.color = ambient_color;
+    ambient.brightness = brightness;
+    clear_color.0 = background;
+}
+
+// Lighting window: a preset for the ambient light and background, and the sun tuned on top of it
+fn lighting_window(mut contexts: EguiContexts, mut view_settings: ResMut<ViewSettings>) {
+    egui::Window::new("Lighting").default_open(false).show(contexts.ctx_mut(), |ui| {
+        let lighting = &mut view_settings.lighting;
+        let mut preset = lighting.preset;
+        egui::ComboBox::from_label("Preset")
+            .selected_text(preset.label())
+            .show_ui(ui, |ui| {
+                for option in LightingPreset::ALL {
+                    ui.selectable_value(&mut preset, option, option.label());
+                }
+            });
+        if preset != lighting.preset || ui.button("Reset to Preset").clicked() {
+            lighting.preset = preset;
+            lighting.sun = preset.sun();
+        }
+        ui.separator();
+        let sun = &mut lighting.sun;
+        ui.add(egui::Slider::new(&mut sun.azimuth, -180.0..=180.0).text("Azimuth").suffix("°"));
+        ui.add(egui::Slider::new(&mut sun.elevation, 0.0..=89.0).text("Elevation").suffix("°"));
+        ui.add(
+            egui::Slider::new(&mut sun.illuminance, 0.0..=light_consts::lux::DIRECT_SUNLIGHT)
+                .logarithmic(true)
+                .text("Illuminance")
+                .suffix(" lx"),
+        );
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgb(&mut sun.color);
+            ui.label("Color");
+        });
+        ui.checkbox(&mut sun.shadows, "Cast shadows");
+        ui.add_enabled_ui(sun.shadows, |ui| {
+            egui::ComboBox::from_label("Shadow map")
+                .selected_text(lighting.shadow_map.label())
+                .show_ui(ui, |ui| {
+                    for quality in ShadowQuality::ALL {
+                        ui.selectable_value(&mut lighting.shadow_map, quality, quality.label());
+                    }
+                });
+        });
+    });
+}
+
+// LIGHTING_FILE, or lighting.json in the user's configuration directory
+fn lighting_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("LIGHTING_FILE") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("pgs-renderman").join("lighting.json"))
+}
+
+fn load_lighting() -> Lighting {
+    let Some(path) = lighting_path() else {
+        return Lighting::default();
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("Ignoring lighting settings in {}: {}", path.display(), e);
+            Lighting::default()
+        }),
+        Err(_) => Lighting::default(),
+    }
+}
+
+// Saves the lighting once it has stopped changing for LIGHTING_SAVE_DELAY, so dragging a slider
+// doesn't write the file every frame
+fn save_lighting(
+    view_settings: Res<ViewSettings>,
+    mut saved: Local<Option<Lighting>>,
+    mut pending: Local<Option<(Lighting, Instant)>>,
+) {
+    let lighting = view_settings.lighting;
+    // What was loaded at startup is already on disk
+    let last_saved = *saved.get_or_insert(lighting);
+    if lighting == last_saved {
+        *pending = None;
+        return;
+    }
+    match *pending {
+        Some((waiting, since)) if waiting == lighting => {
+            if since.elapsed() < LIGHTING_SAVE_DELAY {
+                return;
+            }
+        }
+        _ => {
+            *pending = Some((lighting, Instant::now()));
+            return;
+        }
+    }
+    *saved = Some(lighting);
+    *pending = None;
+    let Some(path) = lighting_path() else {
+        return;
+    };
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&path, serde_json::to_string_pretty(&lighting).unwrap()));
+    if let Err(e) = written {
+        warn!("Failed to save lighting settings to {}: {}", path.display(), e);
+    }
+}
+
+// Shows, hides and resizes the ground plane, and draws its grid
+fn apply_ground(
+    view_settings: Res<ViewSettings>,
+    mut ground: Query<(&mut Visibility, &mut Transform), With<Ground>>,
+    mut gizmos: Gizmos<GroundGizmos>,
+) {
+    let size = view_settings.ground_size;
+    for (mut visibility, mut transform) in &mut ground {
+        visibility.set_if_neq(if view_settings.ground { Visibility::Inherited } else { Visibility::Hidden });
+        if transform.scale.x != size {
+            transform.scale = Vec3::splat(size);
+        }
+    }
+    if !view_settings.ground {
+        return;
+    }
+    // Just above the plane so the lines don't flicker against it
+    let cells = size.floor().max(1.0) as u32;
+    gizmos.grid(
+        Isometry3d::new(Vec3::Y * 0.001, Quat::from_rotation_x(std::f32::consts::FRAC_PI_2)),
+        UVec2::splat(cells),
+        Vec2::splat(size / cells as f32),
+        Color::srgba(0.8, 0.8, 0.8, 0.4),
+    );
+}
+
+// PLY point clouds have no normals to light, so they get an unlit material once their mesh is known
+// Switches the camera between perspective and orthographic without changing what's in frame.
+// PanOrbitCamera uses the orbit radius as the orthographic scale, so a viewport height of
+// 2·tan(fov/2) per unit of scale shows at the focus exactly what the perspective camera did.
+fn apply_projection(view_settings: Res<ViewSettings>, mut cameras: Query<(&mut Projection, &mut PanOrbitCamera)>) {
+    for (mut projection, mut camera) in &mut cameras {
+        let switched = match (&*projection, view_settings.orthographic) {
+            (Projection::Perspective(perspective), true) => Some(Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical { viewport_height: 2.0 * (perspective.fov / 2.0).tan() },
+                ..OrthographicProjection::default_3d()
+            })),
+            (Projection::Orthographic(_), false) => Some(Projection::Perspective(PerspectiveProjection::default())),
+            _ => None,
+        };
+        if let Some(switched) = switched {
+            *projection = switched;
+            camera.force_update = true;
+        }
+    }
+}
+
+fn apply_ui_scale(view_settings: Res<ViewSettings>, mut egui_settings: Query<&mut EguiContextSettings>) {
+    if !view_settings.is_changed() {
+        return;
+    }
+    for mut settings in &mut egui_settings {
+        if settings.scale_factor != view_settings.ui_scale {
+            settings.scale_factor = view_settings.ui_scale;
+        }
+    }
+}
+
+fn assign_ply_materials(
+    mut commands: Commands,
+    ply_models: Query<(Entity, &Mesh3d), PendingPlyMaterial>,
+    meshes: Res<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, mesh) in &ply_models {
+        let Some(mesh) = meshes.get(&mesh.0) else {
+            continue;
+        };
+        let is_point_cloud = mesh.primitive_topology() == PrimitiveTopology::PointList;
+        let material = materials.add(StandardMaterial {
+            unlit: is_point_cloud,
+            double_sided: !is_point_cloud,
+            cull_mode: None,
+            ..default()
+        });
+        commands.entity(entity).insert(MeshMaterial3d(material));
+    }
+}
+
+fn apply_model_transforms(
+    state: Res<ModelState>,
+    upload_state: Res<UploadState>,
+    layout_state: Res<LayoutState>,
+    view_settings: Res<ViewSettings>,
+    mut arrangement: Local<Option<(bool, f32)>>,
+    mut transforms: Query<&mut Transform>,
+) {
+    // View Settings is touched every frame by its widgets, so compare just the grid settings
+    let current = Some((view_settings.arrange_grid, view_settings.grid_spacing));
+    if !state.is_changed() && *arrangement == current {
+        return;
+    }
+    *arrangement = current;
+    let all_models = upload_state.selected_models.is_empty() && layout_state.active.is_none();
+    let grid = if all_models && view_settings.arrange_grid {
+        let ids: Vec<i32> = state.model_entities.iter().map(|(id, _)| *id).collect();
+        grid_transforms(&state.models, &ids, state.order, view_settings.grid_spacing)
+    } else {
+        HashMap::new()
+    };
+    let in_layout = layout_state.active.is_some();
+    for (id, entity) in &state.model_entities {
+        if let Ok(mut transform) = transforms.get_mut(*entity) {
+            *transform = grid.get(id).copied().unwrap_or_else(|| state.placement(*id, in_layout));
         }
     }
 }
@@ -0,0 +1,288 @@
+//! Loader for `.ply` scans: point clouds, or triangle meshes when the file has faces.
+
+use bevy::{
+    asset::{ io::Reader, AssetLoader, LoadContext, RenderAssetUsages },
+    prelude::*,
+    render::mesh::{ Indices, PrimitiveTopology },
+};
+use std::io::{ Error, ErrorKind };
+
+#[derive(Default)]
+pub struct PlyLoader;
+
+impl AssetLoader for PlyLoader {
+    type Asset = Mesh;
+    type Settings = ();
+    type Error = Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Mesh, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        parse_ply(&bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ply"]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+enum Property {
+    Scalar { name: String, ty: ScalarType },
+    List { name: String, count_ty: ScalarType, item_ty: ScalarType },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            other => return Err(format!("Unknown PLY property type: {}", other)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+/// Reads values one at a time from either the ASCII or binary body.
+struct BodyReader<'a> {
+    encoding: Encoding,
+    bytes: &'a [u8],
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl BodyReader<'_> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        if self.encoding == Encoding::Ascii {
+            let token = self.tokens.next().ok_or("Unexpected end of PLY data")?;
+            return token.parse::<f64>().map_err(|e| format!("Invalid PLY value {:?}: {}", token, e));
+        }
+        let size = ty.size();
+        if self.bytes.len() < size {
+            return Err("Unexpected end of PLY data".to_string());
+        }
+        let (head, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(head);
+        if self.encoding == Encoding::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        Ok(match ty {
+            ScalarType::I8 => raw[0] as i8 as f64,
+            ScalarType::U8 => raw[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(raw),
+        })
+    }
+}
+
+fn parse_header(text: &str) -> Result<(Encoding, Vec<Element>), String> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("Missing PLY magic".to_string());
+    }
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    other => return Err(format!("Unknown PLY format: {}", other)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| format!("Invalid element count: {}", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_ty, item_ty, name] => {
+                let element = elements.last_mut().ok_or("PLY property before any element")?;
+                element.properties.push(Property::List {
+                    name: name.to_string(),
+                    count_ty: ScalarType::parse(count_ty)?,
+                    item_ty: ScalarType::parse(item_ty)?,
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or("PLY property before any element")?;
+                element.properties.push(Property::Scalar {
+                    name: name.to_string(),
+                    ty: ScalarType::parse(ty)?,
+                });
+            }
+            ["end_header"] => break,
+            _ => {} // comment, obj_info
+        }
+    }
+    Ok((encoding.ok_or("Missing PLY format line")?, elements))
+}
+
+/// Parses ASCII or binary PLY bytes into a point-list mesh, or a triangle mesh when faces are present.
+pub fn parse_ply(bytes: &[u8]) -> Result<Mesh, String> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or("Missing PLY end_header")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|newline| header_end + newline + 1)
+        .unwrap_or(bytes.len());
+    let header = std::str::from_utf8(&bytes[..body_start]).map_err(|_| "PLY header is not valid text")?;
+    let (encoding, elements) = parse_header(header)?;
+
+    let body = &bytes[body_start..];
+    let body_text = if encoding == Encoding::Ascii {
+        std::str::from_utf8(body).map_err(|_| "ASCII PLY body is not valid text")?
+    } else {
+        ""
+    };
+    let mut reader = BodyReader {
+        encoding,
+        bytes: body,
+        tokens: body_text.split_ascii_whitespace(),
+    };
+
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+    let mut colors: Vec<[f32; 4]> = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut position = [0.0f32; 3];
+            let mut normal = [0.0f32; 3];
+            let mut color = [1.0f32; 4];
+            let (mut has_normal, mut has_color) = (false, false);
+            for property in &element.properties {
+                match property {
+                    Property::Scalar { name, ty } => {
+                        let value = reader.read(*ty)?;
+                        // Integer colors are 0..255, float colors are already 0..1
+                        let channel = if matches!(ty, ScalarType::F32 | ScalarType::F64) {
+                            value as f32
+                        } else {
+                            value as f32 / 255.0
+                        };
+                        match name.as_str() {
+                            "x" => position[0] = value as f32,
+                            "y" => position[1] = value as f32,
+                            "z" => position[2] = value as f32,
+                            "nx" => { normal[0] = value as f32; has_normal = true; }
+                            "ny" => { normal[1] = value as f32; has_normal = true; }
+                            "nz" => { normal[2] = value as f32; has_normal = true; }
+                            "red" | "r" => { color[0] = channel; has_color = true; }
+                            "green" | "g" => { color[1] = channel; has_color = true; }
+                            "blue" | "b" => { color[2] = channel; has_color = true; }
+                            "alpha" | "a" => { color[3] = channel; has_color = true; }
+                            _ => {}
+                        }
+                    }
+                    Property::List { name, count_ty, item_ty } => {
+                        let count = reader.read(*count_ty)? as usize;
+                        let mut polygon = Vec::with_capacity(count);
+                        for _ in 0..count {
+                            polygon.push(reader.read(*item_ty)? as u32);
+                        }
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            // Fan-triangulate polygons
+                            for i in 1..polygon.len().saturating_sub(1) {
+                                indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                positions.push(position);
+                if has_normal {
+                    normals.push(normal);
+                }
+                if has_color {
+                    colors.push(color);
+                }
+            }
+        }
+    }
+
+    if positions.is_empty() {
+        return Err("PLY file has no vertices".to_string());
+    }
+    if let Some(bad) = indices.iter().find(|index| **index as usize >= positions.len()) {
+        return Err(format!("PLY face references missing vertex {}", bad));
+    }
+
+    let topology = if indices.is_empty() {
+        PrimitiveTopology::PointList
+    } else {
+        PrimitiveTopology::TriangleList
+    };
+    let vertex_count = positions.len();
+    let mut mesh = Mesh::new(topology, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    if normals.len() == vertex_count {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    }
+    if colors.len() == vertex_count {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    }
+    if !indices.is_empty() {
+        mesh.insert_indices(Indices::U32(indices));
+        if !mesh.contains_attribute(Mesh::ATTRIBUTE_NORMAL) {
+            mesh.compute_normals();
+        }
+    }
+    Ok(mesh)
+}
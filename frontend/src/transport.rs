@@ -0,0 +1,213 @@
+//! Connections to the backend. A WebSocket is used whenever one can be opened; networks that
+//! block the upgrade fall back to the server's HTTP long-polling endpoint, which carries the
+//! same messages.
+
+use flate2::read::GzDecoder;
+use futures_util::{ SinkExt, StreamExt };
+use protocol::GZIP_MESSAGE_PREFIX;
+use std::io::Read;
+use tokio::{
+    io::{ AsyncReadExt, AsyncWriteExt },
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    connect_async_with_config,
+    tungstenite::{ protocol::WebSocketConfig, Message },
+    MaybeTlsStream,
+    WebSocketStream,
+};
+
+const DEFAULT_BACKEND_URL: &str = "ws://127.0.0.1:8000/ws";
+
+/// The backend's WebSocket endpoint: `BACKEND_URL` when set, e.g. `ws://models.example.com:8000/ws`,
+/// otherwise the local server.
+pub fn backend_url() -> String {
+    std::env::var("BACKEND_URL")
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_BACKEND_URL.to_string())
+}
+
+// Host and port of the backend for plain HTTP, and the path its endpoints share,
+// e.g. ("models.example.com:80", "/catalog") for ws://models.example.com/catalog/ws
+fn http_endpoint(url: &str) -> Result<(String, String), String> {
+    let Some(rest) = url.strip_prefix("ws://") else {
+        return Err(if url.starts_with("wss://") {
+            "HTTP long polling is only available for ws:// backends".to_string()
+        } else {
+            format!("backend URL {} is not a ws:// or wss:// URL", url)
+        });
+    };
+    let (authority, path) = rest.split_once('/').map_or((rest, ""), |(authority, path)| (authority, path));
+    let address = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let path = format!("/{}", path.trim_end_matches('/'));
+    let base = path.strip_suffix("/ws").unwrap_or(&path).trim_end_matches('/');
+    Ok((address, base.to_string()))
+}
+
+/// Carries protocol messages as JSON text, whichever way the connection reaches the server.
+pub trait Transport {
+    /// Whether the server may send gzip-compressed messages this way once asked to.
+    const COMPRESSION: bool;
+    /// Sends one request.
+    async fn send(&mut self, text: String) -> Result<(), String>;
+    /// Waits for the next message. Safe to cancel, so it can sit in a `select!`.
+    async fn recv(&mut self) -> Result<String, String>;
+    /// Called periodically so idle connections aren't dropped along the way.
+    async fn keep_alive(&mut self) -> Result<(), String>;
+}
+
+pub struct WebSocketTransport(WebSocketStream<MaybeTlsStream<TcpStream>>);
+
+impl WebSocketTransport {
+    pub async fn connect() -> Result<Self, String> {
+        let mut config = WebSocketConfig::default();
+        config.max_message_size = Some(100 * 1024 * 1024);
+        config.max_frame_size = Some(100 * 1024 * 1024);
+        config.accept_unmasked_frames = false;
+        // wss:// URLs need tokio-tungstenite built with a TLS feature, otherwise this says so
+        let (stream, _) = connect_async_with_config(backend_url(), Some(config), false).await.map_err(|e| e.to_string())?;
+        Ok(WebSocketTransport(stream))
+    }
+}
+
+impl Transport for WebSocketTransport {
+    const COMPRESSION: bool = true;
+
+    async fn send(&mut self, text: String) -> Result<(), String> {
+        self.0.send(Message::Text(text.into())).await.map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Result<String, String> {
+        // tungstenite reassembles fragmented frames, so every message here is complete
+        loop {
+            match self.0.next().await {
+                Some(Ok(Message::Text(text))) if text.trim().is_empty() => {}
+                Some(Ok(Message::Text(text))) => return Ok(text.to_string()),
+                Some(Ok(Message::Binary(data))) if data.first() == Some(&GZIP_MESSAGE_PREFIX) => {
+                    let mut text = String::new();
+                    GzDecoder::new(&data[1..])
+                        .read_to_string(&mut text)
+                        .map_err(|e| format!("compressed message could not be decoded: {}", e))?;
+                    return Ok(text);
+                }
+                // Some proxies relay text as binary frames
+                Some(Ok(Message::Binary(data))) => match String::from_utf8(data.to_vec()) {
+                    Ok(text) if text.trim().is_empty() => {}
+                    Ok(text) => return Ok(text),
+                    Err(e) => return Err(format!("binary message is not UTF-8 text: {}", e)),
+                },
+                Some(Ok(Message::Ping(_))) => {
+                    self.0.send(Message::Pong(vec![].into())).await.map_err(|e| format!("Failed to send pong: {}", e))?;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.to_string()),
+                None => return Err("connection closed by server".to_string()),
+            }
+        }
+    }
+
+    async fn keep_alive(&mut self) -> Result<(), String> {
+        self.0.send(Message::Ping(vec![].into())).await.map_err(|e| format!("Failed to send ping: {}", e))
+    }
+}
+
+/// Long polling over plain HTTP. A background task keeps one poll outstanding and queues
+/// whatever it returns, so `recv` never abandons a response half read.
+pub struct PollingTransport {
+    address: String, // host and port of the backend
+    session_path: String,
+    messages: mpsc::UnboundedReceiver<Result<String, String>>,
+    poller: JoinHandle<()>,
+}
+
+impl PollingTransport {
+    pub async fn connect() -> Result<Self, String> {
+        let (address, base) = http_endpoint(&backend_url())?;
+        let (status, body) = http_request(&address, "POST", &format!("{}/poll", base), "").await?;
+        if status != 200 {
+            return Err(format!("server answered {} when opening a polling session", status));
+        }
+        let session = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|value| value["session"].as_str().map(str::to_string))
+            .ok_or("server sent no polling session")?;
+        let (message_tx, messages) = mpsc::unbounded_channel();
+        let session_path = format!("{}/poll/{}", base, session);
+        let (poll_address, path) = (address.clone(), session_path.clone());
+        let poller = tokio::spawn(async move {
+            loop {
+                let error = match http_request(&poll_address, "GET", &path, "").await {
+                    // One message per line; an empty body just means nothing arrived in time
+                    Ok((200, body)) => {
+                        for line in body.lines().filter(|line| !line.is_empty()) {
+                            if message_tx.send(Ok(line.to_string())).is_err() {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    Ok((status, _)) => format!("server answered {} to a poll", status),
+                    Err(e) => e,
+                };
+                let _ = message_tx.send(Err(error));
+                return;
+            }
+        });
+        Ok(PollingTransport { address, session_path, messages, poller })
+    }
+}
+
+impl Drop for PollingTransport {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+impl Transport for PollingTransport {
+    // Poll responses are lines of text
+    const COMPRESSION: bool = false;
+
+    async fn send(&mut self, text: String) -> Result<(), String> {
+        match http_request(&self.address, "POST", &self.session_path, &text).await? {
+            (202, _) => Ok(()),
+            (status, _) => Err(format!("server answered {}", status)),
+        }
+    }
+
+    async fn recv(&mut self) -> Result<String, String> {
+        self.messages.recv().await.unwrap_or_else(|| Err("polling stopped".to_string()))
+    }
+
+    async fn keep_alive(&mut self) -> Result<(), String> {
+        // Each poll already keeps the session open
+        Ok(())
+    }
+}
+
+// One request per connection; the server closes it after answering
+async fn http_request(address: &str, method: &str, path: &str, body: &str) -> Result<(u16, String), String> {
+    let mut stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        address,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|e| e.to_string())?;
+    let response = String::from_utf8(response).map_err(|e| e.to_string())?;
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("malformed HTTP response")?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or("malformed HTTP status line")?;
+    Ok((status, body.to_string()))
+}
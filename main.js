@@ -1,7 +1,17 @@
 // Scene setup
 const scene = new THREE.Scene();
-const camera = new THREE.PerspectiveCamera(75, window.innerWidth / window.innerHeight, 0.1, 1000);
+const perspectiveCamera = new THREE.PerspectiveCamera(75, window.innerWidth / window.innerHeight, 0.1, 1000);
+// The frustum is sized by matchPerspectiveFraming; the negative near plane keeps models the camera sits inside visible
+const orthographicCamera = new THREE.OrthographicCamera(-1, 1, 1, -1, -1000, 1000);
+let camera = perspectiveCamera;
 const renderer = new THREE.WebGLRenderer({ antialias: true });
+// Render at the display's density so HiDPI screens stay sharp, capped because every doubling
+// quadruples the pixels shaded; ?pixelRatio=<n> in the URL overrides it
+const requestedPixelRatio = parseFloat(new URLSearchParams(window.location.search).get('pixelRatio'));
+function pixelRatio() {
+    return requestedPixelRatio > 0 ? requestedPixelRatio : Math.min(window.devicePixelRatio || 1, 2);
+}
+renderer.setPixelRatio(pixelRatio());
 renderer.setSize(window.innerWidth, window.innerHeight);
 document.body.appendChild(renderer.domElement);
 
@@ -28,85 +38,253 @@ const controls = new THREE.OrbitControls(camera, renderer.domElement);
 controls.enableDamping = true;
 controls.dampingFactor = 0.05;
 
-// glTF loader
+// glTF and PLY loaders
 const loader = new THREE.GLTFLoader();
+const plyLoader = new THREE.PLYLoader();
 let currentModels = [];
 
-// WebSocket setup
-const ws = new WebSocket('ws://127.0.0.1:8000/ws');
 const statusDiv = document.getElementById('status');
 const modelSelect = document.getElementById('modelSelect');
+const keepCameraCheckbox = document.getElementById('keepCamera');
+const frameButton = document.getElementById('frameButton');
+const orthographicCheckbox = document.getElementById('orthographic');
+const toastDiv = document.getElementById('toast');
+let toastTimeout = null;
 let requestTimeout = null;
 let allModels = [];
+let accessCodes = {}; // model id -> access code entered for protected models
+const fetchedModels = new Map(); // model id -> get_by_id response, most recently shown last
+const FETCHED_MODEL_LIMIT = 5; // switching back to one of these needs no download
 
-ws.onopen = () => {
-    console.log('WebSocket connected');
-    statusDiv.textContent = 'Connected to WebSocket';
-    statusDiv.style.color = 'green';
-    // Request all models on connection
-    const getAllRequest = { action: 'get_all' };
-    console.log('Sending get_all request:', getAllRequest);
-    ws.send(JSON.stringify(getAllRequest));
+// Connection to the backend: a WebSocket, or HTTP long polling when the WebSocket can't be opened
+// (e.g. behind a proxy that blocks upgrades). Both carry the same JSON messages.
+const server = {
+    open: false,
+    send: () => {},
+    decompresses: false, // can take gzip-compressed messages, offered in the hello
 };
+// First byte of a binary message holding gzip-compressed JSON (protocol::GZIP_MESSAGE_PREFIX)
+const GZIP_MESSAGE_PREFIX = 0x01;
 
-ws.onclose = () => {
-    console.log('WebSocket disconnected');
-    statusDiv.textContent = 'Disconnected from WebSocket';
-    statusDiv.style.color = 'red';
-    clearTimeout(requestTimeout);
-};
+// The backend's WebSocket endpoint, from window.BACKEND_WS_URL or <meta name="backend-url">
+// when the page sets one; wss:// pairs with https:// for the polling fallback
+function backendUrl() {
+    const meta = document.querySelector('meta[name="backend-url"]');
+    return window.BACKEND_WS_URL || (meta && meta.content) || 'ws://127.0.0.1:8000/ws';
+}
+
+function pollingUrl() {
+    const url = new URL(backendUrl());
+    url.protocol = url.protocol === 'wss:' ? 'https:' : 'http:';
+    url.pathname = url.pathname.replace(/\/?(ws\/?)?$/, '') + '/poll';
+    return url.toString();
+}
+
+function onServerOpen(transport) {
+    console.log(`Connected over ${transport}`);
+    server.open = true;
+    statusDiv.textContent = `Connected (${transport})`;
+    statusDiv.style.color = 'green';
+}
 
-ws.onerror = (error) => {
-    console.error('WebSocket error:', error);
-    statusDiv.textContent = 'WebSocket error';
+function onServerClose(reason) {
+    console.log('Disconnected:', reason);
+    server.open = false;
+    statusDiv.textContent = `Disconnected from server (${reason})`;
     statusDiv.style.color = 'red';
     clearTimeout(requestTimeout);
-};
+}
+
+function connectWebSocket() {
+    const ws = new WebSocket(backendUrl());
+    // Binary frames arrive as ArrayBuffers, decoded below, rather than Blobs that would need awaiting
+    ws.binaryType = 'arraybuffer';
+    let opened = false;
+    ws.onopen = () => {
+        opened = true;
+        server.send = text => ws.send(text);
+        // Polling responses are plain text, so only WebSocket connections ask for compression
+        server.decompresses = typeof DecompressionStream !== 'undefined';
+        onServerOpen('WebSocket');
+    };
+    ws.onclose = () => {
+        if (opened) {
+            onServerClose('WebSocket closed');
+        } else {
+            startPolling();
+        }
+    };
+    ws.onerror = (error) => {
+        console.error('WebSocket error:', error);
+    };
+    // Decompression is asynchronous, so messages are chained to keep them in order
+    let incoming = Promise.resolve();
+    ws.onmessage = (event) => {
+        // The browser hands over whole messages, however many frames they were sent in
+        if (event.data instanceof ArrayBuffer) {
+            incoming = incoming
+                .then(() => decodeBinaryMessage(event.data))
+                .then(handleServerMessage, e => console.error('Binary message could not be decoded:', e));
+        } else {
+            incoming = incoming.then(() => handleServerMessage(event.data));
+        }
+    };
+}
+
+// Gzip-compressed JSON behind GZIP_MESSAGE_PREFIX once compression is on; otherwise text
+// some proxies relay as binary frames
+async function decodeBinaryMessage(buffer) {
+    const bytes = new Uint8Array(buffer);
+    if (bytes[0] === GZIP_MESSAGE_PREFIX) {
+        const stream = new Blob([bytes.subarray(1)]).stream().pipeThrough(new DecompressionStream('gzip'));
+        return new Response(stream).text();
+    }
+    return new TextDecoder('utf-8', { fatal: true }).decode(bytes);
+}
 
-ws.onmessage = (event) => {
-    console.log('Received WebSocket message, type:', typeof event.data, 'data:', event.data);
+async function startPolling() {
+    console.log('WebSocket unavailable, falling back to HTTP long polling');
+    const pollUrl = pollingUrl();
+    try {
+        const response = await fetch(pollUrl, { method: 'POST' });
+        if (!response.ok) {
+            throw new Error(`server answered ${response.status}`);
+        }
+        const { session } = await response.json();
+        server.send = text => {
+            fetch(`${pollUrl}/${session}`, { method: 'POST', body: text })
+                .catch(error => console.error('Failed to send request:', error));
+        };
+        onServerOpen('HTTP polling');
+        while (true) {
+            // Answered as soon as messages are queued, one per line, or empty after a while
+            const poll = await fetch(`${pollUrl}/${session}`);
+            if (!poll.ok) {
+                throw new Error(`server answered ${poll.status}`);
+            }
+            const body = await poll.text();
+            body.split('\n').filter(line => line).forEach(handleServerMessage);
+        }
+    } catch (error) {
+        console.error('HTTP polling failed:', error);
+        onServerClose('WebSocket and HTTP polling unavailable');
+    }
+}
+
+connectWebSocket();
+
+function handleServerMessage(text) {
+    console.log('Received server message, type:', typeof text, 'data:', text);
     clearTimeout(requestTimeout);
-    if (typeof event.data !== 'string') {
-        console.error('Expected string message, got:', typeof event.data);
+    if (typeof text !== 'string') {
+        console.error('Expected string message, got:', typeof text);
         statusDiv.textContent = 'Unexpected message type from server';
         statusDiv.style.color = 'red';
         return;
     }
+    if (!text.trim()) {
+        console.warn('Ignoring empty server message');
+        return;
+    }
     try {
-        const data = JSON.parse(event.data);
+        const data = JSON.parse(text);
         console.log('Parsed response:', data);
         if (data.error) {
             console.log('Server error:', data.error);
-            statusDiv.textContent = `Error: ${data.error}`;
+            if (data.error === 'model not found' && data.id !== undefined) {
+                // Deleted since the list was sent; drop it now rather than at the next broadcast
+                allModels = allModels.filter(model => model.id !== data.id);
+                updateModelSelect(allModels);
+                updateScene();
+            }
+            if (data.error.includes('access code')) {
+                delete accessCodes[modelSelect.value];
+            }
+            // Say which request failed when the server names it
+            statusDiv.textContent = data.action ? `${data.action.replace(/_/g, ' ')} failed: ${data.error}` : `Error: ${data.error}`;
             statusDiv.style.color = 'red';
+        } else if (data.hello) {
+            // Server's default framing for this catalog
+            camera.position.fromArray(data.hello.camera_position);
+            controls.target.fromArray(data.hello.camera_focus);
+            // Opt in before requesting the model list, its largest message
+            if (server.decompresses && (data.hello.compression || []).includes('gzip')) {
+                server.send(JSON.stringify({ action: 'set_compression', encoding: 'gzip' }));
+            }
+            // Only the list; model bytes are fetched once a model is shown
+            const listRequest = { action: 'list_metadata' };
+            console.log('Sending list_metadata request:', listRequest);
+            server.send(JSON.stringify(listRequest));
         } else if (Array.isArray(data)) {
-            // Handle get_all response
+            // Handle list_metadata and get_all responses, which may leave out the bytes
             console.log('Received model list:', data);
-            allModels = data;
+            const known = new Map(allModels.map(model => [model.id, model]));
+            allModels = data.map(model => {
+                const previous = known.get(model.id);
+                return model.model_data || !previous ? model : { ...model, model_data: previous.model_data };
+            });
+            for (const id of fetchedModels.keys()) {
+                if (!data.some(model => model.id === id)) {
+                    fetchedModels.delete(id);
+                }
+            }
             updateModelSelect(data);
             // Update scene based on current selection
             updateScene();
-        } else if (data.id && data.model_data) {
-            // Handle get_by_id response
+        } else if (data.model_updated) {
+            // A model was added or its details changed; keep any bytes already fetched
+            const model = data.model_updated;
+            fetchedModels.delete(model.id);
+            const previous = allModels.find(known => known.id === model.id);
+            // Unless a revert replaced them, in which case the model is fetched again when shown
+            const merged = model.model_data || !previous || model.data_replaced ? model : { ...model, model_data: previous.model_data };
+            allModels = previous ? allModels.map(known => known.id === model.id ? merged : known) : [...allModels, merged];
+            updateModelSelect(allModels);
+            updateScene();
+        } else if (data.model_deleted) {
+            fetchedModels.delete(data.model_deleted.id);
+            allModels = allModels.filter(model => model.id !== data.model_deleted.id);
+            updateModelSelect(allModels);
+            updateScene();
+        } else if (data.models && data.missing) {
+            // get_many response with the bytes the "all" view was waiting for
+            allModels = allModels
+                .filter(model => !data.missing.includes(model.id))
+                .map(model => data.models.find(fetched => fetched.id === model.id && fetched.model_data) || model);
+            if (modelSelect.value === 'all') {
+                updateScene();
+            }
+        } else if (data.id && typeof data.model_data === 'string') {
+            // Handle get_by_id response; upload broadcasts without bytes only announce the model
             console.log('Received model:', data);
-            if (modelSelect.value == data.id) { // Ensure the response matches the selected model
+            if (data.model_data) {
+                rememberFetchedModel(data);
+            }
+            // The user may have picked another model while this one was on its way
+            if (data.model_data && modelSelect.value == data.id) { // Ensure the response matches the selected model
                 loadModelFromResponse([data], true);
             }
+        } else if (data.layouts || data.templates || data.layout || data.layout_updated || data.storage || data.metadata || data.thumbnail || data.thumbnail_invalidated || data.comment || data.comments || data.obj_export || data.whoami || data.presence || data.collaborator_view) {
+            // Layouts, thumbnails, comments, metadata, OBJ exports, sessions, presence and storage notices are only used by the native client
+            console.log('Ignoring native client message:', data);
         } else {
             console.log('Unexpected response format:', data);
             statusDiv.textContent = 'Unexpected server response';
             statusDiv.style.color = 'red';
         }
     } catch (e) {
-        console.error('Parse error:', e, 'Raw message:', event.data);
+        // Only the ends are logged; a truncated catalog message can be megabytes long
+        const excerpt = text.length > 400 ? `${text.slice(0, 200)} … ${text.slice(-200)}` : text;
+        console.error(`Parse error in a ${text.length}-character message:`, e, 'Raw message:', excerpt);
         statusDiv.textContent = 'Invalid response from server';
         statusDiv.style.color = 'red';
     }
-};
+}
 
 function updateModelSelect(models) {
     console.log('Updating dropdown with models:', models);
     const currentSelection = modelSelect.value;
+    const currentLabel = modelSelect.selectedOptions.length ? modelSelect.selectedOptions[0].textContent : '';
     modelSelect.innerHTML = '<option value="">Select a model</option><option value="all">Load All Models</option>';
     if (models.length === 0) {
         statusDiv.textContent = 'No models available in database';
@@ -118,10 +296,17 @@ function updateModelSelect(models) {
         return;
     }
     models.forEach((model) => {
-        if (model.id && model.model_data) {
+        if (model.id) {
             const option = document.createElement('option');
             option.value = model.id;
             option.textContent = model.name ? `${model.name} (ID: ${model.id})` : `Model ID: ${model.id}`;
+            if (model.protected) {
+                option.textContent += ' [locked]';
+            }
+            option.title = `Uploaded by ${model.created_by || 'Anonymous'}`;
+            if (model.created_at) {
+                option.title += ` on ${new Date(model.created_at * 1000).toLocaleString()}`;
+            }
             modelSelect.appendChild(option);
         } else {
             console.log('Skipping invalid model:', model);
@@ -129,7 +314,13 @@ function updateModelSelect(models) {
     });
     if (currentSelection && currentSelection !== 'all') {
         const optionExists = Array.from(modelSelect.options).some(option => option.value === currentSelection);
-        modelSelect.value = optionExists ? currentSelection : '';
+        if (!optionExists) {
+            // Another user deleted the selected model; fall back to showing everything
+            modelSelect.value = 'all';
+            showToast(`${currentLabel} was removed from the catalog`);
+        } else {
+            modelSelect.value = currentSelection;
+        }
     } else {
         modelSelect.value = currentSelection || '';
     }
@@ -137,6 +328,23 @@ function updateModelSelect(models) {
     statusDiv.style.color = 'green';
 }
 
+function rememberFetchedModel(model) {
+    fetchedModels.delete(model.id);
+    fetchedModels.set(model.id, model);
+    if (fetchedModels.size > FETCHED_MODEL_LIMIT) {
+        fetchedModels.delete(fetchedModels.keys().next().value);
+    }
+}
+
+function showToast(text) {
+    toastDiv.textContent = text;
+    toastDiv.style.display = 'block';
+    clearTimeout(toastTimeout);
+    toastTimeout = setTimeout(() => {
+        toastDiv.style.display = 'none';
+    }, 5000);
+}
+
 function loadModelFromResponse(models, clearScene = false) {
     console.log('Loading models:', models);
     if (clearScene) {
@@ -157,28 +365,29 @@ function loadModelFromResponse(models, clearScene = false) {
             }
             const arrayBuffer = bytes.buffer;
 
-            loader.parse(arrayBuffer, '', (gltf) => {
-                const modelScene = gltf.scene;
+            const onParsed = (modelScene) => {
                 modelScene.position.set(index * 3, 0, 0);
                 scene.add(modelScene);
                 currentModels.push(modelScene);
                 loadedCount++;
 
-                const box = new THREE.Box3();
-                currentModels.forEach(m => box.expandByObject(m));
-                const center = box.getCenter(new THREE.Vector3());
-                const size = box.getSize(new THREE.Vector3());
-                const maxDim = Math.max(size.x, size.y, size.z, 5);
-                camera.position.set(center.x, center.y, center.z + maxDim * 2);
-                controls.target = center;
-                spotLight.target.position.copy(center);
+                // Leave the user's view alone unless they opted out of a fixed camera
+                if (!keepCameraCheckbox.checked) {
+                    frameModels();
+                }
 
                 if (loadedCount === totalModels) {
                     statusDiv.textContent = `Loaded ${totalModels} model${totalModels > 1 ? 's' : ''}`;
                     statusDiv.style.color = 'green';
                     console.log('All models loaded successfully');
                 }
-            }, (error) => {
+            };
+
+            if (model.format === 'ply') {
+                onParsed(plyToObject(plyLoader.parse(arrayBuffer)));
+                return;
+            }
+            loader.parse(arrayBuffer, '', (gltf) => onParsed(gltf.scene), (error) => {
                 statusDiv.textContent = `Error loading model ID: ${model.id}`;
                 statusDiv.style.color = 'red';
                 console.error(`Error loading glTF for ID ${model.id}:`, error);
@@ -201,6 +410,71 @@ function loadModelFromResponse(models, clearScene = false) {
     });
 }
 
+function plyToObject(geometry) {
+    // PLY files without faces are point clouds
+    const hasColors = geometry.hasAttribute('color');
+    if (!geometry.index) {
+        const material = new THREE.PointsMaterial({ size: 0.01, vertexColors: hasColors });
+        return new THREE.Points(geometry, material);
+    }
+    if (!geometry.hasAttribute('normal')) {
+        geometry.computeVertexNormals();
+    }
+    const material = new THREE.MeshStandardMaterial({ vertexColors: hasColors, side: THREE.DoubleSide });
+    return new THREE.Mesh(geometry, material);
+}
+
+function frameModels() {
+    if (currentModels.length === 0) {
+        return;
+    }
+    const box = new THREE.Box3();
+    currentModels.forEach(m => box.expandByObject(m));
+    const center = box.getCenter(new THREE.Vector3());
+    const size = box.getSize(new THREE.Vector3());
+    const maxDim = Math.max(size.x, size.y, size.z, 5);
+    camera.position.set(center.x, center.y, center.z + maxDim * 2);
+    controls.target = center;
+    spotLight.target.position.copy(center);
+    if (camera === orthographicCamera) {
+        matchPerspectiveFraming(maxDim * 2);
+    }
+}
+
+// Sizes the orthographic view to what the perspective camera shows at `distance` from it
+function matchPerspectiveFraming(distance) {
+    const height = 2 * distance * Math.tan(THREE.MathUtils.degToRad(perspectiveCamera.fov) / 2);
+    const aspect = window.innerWidth / window.innerHeight;
+    orthographicCamera.top = height / 2;
+    orthographicCamera.bottom = -height / 2;
+    orthographicCamera.left = -height * aspect / 2;
+    orthographicCamera.right = height * aspect / 2;
+    orthographicCamera.zoom = 1;
+    orthographicCamera.updateProjectionMatrix();
+}
+
+// Swaps cameras at the same position and orientation, keeping the orbit target in frame
+function setOrthographic(enabled) {
+    const next = enabled ? orthographicCamera : perspectiveCamera;
+    if (next === camera) {
+        return;
+    }
+    next.position.copy(camera.position);
+    next.quaternion.copy(camera.quaternion);
+    if (enabled) {
+        matchPerspectiveFraming(camera.position.distanceTo(controls.target));
+    } else {
+        // Move to the distance at which perspective shows what the (zoomed) orthographic view did
+        const height = (orthographicCamera.top - orthographicCamera.bottom) / orthographicCamera.zoom;
+        const distance = height / 2 / Math.tan(THREE.MathUtils.degToRad(perspectiveCamera.fov) / 2);
+        const direction = next.position.clone().sub(controls.target).normalize();
+        next.position.copy(controls.target).addScaledVector(direction, distance);
+    }
+    camera = next;
+    controls.object = camera;
+    controls.update();
+}
+
 function updateScene() {
     const modelId = modelSelect.value;
     if (modelId === 'all') {
@@ -212,15 +486,25 @@ function updateScene() {
             currentModels = [];
             return;
         }
-        console.log('Loading all models:', allModels);
-        loadModelFromResponse(allModels, true);
+        // Protected models are only loaded individually with their access code
+        const openModels = allModels.filter(model => !model.protected);
+        const unfetched = openModels.filter(model => !model.model_data).map(model => model.id);
+        if (unfetched.length > 0 && server.open) {
+            const getManyRequest = { action: 'get_many', ids: unfetched };
+            console.log('Sending get_many request:', getManyRequest);
+            server.send(JSON.stringify(getManyRequest));
+            statusDiv.textContent = `Fetching ${unfetched.length} model${unfetched.length > 1 ? 's' : ''}...`;
+            return;
+        }
+        console.log('Loading all models:', openModels);
+        loadModelFromResponse(openModels, true);
         statusDiv.textContent = 'Requesting all models...';
     } else if (modelId) {
         const modelIdNum = parseInt(modelId);
-        if (!isNaN(modelIdNum) && modelIdNum > 0 && ws.readyState === WebSocket.OPEN) {
+        if (!isNaN(modelIdNum) && modelIdNum > 0 && server.open) {
             // Check if the model still exists in allModels
-            const modelExists = allModels.some(model => model.id === modelIdNum);
-            if (!modelExists) {
+            const selectedModel = allModels.find(model => model.id === modelIdNum);
+            if (!selectedModel) {
                 statusDiv.textContent = 'Selected model no longer exists';
                 statusDiv.style.color = 'red';
                 modelSelect.value = '';
@@ -228,9 +512,31 @@ function updateScene() {
                 currentModels = [];
                 return;
             }
+            const fetched = fetchedModels.get(modelIdNum);
+            if (fetched) {
+                rememberFetchedModel(fetched);
+                loadModelFromResponse([fetched], true);
+                return;
+            }
             const getByIdRequest = { action: 'get_by_id', id: modelIdNum };
+            // Low-memory devices ask for a reduced-detail variant; the server falls back to full detail
+            if (navigator.deviceMemory && navigator.deviceMemory < 4) {
+                getByIdRequest.lod = navigator.deviceMemory <= 1 ? 'low' : 'medium';
+            }
+            if (selectedModel.protected) {
+                if (!accessCodes[modelIdNum]) {
+                    const code = window.prompt(`Model ${modelIdNum} is protected. Enter its access code:`);
+                    if (!code) {
+                        statusDiv.textContent = 'Access code required';
+                        statusDiv.style.color = 'orange';
+                        return;
+                    }
+                    accessCodes[modelIdNum] = code;
+                }
+                getByIdRequest.access_code = accessCodes[modelIdNum];
+            }
             console.log('Sending get_by_id request:', getByIdRequest);
-            ws.send(JSON.stringify(getByIdRequest));
+            server.send(JSON.stringify(getByIdRequest));
             statusDiv.textContent = 'Requesting model...';
             requestTimeout = setTimeout(() => {
                 statusDiv.textContent = 'No response from server';
@@ -252,9 +558,23 @@ modelSelect.addEventListener('change', () => {
     updateScene();
 });
 
+frameButton.addEventListener('click', () => {
+    frameModels();
+});
+
+orthographicCheckbox.addEventListener('change', () => {
+    setOrthographic(orthographicCheckbox.checked);
+});
+
 window.addEventListener('resize', () => {
-    camera.aspect = window.innerWidth / window.innerHeight;
-    camera.updateProjectionMatrix();
+    perspectiveCamera.aspect = window.innerWidth / window.innerHeight;
+    perspectiveCamera.updateProjectionMatrix();
+    const height = orthographicCamera.top - orthographicCamera.bottom;
+    orthographicCamera.left = -height * perspectiveCamera.aspect / 2;
+    orthographicCamera.right = height * perspectiveCamera.aspect / 2;
+    orthographicCamera.updateProjectionMatrix();
+    // Moving the window to another monitor can change the density
+    renderer.setPixelRatio(pixelRatio());
     renderer.setSize(window.innerWidth, window.innerHeight);
 });
 
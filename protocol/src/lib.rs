@@ -0,0 +1,849 @@
+//! The WebSocket protocol shared by the backend and the native frontend: every
+//! request, response and pushed message, plus the action names requests use.
+
+use serde::{ Deserialize, Serialize };
+use std::{ collections::BTreeMap, time::Duration };
+
+/// Bumped whenever a change would break clients built against an older definition.
+/// Sent in the hello message so either side can tell it is talking to a mismatched peer.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Message encoding a client can opt in to with `set_compression` once the hello offers it.
+pub const GZIP_ENCODING: &str = "gzip";
+/// First byte of a binary message holding gzip-compressed JSON; the rest is the gzip stream.
+/// Plain JSON never starts with it, so uncompressed binary frames stay unambiguous.
+pub const GZIP_MESSAGE_PREFIX: u8 = 0x01;
+
+/// How often a client may share its view with `set_view`. The server broadcasts each
+/// session's view at most twice as often and only stores anything faster.
+pub const VIEW_SHARE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Values of the `action` tag of `ClientMessage`, as the server and permissions name them.
+pub mod actions {
+    pub const GET_BY_ID: &str = "get_by_id";
+    pub const GET_ALL: &str = "get_all";
+    pub const GET_MANY: &str = "get_many";
+    pub const INSERT: &str = "insert";
+    pub const DELETE: &str = "delete";
+    pub const SET_ACCESS_CODE: &str = "set_access_code";
+    pub const SET_THUMBNAIL: &str = "set_thumbnail";
+    pub const GET_THUMBNAIL: &str = "get_thumbnail";
+    pub const REGENERATE_THUMBNAIL: &str = "regenerate_thumbnail";
+    pub const SAVE_LAYOUT: &str = "save_layout";
+    pub const LIST_LAYOUTS: &str = "list_layouts";
+    pub const LOAD_LAYOUT: &str = "load_layout";
+    pub const COPY_TO_SCENE: &str = "copy_to_scene";
+    pub const COPY_TRANSFORM: &str = "copy_transform";
+    pub const SET_TRANSFORM: &str = "set_transform";
+    pub const ADD_COMMENT: &str = "add_comment";
+    pub const LIST_COMMENTS: &str = "list_comments";
+    pub const GET_METADATA: &str = "get_metadata";
+    pub const SET_METADATA: &str = "set_metadata";
+    pub const SET_TRANSFORM_LOCK: &str = "set_transform_lock";
+    pub const EXPORT_OBJ: &str = "export_obj";
+    pub const WHOAMI: &str = "whoami";
+    pub const LIST_TEMPLATES: &str = "list_templates";
+    pub const INSTANTIATE_TEMPLATE: &str = "instantiate_template";
+    pub const SET_COMPRESSION: &str = "set_compression";
+    pub const VALIDATE: &str = "validate";
+    pub const RENAME: &str = "rename";
+    pub const LIST_METADATA: &str = "list_metadata";
+    pub const SET_NAME: &str = "set_name";
+    pub const SET_VIEW: &str = "set_view";
+    pub const HISTORY: &str = "history";
+    pub const REVERT: &str = "revert";
+    pub const TRANSFORM: &str = "transform";
+
+    /// Every action, for permission checks that list what a role may do.
+    pub const ALL: [&str; 33] = [
+        GET_BY_ID,
+        GET_ALL,
+        GET_MANY,
+        INSERT,
+        DELETE,
+        SET_ACCESS_CODE,
+        SET_THUMBNAIL,
+        GET_THUMBNAIL,
+        REGENERATE_THUMBNAIL,
+        SAVE_LAYOUT,
+        LIST_LAYOUTS,
+        LOAD_LAYOUT,
+        COPY_TO_SCENE,
+        COPY_TRANSFORM,
+        SET_TRANSFORM,
+        ADD_COMMENT,
+        LIST_COMMENTS,
+        GET_METADATA,
+        SET_METADATA,
+        SET_TRANSFORM_LOCK,
+        EXPORT_OBJ,
+        WHOAMI,
+        LIST_TEMPLATES,
+        INSTANTIATE_TEMPLATE,
+        SET_COMPRESSION,
+        VALIDATE,
+        RENAME,
+        LIST_METADATA,
+        SET_NAME,
+        SET_VIEW,
+        HISTORY,
+        REVERT,
+        TRANSFORM,
+    ];
+
+    /// Actions that change stored data, all refused by a read-only server.
+    pub const MUTATING: [&str; 16] = [
+        INSERT,
+        DELETE,
+        RENAME,
+        SET_ACCESS_CODE,
+        SET_THUMBNAIL,
+        REGENERATE_THUMBNAIL,
+        SAVE_LAYOUT,
+        INSTANTIATE_TEMPLATE,
+        COPY_TO_SCENE,
+        COPY_TRANSFORM,
+        SET_TRANSFORM,
+        ADD_COMMENT,
+        SET_METADATA,
+        SET_TRANSFORM_LOCK,
+        REVERT,
+        TRANSFORM,
+    ];
+}
+
+/// A request from a client, tagged by its `action`. Fields a handler can't do without are
+/// required, so a request missing one fails to parse instead of reaching the handler.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientMessage {
+    GetById {
+        id: i32,
+        access_code: Option<String>, // current code for protected models
+        lod: Option<String>, // preferred detail level, "full", "medium" or "low"
+    },
+    GetAll,
+    GetMany {
+        ids: Vec<i32>, // models to fetch in one round-trip
+        lod: Option<String>, // as in get_by_id, for every unprotected model
+    },
+    Insert {
+        model_data: String, // base64-encoded model data
+        name: Option<String>,
+        format: Option<String>, // the uploader's guess, only logged; the server detects the format from the bytes
+        author: Option<String>, // display name, "Anonymous" when missing
+        transform: Option<ModelTransform>, // where the model starts out, unplaced when missing
+    },
+    Validate {
+        model_data: Option<String>, // as in insert; the report says so when it is missing
+        name: Option<String>,
+        format: Option<String>,
+    },
+    Delete {
+        id: i32,
+    },
+    Rename {
+        id: i32,
+        version: i64, // the model version the edit was made against, as in every versioned edit
+        name: Option<String>, // None or blank clears the name
+    },
+    SetAccessCode {
+        id: i32,
+        version: i64,
+        access_code: Option<String>, // current code, needed to change or clear an existing one
+        new_access_code: Option<String>, // None or empty clears the code
+    },
+    SetThumbnail {
+        id: i32,
+        thumbnail_data: String, // base64-encoded PNG
+        access_code: Option<String>,
+    },
+    GetThumbnail {
+        id: i32,
+        access_code: Option<String>,
+    },
+    RegenerateThumbnail {
+        id: i32,
+        access_code: Option<String>,
+    },
+    SaveLayout {
+        name: String,
+        entries: Vec<LayoutEntry>, // model placements
+        template: Option<bool>, // share it as a template instead of a working layout
+    },
+    ListLayouts,
+    LoadLayout {
+        id: i32, // layout or template
+    },
+    CopyToScene {
+        id: i32,
+        target_id: i32, // layout that receives the model
+    },
+    CopyTransform {
+        id: i32,
+        source_id: i32, // model whose placement is copied onto `id`
+        target_id: i32, // layout holding both
+    },
+    SetTransform {
+        target_id: i32, // layout whose placements change
+        entries: Vec<LayoutEntry>, // the new placements, at least one
+    },
+    AddComment {
+        id: i32,
+        author: Option<String>, // display name, "Anonymous" when missing
+        #[serde(default)]
+        text: String,
+    },
+    ListComments {
+        id: i32,
+    },
+    GetMetadata {
+        id: i32,
+    },
+    SetMetadata {
+        id: i32,
+        version: i64,
+        metadata: BTreeMap<String, String>, // replaces every entry of the model
+    },
+    SetTransformLock {
+        id: i32,
+        version: i64,
+        locked: bool, // whether the model's placement may change
+    },
+    ExportObj {
+        id: i32,
+        name: Option<String>, // base name for the exported files
+        access_code: Option<String>,
+    },
+    Whoami,
+    ListTemplates,
+    InstantiateTemplate {
+        id: i32, // template to copy
+        name: Option<String>, // name of the new layout, the template's when missing
+    },
+    SetCompression {
+        encoding: Option<String>, // "gzip" for compressed large messages, None or empty for plain text
+    },
+    ListMetadata,
+    SetName {
+        #[serde(default)]
+        name: String, // blank goes back to the placeholder
+    },
+    SetView {
+        view: ViewState, // the sender's camera and selection, for collaborators following along
+    },
+    History {
+        id: i32,
+    },
+    Revert {
+        id: i32,
+        version: i64,
+        restore_version: i64, // the recorded version to put back
+        access_code: Option<String>,
+    },
+    Transform {
+        id: i32,
+        version: i64,
+        transform: Option<ModelTransform>, // the model's new placement; None clears it
+    },
+}
+
+impl ClientMessage {
+    /// The request's name in `actions`, for permission checks and error replies.
+    pub fn action(&self) -> &'static str {
+        match self {
+            ClientMessage::GetById { .. } => actions::GET_BY_ID,
+            ClientMessage::GetAll => actions::GET_ALL,
+            ClientMessage::GetMany { .. } => actions::GET_MANY,
+            ClientMessage::Insert { .. } => actions::INSERT,
+            ClientMessage::Validate { .. } => actions::VALIDATE,
+            ClientMessage::Delete { .. } => actions::DELETE,
+            ClientMessage::Rename { .. } => actions::RENAME,
+            ClientMessage::SetAccessCode { .. } => actions::SET_ACCESS_CODE,
+            ClientMessage::SetThumbnail { .. } => actions::SET_THUMBNAIL,
+            ClientMessage::GetThumbnail { .. } => actions::GET_THUMBNAIL,
+            ClientMessage::RegenerateThumbnail { .. } => actions::REGENERATE_THUMBNAIL,
+            ClientMessage::SaveLayout { .. } => actions::SAVE_LAYOUT,
+            ClientMessage::ListLayouts => actions::LIST_LAYOUTS,
+            ClientMessage::LoadLayout { .. } => actions::LOAD_LAYOUT,
+            ClientMessage::CopyToScene { .. } => actions::COPY_TO_SCENE,
+            ClientMessage::CopyTransform { .. } => actions::COPY_TRANSFORM,
+            ClientMessage::SetTransform { .. } => actions::SET_TRANSFORM,
+            ClientMessage::AddComment { .. } => actions::ADD_COMMENT,
+            ClientMessage::ListComments { .. } => actions::LIST_COMMENTS,
+            ClientMessage::GetMetadata { .. } => actions::GET_METADATA,
+            ClientMessage::SetMetadata { .. } => actions::SET_METADATA,
+            ClientMessage::SetTransformLock { .. } => actions::SET_TRANSFORM_LOCK,
+            ClientMessage::ExportObj { .. } => actions::EXPORT_OBJ,
+            ClientMessage::Whoami => actions::WHOAMI,
+            ClientMessage::ListTemplates => actions::LIST_TEMPLATES,
+            ClientMessage::InstantiateTemplate { .. } => actions::INSTANTIATE_TEMPLATE,
+            ClientMessage::SetCompression { .. } => actions::SET_COMPRESSION,
+            ClientMessage::ListMetadata => actions::LIST_METADATA,
+            ClientMessage::SetName { .. } => actions::SET_NAME,
+            ClientMessage::SetView { .. } => actions::SET_VIEW,
+            ClientMessage::History { .. } => actions::HISTORY,
+            ClientMessage::Revert { .. } => actions::REVERT,
+            ClientMessage::Transform { .. } => actions::TRANSFORM,
+        }
+    }
+
+    /// Every model the request names. The server checks they exist before acting and answers
+    /// `ServerMessage::ModelNotFound` for the first that doesn't.
+    pub fn named_models(&self) -> Vec<i32> {
+        match self {
+            ClientMessage::GetById { id, .. }
+            | ClientMessage::Delete { id }
+            | ClientMessage::Rename { id, .. }
+            | ClientMessage::SetAccessCode { id, .. }
+            | ClientMessage::SetThumbnail { id, .. }
+            | ClientMessage::GetThumbnail { id, .. }
+            | ClientMessage::RegenerateThumbnail { id, .. }
+            | ClientMessage::CopyToScene { id, .. }
+            | ClientMessage::AddComment { id, .. }
+            | ClientMessage::ListComments { id }
+            | ClientMessage::GetMetadata { id }
+            | ClientMessage::SetMetadata { id, .. }
+            | ClientMessage::SetTransformLock { id, .. }
+            | ClientMessage::ExportObj { id, .. }
+            | ClientMessage::History { id }
+            | ClientMessage::Revert { id, .. }
+            | ClientMessage::Transform { id, .. } => vec![*id],
+            ClientMessage::CopyTransform { id, source_id, .. } => vec![*id, *source_id],
+            ClientMessage::SetTransform { entries, .. } => entries.iter().map(|entry| entry.model_id).collect(),
+            // get_many reports missing models in its reply instead
+            ClientMessage::GetMany { .. }
+            | ClientMessage::GetAll
+            | ClientMessage::Insert { .. }
+            | ClientMessage::Validate { .. }
+            | ClientMessage::SaveLayout { .. }
+            | ClientMessage::ListLayouts
+            | ClientMessage::LoadLayout { .. }
+            | ClientMessage::Whoami
+            | ClientMessage::ListTemplates
+            | ClientMessage::InstantiateTemplate { .. }
+            | ClientMessage::SetCompression { .. }
+            | ClientMessage::ListMetadata
+            | ClientMessage::SetName { .. }
+            | ClientMessage::SetView { .. } => vec![],
+        }
+    }
+}
+
+// Fields added after the first release default so older servers still parse
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ModelResponse {
+    pub id: i32,
+    pub name: Option<String>,
+    pub model_data: String, // base64-encoded model data, empty when withheld or left out of a broadcast
+    #[serde(default)]
+    pub protected: bool, // bytes require an access code via get_by_id
+    #[serde(default = "default_format")]
+    pub format: String, // "gltf" or "ply", picks the client-side loader
+    #[serde(default)]
+    pub texture_warnings: bool, // some embedded textures failed to decode on upload
+    #[serde(default)]
+    pub lods: Vec<String>, // detail levels that can be requested, always including "full"
+    #[serde(default = "default_lod")]
+    pub lod: String, // level of model_data in this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>, // insert: stored, but something needs the uploader's attention
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture_bytes_saved: Option<u64>, // insert: shrinkage from scaling down oversized textures
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>, // get_by_id: custom key/value fields
+    #[serde(default)]
+    pub transform_locked: bool, // reference geometry whose placement in layouts can't be changed
+    #[serde(default = "default_author")]
+    pub created_by: String, // display name given by the uploader
+    #[serde(default)]
+    pub version: i64, // bumped by every edit; 0 from servers without versioning
+    #[serde(default)]
+    pub created_at: i64, // upload time in seconds since the Unix epoch, 0 when unknown
+    #[serde(default)]
+    pub updated_at: i64, // last edit, as created_at
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub data_replaced: bool, // model_updated: the bytes changed too, so fetched copies are out of date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<ModelTransform>, // the model's own placement, None until it is given one
+}
+
+fn default_format() -> String {
+    "gltf".to_string()
+}
+
+fn default_lod() -> String {
+    "full".to_string()
+}
+
+fn default_author() -> String {
+    "Anonymous".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LayoutEntry {
+    pub model_id: i32,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4], // quaternion (x, y, z, w)
+    pub scale: [f32; 3],
+}
+
+/// Where a model sits when no shown layout places it. Unlike layout entries it belongs to the
+/// model, so every client showing the model outside a layout puts it in the same spot.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModelTransform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4], // quaternion (x, y, z, w)
+    pub scale: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LayoutSummary {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LayoutResponse {
+    pub id: i32,
+    pub name: String,
+    pub entries: Vec<LayoutEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThumbnailResponse {
+    pub id: i32,
+    pub version: String, // content hash of the model bytes the image was rendered from
+    pub stale: bool, // the model changed since; serve the old image until a client regenerates it
+    pub image_data: String, // base64-encoded PNG, empty when none has been generated yet
+}
+
+/// Sent once to every client right after it connects.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HelloResponse {
+    #[serde(default)]
+    pub protocol_version: u32, // PROTOCOL_VERSION of the server, 0 before versioning
+    pub camera_position: [f32; 3], // default camera placement for this catalog
+    pub camera_focus: [f32; 3], // point the default camera orbits around
+    #[serde(default)]
+    pub read_only: bool, // mutating actions are rejected, clients should hide their controls
+    #[serde(default)]
+    pub compression: Vec<String>, // encodings set_compression accepts on this connection, empty when off
+    #[serde(default)]
+    pub max_upload_bytes: Option<u64>, // largest model insert accepts, so clients can refuse bigger files up front
+}
+
+/// Catalog storage against the server's quota, pushed when it changes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StorageStatus {
+    pub used_bytes: u64,
+    pub quota_bytes: u64,
+    pub nearing_capacity: bool, // past the soft threshold; uploads still succeed until the quota
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommentResponse {
+    pub id: i32,
+    pub model_id: i32,
+    pub author: String,
+    pub text: String,
+    pub created_at: i64, // seconds since the Unix epoch
+}
+
+/// A glTF model converted to Wavefront OBJ, answered to export_obj.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ObjExport {
+    pub model_id: i32,
+    pub obj: String,
+    pub mtl: String,
+    pub textures: Vec<ExportedTexture>, // images the MTL references, to be saved next to it
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportedTexture {
+    pub file_name: String,
+    pub data: String, // base64-encoded PNG or JPEG
+}
+
+/// The requesting connection as the server sees it, answered to whoami.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub session_id: String, // assigned per connection; a reconnect gets a new one
+    pub role: String, // "editor" or "viewer" unless the server defines its own roles
+    #[serde(default)]
+    pub allowed_actions: Vec<String>, // what this session may request; anything else is refused with an error
+}
+
+/// A connected session, as listed to every client.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Collaborator {
+    pub session_id: String,
+    pub name: String, // picked with set_name, a placeholder until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<ViewState>, // last shared with set_view, None until the client shares one
+}
+
+/// Where a client's orbit camera looks and which models it shows.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewState {
+    pub focus: [f32; 3], // point the camera orbits around
+    pub yaw: f32, // radians
+    pub pitch: f32, // radians
+    pub radius: f32, // distance from the focus, or the orthographic scale
+    #[serde(default)]
+    pub selected_models: Vec<i32>, // models composing the sender's scene, empty while all are shown
+}
+
+/// Answer to validate: what insert would make of an upload, without storing anything.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub valid: bool, // insert would accept it
+    pub stats: UploadStats,
+    pub warnings: Vec<String>, // problems insert stores the model with anyway, like undecodable textures
+    pub errors: Vec<String>, // reasons insert would refuse it
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UploadStats {
+    pub format: Option<String>, // as detected from the bytes, None when unrecognized
+    pub upload_bytes: u64,
+    pub stored_bytes: u64, // model, original and detail levels together, as counted against the quota
+    pub texture_bytes_saved: Option<u64>, // from scaling down oversized textures, when MAX_TEXTURE_SIZE applies
+    pub lods: Vec<String>, // detail levels that would be stored, always including "full"
+}
+
+/// One entry of a model's history, the state an edit left it in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ModelVersion {
+    pub version: i64,
+    pub change: String, // what the edit did, e.g. "renamed to Tower"
+    pub name: Option<String>, // the model's name at this version
+    pub changed_at: i64, // seconds since the Unix epoch, 0 when unknown
+}
+
+/// Broadcast when a model is deleted; clients drop it from their lists.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeletedModel {
+    pub id: i32,
+    pub session_id: String, // connection that deleted it, so that client can tell its own deletes apart
+}
+
+/// Every message shape the backend can push over the socket. Untagged, so each
+/// variant is told apart by its keys and order matters for overlapping shapes.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    Models(Vec<ModelResponse>),
+    ManyModels { models: Vec<ModelResponse>, missing: Vec<i32> },
+    Layouts { layouts: Vec<LayoutSummary> },
+    Templates { templates: Vec<LayoutSummary> }, // list_templates reply, broadcast when one is saved
+    Layout { layout: LayoutResponse },
+    LayoutUpdated { layout_updated: LayoutResponse }, // broadcast when a saved layout's placements change
+    Thumbnail { thumbnail: ThumbnailResponse },
+    ThumbnailInvalidated { thumbnail_invalidated: i32 },
+    Storage { storage: StorageStatus },
+    Hello { hello: HelloResponse },
+    Comments { model_id: i32, comments: Vec<CommentResponse> },
+    NewComment { comment: CommentResponse },
+    Metadata { model_id: i32, metadata: BTreeMap<String, String> }, // get_metadata reply, broadcast after set_metadata
+    History { model_id: i32, history: Vec<ModelVersion> }, // history reply, oldest version first
+    ObjExport { obj_export: ObjExport },
+    Whoami { whoami: SessionInfo },
+    Presence { presence: Vec<Collaborator> }, // everyone connected, broadcast when someone joins, leaves or renames
+    CollaboratorView { collaborator_view: Collaborator }, // broadcast after set_view, throttled per VIEW_SHARE_INTERVAL
+    ModelDeleted { model_deleted: DeletedModel },
+    ModelUpdated { model_updated: ModelResponse }, // broadcast when a model is added or its list entry changes
+    Validation { validation: ValidationReport },
+    StaleVersion { error: String, id: i32, version: i64, action: String }, // must precede ModelNotFound and Error, which would also match
+    ModelNotFound { error: String, id: i32 }, // must precede Error, which would also match
+    Error {
+        error: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        action: Option<String>, // request that failed, for clients to say what went wrong
+    },
+    Model(ModelResponse),
+}
+
+impl ServerMessage {
+    /// The answer to any request naming a model that doesn't exist (or no longer does).
+    pub fn model_not_found(id: i32) -> Self {
+        ServerMessage::ModelNotFound { error: "model not found".to_string(), id }
+    }
+
+    /// The answer to an edit made against an older version of the model than the stored one,
+    /// which someone else changed in the meantime. Nothing was written.
+    pub fn stale_version(id: i32, version: i64, action: &str) -> Self {
+        ServerMessage::StaleVersion { error: "stale version".to_string(), id, version, action: action.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trips() {
+        let request = ClientMessage::SaveLayout {
+            name: "Yard".to_string(),
+            entries: vec![LayoutEntry {
+                model_id: 1,
+                translation: [1.0, 2.0, 3.0],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                scale: [1.0; 3],
+            }],
+            template: Some(false),
+        };
+        let json: serde_json::Value = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["action"], request.action());
+        let parsed: ClientMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn transform_request_round_trips() {
+        let transform = ModelTransform { translation: [0.5, 0.0, 3.0], rotation: [0.0, 0.0, 0.0, 1.0], scale: [1.0, 2.0, 1.0] };
+        let request = ClientMessage::Transform { id: 4, version: 2, transform: Some(transform) };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            r#"{"action":"transform","id":4,"version":2,"transform":{"translation":[0.5,0.0,3.0],"rotation":[0.0,0.0,0.0,1.0],"scale":[1.0,2.0,1.0]}}"#
+        );
+        assert_eq!(serde_json::from_str::<ClientMessage>(&json).unwrap(), request);
+        // Clearing a placement may leave the field out
+        let cleared: ClientMessage = serde_json::from_str(r#"{"action":"transform","id":4,"version":2}"#).unwrap();
+        assert_eq!(cleared, ClientMessage::Transform { id: 4, version: 2, transform: None });
+    }
+
+    #[test]
+    fn sparse_request_parses() {
+        // The web client only sends the fields an action needs
+        let parsed: ClientMessage = serde_json::from_str(r#"{"action":"get_by_id","id":3}"#).unwrap();
+        assert_eq!(parsed, ClientMessage::GetById { id: 3, access_code: None, lod: None });
+        let parsed: ClientMessage = serde_json::from_str(r#"{"action":"list_layouts"}"#).unwrap();
+        assert_eq!(parsed, ClientMessage::ListLayouts);
+    }
+
+    #[test]
+    fn unknown_or_incomplete_requests_are_rejected() {
+        for json in [r#"{"action":"explode"}"#, r#"{"id":3}"#, r#"{"action":"rename","id":3}"#, r#"{"action":"delete"}"#] {
+            assert!(serde_json::from_str::<ClientMessage>(json).is_err(), "{} parsed", json);
+        }
+    }
+
+    #[test]
+    fn named_models_cover_every_model_a_request_touches() {
+        let copy = ClientMessage::CopyTransform { id: 2, source_id: 5, target_id: 9 };
+        assert_eq!(copy.named_models(), vec![2, 5]);
+        let entry = |model_id| LayoutEntry { model_id, translation: [0.0; 3], rotation: [0.0, 0.0, 0.0, 1.0], scale: [1.0; 3] };
+        let moved = ClientMessage::SetTransform { target_id: 9, entries: vec![entry(3), entry(4)] };
+        assert_eq!(moved.named_models(), vec![3, 4]);
+        assert_eq!(ClientMessage::Transform { id: 6, version: 1, transform: None }.named_models(), vec![6]);
+        assert!(ClientMessage::LoadLayout { id: 9 }.named_models().is_empty());
+    }
+
+    #[test]
+    fn response_round_trips() {
+        let response = ModelResponse {
+            id: 7,
+            name: Some("Crate".to_string()),
+            model_data: "AAAA".to_string(),
+            protected: true,
+            format: "ply".to_string(),
+            texture_warnings: true,
+            lods: vec!["full".to_string(), "low".to_string()],
+            lod: "low".to_string(),
+            warning: None,
+            texture_bytes_saved: Some(2048),
+            metadata: BTreeMap::from([("license".to_string(), "CC-BY".to_string())]),
+            transform_locked: true,
+            created_by: "Alice".to_string(),
+            version: 3,
+            created_at: 1_700_000_000,
+            updated_at: 1_700_000_600,
+            data_replaced: true,
+            transform: Some(ModelTransform { translation: [1.0, 0.0, -2.5], rotation: [0.0, 0.6, 0.0, 0.8], scale: [2.0; 3] }),
+        };
+        let parsed: ModelResponse = serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn server_messages_keep_their_shape() {
+        let error = serde_json::to_string(&ServerMessage::Error { error: "Model not found".to_string(), action: None }).unwrap();
+        assert_eq!(error, r#"{"error":"Model not found"}"#);
+        let invalidated = serde_json::to_string(&ServerMessage::ThumbnailInvalidated { thumbnail_invalidated: 4 }).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&invalidated).unwrap(),
+            ServerMessage::ThumbnailInvalidated { thumbnail_invalidated: 4 }
+        ));
+        let comments = r#"{"model_id":2,"comments":[]}"#;
+        assert!(matches!(serde_json::from_str(comments).unwrap(), ServerMessage::Comments { model_id: 2, .. }));
+        let version = ModelVersion { version: 2, change: "renamed to Box".to_string(), name: Some("Box".to_string()), changed_at: 0 };
+        let history = serde_json::to_string(&ServerMessage::History { model_id: 2, history: vec![version.clone()] }).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&history).unwrap(),
+            ServerMessage::History { model_id: 2, history } if history == [version]
+        ));
+        let not_found = serde_json::to_string(&ServerMessage::model_not_found(5)).unwrap();
+        assert_eq!(not_found, r#"{"error":"model not found","id":5}"#);
+        assert!(matches!(serde_json::from_str(&not_found).unwrap(), ServerMessage::ModelNotFound { id: 5, .. }));
+        let stale = serde_json::to_string(&ServerMessage::stale_version(5, 4, "rename")).unwrap();
+        assert!(matches!(serde_json::from_str(&stale).unwrap(), ServerMessage::StaleVersion { id: 5, version: 4, .. }));
+        assert!(matches!(serde_json::from_str(&error).unwrap(), ServerMessage::Error { action: None, .. }));
+        let failed = r#"{"error":"Invalid base64 data","action":"insert"}"#;
+        assert!(matches!(serde_json::from_str(failed).unwrap(), ServerMessage::Error { action: Some(action), .. } if action == "insert"));
+        let deleted = r#"{"model_deleted":{"id":3,"session_id":"7"}}"#;
+        assert!(matches!(serde_json::from_str(deleted).unwrap(), ServerMessage::ModelDeleted { .. }));
+        let updated = r#"{"model_updated":{"id":3,"name":"Box","model_data":""}}"#;
+        assert!(matches!(serde_json::from_str(updated).unwrap(), ServerMessage::ModelUpdated { .. }));
+        let presence = r#"{"presence":[{"session_id":"2","name":"Guest 2"}]}"#;
+        assert!(matches!(serde_json::from_str(presence).unwrap(), ServerMessage::Presence { presence } if presence.len() == 1));
+        let view = ViewState { focus: [0.0; 3], yaw: 0.5, pitch: 0.25, radius: 8.0, selected_models: vec![2, 5] };
+        let collaborator_view = Collaborator { session_id: "3".to_string(), name: "Bob".to_string(), view: Some(view.clone()) };
+        let moved = serde_json::to_string(&ServerMessage::CollaboratorView { collaborator_view }).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&moved).unwrap(),
+            ServerMessage::CollaboratorView { collaborator_view } if collaborator_view.view == Some(view)
+        ));
+        let validation = serde_json::to_string(&ServerMessage::Validation { validation: ValidationReport::default() }).unwrap();
+        assert!(matches!(serde_json::from_str(&validation).unwrap(), ServerMessage::Validation { .. }));
+        let model = r#"{"id":1,"name":"Box","model_data":""}"#;
+        assert!(matches!(serde_json::from_str(model).unwrap(), ServerMessage::Model(_)));
+    }
+
+    // Numbers the variants without a wildcard, so a new one doesn't compile until it is
+    // given a sample in every_server_message_round_trips
+    fn variant_index(message: &ServerMessage) -> usize {
+        match message {
+            ServerMessage::Models(_) => 0,
+            ServerMessage::ManyModels { .. } => 1,
+            ServerMessage::Layouts { .. } => 2,
+            ServerMessage::Templates { .. } => 3,
+            ServerMessage::Layout { .. } => 4,
+            ServerMessage::LayoutUpdated { .. } => 5,
+            ServerMessage::Thumbnail { .. } => 6,
+            ServerMessage::ThumbnailInvalidated { .. } => 7,
+            ServerMessage::Storage { .. } => 8,
+            ServerMessage::Hello { .. } => 9,
+            ServerMessage::Comments { .. } => 10,
+            ServerMessage::NewComment { .. } => 11,
+            ServerMessage::Metadata { .. } => 12,
+            ServerMessage::History { .. } => 13,
+            ServerMessage::ObjExport { .. } => 14,
+            ServerMessage::Whoami { .. } => 15,
+            ServerMessage::Presence { .. } => 16,
+            ServerMessage::CollaboratorView { .. } => 17,
+            ServerMessage::ModelDeleted { .. } => 18,
+            ServerMessage::ModelUpdated { .. } => 19,
+            ServerMessage::Validation { .. } => 20,
+            ServerMessage::StaleVersion { .. } => 21,
+            ServerMessage::ModelNotFound { .. } => 22,
+            ServerMessage::Error { .. } => 23,
+            ServerMessage::Model(_) => 24,
+        }
+    }
+
+    #[test]
+    fn every_server_message_round_trips() {
+        let model = ModelResponse {
+            id: 1,
+            name: Some("Box".to_string()),
+            model_data: String::new(),
+            protected: false,
+            format: "gltf".to_string(),
+            texture_warnings: false,
+            lods: vec!["full".to_string()],
+            lod: "full".to_string(),
+            warning: None,
+            texture_bytes_saved: None,
+            metadata: BTreeMap::new(),
+            transform_locked: false,
+            created_by: "Anonymous".to_string(),
+            version: 1,
+            created_at: 0,
+            updated_at: 0,
+            data_replaced: false,
+            transform: None,
+        };
+        let layout = LayoutResponse { id: 2, name: "Yard".to_string(), entries: vec![] };
+        let summary = LayoutSummary { id: 2, name: "Yard".to_string() };
+        let comment = CommentResponse { id: 3, model_id: 1, author: "Ann".to_string(), text: "Nice".to_string(), created_at: 0 };
+        let collaborator = Collaborator { session_id: "4".to_string(), name: "Guest 4".to_string(), view: None };
+        let hello = HelloResponse {
+            protocol_version: PROTOCOL_VERSION,
+            camera_position: [0.0; 3],
+            camera_focus: [0.0; 3],
+            read_only: false,
+            compression: vec![],
+            max_upload_bytes: None,
+        };
+        let messages = vec![
+            ServerMessage::Models(vec![model.clone()]),
+            ServerMessage::ManyModels { models: vec![model.clone()], missing: vec![9] },
+            ServerMessage::Layouts { layouts: vec![summary.clone()] },
+            ServerMessage::Templates { templates: vec![summary] },
+            ServerMessage::Layout { layout: layout.clone() },
+            ServerMessage::LayoutUpdated { layout_updated: layout },
+            ServerMessage::Thumbnail {
+                thumbnail: ThumbnailResponse { id: 1, version: "abc".to_string(), stale: false, image_data: String::new() },
+            },
+            ServerMessage::ThumbnailInvalidated { thumbnail_invalidated: 1 },
+            ServerMessage::Storage { storage: StorageStatus { used_bytes: 1, quota_bytes: 2, nearing_capacity: false } },
+            ServerMessage::Hello { hello },
+            ServerMessage::Comments { model_id: 1, comments: vec![comment.clone()] },
+            ServerMessage::NewComment { comment },
+            ServerMessage::Metadata { model_id: 1, metadata: BTreeMap::from([("k".to_string(), "v".to_string())]) },
+            ServerMessage::History {
+                model_id: 1,
+                history: vec![ModelVersion { version: 1, change: "uploaded".to_string(), name: None, changed_at: 0 }],
+            },
+            ServerMessage::ObjExport {
+                obj_export: ObjExport { model_id: 1, obj: String::new(), mtl: String::new(), textures: vec![] },
+            },
+            ServerMessage::Whoami { whoami: SessionInfo { session_id: "4".to_string(), role: "editor".to_string(), allowed_actions: vec![] } },
+            ServerMessage::Presence { presence: vec![collaborator.clone()] },
+            ServerMessage::CollaboratorView { collaborator_view: collaborator },
+            ServerMessage::ModelDeleted { model_deleted: DeletedModel { id: 1, session_id: "4".to_string() } },
+            ServerMessage::ModelUpdated { model_updated: model.clone() },
+            ServerMessage::Validation { validation: ValidationReport::default() },
+            ServerMessage::stale_version(1, 2, actions::RENAME),
+            ServerMessage::model_not_found(1),
+            ServerMessage::Error { error: "Unknown action: fly".to_string(), action: Some("fly".to_string()) },
+            ServerMessage::Model(model),
+        ];
+        for (index, message) in messages.iter().enumerate() {
+            assert_eq!(variant_index(message), index, "samples are listed in variant order");
+            let json = serde_json::to_string(message).unwrap();
+            let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+            assert_eq!(variant_index(&parsed), index, "{} parsed as another message", json);
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn action_lists_name_known_actions() {
+        for action in &actions::MUTATING {
+            assert!(actions::ALL.contains(action), "{} is missing from ALL", action);
+        }
+        for (index, action) in actions::ALL.iter().enumerate() {
+            assert!(!actions::ALL[..index].contains(action), "{} is listed twice", action);
+        }
+    }
+
+    #[test]
+    fn hello_from_older_server_parses() {
+        let parsed: HelloResponse = serde_json::from_str(r#"{"camera_position":[0,1,2],"camera_focus":[0,0,0]}"#).unwrap();
+        assert_eq!(parsed.protocol_version, 0);
+        assert!(!parsed.read_only && parsed.compression.is_empty());
+        assert_eq!(parsed.max_upload_bytes, None);
+    }
+
+    #[test]
+    fn response_from_older_server_parses() {
+        let parsed: ModelResponse = serde_json::from_str(r#"{"id":1,"name":null,"model_data":""}"#).unwrap();
+        assert_eq!(parsed.format, "gltf");
+        assert_eq!(parsed.lod, "full");
+        assert!(!parsed.protected && parsed.lods.is_empty());
+        assert_eq!(parsed.version, 0);
+    }
+}